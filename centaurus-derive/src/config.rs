@@ -37,6 +37,7 @@ pub fn config(input: TokenStream) -> TokenStream {
   let mut auth_field: Option<Ident> = None;
   let mut oidc_field: Option<Ident> = None;
   let mut mail_field: Option<Ident> = None;
+  let mut rate_limit_field: Option<Ident> = None;
 
   for field in files {
     for attr in &field.attrs {
@@ -85,6 +86,15 @@ pub fn config(input: TokenStream) -> TokenStream {
           .to_compile_error();
         }
         mail_field = field.ident.clone();
+      } else if attr.path().is_ident("rate_limit") {
+        if rate_limit_field.is_some() {
+          return syn::Error::new_spanned(
+            &field.ident,
+            "Multiple fields with #[rate_limit] attribute found",
+          )
+          .to_compile_error();
+        }
+        rate_limit_field = field.ident.clone();
       } else if attr.path().is_ident("auth") {
         if auth_field.is_some() {
           return syn::Error::new_spanned(
@@ -166,6 +176,16 @@ pub fn config(input: TokenStream) -> TokenStream {
     quote! {}
   };
 
+  let rate_limit_impl = if let Some(rate_limit_field) = rate_limit_field {
+    quote! {
+      fn rate_limit(&self) -> Option<&#path::backend::config::RateLimitConfig> {
+        Some(&self.#rate_limit_field)
+      }
+    }
+  } else {
+    quote! {}
+  };
+
   quote! {
     impl #path::backend::config::Config for #name {
       fn base(&self) -> &#path::backend::config::BaseConfig {
@@ -181,6 +201,8 @@ pub fn config(input: TokenStream) -> TokenStream {
       #oidc_impl
 
       #mail_impl
+
+      #rate_limit_impl
     }
   }
 }
@@ -249,6 +271,28 @@ mod tests {
     // The optional oidc/mail accessors are generated when their fields exist.
     assert!(output.contains("fn oidc"));
     assert!(output.contains("fn mail"));
+    assert!(!output.contains("fn rate_limit"));
+  }
+
+  #[test]
+  fn test_config_derive_rate_limit() {
+    let input = quote! {
+      struct MyConfig {
+        #[base]
+        base: BaseConfig,
+        #[metrics]
+        metrics: MetricsConfig,
+        #[site]
+        site: SiteConfig,
+        #[auth]
+        auth: AuthConfig,
+        #[rate_limit]
+        rate_limit: RateLimitConfig,
+      }
+    };
+    let output = config(input).to_string();
+    assert!(!output.contains("compile_error"));
+    assert!(output.contains("fn rate_limit"));
   }
 
   #[test]
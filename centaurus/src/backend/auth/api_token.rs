@@ -0,0 +1,158 @@
+use chrono::{NaiveDateTime, Utc};
+use uuid::Uuid;
+
+use crate::{
+  backend::auth::{
+    jwt_state::JwtClaims,
+    token::{generate_token, hash_token},
+  },
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::Result,
+};
+
+/// Makes personal access tokens recognizable, e.g. for secret scanners, and
+/// tells them apart from JWTs.
+pub const API_TOKEN_PREFIX: &str = "cnt_";
+/// Scope for routes without a permission, these manage the owner's own
+/// account. Every user may grant it to their tokens.
+pub const ACCOUNT_SCOPE: &str = "account:self";
+/// Claim carrying the token id in the claims handed to
+/// [`crate::backend::auth::jwt_auth::Auth`].
+pub const API_TOKEN_CLAIM: &str = "api_token";
+
+pub struct ApiTokenAuth {
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub exp: Option<NaiveDateTime>,
+  pub permissions: Vec<String>,
+}
+
+impl ApiTokenAuth {
+  /// Claims equivalent to the token, so the same auth checks run for tokens
+  /// and JWTs.
+  pub fn claims(&self, iss: &str) -> JwtClaims {
+    JwtClaims {
+      exp: self
+        .exp
+        .map(|exp| exp.and_utc().timestamp())
+        .unwrap_or(i64::MAX),
+      iss: iss.to_string(),
+      sub: self.user_id,
+      sid: None,
+      additional_claims: [(API_TOKEN_CLAIM.to_string(), self.id.to_string().into())].into(),
+    }
+  }
+}
+
+pub fn generate_api_token() -> String {
+  format!("{API_TOKEN_PREFIX}{}", generate_token(48))
+}
+
+pub fn is_api_token(token: &str) -> bool {
+  token.starts_with(API_TOKEN_PREFIX)
+}
+
+/// Look up the token and record its use. Fails for unknown and expired tokens.
+pub async fn validate_api_token(db: &Connection, token: &str) -> Result<ApiTokenAuth> {
+  let Some((stored, permissions)) = db.api_token().get_api_token(&hash_token(token)).await? else {
+    bail!(UNAUTHORIZED, "invalid token");
+  };
+
+  if stored.exp.is_some_and(|exp| exp < Utc::now().naive_utc()) {
+    bail!(UNAUTHORIZED, "token expired");
+  }
+  if !db.user().get_user_by_id(stored.user_id).await?.active {
+    bail!(UNAUTHORIZED, "token owner is deactivated");
+  }
+
+  db.api_token().touch_token(&stored).await?;
+
+  Ok(ApiTokenAuth {
+    id: stored.id,
+    user_id: stored.user_id,
+    exp: stored.exp,
+    permissions,
+  })
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::connect_db;
+  use crate::db::migrations::Migrator;
+  use chrono::Duration;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[test]
+  fn test_generated_tokens_have_prefix() {
+    let token = generate_api_token();
+    assert!(is_api_token(&token));
+    assert!(!is_api_token("eyJhbGciOiJSUzI1NiJ9"));
+    assert_ne!(token, generate_api_token());
+  }
+
+  #[tokio::test]
+  async fn test_validate_api_token() {
+    let (conn, user) = setup().await;
+    let token = generate_api_token();
+    conn
+      .api_token()
+      .create_api_token(
+        user,
+        "ci".into(),
+        hash_token(&token),
+        vec!["user:view".into()],
+        None,
+      )
+      .await
+      .unwrap();
+
+    let auth = validate_api_token(&conn, &token).await.unwrap();
+    assert_eq!(auth.user_id, user);
+    assert_eq!(auth.permissions, vec!["user:view"]);
+    assert!(
+      validate_api_token(&conn, &generate_api_token())
+        .await
+        .is_err()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_expired_api_token_is_rejected() {
+    let (conn, user) = setup().await;
+    let token = generate_api_token();
+    conn
+      .api_token()
+      .create_api_token(
+        user,
+        "old".into(),
+        hash_token(&token),
+        Vec::new(),
+        Some((Utc::now() - Duration::hours(1)).naive_utc()),
+      )
+      .await
+      .unwrap();
+
+    assert!(validate_api_token(&conn, &token).await.is_err());
+  }
+}
@@ -0,0 +1,88 @@
+use aide::axum::routing::{ApiMethodRouter, post_with};
+use axum::Json;
+use serde::{Deserialize, Serialize};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    BackendRouter,
+    auth::{jwt_state::JwtState, session::ClientInfo, token::hash_token},
+    middleware::rate_limiter::RateLimiter,
+  },
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::Result,
+};
+
+pub fn router(rate_limiter: &mut RateLimiter) -> BackendRouter {
+  BackendRouter::new()
+    .api_route("/", client_credentials_route())
+    .layer(rate_limiter.create_limiter("client"))
+}
+
+pub fn client_credentials_route() -> ApiMethodRouter<()> {
+  post_with(client_credentials, |op| op.id("clientCredentials"))
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct ClientCredentialsReq {
+  client_id: Uuid,
+  client_secret: String,
+}
+
+#[derive(Serialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct ClientCredentialsResponse {
+  access_token: String,
+  token_type: &'static str,
+  expires_in: i64,
+}
+
+/// Exchange the credentials of a service account for a short lived access
+/// token. No refresh token is created, clients simply request a new token once
+/// the old one expired. All tokens of the account share a session, so setting
+/// a new secret revokes them.
+async fn client_credentials(
+  jwt: JwtState,
+  db: Connection,
+  client: ClientInfo,
+  Json(req): Json<ClientCredentialsReq>,
+) -> Result<Json<ClientCredentialsResponse>> {
+  if !db
+    .service_account()
+    .use_client_secret(req.client_id, &hash_token(&req.client_secret))
+    .await?
+  {
+    bail!(UNAUTHORIZED, "Invalid client credentials");
+  }
+
+  // service accounts can't log in otherwise, every session is one of these
+  let sid = match db
+    .session()
+    .list_user_sessions(req.client_id, None)
+    .await?
+    .first()
+  {
+    Some(session) => session.uuid,
+    None => {
+      db.session()
+        .create_session(
+          req.client_id,
+          client.device(),
+          client.ip.clone(),
+          client.user_agent.clone(),
+        )
+        .await?
+    }
+  };
+  let access_token = jwt.create_raw_session_token(req.client_id, sid)?;
+  debug!("Service account authenticated: {}", req.client_id);
+
+  Ok(Json(ClientCredentialsResponse {
+    access_token,
+    token_type: "Bearer",
+    expires_in: jwt.exp,
+  }))
+}
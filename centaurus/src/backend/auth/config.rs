@@ -6,7 +6,10 @@ use serde::Serialize;
 use crate::{
   backend::{
     BackendRouter,
-    auth::{oidc::OidcState, settings::UserSettings},
+    auth::{
+      oidc::{OidcProvider, OidcState},
+      settings::UserSettings,
+    },
   },
   db::{init::Connection, tables::ConnectionExt},
   error::Result,
@@ -30,6 +33,11 @@ enum SSOType {
 struct AuthConfig {
   sso_type: SSOType,
   instant_redirect: bool,
+  /// Providers that can be used with `/oidc/{name}/url`.
+  providers: Vec<OidcProvider>,
+  /// Whether users can log in with `/saml/url`.
+  #[cfg(feature = "saml")]
+  saml_enabled: bool,
   #[cfg(feature = "mail")]
   mail_enabled: bool,
 }
@@ -40,13 +48,21 @@ async fn config(
   #[cfg(feature = "mail")] mailer: crate::mail::Mailer,
   db: Connection,
 ) -> Result<Json<AuthConfig>> {
-  let sso_type = if oidc.is_enabled().await {
+  let providers = oidc.providers().await;
+  let sso_type = if !providers.is_empty() {
     SSOType::Oidc
   } else {
     SSOType::None
   };
 
   let user_settings = db.settings().get_settings::<UserSettings>().await?;
+  #[cfg(feature = "saml")]
+  let saml_enabled = db
+    .settings()
+    .get_settings::<crate::backend::auth::settings::SamlSettings>()
+    .await?
+    .saml()
+    .is_some();
   #[cfg(feature = "mail")]
   let mail_enabled = mailer.is_active().await;
 
@@ -57,6 +73,9 @@ async fn config(
   Ok(Json(AuthConfig {
     sso_type,
     instant_redirect,
+    providers,
+    #[cfg(feature = "saml")]
+    saml_enabled,
     #[cfg(feature = "mail")]
     mail_enabled,
   }))
@@ -0,0 +1,92 @@
+use aide::axum::routing::{ApiMethodRouter, post_with};
+use axum::{Json, Router, routing::get};
+use http::header;
+use jsonwebtoken::jwk::JwkSet;
+use serde::Serialize;
+
+use crate::{
+  backend::{
+    BackendRouter,
+    auth::{jwt_auth::JwtAuth, jwt_state::JwtState, permission::SettingsEdit},
+  },
+  db::init::Connection,
+  error::Result,
+};
+
+pub const JWKS_PATH: &str = "/.well-known/jwks.json";
+
+/// Served outside of `/api` so other services can find the keys at the
+/// conventional location.
+pub fn well_known_router() -> Router {
+  Router::new().route(JWKS_PATH, get(jwks))
+}
+
+pub fn router() -> BackendRouter {
+  BackendRouter::new().api_route("/rotate", rotate_key_route())
+}
+
+pub fn rotate_key_route() -> ApiMethodRouter<()> {
+  post_with(rotate_key, |op| op.id("rotateJwtKey"))
+}
+
+async fn jwks(jwt: JwtState) -> ([(header::HeaderName, &'static str); 1], Json<JwkSet>) {
+  (
+    [(header::CACHE_CONTROL, "public, max-age=300")],
+    Json(jwt.jwks()),
+  )
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct RotateKeyResponse {
+  kid: String,
+}
+
+async fn rotate_key(
+  _auth: JwtAuth<SettingsEdit>,
+  jwt: JwtState,
+  db: Connection,
+) -> Result<Json<RotateKeyResponse>> {
+  let kid = jwt.rotate_key(&db).await?;
+  Ok(Json(RotateKeyResponse { kid }))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::auth::settings::AuthConfig;
+  use crate::db::config::DBConfig;
+  use crate::db::init::connect_db;
+  use crate::db::migrations::Migrator;
+  use axum::{Extension, body::Body};
+  use http::{Request, StatusCode};
+  use sea_orm_migration::MigratorTrait;
+  use tower::ServiceExt;
+
+  #[tokio::test]
+  async fn test_jwks_route_lists_current_key() {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
+
+    let response = well_known_router()
+      .layer(Extension(jwt.clone()))
+      .oneshot(
+        Request::builder()
+          .uri(JWKS_PATH)
+          .body(Body::empty())
+          .unwrap(),
+      )
+      .await
+      .unwrap();
+
+    assert_eq!(response.status(), StatusCode::OK);
+    assert!(response.headers().contains_key(header::CACHE_CONTROL));
+    let body = axum::body::to_bytes(response.into_body(), usize::MAX)
+      .await
+      .unwrap();
+    let set: JwkSet = serde_json::from_slice(&body).unwrap();
+    assert_eq!(set.keys.len(), 1);
+    assert!(set.find(&jwt.current_kid()).is_some());
+  }
+}
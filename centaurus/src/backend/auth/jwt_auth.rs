@@ -8,14 +8,18 @@ use uuid::Uuid;
 use crate::{
   backend::{
     auth::{
+      api_token::{ACCOUNT_SCOPE, is_api_token, validate_api_token},
       jwt::jwt_from_request,
-      jwt_state::{JWT_COOKIE_NAME, JwtClaims, JwtState},
+      jwt_state::{
+        AUDIENCE_CLAIM, JWT_COOKIE_NAME, JwtClaims, JwtState, MFA_PENDING_CLAIM,
+        SERVICE_TOKEN_CLAIM,
+      },
       permission::{NoPerm, Permission},
     },
     request::extract::StateExtractExt,
   },
   bail,
-  db::{init::Connection, tables::ConnectionExt},
+  db::{init::Connection, permission::has_permission, tables::ConnectionExt},
   error::ErrorReport,
 };
 
@@ -23,6 +27,9 @@ use crate::{
 pub struct JwtAuth<P: Permission = NoPerm> {
   pub user_id: Uuid,
   pub exp: i64,
+  pub sid: Option<Uuid>,
+  /// Set when the request was authenticated with a personal access token.
+  pub api_token: Option<Uuid>,
   _perm: PhantomData<P>,
 }
 
@@ -46,10 +53,59 @@ impl<S: Sync, P: Permission> FromRequestParts<S> for JwtAuth<P> {
     let db = parts.extract_state::<Connection>().await;
     let state = parts.extract_state::<JwtState>().await;
 
-    let Ok(claims) = state.validate_token(&token) else {
+    if is_api_token(&token) {
+      let api_token = validate_api_token(&db, &token).await?;
+      // the token is limited to its own permissions, the owner still has to
+      // hold them as well. Routes without a permission act on the owner's
+      // account and need the account scope.
+      let required = match P::name() {
+        "" => ACCOUNT_SCOPE,
+        name => name,
+      };
+      if !has_permission(&api_token.permissions, required) {
+        bail!(FORBIDDEN, "token lacks the required permission");
+      }
+      let claims = api_token.claims(&state.iss);
+      state.auth.check(&db, parts, &token, &claims).await?;
+      P::check(&db, api_token.user_id, parts).await?;
+
+      return Ok(JwtAuth {
+        user_id: api_token.user_id,
+        exp: claims.exp,
+        sid: None,
+        api_token: Some(api_token.id),
+        _perm: PhantomData,
+      });
+    }
+
+    let Ok(claims) = state.validate_token(&token).await else {
       tracing::error!("invalid token claims for token: {}", token);
       bail!(UNAUTHORIZED, "invalid token");
     };
+    if claims.additional_claims.contains_key(MFA_PENDING_CLAIM) {
+      bail!(UNAUTHORIZED, "second factor required");
+    }
+    if claims.additional_claims.contains_key(AUDIENCE_CLAIM) {
+      bail!(UNAUTHORIZED, "token was issued to another client");
+    }
+
+    // checked for every auth, so revoking a session always ends it
+    match claims.sid {
+      Some(sid) => {
+        let Ok(active) = db.session().touch_session(sid).await else {
+          bail!("failed to validate session");
+        };
+        if !active {
+          bail!(UNAUTHORIZED, "session has been revoked");
+        }
+      }
+      None
+        if claims.additional_claims.get(SERVICE_TOKEN_CLAIM)
+          == Some(&serde_json::Value::Bool(true)) => {}
+      None => {
+        bail!(UNAUTHORIZED, "token has no session");
+      }
+    }
 
     state.auth.check(&db, parts, &token, &claims).await?;
     P::check(&db, claims.sub, parts).await?;
@@ -57,6 +113,8 @@ impl<S: Sync, P: Permission> FromRequestParts<S> for JwtAuth<P> {
     Ok(JwtAuth {
       user_id: claims.sub,
       exp: claims.exp,
+      sid: claims.sid,
+      api_token: None,
       _perm: PhantomData,
     })
   }
@@ -151,6 +209,15 @@ mod tests {
       .unwrap()
   }
 
+  async fn session_token(conn: &Connection, state: &JwtState, uid: Uuid) -> String {
+    let sid = conn
+      .session()
+      .create_session(uid, None, None, None)
+      .await
+      .unwrap();
+    state.create_raw_session_token(uid, sid).unwrap()
+  }
+
   fn parts_with_token(db: Connection, state: JwtState, token: &str) -> Parts {
     http::Request::builder()
       .uri("/")
@@ -178,7 +245,7 @@ mod tests {
     let conn = db().await;
     let state = JwtState::init(&AuthConfig::default(), &conn).await;
     let token = state.create_raw_token(Uuid::now_v7()).unwrap();
-    let claims = state.validate_token(&token).unwrap();
+    let claims = state.validate_token(&token).await.unwrap();
 
     let mut parts = empty_parts();
     assert!(
@@ -196,7 +263,7 @@ mod tests {
     let conn = db().await;
     let state = JwtState::init(&AuthConfig::default(), &conn).await;
     let token = state.create_raw_token(Uuid::now_v7()).unwrap();
-    let claims = state.validate_token(&token).unwrap();
+    let claims = state.validate_token(&token).await.unwrap();
 
     conn
       .invalid_jwt()
@@ -252,7 +319,7 @@ mod tests {
     let conn = db().await;
     let uid = create_user(&conn).await;
     let state = JwtState::init(&AuthConfig::default(), &conn).await;
-    let token = state.create_raw_token(uid).unwrap();
+    let token = session_token(&conn, &state, uid).await;
 
     let mut parts = parts_with_token(conn.clone(), state, &token);
     let Ok(auth) =
@@ -263,6 +330,68 @@ mod tests {
     assert_eq!(auth.user_id, uid);
   }
 
+  #[tokio::test]
+  async fn test_from_request_parts_rejects_token_without_session() {
+    let conn = db().await;
+    let uid = create_user(&conn).await;
+    let state = JwtState::init(&AuthConfig::default(), &conn).await;
+    let token = state.create_raw_token(uid).unwrap();
+
+    let mut parts = parts_with_token(conn.clone(), state, &token);
+    let Err(err) =
+      <JwtAuth<NoPerm> as FromRequestParts<()>>::from_request_parts(&mut parts, &()).await
+    else {
+      panic!("expected token without session to be rejected");
+    };
+    assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+  }
+
+  #[tokio::test]
+  async fn test_from_request_parts_accepts_service_token() {
+    let conn = db().await;
+    let uid = create_user(&conn).await;
+    let state = JwtState::init(&AuthConfig::default(), &conn).await;
+    let token = state.create_service_token(uid).unwrap();
+
+    let mut parts = parts_with_token(conn.clone(), state, &token);
+    let Ok(auth) =
+      <JwtAuth<NoPerm> as FromRequestParts<()>>::from_request_parts(&mut parts, &()).await
+    else {
+      panic!("expected service token to authenticate");
+    };
+    assert_eq!(auth.user_id, uid);
+  }
+
+  #[tokio::test]
+  async fn test_from_request_parts_rejects_revoked_session_with_custom_auth() {
+    let conn = db().await;
+    let uid = create_user(&conn).await;
+    let called = Arc::new(AtomicBool::new(false));
+    let auth = RecordingAuth {
+      allow: true,
+      called: called.clone(),
+      seen_token: Arc::new(Mutex::new(None)),
+      seen_sub: Arc::new(Mutex::new(None)),
+    };
+    let state = JwtState::init_with_auth(&AuthConfig::default(), &conn, auth).await;
+    let sid = conn
+      .session()
+      .create_session(uid, None, None, None)
+      .await
+      .unwrap();
+    let token = state.create_raw_session_token(uid, sid).unwrap();
+    conn.session().revoke_session(sid).await.unwrap();
+
+    let mut parts = parts_with_token(conn.clone(), state, &token);
+    let Err(err) =
+      <JwtAuth<NoPerm> as FromRequestParts<()>>::from_request_parts(&mut parts, &()).await
+    else {
+      panic!("expected revoked session to be rejected");
+    };
+    assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+    assert!(!called.load(Ordering::Relaxed));
+  }
+
   #[tokio::test]
   async fn test_from_request_parts_rejects_garbage_token() {
     let conn = db().await;
@@ -304,7 +433,7 @@ mod tests {
     let conn = db().await;
     let uid = create_user(&conn).await;
     let state = JwtState::init(&AuthConfig::default(), &conn).await;
-    let token = state.create_raw_token(uid).unwrap();
+    let token = session_token(&conn, &state, uid).await;
 
     conn
       .invalid_jwt()
@@ -330,7 +459,7 @@ mod tests {
     let conn = db().await;
     let uid = create_user(&conn).await;
     let state = JwtState::init(&AuthConfig::default(), &conn).await;
-    let token = state.create_raw_token(uid).unwrap();
+    let token = session_token(&conn, &state, uid).await;
 
     // User exists and token is valid, but lacks the required permission.
     let mut parts = parts_with_token(conn.clone(), state, &token);
@@ -359,7 +488,7 @@ mod tests {
       .unwrap();
 
     let state = JwtState::init(&AuthConfig::default(), &conn).await;
-    let token = state.create_raw_token(uid).unwrap();
+    let token = session_token(&conn, &state, uid).await;
 
     let mut parts = parts_with_token(conn.clone(), state, &token);
     let Ok(auth) =
@@ -384,7 +513,7 @@ mod tests {
       seen_sub: seen_sub.clone(),
     };
     let state = JwtState::init_with_auth(&AuthConfig::default(), &conn, auth).await;
-    let token = state.create_raw_token(uid).unwrap();
+    let token = session_token(&conn, &state, uid).await;
 
     let mut parts = parts_with_token(conn.clone(), state, &token);
     let Ok(result) =
@@ -410,7 +539,7 @@ mod tests {
       seen_sub: Arc::new(Mutex::new(None)),
     };
     let state = JwtState::init_with_auth(&AuthConfig::default(), &conn, auth).await;
-    let token = state.create_raw_token(uid).unwrap();
+    let token = session_token(&conn, &state, uid).await;
 
     let mut parts = parts_with_token(conn.clone(), state, &token);
     let Err(err) =
@@ -421,6 +550,78 @@ mod tests {
     assert_eq!(err.status, StatusCode::UNAUTHORIZED);
   }
 
+  #[tokio::test]
+  async fn test_from_request_parts_runs_custom_auth_for_api_tokens() {
+    use crate::backend::auth::api_token::{API_TOKEN_CLAIM, generate_api_token};
+    use crate::backend::auth::token::hash_token;
+
+    let conn = db().await;
+    let uid = create_user(&conn).await;
+    let token = generate_api_token();
+    let token_id = conn
+      .api_token()
+      .create_api_token(
+        uid,
+        "cli".into(),
+        hash_token(&token),
+        vec![ACCOUNT_SCOPE.into()],
+        None,
+      )
+      .await
+      .unwrap();
+    let called = Arc::new(AtomicBool::new(false));
+    let auth = RecordingAuth {
+      allow: false,
+      called: called.clone(),
+      seen_token: Arc::new(Mutex::new(None)),
+      seen_sub: Arc::new(Mutex::new(None)),
+    };
+    let state = JwtState::init_with_auth(&AuthConfig::default(), &conn, auth).await;
+
+    let mut parts = parts_with_token(conn.clone(), state, &token);
+    let Err(err) =
+      <JwtAuth<NoPerm> as FromRequestParts<()>>::from_request_parts(&mut parts, &()).await
+    else {
+      panic!("expected custom auth to reject the api token");
+    };
+    assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+    assert!(called.load(Ordering::Relaxed));
+
+    let claims = validate_api_token(&conn, &token)
+      .await
+      .unwrap()
+      .claims("iss");
+    assert_eq!(claims.sub, uid);
+    assert_eq!(
+      claims.additional_claims[API_TOKEN_CLAIM],
+      token_id.to_string()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_from_request_parts_rejects_api_token_without_account_scope() {
+    use crate::backend::auth::api_token::generate_api_token;
+    use crate::backend::auth::token::hash_token;
+
+    let conn = db().await;
+    let uid = create_user(&conn).await;
+    let token = generate_api_token();
+    conn
+      .api_token()
+      .create_api_token(uid, "ci".into(), hash_token(&token), Vec::new(), None)
+      .await
+      .unwrap();
+    let state = JwtState::init(&AuthConfig::default(), &conn).await;
+
+    let mut parts = parts_with_token(conn.clone(), state, &token);
+    let Err(err) =
+      <JwtAuth<NoPerm> as FromRequestParts<()>>::from_request_parts(&mut parts, &()).await
+    else {
+      panic!("expected api token without account scope to be rejected");
+    };
+    assert_eq!(err.status, StatusCode::FORBIDDEN);
+  }
+
   // ----- OptionalFromRequestParts -----
 
   #[tokio::test]
@@ -428,7 +629,7 @@ mod tests {
     let conn = db().await;
     let uid = create_user(&conn).await;
     let state = JwtState::init(&AuthConfig::default(), &conn).await;
-    let token = state.create_raw_token(uid).unwrap();
+    let token = session_token(&conn, &state, uid).await;
 
     let mut parts = parts_with_token(conn.clone(), state, &token);
     let Ok(auth) =
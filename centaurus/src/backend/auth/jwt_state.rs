@@ -1,15 +1,23 @@
 use std::{
   collections::HashMap,
-  sync::{Arc, atomic::AtomicI32},
+  sync::{
+    Arc, RwLock,
+    atomic::{AtomicI32, AtomicI64, Ordering},
+  },
 };
 
 use aide::OperationIo;
 use axum::{Extension, extract::FromRequestParts};
-use axum_extra::extract::cookie::{Cookie, SameSite};
-use chrono::{Duration, Utc};
+use axum_extra::extract::{
+  CookieJar,
+  cookie::{Cookie, SameSite},
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use http::StatusCode;
 use jsonwebtoken::{
-  Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, encode,
+  Algorithm, DecodingKey, EncodingKey, Header, Validation, decode, decode_header, encode,
   errors::{Error, ErrorKind},
+  jwk::{Jwk, JwkSet, PublicKeyUse},
 };
 use rsa::{
   RsaPrivateKey, RsaPublicKey,
@@ -18,25 +26,55 @@ use rsa::{
   rand_core::OsRng,
 };
 use serde::{Deserialize, Serialize};
-use tracing::info;
+use tokio::{spawn, task::spawn_blocking, time::sleep};
+use tracing::{info, warn};
 use uuid::Uuid;
 
 use crate::{
   backend::auth::{
     jwt_auth::{Auth, StatelessAuth},
+    session::ClientInfo,
     settings::AuthConfig,
+    token::{generate_token, hash_token},
   },
-  db::{init::Connection, tables::ConnectionExt},
-  error::Result,
+  backend::store::Store,
+  bail,
+  db::{entities::key, init::Connection, tables::ConnectionExt},
+  error::{ErrorReportStatusExt, Result},
 };
 
 pub const JWT_COOKIE_NAME: &str = "centaurus_jwt";
+pub const REFRESH_COOKIE_NAME: &str = "centaurus_refresh";
+/// Holds the partial token of a login that still needs a second factor.
+pub const MFA_COOKIE_NAME: &str = "centaurus_mfa";
+/// Claim marking a partial token, [`crate::backend::auth::jwt_auth::JwtAuth`] rejects these.
+pub const MFA_PENDING_CLAIM: &str = "mfa_pending";
+const MFA_TOKEN_EXPIRATION: i64 = 5 * 60;
+/// Claim of tokens issued to downstream OIDC clients, which are not accepted
+/// by [`crate::backend::auth::jwt_auth::JwtAuth`].
+pub const AUDIENCE_CLAIM: &str = "aud";
+/// Claim of tokens that are not bound to a session, see
+/// [`JwtState::create_service_token`].
+pub const SERVICE_TOKEN_CLAIM: &str = "service";
+pub const JWT_KEY_NAME: &str = "jwt";
+const KEY_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+/// Minimum seconds between reloads caused by tokens with an unknown kid.
+const UNKNOWN_KID_RELOAD_INTERVAL: i64 = 10;
+const REFRESH_CLEANUP_INTERVAL: std::time::Duration = std::time::Duration::from_secs(600);
+const REFRESH_SUCCESSOR: &str = "refresh_successor";
+/// How often a reused refresh token looks for its successor, which a
+/// concurrent refresh may not have stored yet.
+const SUCCESSOR_ATTEMPTS: u32 = 10;
+const SUCCESSOR_POLL: std::time::Duration = std::time::Duration::from_millis(50);
 
 #[derive(Serialize, Deserialize, Clone, Debug)]
 pub struct JwtClaims {
   pub exp: i64,
   pub iss: String,
   pub sub: Uuid,
+  /// Session this token belongs to, see [`crate::db::tables::session::SessionTable`].
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub sid: Option<Uuid>,
   #[serde(flatten)]
   pub additional_claims: HashMap<String, serde_json::Value>,
 }
@@ -44,16 +82,77 @@ pub struct JwtClaims {
 #[derive(Clone, FromRequestParts, OperationIo)]
 #[from_request(via(Extension))]
 pub struct JwtState {
-  header: Header,
-  encoding_key: EncodingKey,
-  decoding_key: DecodingKey,
+  keys: Arc<RwLock<JwtKeys>>,
   validation: Validation,
   pub iss: String,
   pub exp: i64,
+  pub refresh_exp: i64,
+  pub refresh_grace: i64,
+  pub rotation: Option<i64>,
   pub auth: Arc<dyn Auth + Send + Sync>,
+  db: Connection,
+  /// Timestamp of the last reload caused by an unknown kid.
+  kid_reload: Arc<AtomicI64>,
+}
+
+/// The signing key is always the newest key; every other loaded key is only
+/// used to validate tokens issued before the last rotation.
+struct JwtKeys {
+  header: Header,
+  encoding_key: EncodingKey,
+  created: Option<NaiveDateTime>,
+  decoding_keys: HashMap<String, DecodingKey>,
+  jwks: JwkSet,
+}
+
+impl JwtKeys {
+  fn from_models(models: &[key::Model]) -> Result<Self> {
+    let mut decoding_keys = HashMap::new();
+    let mut jwks = JwkSet { keys: Vec::new() };
+    let mut current = None;
+
+    for model in models {
+      let kid = model.id.to_string();
+      let private_key = RsaPrivateKey::from_pkcs1_pem(&model.private_key)
+        .status(StatusCode::INTERNAL_SERVER_ERROR)?;
+      let public_key_pem = RsaPublicKey::from(private_key)
+        .to_pkcs1_pem(LineEnding::CRLF)
+        .status(StatusCode::INTERNAL_SERVER_ERROR)?;
+
+      let encoding_key = EncodingKey::from_rsa_pem(model.private_key.as_bytes())?;
+      let mut jwk = Jwk::from_encoding_key(&encoding_key, Algorithm::RS256)?;
+      jwk.common.key_id = Some(kid.clone());
+      jwk.common.public_key_use = Some(PublicKeyUse::Signature);
+
+      decoding_keys.insert(
+        kid.clone(),
+        DecodingKey::from_rsa_pem(public_key_pem.as_bytes())?,
+      );
+      jwks.keys.push(jwk);
+      current = Some((kid, encoding_key, model.created));
+    }
+
+    let Some((kid, encoding_key, created)) = current else {
+      bail!(INTERNAL_SERVER_ERROR, "no jwt key found");
+    };
+
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some(kid);
+
+    Ok(Self {
+      header,
+      encoding_key,
+      created,
+      decoding_keys,
+      jwks,
+    })
+  }
 }
 
 impl JwtState {
+  /// Token without a session.
+  /// [`JwtAuth`](crate::backend::auth::jwt_auth::JwtAuth) only accepts tokens
+  /// with a session or service tokens.
   pub fn create_raw_token(&self, uuid: Uuid) -> Result<String> {
     self.create_raw_token_custom(uuid, HashMap::new())
   }
@@ -62,9 +161,19 @@ impl JwtState {
     &self,
     uuid: Uuid,
     additional_claims: HashMap<String, serde_json::Value>,
+  ) -> Result<String> {
+    self.encode_claims(uuid, None, self.exp, additional_claims)
+  }
+
+  fn encode_claims(
+    &self,
+    uuid: Uuid,
+    sid: Option<Uuid>,
+    lifetime: i64,
+    additional_claims: HashMap<String, serde_json::Value>,
   ) -> Result<String> {
     let exp = Utc::now()
-      .checked_add_signed(Duration::seconds(self.exp))
+      .checked_add_signed(Duration::seconds(lifetime))
       .ok_or(Error::from(ErrorKind::ExpiredSignature))?
       .timestamp();
 
@@ -72,29 +181,343 @@ impl JwtState {
       exp,
       iss: self.iss.clone(),
       sub: uuid,
+      sid,
       additional_claims,
     };
 
-    Ok(encode(&self.header, &claims, &self.encoding_key)?)
+    let keys = self.keys.read().expect("jwt keys lock poisoned");
+    Ok(encode(&keys.header, &claims, &keys.encoding_key)?)
   }
 
+  /// Cookie with a token from [`JwtState::create_raw_token`], which has no
+  /// session.
   pub fn create_token<'c>(&self, uuid: Uuid) -> Result<Cookie<'c>> {
     let token = self.create_raw_token(uuid)?;
     Ok(self.create_cookie(JWT_COOKIE_NAME, token))
   }
 
+  /// Access token of the session `sid`, it stops working once the session is
+  /// revoked.
+  pub fn create_raw_session_token(&self, uuid: Uuid, sid: Uuid) -> Result<String> {
+    self.encode_claims(uuid, Some(sid), self.exp, HashMap::new())
+  }
+
+  /// Token for other services of the app, accepted without a session. It
+  /// can't be revoked before it expires.
+  pub fn create_service_token(&self, uuid: Uuid) -> Result<String> {
+    let claims = HashMap::from([(
+      SERVICE_TOKEN_CLAIM.to_string(),
+      serde_json::Value::Bool(true),
+    )]);
+    self.create_raw_token_custom(uuid, claims)
+  }
+
+  fn create_session_token<'c>(&self, uuid: Uuid, sid: Uuid) -> Result<Cookie<'c>> {
+    let token = self.create_raw_session_token(uuid, sid)?;
+    Ok(self.create_cookie(JWT_COOKIE_NAME, token))
+  }
+
+  /// Short lived token proving the password step of a login, only accepted by
+  /// the second factor endpoint.
+  pub fn create_mfa_token<'c>(&self, uuid: Uuid) -> Result<Cookie<'c>> {
+    let claims = HashMap::from([(MFA_PENDING_CLAIM.to_string(), serde_json::Value::Bool(true))]);
+    let token = self.encode_claims(uuid, None, MFA_TOKEN_EXPIRATION, claims)?;
+    Ok(self.create_cookie(MFA_COOKIE_NAME, token))
+  }
+
+  /// User id from the partial token of a login waiting for its second factor.
+  pub async fn validate_mfa_token(&self, cookies: &CookieJar) -> Result<Uuid> {
+    let Some(cookie) = cookies.get(MFA_COOKIE_NAME) else {
+      bail!(UNAUTHORIZED, "Missing second factor token");
+    };
+
+    let Ok(claims) = self.validate_token(cookie.value()).await else {
+      bail!(UNAUTHORIZED, "Invalid second factor token");
+    };
+    if !claims.additional_claims.contains_key(MFA_PENDING_CLAIM) {
+      bail!(UNAUTHORIZED, "Invalid second factor token");
+    }
+
+    Ok(claims.sub)
+  }
+
   pub fn create_cookie<'c>(&self, name: &'static str, value: String) -> Cookie<'c> {
+    let max_age = match name {
+      REFRESH_COOKIE_NAME => self.refresh_exp,
+      MFA_COOKIE_NAME => MFA_TOKEN_EXPIRATION,
+      _ => self.exp,
+    };
+
     Cookie::build((name, value))
       .http_only(true)
-      .max_age(time::Duration::seconds(self.exp))
+      .max_age(time::Duration::seconds(max_age))
       .same_site(SameSite::Lax)
       .secure(true)
       .path("/")
       .build()
   }
 
-  pub fn validate_token(&self, token: &str) -> std::result::Result<JwtClaims, Error> {
-    Ok(decode::<JwtClaims>(token, &self.decoding_key, &self.validation)?.claims)
+  /// Start a new session and add its access and refresh token. The session id
+  /// is used as the refresh token family.
+  pub async fn create_login(
+    &self,
+    db: &Connection,
+    cookies: CookieJar,
+    user: Uuid,
+    client: &ClientInfo,
+  ) -> Result<CookieJar> {
+    Ok(self.start_session(db, cookies, user, client).await?.1)
+  }
+
+  /// Like [`Self::create_login`], but remembers the provider and its session
+  /// id, so the provider can end the session again.
+  pub async fn create_oidc_login(
+    &self,
+    db: &Connection,
+    cookies: CookieJar,
+    user: Uuid,
+    client: &ClientInfo,
+    provider: String,
+    oidc_sid: Option<String>,
+  ) -> Result<CookieJar> {
+    let (sid, cookies) = self.start_session(db, cookies, user, client).await?;
+    db.session()
+      .set_oidc_session(sid, provider, oidc_sid)
+      .await?;
+
+    Ok(cookies)
+  }
+
+  async fn start_session(
+    &self,
+    db: &Connection,
+    cookies: CookieJar,
+    user: Uuid,
+    client: &ClientInfo,
+  ) -> Result<(Uuid, CookieJar)> {
+    if !db.user().get_user_by_id(user).await?.active {
+      bail!(FORBIDDEN, "Account is deactivated");
+    }
+
+    let sid = db
+      .session()
+      .create_session(
+        user,
+        client.device(),
+        client.ip.clone(),
+        client.user_agent.clone(),
+      )
+      .await?;
+    let refresh = self.create_refresh_token(db, user, sid).await?;
+
+    Ok((
+      sid,
+      cookies
+        .add(self.create_session_token(user, sid)?)
+        .add(refresh),
+    ))
+  }
+
+  async fn create_refresh_token<'c>(
+    &self,
+    db: &Connection,
+    user: Uuid,
+    family: Uuid,
+  ) -> Result<Cookie<'c>> {
+    let token = generate_token(64);
+    self.store_refresh_token(db, user, family, &token).await?;
+
+    Ok(self.create_cookie(REFRESH_COOKIE_NAME, token))
+  }
+
+  async fn store_refresh_token(
+    &self,
+    db: &Connection,
+    user: Uuid,
+    family: Uuid,
+    token: &str,
+  ) -> Result<()> {
+    let exp = Utc::now() + Duration::seconds(self.refresh_exp);
+    db.refresh_token()
+      .create_refresh_token(user, family, hash_token(token), exp)
+      .await
+  }
+
+  /// The token that replaced the refresh token `hash`, while it is in the
+  /// grace period.
+  async fn refresh_successor(&self, store: &Store, hash: &str) -> Result<Option<String>> {
+    if self.refresh_grace <= 0 {
+      return Ok(None);
+    }
+    for _ in 0..SUCCESSOR_ATTEMPTS {
+      if let Some(successor) = store.get(REFRESH_SUCCESSOR, hash).await? {
+        return Ok(Some(successor));
+      }
+      sleep(SUCCESSOR_POLL).await;
+    }
+    Ok(None)
+  }
+
+  /// Exchange a refresh token for a new access and refresh token. Every refresh
+  /// token can only be used once, presenting it again revokes its whole family.
+  /// Within [`AuthConfig::auth_refresh_grace`] of its rotation it returns the
+  /// token that replaced it instead.
+  pub async fn refresh(
+    &self,
+    db: &Connection,
+    store: &Store,
+    cookies: CookieJar,
+    token: &str,
+  ) -> Result<(Uuid, CookieJar)> {
+    let hash = hash_token(token);
+    let Some(stored) = db.refresh_token().get_refresh_token(&hash).await? else {
+      bail!(UNAUTHORIZED, "Invalid refresh token");
+    };
+
+    if !db.refresh_token().consume_refresh_token(stored.id).await? {
+      if let Some(successor) = self.refresh_successor(store, &hash).await? {
+        if !db.session().touch_session(stored.family).await? {
+          bail!(UNAUTHORIZED, "Session has been revoked");
+        }
+        let cookies = cookies
+          .add(self.create_session_token(stored.user_id, stored.family)?)
+          .add(self.create_cookie(REFRESH_COOKIE_NAME, successor));
+        return Ok((stored.user_id, cookies));
+      }
+
+      warn!(
+        "Refresh token reuse detected for user {}, revoking token family",
+        stored.user_id
+      );
+      db.session().revoke_session(stored.family).await?;
+      bail!(UNAUTHORIZED, "Refresh token has already been used");
+    }
+
+    if stored.exp < Utc::now().naive_utc() {
+      db.session().revoke_session(stored.family).await?;
+      bail!(UNAUTHORIZED, "Refresh token expired");
+    }
+
+    if !db.session().touch_session(stored.family).await? {
+      db.refresh_token().revoke_family(stored.family).await?;
+      bail!(UNAUTHORIZED, "Session has been revoked");
+    }
+
+    let successor = generate_token(64);
+    self
+      .store_refresh_token(db, stored.user_id, stored.family, &successor)
+      .await?;
+    if self.refresh_grace > 0 {
+      let grace = std::time::Duration::from_secs(self.refresh_grace as u64);
+      store
+        .set(REFRESH_SUCCESSOR, &hash, successor.clone(), grace)
+        .await?;
+    }
+    let cookies = cookies
+      .add(self.create_session_token(stored.user_id, stored.family)?)
+      .add(self.create_cookie(REFRESH_COOKIE_NAME, successor));
+
+    Ok((stored.user_id, cookies))
+  }
+
+  /// A token with an unknown kid may be signed by a key another instance just
+  /// rotated to, so the keys are reloaded at most every few seconds.
+  pub async fn validate_token(&self, token: &str) -> std::result::Result<JwtClaims, Error> {
+    let kid = decode_header(token)?
+      .kid
+      .ok_or(Error::from(ErrorKind::InvalidToken))?;
+
+    let known = self
+      .keys
+      .read()
+      .expect("jwt keys lock poisoned")
+      .decoding_keys
+      .contains_key(&kid);
+    if !known
+      && self.claim_kid_reload()
+      && let Err(e) = self.reload_keys(&self.db).await
+    {
+      warn!("Failed to reload jwt keys: {:?}", e);
+    }
+
+    let keys = self.keys.read().expect("jwt keys lock poisoned");
+    let decoding_key = keys
+      .decoding_keys
+      .get(&kid)
+      .ok_or(Error::from(ErrorKind::InvalidToken))?;
+
+    Ok(decode::<JwtClaims>(token, decoding_key, &self.validation)?.claims)
+  }
+
+  /// Public keys of every key that is still accepted, for `/.well-known/jwks.json`.
+  pub fn jwks(&self) -> JwkSet {
+    self
+      .keys
+      .read()
+      .expect("jwt keys lock poisoned")
+      .jwks
+      .clone()
+  }
+
+  pub fn current_kid(&self) -> String {
+    let keys = self.keys.read().expect("jwt keys lock poisoned");
+    keys.header.kid.clone().unwrap_or_default()
+  }
+
+  /// Generate a new signing key. Tokens signed with older keys stay valid
+  /// until they expire, after which the old keys are deleted.
+  pub async fn rotate_key(&self, db: &Connection) -> Result<String> {
+    let key = spawn_blocking(generate_key)
+      .await
+      .status(StatusCode::INTERNAL_SERVER_ERROR)?;
+    let kid = Uuid::now_v7();
+
+    db.key().create_key(JWT_KEY_NAME.into(), key, kid).await?;
+    self.reload_keys(db).await?;
+
+    info!("Rotated JWT signing key, new kid: {}", kid);
+    Ok(kid.to_string())
+  }
+
+  /// Reload the keys from the database so rotations done by other instances
+  /// are picked up.
+  pub async fn reload_keys(&self, db: &Connection) -> Result<()> {
+    let models = prune_keys(db, self.exp).await?;
+    let keys = JwtKeys::from_models(&models)?;
+    *self.keys.write().expect("jwt keys lock poisoned") = keys;
+    Ok(())
+  }
+
+  fn claim_kid_reload(&self) -> bool {
+    let now = Utc::now().timestamp();
+    let last = self.kid_reload.load(Ordering::Relaxed);
+    now - last >= UNKNOWN_KID_RELOAD_INTERVAL
+      && self
+        .kid_reload
+        .compare_exchange(last, now, Ordering::Relaxed, Ordering::Relaxed)
+        .is_ok()
+  }
+
+  /// Reload the keys and rotate if the newest stored key is due, so a rotation
+  /// by another instance isn't repeated.
+  async fn check_keys(&self, db: &Connection) -> Result<()> {
+    self.reload_keys(db).await?;
+    if self.rotation_due() {
+      self.rotate_key(db).await?;
+    }
+    Ok(())
+  }
+
+  fn rotation_due(&self) -> bool {
+    let Some(rotation) = self.rotation else {
+      return false;
+    };
+    let created = self.keys.read().expect("jwt keys lock poisoned").created;
+
+    match created {
+      Some(created) => created + Duration::seconds(rotation) < Utc::now().naive_utc(),
+      None => true,
+    }
   }
 
   pub async fn init(config: &AuthConfig, db: &Connection) -> Self {
@@ -102,54 +525,104 @@ impl JwtState {
   }
 
   pub async fn init_with_auth<A: Auth>(config: &AuthConfig, db: &Connection, auth: A) -> Self {
-    let (key, kid) = if let Ok(key) = db.key().get_key_by_name("jwt".into()).await {
-      (key.private_key, key.id.to_string())
-    } else {
-      let mut rng = OsRng {};
-      info!("Generating new JWT RSA keypair. This may take a few seconds...");
-      let bits = if cfg!(feature = "test") { 512 } else { 4096 };
-      let private_key = RsaPrivateKey::new(&mut rng, bits).expect("Failed to create Rsa key");
-      let key = private_key
-        .to_pkcs1_pem(LineEnding::CRLF)
-        .expect("Failed to export private key")
-        .to_string();
+    let mut models = prune_keys(db, config.auth_jwt_expiration)
+      .await
+      .expect("Failed to load jwt keys");
 
-      let uuid = Uuid::new_v4();
+    if models.is_empty() {
+      info!("Generating new JWT RSA keypair. This may take a few seconds...");
+      let key = generate_key();
+      let uuid = Uuid::now_v7();
 
       db.key()
-        .create_key("jwt".into(), key.clone(), uuid)
+        .create_key(JWT_KEY_NAME.into(), key, uuid)
         .await
         .expect("Failed to save key");
 
-      (key, uuid.to_string())
-    };
-
-    let private_key = RsaPrivateKey::from_pkcs1_pem(&key).expect("Failed to load public key");
-    let public_key = RsaPublicKey::from(private_key);
-    let public_key_pem = public_key
-      .to_pkcs1_pem(LineEnding::CRLF)
-      .expect("Failed to export public key");
-
-    let mut header = Header::new(Algorithm::RS256);
-    header.kid = Some(kid.clone());
+      models = db
+        .key()
+        .list_keys_by_name(JWT_KEY_NAME.into())
+        .await
+        .expect("Failed to load jwt keys");
+    }
 
-    let encoding_key =
-      EncodingKey::from_rsa_pem(key.as_bytes()).expect("Failed to create encoding key");
-    let decoding_key =
-      DecodingKey::from_rsa_pem(public_key_pem.as_bytes()).expect("Failed to create decoding key");
+    let keys = JwtKeys::from_models(&models).expect("Failed to load jwt keys");
     let mut validation = Validation::new(Algorithm::RS256);
     validation.validate_aud = false;
 
-    Self {
-      header,
-      encoding_key,
-      decoding_key,
+    let state = Self {
+      keys: Arc::new(RwLock::new(keys)),
       validation,
       iss: config.auth_issuer.clone(),
       exp: config.auth_jwt_expiration,
+      refresh_exp: config.auth_refresh_expiration,
+      refresh_grace: config.auth_refresh_grace,
+      rotation: config.auth_jwt_key_rotation,
       auth: Arc::new(auth),
+      db: db.clone(),
+      kid_reload: Default::default(),
+    };
+
+    spawn({
+      let state = state.clone();
+      let db = db.clone();
+      async move {
+        loop {
+          sleep(KEY_CHECK_INTERVAL).await;
+          if let Err(e) = state.check_keys(&db).await {
+            warn!("Failed to refresh jwt keys: {:?}", e);
+          }
+        }
+      }
+    });
+
+    spawn({
+      let db = db.clone();
+      let refresh_exp = state.refresh_exp;
+      async move {
+        loop {
+          sleep(REFRESH_CLEANUP_INTERVAL).await;
+          if let Err(e) = db.refresh_token().remove_expired().await {
+            warn!("Failed to remove expired refresh tokens: {:?}", e);
+          }
+          let inactive = Utc::now().naive_utc() - Duration::seconds(refresh_exp);
+          if let Err(e) = db.session().remove_inactive(inactive).await {
+            warn!("Failed to remove inactive sessions: {:?}", e);
+          }
+        }
+      }
+    });
+
+    state
+  }
+}
+
+fn generate_key() -> String {
+  let mut rng = OsRng {};
+  let bits = if cfg!(feature = "test") { 512 } else { 4096 };
+  let private_key = RsaPrivateKey::new(&mut rng, bits).expect("Failed to create Rsa key");
+  private_key
+    .to_pkcs1_pem(LineEnding::CRLF)
+    .expect("Failed to export private key")
+    .to_string()
+}
+
+/// Delete keys that can no longer have valid tokens: once the newest key is
+/// older than the token lifetime, every token signed by an older key has expired.
+async fn prune_keys(db: &Connection, exp: i64) -> Result<Vec<key::Model>> {
+  let mut models = db.key().list_keys_by_name(JWT_KEY_NAME.into()).await?;
+
+  if let Some(created) = models.last().and_then(|newest| newest.created)
+    && created + Duration::seconds(exp) < Utc::now().naive_utc()
+  {
+    let newest = models.split_off(models.len() - 1);
+    for model in models {
+      db.key().delete_key(model.id).await?;
     }
+    models = newest;
   }
+
+  Ok(models)
 }
 
 #[derive(FromRequestParts, Clone, Default, OperationIo)]
@@ -176,7 +649,7 @@ mod tests {
     let state = JwtState::init(&config, &conn).await;
     let uuid = Uuid::now_v7();
     let token = state.create_raw_token(uuid).unwrap();
-    let claims = state.validate_token(&token).unwrap();
+    let claims = state.validate_token(&token).await.unwrap();
     assert_eq!(claims.sub, uuid);
     assert_eq!(claims.iss, config.auth_issuer);
   }
@@ -189,10 +662,134 @@ mod tests {
     JwtState::init(&config, &conn).await
   }
 
+  #[tokio::test]
+  async fn test_rotate_key_keeps_old_tokens_valid() {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let state = JwtState::init(&AuthConfig::default(), &conn).await;
+
+    let old_kid = state.current_kid();
+    let old_token = state.create_raw_token(Uuid::now_v7()).unwrap();
+
+    let new_kid = state.rotate_key(&conn).await.unwrap();
+    assert_ne!(old_kid, new_kid);
+    assert_eq!(state.current_kid(), new_kid);
+
+    // New tokens carry the new kid, old ones still validate.
+    let new_token = state.create_raw_token(Uuid::now_v7()).unwrap();
+    assert_eq!(
+      decode_header(&new_token).unwrap().kid,
+      Some(new_kid.clone())
+    );
+    assert!(state.validate_token(&old_token).await.is_ok());
+    assert!(state.validate_token(&new_token).await.is_ok());
+
+    let jwks = state.jwks();
+    assert_eq!(jwks.keys.len(), 2);
+    assert!(jwks.find(&old_kid).is_some());
+    assert!(jwks.find(&new_kid).is_some());
+  }
+
+  #[tokio::test]
+  async fn test_reload_picks_up_rotation_from_other_instance() {
+    // Two instances sharing one database, e.g. replicas behind a load balancer.
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let state_a = JwtState::init(&AuthConfig::default(), &conn).await;
+    let state_b = JwtState::init(&AuthConfig::default(), &conn).await;
+    assert_eq!(state_a.current_kid(), state_b.current_kid());
+
+    // The unknown kid makes the other instance reload its keys.
+    state_a.rotate_key(&conn).await.unwrap();
+    let token = state_a.create_raw_token(Uuid::now_v7()).unwrap();
+    assert!(state_b.validate_token(&token).await.is_ok());
+    assert_eq!(state_a.current_kid(), state_b.current_kid());
+
+    // Reloads for unknown kids are rate limited.
+    state_a.rotate_key(&conn).await.unwrap();
+    let token = state_a.create_raw_token(Uuid::now_v7()).unwrap();
+    assert!(state_b.validate_token(&token).await.is_err());
+
+    state_b.reload_keys(&conn).await.unwrap();
+    assert!(state_b.validate_token(&token).await.is_ok());
+  }
+
+  #[tokio::test]
+  async fn test_check_keys_skips_rotation_done_by_other_instance() {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let config = AuthConfig {
+      auth_jwt_key_rotation: Some(1),
+      ..Default::default()
+    };
+    let state_a = JwtState::init(&config, &conn).await;
+    let state_b = JwtState::init(&config, &conn).await;
+
+    // Both loaded keys are due, but only one instance rotates.
+    tokio::time::sleep(std::time::Duration::from_millis(1100)).await;
+    state_a.check_keys(&conn).await.unwrap();
+    state_b.check_keys(&conn).await.unwrap();
+
+    let keys = conn
+      .key()
+      .list_keys_by_name(JWT_KEY_NAME.into())
+      .await
+      .unwrap();
+    assert_eq!(keys.len(), 2);
+    assert_eq!(state_a.current_kid(), state_b.current_kid());
+  }
+
+  #[tokio::test]
+  async fn test_old_keys_are_pruned_after_token_lifetime() {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let config = AuthConfig {
+      auth_jwt_expiration: 0,
+      ..Default::default()
+    };
+    let state = JwtState::init(&config, &conn).await;
+    let old_kid = state.current_kid();
+
+    state.rotate_key(&conn).await.unwrap();
+    tokio::time::sleep(std::time::Duration::from_millis(10)).await;
+    state.reload_keys(&conn).await.unwrap();
+
+    let keys = conn
+      .key()
+      .list_keys_by_name(JWT_KEY_NAME.into())
+      .await
+      .unwrap();
+    assert_eq!(keys.len(), 1);
+    assert!(state.jwks().find(&old_kid).is_none());
+  }
+
+  #[tokio::test]
+  async fn test_rotation_due() {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+
+    let state = JwtState::init(&AuthConfig::default(), &conn).await;
+    assert!(!state.rotation_due());
+
+    let config = AuthConfig {
+      auth_jwt_key_rotation: None,
+      ..Default::default()
+    };
+    let state = JwtState::init(&config, &conn).await;
+    assert!(!state.rotation_due());
+
+    let config = AuthConfig {
+      auth_jwt_key_rotation: Some(-1),
+      ..Default::default()
+    };
+    let state = JwtState::init(&config, &conn).await;
+    assert!(state.rotation_due());
+  }
+
   #[tokio::test]
   async fn test_validate_rejects_garbage_token() {
     let state = test_state().await;
-    assert!(state.validate_token("not.a.jwt").is_err());
+    assert!(state.validate_token("not.a.jwt").await.is_err());
   }
 
   #[tokio::test]
@@ -201,7 +798,7 @@ mod tests {
     let state_a = test_state().await;
     let state_b = test_state().await;
     let token = state_a.create_raw_token(Uuid::now_v7()).unwrap();
-    assert!(state_b.validate_token(&token).is_err());
+    assert!(state_b.validate_token(&token).await.is_err());
   }
 
   #[tokio::test]
@@ -222,7 +819,7 @@ mod tests {
     let cookie = state.create_token(uuid).unwrap();
     assert_eq!(cookie.name(), JWT_COOKIE_NAME);
     // The cookie's value must be a token that validates back to the same user.
-    let claims = state.validate_token(cookie.value()).unwrap();
+    let claims = state.validate_token(cookie.value()).await.unwrap();
     assert_eq!(claims.sub, uuid);
   }
 
@@ -231,7 +828,7 @@ mod tests {
     // The plain constructor delegates to the custom one with an empty map.
     let state = test_state().await;
     let token = state.create_raw_token(Uuid::now_v7()).unwrap();
-    let claims = state.validate_token(&token).unwrap();
+    let claims = state.validate_token(&token).await.unwrap();
     assert!(claims.additional_claims.is_empty());
   }
 
@@ -244,7 +841,7 @@ mod tests {
     extra.insert("level".to_string(), serde_json::json!(42));
 
     let token = state.create_raw_token_custom(uuid, extra.clone()).unwrap();
-    let claims = state.validate_token(&token).unwrap();
+    let claims = state.validate_token(&token).await.unwrap();
 
     assert_eq!(claims.sub, uuid);
     assert_eq!(claims.iss, state.iss);
@@ -289,7 +886,7 @@ mod tests {
     let state = JwtState::init(&config, &conn).await;
 
     let token = state.create_raw_token(Uuid::now_v7()).unwrap();
-    let claims = state.validate_token(&token).unwrap();
+    let claims = state.validate_token(&token).await.unwrap();
 
     let mut parts = http::Request::builder()
       .body(axum::body::Body::empty())
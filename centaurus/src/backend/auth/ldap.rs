@@ -0,0 +1,1039 @@
+use std::{collections::HashMap, time::Duration};
+
+use aide::axum::routing::{ApiMethodRouter, post_with};
+use argon2::password_hash::SaltString;
+use ldap3::{Ldap, LdapConnAsync, LdapConnSettings, Scope, SearchEntry, drive, ldap_escape};
+use rsa::rand_core::OsRng;
+use tokio::{spawn, time::sleep};
+use tracing::{debug, info, warn};
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    BackendRouter,
+    auth::{
+      jwt_auth::JwtAuth,
+      lockout::{check_lockout, record_failure},
+      oidc::sync_user_groups,
+      permission::SettingsEdit,
+      pw_state::PasswordState,
+      settings::{LdapConfig, LdapSettings},
+    },
+    config::SiteConfig,
+    endpoints::websocket::state::{UpdateMessage, Updater},
+    middleware::rate_limiter::RateLimiter,
+  },
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::Result,
+  mail::Mailer,
+};
+
+/// Identities are stored like the ones of a named OIDC provider.
+pub const LDAP_PROVIDER: &str = "ldap";
+const TIMEOUT: Duration = Duration::from_secs(10);
+/// Used while LDAP is not configured, so enabling it takes effect without a
+/// restart.
+const IDLE_INTERVAL: Duration = Duration::from_secs(60);
+const INVALID_CREDENTIALS: u32 = 49;
+const NO_SUCH_OBJECT: u32 = 32;
+
+pub fn router(rate_limiter: &mut RateLimiter) -> BackendRouter {
+  BackendRouter::new()
+    .api_route("/test", test_ldap_route())
+    .layer(rate_limiter.create_limiter("ldap"))
+}
+
+pub fn test_ldap_route() -> ApiMethodRouter<()> {
+  post_with(test_ldap, |op| op.id("testLdap"))
+}
+
+/// Connects with the saved settings and looks up the user base.
+async fn test_ldap(_auth: JwtAuth<SettingsEdit>, db: Connection) -> Result<()> {
+  let Some(config) = db.settings().get_settings::<LdapSettings>().await?.ldap() else {
+    bail!(BAD_REQUEST, "LDAP not configured");
+  };
+
+  let mut ldap = connect(&config).await?;
+  ldap
+    .search(
+      &config.user_base_dn,
+      Scope::Base,
+      "(objectClass=*)",
+      vec!["1.1"],
+    )
+    .await?
+    .success()?;
+  ldap.unbind().await.ok();
+
+  Ok(())
+}
+
+/// Entry of a user in the directory.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdapUser {
+  pub dn: String,
+  /// Value of the id attribute, or the DN.
+  pub subject: String,
+  pub email: String,
+  pub name: String,
+  /// Names of the directory groups, only loaded with group sync.
+  pub groups: Vec<String>,
+}
+
+async fn open(config: &LdapConfig) -> Result<Ldap> {
+  let settings = LdapConnSettings::new()
+    .set_conn_timeout(TIMEOUT)
+    .set_starttls(config.starttls);
+  let (conn, ldap) = LdapConnAsync::with_settings(settings, config.url.as_str()).await?;
+  drive!(conn);
+
+  Ok(ldap)
+}
+
+/// Connection bound as the search account.
+async fn connect(config: &LdapConfig) -> Result<Ldap> {
+  let mut ldap = open(config).await?;
+  if let Some(bind_dn) = &config.bind_dn {
+    ldap
+      .with_timeout(TIMEOUT)
+      .simple_bind(bind_dn, config.bind_password.as_deref().unwrap_or_default())
+      .await?
+      .success()?;
+  }
+
+  Ok(ldap)
+}
+
+/// Checks the password by binding as the entry `login` resolves to. Returns
+/// `None` if there is no such entry or the password is wrong.
+pub async fn authenticate(
+  config: &LdapConfig,
+  login: &str,
+  password: &str,
+) -> Result<Option<LdapUser>> {
+  // a simple bind without password is an unauthenticated bind, which
+  // succeeds for every DN
+  if password.is_empty() {
+    return Ok(None);
+  }
+
+  let mut ldap = connect(config).await?;
+  let filter = config.user_filter.replace("{login}", &ldap_escape(login));
+  let Some(entry) = find_entry(
+    &mut ldap,
+    config,
+    &config.user_base_dn,
+    Scope::Subtree,
+    &filter,
+  )
+  .await?
+  else {
+    return Ok(None);
+  };
+
+  // the search connection keeps its permissions for the group lookup
+  let mut user_ldap = open(config).await?;
+  let bind = user_ldap
+    .with_timeout(TIMEOUT)
+    .simple_bind(&entry.dn, password)
+    .await?;
+  user_ldap.unbind().await.ok();
+  if bind.rc == INVALID_CREDENTIALS {
+    return Ok(None);
+  }
+  bind.success()?;
+
+  let user = load_user(&mut ldap, config, entry).await?;
+  ldap.unbind().await.ok();
+  Ok(Some(user))
+}
+
+/// Looks up the entry of a linked user by its subject.
+async fn find_user(
+  ldap: &mut Ldap,
+  config: &LdapConfig,
+  subject: &str,
+) -> Result<Option<LdapUser>> {
+  let entry = match &config.id_attribute {
+    Some(attribute) => {
+      let filter = subject_filter(attribute, subject);
+      find_entry(ldap, config, &config.user_base_dn, Scope::Subtree, &filter).await?
+    }
+    None => find_entry(ldap, config, subject, Scope::Base, "(objectClass=*)").await?,
+  };
+
+  match entry {
+    Some(entry) => Ok(Some(load_user(ldap, config, entry).await?)),
+    None => Ok(None),
+  }
+}
+
+/// The only entry matching `filter`, ambiguous results match no one.
+async fn find_entry(
+  ldap: &mut Ldap,
+  config: &LdapConfig,
+  base: &str,
+  scope: Scope,
+  filter: &str,
+) -> Result<Option<SearchEntry>> {
+  let mut attributes = vec![
+    config.email_attribute.as_str(),
+    config.name_attribute.as_str(),
+  ];
+  if let Some(id_attribute) = &config.id_attribute {
+    attributes.push(id_attribute);
+  }
+
+  let result = ldap
+    .with_timeout(TIMEOUT)
+    .search(base, scope, filter, attributes)
+    .await?;
+  if result.1.rc == NO_SUCH_OBJECT {
+    return Ok(None);
+  }
+  let (entries, _) = result.success()?;
+
+  let mut entries = entries.into_iter().map(SearchEntry::construct);
+  match (entries.next(), entries.next()) {
+    (Some(entry), None) => Ok(Some(entry)),
+    (Some(_), Some(_)) => {
+      warn!("LDAP filter {} matches multiple entries", filter);
+      Ok(None)
+    }
+    (None, _) => Ok(None),
+  }
+}
+
+async fn load_user(ldap: &mut Ldap, config: &LdapConfig, entry: SearchEntry) -> Result<LdapUser> {
+  let subject = match &config.id_attribute {
+    Some(attribute) => match first(&entry.attrs, attribute) {
+      Some(value) => value.to_string(),
+      // binary ids like objectGUID are stored as hex
+      None => {
+        let Some(value) = entry.bin_attrs.get(attribute).and_then(|v| v.first()) else {
+          bail!(BAD_GATEWAY, "LDAP entry {} has no {}", entry.dn, attribute);
+        };
+        value.iter().map(|byte| format!("{byte:02x}")).collect()
+      }
+    },
+    None => entry.dn.clone(),
+  };
+  let Some(email) = first(&entry.attrs, &config.email_attribute) else {
+    bail!(BAD_GATEWAY, "LDAP entry {} has no email", entry.dn);
+  };
+  let email = email.to_lowercase();
+  let name = first(&entry.attrs, &config.name_attribute)
+    .map(str::to_string)
+    .unwrap_or_else(|| email.clone());
+
+  let mut groups = Vec::new();
+  if config.group_sync {
+    let filter = config.group_filter.replace("{dn}", &ldap_escape(&entry.dn));
+    let (entries, _) = ldap
+      .with_timeout(TIMEOUT)
+      .search(
+        &config.group_base_dn,
+        Scope::Subtree,
+        &filter,
+        vec![config.group_name_attribute.as_str()],
+      )
+      .await?
+      .success()?;
+    for group in entries.into_iter().map(SearchEntry::construct) {
+      if let Some(name) = first(&group.attrs, &config.group_name_attribute) {
+        groups.push(name.to_string());
+      }
+    }
+  }
+
+  Ok(LdapUser {
+    dn: entry.dn,
+    subject,
+    email,
+    name,
+    groups,
+  })
+}
+
+/// Attribute names are case insensitive.
+fn first<'a>(attributes: &'a HashMap<String, Vec<String>>, name: &str) -> Option<&'a str> {
+  attributes
+    .iter()
+    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+    .and_then(|(_, values)| values.first())
+    .map(String::as_str)
+}
+
+/// Filter for the entry with `subject` as id. Subjects of binary ids are
+/// stored as hex, so those are matched as bytes too.
+fn subject_filter(attribute: &str, subject: &str) -> String {
+  let filter = format!("({}={})", attribute, ldap_escape(subject));
+  let is_hex = !subject.is_empty()
+    && subject.len().is_multiple_of(2)
+    && subject.bytes().all(|b| b.is_ascii_hexdigit());
+  if !is_hex {
+    return filter;
+  }
+
+  let bytes: String = subject
+    .as_bytes()
+    .chunks(2)
+    .map(|pair| format!("\\{}", String::from_utf8_lossy(pair)))
+    .collect();
+  format!("(|{}({}={}))", filter, attribute, bytes)
+}
+
+/// Password login of the `/auth/password` flow against the directory.
+/// Returns `None` if the login belongs to a local account, which is checked
+/// against the stored password instead.
+#[allow(clippy::too_many_arguments)]
+pub async fn login<T: UpdateMessage>(
+  db: &Connection,
+  state: &PasswordState,
+  mailer: &Mailer,
+  site: &SiteConfig,
+  updater: Updater<T>,
+  login: &str,
+  password: &str,
+) -> Result<Option<Uuid>> {
+  let Some(config) = db.settings().get_settings::<LdapSettings>().await?.ldap() else {
+    return Ok(None);
+  };
+
+  let local = db.user().try_get_user_by_email(login).await?;
+  let linked = match &local {
+    Some(user) if user.service_account => return Ok(None),
+    Some(user) => {
+      db.oidc_identity()
+        .has_identity(user.id, LDAP_PROVIDER)
+        .await?
+    }
+    None => false,
+  };
+  if local.is_some() && !linked && !config.link_by_email {
+    return Ok(None);
+  }
+  if let Some(user) = &local
+    && linked
+  {
+    check_lockout(db, user.id).await?;
+  }
+
+  let password = state.decrypt_password(password)?;
+  let ldap_user = match authenticate(&config, login, &password).await {
+    Ok(ldap_user) => ldap_user,
+    // local accounts can still log in while the directory is unavailable
+    Err(err) if !linked => {
+      warn!("LDAP login failed: {:?}", err.error);
+      return Ok(None);
+    }
+    Err(err) => return Err(err),
+  };
+  let Some(ldap_user) = ldap_user else {
+    if let Some(user) = &local
+      && linked
+    {
+      record_failure(db, mailer, site, user.id).await?;
+      bail!(UNAUTHORIZED, "Invalid email or password");
+    }
+    return Ok(None);
+  };
+
+  let user = match db
+    .oidc_identity()
+    .resolve_user(
+      LDAP_PROVIDER,
+      &ldap_user.subject,
+      &ldap_user.email,
+      config.link_by_email,
+    )
+    .await?
+  {
+    Some(user) => {
+      if db
+        .user()
+        .sync_from_oidc(user.id, &ldap_user.name, &ldap_user.email)
+        .await?
+      {
+        updater.send_to(user.id, T::user(user.id)).await;
+      }
+      user.id
+    }
+    None => {
+      if !config.create_user {
+        bail!(FORBIDDEN, "No account for this directory user");
+      }
+
+      let user = db
+        .user()
+        .create_user(
+          ldap_user.name.clone(),
+          ldap_user.email.clone(),
+          String::new(),
+          SaltString::generate(OsRng {}).to_string(),
+          true,
+          None,
+        )
+        .await?;
+      db.oidc_identity()
+        .link(user, LDAP_PROVIDER, &ldap_user.subject)
+        .await?;
+      user
+    }
+  };
+
+  if config.group_sync {
+    sync_user_groups(user, &config.groups(&ldap_user.groups), db, updater).await?;
+  }
+
+  debug!("LDAP user authenticated: {}", user);
+  Ok(Some(user))
+}
+
+/// Syncs the groups, names and emails of all LDAP users with the directory
+/// every `ldap_sync_interval` seconds. Has to be started by the app, as it
+/// needs its [`Updater`].
+pub fn spawn_sync<T: UpdateMessage>(db: Connection, updater: Updater<T>) {
+  spawn(async move {
+    loop {
+      let interval = match db.settings().get_settings::<LdapSettings>().await {
+        Ok(settings) => match settings.ldap() {
+          Some(config) if config.sync_interval > 0 => {
+            if config.group_sync
+              && let Err(e) = sync_users(&db, &config, &updater).await
+            {
+              warn!("Failed to sync LDAP users: {:?}", e.error);
+            }
+            Duration::from_secs(config.sync_interval)
+          }
+          _ => IDLE_INTERVAL,
+        },
+        Err(e) => {
+          warn!("Failed to load LDAP settings: {:?}", e.error);
+          IDLE_INTERVAL
+        }
+      };
+      sleep(interval).await;
+    }
+  });
+}
+
+/// Returns the number of users found in the directory.
+pub async fn sync_users<T: UpdateMessage>(
+  db: &Connection,
+  config: &LdapConfig,
+  updater: &Updater<T>,
+) -> Result<usize> {
+  let identities = db
+    .oidc_identity()
+    .list_provider_identities(LDAP_PROVIDER)
+    .await?;
+  if identities.is_empty() {
+    return Ok(0);
+  }
+
+  let mut ldap = connect(config).await?;
+  let mut synced = 0;
+  for identity in identities {
+    match sync_user(
+      &mut ldap,
+      db,
+      config,
+      updater,
+      identity.user_id,
+      &identity.subject,
+    )
+    .await
+    {
+      Ok(true) => synced += 1,
+      Ok(false) => {}
+      Err(e) => warn!(
+        "Failed to sync LDAP user {}: {:?}",
+        identity.subject, e.error
+      ),
+    }
+  }
+  ldap.unbind().await.ok();
+
+  info!("Synced {} LDAP users", synced);
+  Ok(synced)
+}
+
+/// Returns whether the user was found in the directory. Users removed from it
+/// lose their groups and sessions.
+async fn sync_user<T: UpdateMessage>(
+  ldap: &mut Ldap,
+  db: &Connection,
+  config: &LdapConfig,
+  updater: &Updater<T>,
+  user: Uuid,
+  subject: &str,
+) -> Result<bool> {
+  let Some(ldap_user) = find_user(ldap, config, subject).await? else {
+    debug!("LDAP user {} not found, removing its groups", subject);
+    sync_user_groups(user, &[], db, updater.clone()).await?;
+    db.session().revoke_user_sessions(user, None).await?;
+    return Ok(false);
+  };
+
+  if db
+    .user()
+    .sync_from_oidc(user, &ldap_user.name, &ldap_user.email)
+    .await?
+  {
+    updater.send_to(user, T::user(user)).await;
+  }
+  sync_user_groups(user, &config.groups(&ldap_user.groups), db, updater.clone()).await?;
+  Ok(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::auth::init_pw_state;
+  use crate::backend::auth::settings::AuthConfig;
+  use crate::backend::endpoints::websocket::state::UpdateState;
+  use crate::backend::store::Store;
+  use crate::db::{config::DBConfig, init::connect_db, migrations::Migrator};
+  use crate::mail::MailSettings;
+  use base64::{Engine, prelude::BASE64_STANDARD};
+  use http::StatusCode;
+  use ldap3::asn1::{PL, StructureTag, TagClass, parse_tag};
+  use rsa::{Pkcs1v15Encrypt, RsaPublicKey, pkcs1::DecodeRsaPublicKey};
+  use sea_orm_migration::MigratorTrait;
+  use serde::{Deserialize, Serialize};
+  use std::sync::{Arc, Mutex};
+  use tokio::{
+    io::{AsyncReadExt, AsyncWriteExt},
+    net::{TcpListener, TcpStream},
+  };
+  use url::Url;
+
+  const SERVICE: &str = "cn=service,dc=example,dc=com";
+  const ALICE: &str = "cn=alice,ou=people,dc=example,dc=com";
+  const BOB: &str = "cn=bob,ou=people,dc=example,dc=com";
+  const GUID: [u8; 4] = [0xff, 0x00, 0x2a, 0x80];
+
+  #[derive(Serialize, Deserialize, Clone, Debug)]
+  enum Msg {
+    A,
+  }
+  impl UpdateMessage for Msg {
+    fn settings() -> Self {
+      Msg::A
+    }
+    fn group(_: Uuid) -> Self {
+      Msg::A
+    }
+    fn user(_: Uuid) -> Self {
+      Msg::A
+    }
+    fn user_permissions() -> Self {
+      Msg::A
+    }
+  }
+
+  struct Entry {
+    dn: &'static str,
+    password: Option<&'static str>,
+    attributes: Vec<(&'static str, Vec<Vec<u8>>)>,
+  }
+
+  impl Entry {
+    fn new(dn: &'static str, password: Option<&'static str>) -> Self {
+      Self {
+        dn,
+        password,
+        attributes: Vec::new(),
+      }
+    }
+
+    fn attribute(mut self, name: &'static str, values: &[&str]) -> Self {
+      let values = values
+        .iter()
+        .map(|value| value.as_bytes().to_vec())
+        .collect();
+      self.attributes.push((name, values));
+      self
+    }
+
+    fn values(&self, name: &str) -> impl Iterator<Item = &Vec<u8>> {
+      self
+        .attributes
+        .iter()
+        .filter(move |(key, _)| key.eq_ignore_ascii_case(name))
+        .flat_map(|(_, values)| values)
+    }
+  }
+
+  /// In process stand-in for an LDAP server. Speaks just enough of the
+  /// protocol for simple binds and searches, only bound connections can
+  /// search.
+  #[derive(Clone)]
+  struct Directory(Arc<Mutex<Vec<Entry>>>);
+
+  impl Directory {
+    fn new() -> Self {
+      let entries = vec![
+        Entry::new(SERVICE, Some("service")),
+        Entry::new(ALICE, Some("secret"))
+          .attribute("objectClass", &["person"])
+          .attribute("uid", &["alice"])
+          .attribute("mail", &["Alice@Example.com"])
+          .attribute("cn", &["Alice"])
+          .attribute("entryUUID", &["alice-uuid"]),
+        Entry {
+          attributes: vec![("objectGUID", vec![GUID.to_vec()])],
+          ..Entry::new(BOB, Some("hunter2"))
+        }
+        .attribute("objectClass", &["person"])
+        .attribute("uid", &["bob"])
+        .attribute("mail", &["bob@example.com"])
+        .attribute("cn", &["Bob"]),
+        Entry::new("cn=admins,ou=groups,dc=example,dc=com", None)
+          .attribute("objectClass", &["groupOfNames"])
+          .attribute("cn", &["admins"])
+          .attribute("member", &[ALICE]),
+        Entry::new("cn=staff,ou=groups,dc=example,dc=com", None)
+          .attribute("objectClass", &["groupOfNames"])
+          .attribute("cn", &["staff"])
+          .attribute("member", &[ALICE, BOB]),
+      ];
+      Self(Arc::new(Mutex::new(entries)))
+    }
+
+    fn update(&self, dn: &str, name: &'static str, values: &[&str]) {
+      let mut entries = self.0.lock().unwrap();
+      let entry = entries.iter_mut().find(|entry| entry.dn == dn).unwrap();
+      entry.attributes.retain(|(key, _)| *key != name);
+      entry.attributes.push((
+        name,
+        values
+          .iter()
+          .map(|value| value.as_bytes().to_vec())
+          .collect(),
+      ));
+    }
+
+    async fn serve(self) -> Url {
+      let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+      let url = Url::parse(&format!("ldap://{}", listener.local_addr().unwrap())).unwrap();
+      tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+          tokio::spawn(self.clone().handle(stream));
+        }
+      });
+      url
+    }
+
+    async fn handle(self, mut stream: TcpStream) {
+      let mut buf = Vec::new();
+      let mut bound = false;
+      loop {
+        let message = loop {
+          if let Ok((rest, message)) = parse_tag(&buf) {
+            buf.drain(..buf.len() - rest.len());
+            break message;
+          }
+          let mut chunk = [0; 4096];
+          match stream.read(&mut chunk).await {
+            Ok(0) | Err(_) => return,
+            Ok(n) => buf.extend_from_slice(&chunk[..n]),
+          }
+        };
+
+        let mut parts = message.expect_constructed().unwrap().into_iter();
+        let id = parts.next().unwrap();
+        let op = parts.next().unwrap();
+        let responses = match op.id {
+          0 => {
+            let request = op.expect_constructed().unwrap();
+            let dn = text(&request[1]);
+            let password = primitive(&request[2]);
+            bound = !dn.is_empty() && self.bind(&dn, &password);
+            let rc = if dn.is_empty() || bound { 0 } else { 49 };
+            vec![result(1, rc)]
+          }
+          3 if !bound => vec![result(5, 50)],
+          3 => self.search(op.expect_constructed().unwrap()),
+          _ => return,
+        };
+
+        for response in responses {
+          let mut out = Vec::new();
+          encode(
+            tag(TagClass::Universal, 16, PL::C(vec![id.clone(), response])),
+            &mut out,
+          );
+          if stream.write_all(&out).await.is_err() {
+            return;
+          }
+        }
+      }
+    }
+
+    fn bind(&self, dn: &str, password: &[u8]) -> bool {
+      self.0.lock().unwrap().iter().any(|entry| {
+        entry.dn.eq_ignore_ascii_case(dn) && entry.password.map(str::as_bytes) == Some(password)
+      })
+    }
+
+    fn search(&self, request: Vec<StructureTag>) -> Vec<StructureTag> {
+      let base = text(&request[0]).to_lowercase();
+      let scope = primitive(&request[1])[0];
+      let entries = self.0.lock().unwrap();
+      if scope == 0 && !entries.iter().any(|entry| entry.dn.to_lowercase() == base) {
+        return vec![result(5, 32)];
+      }
+
+      let mut responses: Vec<_> = entries
+        .iter()
+        .filter(|entry| {
+          let dn = entry.dn.to_lowercase();
+          let in_scope = match scope {
+            0 => dn == base,
+            _ => dn.ends_with(&format!(",{base}")),
+          };
+          in_scope && matches(&request[6], entry)
+        })
+        .map(|entry| {
+          let attributes = entry
+            .attributes
+            .iter()
+            .map(|(name, values)| {
+              let values = values.iter().map(|value| octet(value)).collect();
+              tag(
+                TagClass::Universal,
+                16,
+                PL::C(vec![
+                  octet(name.as_bytes()),
+                  tag(TagClass::Universal, 17, PL::C(values)),
+                ]),
+              )
+            })
+            .collect();
+          tag(
+            TagClass::Application,
+            4,
+            PL::C(vec![
+              octet(entry.dn.as_bytes()),
+              tag(TagClass::Universal, 16, PL::C(attributes)),
+            ]),
+          )
+        })
+        .collect();
+      responses.push(result(5, 0));
+      responses
+    }
+  }
+
+  /// and, or, not, equality and presence filters.
+  fn matches(filter: &StructureTag, entry: &Entry) -> bool {
+    match (filter.id, &filter.payload) {
+      (0, PL::C(filters)) => filters.iter().all(|filter| matches(filter, entry)),
+      (1, PL::C(filters)) => filters.iter().any(|filter| matches(filter, entry)),
+      (2, PL::C(filters)) => !matches(&filters[0], entry),
+      (3, PL::C(pair)) => {
+        let value = primitive(&pair[1]);
+        entry
+          .values(&text(&pair[0]))
+          .any(|stored| stored.eq_ignore_ascii_case(&value))
+      }
+      (7, PL::P(name)) => {
+        let name = String::from_utf8_lossy(name);
+        name.eq_ignore_ascii_case("objectClass") || entry.values(&name).next().is_some()
+      }
+      _ => false,
+    }
+  }
+
+  fn tag(class: TagClass, id: u64, payload: PL) -> StructureTag {
+    StructureTag { class, id, payload }
+  }
+
+  fn octet(value: &[u8]) -> StructureTag {
+    tag(TagClass::Universal, 4, PL::P(value.to_vec()))
+  }
+
+  fn result(op: u64, rc: u8) -> StructureTag {
+    tag(
+      TagClass::Application,
+      op,
+      PL::C(vec![
+        tag(TagClass::Universal, 10, PL::P(vec![rc])),
+        octet(b""),
+        octet(b""),
+      ]),
+    )
+  }
+
+  fn primitive(tag: &StructureTag) -> Vec<u8> {
+    tag.clone().expect_primitive().unwrap()
+  }
+
+  fn text(tag: &StructureTag) -> String {
+    String::from_utf8(primitive(tag)).unwrap()
+  }
+
+  fn encode(tag: StructureTag, out: &mut Vec<u8>) {
+    let (constructed, content) = match tag.payload {
+      PL::P(content) => (0, content),
+      PL::C(tags) => {
+        let mut content = Vec::new();
+        for tag in tags {
+          encode(tag, &mut content);
+        }
+        (0x20, content)
+      }
+    };
+
+    out.push(((tag.class as u8) << 6) | constructed | tag.id as u8);
+    if content.len() < 0x80 {
+      out.push(content.len() as u8);
+    } else {
+      let len = (content.len() as u32).to_be_bytes();
+      let len: Vec<_> = len.into_iter().skip_while(|byte| *byte == 0).collect();
+      out.push(0x80 | len.len() as u8);
+      out.extend(len);
+    }
+    out.extend(content);
+  }
+
+  fn settings(url: Url) -> LdapSettings {
+    LdapSettings {
+      ldap_enabled: Some(true),
+      ldap_url: Some(url),
+      ldap_bind_dn: Some(SERVICE.to_string()),
+      ldap_bind_password: Some("service".to_string()),
+      ldap_user_base_dn: Some("ou=people,dc=example,dc=com".to_string()),
+      ldap_user_filter: Some("(&(objectClass=person)(|(mail={login})(uid={login})))".to_string()),
+      ldap_group_sync: Some(true),
+      ldap_group_base_dn: Some("ou=groups,dc=example,dc=com".to_string()),
+      ldap_create_user: Some(true),
+      ..Default::default()
+    }
+  }
+
+  async fn db() -> Connection {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let admin = conn.group().create_group("Admin".into()).await.unwrap();
+    conn.setup().set_admin_group_created(admin).await.unwrap();
+    conn
+  }
+
+  async fn group_names(conn: &Connection, user: Uuid) -> Vec<String> {
+    let mut names: Vec<_> = conn
+      .user()
+      .get_user_groups(user)
+      .await
+      .unwrap()
+      .into_iter()
+      .map(|group| group.name)
+      .collect();
+    names.sort();
+    names
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_binds_as_user() {
+    let url = Directory::new().serve().await;
+    let config = settings(url).ldap().unwrap();
+
+    let user = authenticate(&config, "alice@example.com", "secret")
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(user.dn, ALICE);
+    assert_eq!(user.subject, ALICE);
+    assert_eq!(user.email, "alice@example.com");
+    assert_eq!(user.name, "Alice");
+    assert_eq!(user.groups, vec!["admins", "staff"]);
+
+    let by_uid = authenticate(&config, "alice", "secret").await.unwrap();
+    assert_eq!(by_uid.map(|user| user.dn).as_deref(), Some(ALICE));
+
+    assert!(
+      authenticate(&config, "alice", "wrong")
+        .await
+        .unwrap()
+        .is_none()
+    );
+    assert!(
+      authenticate(&config, "nobody", "secret")
+        .await
+        .unwrap()
+        .is_none()
+    );
+    // an unauthenticated bind would succeed without a password
+    assert!(authenticate(&config, "alice", "").await.unwrap().is_none());
+    // the login is escaped, wildcards match nothing
+    assert!(
+      authenticate(&config, "*", "secret")
+        .await
+        .unwrap()
+        .is_none()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_authenticate_requires_service_bind() {
+    let url = Directory::new().serve().await;
+    let mut settings = settings(url);
+    settings.ldap_bind_password = Some("wrong".to_string());
+    let config = settings.ldap().unwrap();
+
+    assert!(authenticate(&config, "alice", "secret").await.is_err());
+  }
+
+  #[tokio::test]
+  async fn test_binary_id_attribute() {
+    let url = Directory::new().serve().await;
+    let mut settings = settings(url);
+    settings.ldap_id_attribute = Some("objectGUID".to_string());
+    let config = settings.ldap().unwrap();
+
+    let user = authenticate(&config, "bob", "hunter2")
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(user.subject, "ff002a80");
+    assert_eq!(user.groups, vec!["staff"]);
+
+    let mut ldap = connect(&config).await.unwrap();
+    let found = find_user(&mut ldap, &config, &user.subject).await.unwrap();
+    assert_eq!(found, Some(user));
+  }
+
+  #[test]
+  fn test_subject_filter() {
+    assert_eq!(subject_filter("entryUUID", "a*b"), "(entryUUID=a\\2ab)");
+    assert_eq!(
+      subject_filter("objectGUID", "ff00"),
+      "(|(objectGUID=ff00)(objectGUID=\\ff\\00))"
+    );
+  }
+
+  #[tokio::test]
+  async fn test_login_creates_links_and_syncs_user() {
+    let directory = Directory::new();
+    let url = directory.clone().serve().await;
+    let conn = db().await;
+    let mut settings = settings(url);
+    settings.ldap_id_attribute = Some("entryUUID".to_string());
+    conn.settings().save_settings(&settings).await.unwrap();
+    conn.group().create_group("admins".into()).await.unwrap();
+    conn.group().create_group("staff".into()).await.unwrap();
+
+    let config = AuthConfig::default();
+    let pw = init_pw_state(&config, &conn).await;
+    let key = RsaPublicKey::from_pkcs1_pem(&pw.pub_key).unwrap();
+    let encrypt = |password: &str| {
+      BASE64_STANDARD.encode(
+        key
+          .encrypt(&mut OsRng, Pkcs1v15Encrypt, password.as_bytes())
+          .unwrap(),
+      )
+    };
+    let mailer = Mailer::new(MailSettings::default()).await;
+    let site = SiteConfig::default();
+    let updater: Updater<Msg> = UpdateState::<Msg>::init(&Store::memory()).await.unwrap().1;
+    let login = |login: &'static str, password: String| {
+      let (conn, pw, mailer, site, updater) = (
+        conn.clone(),
+        pw.clone(),
+        mailer.clone(),
+        site.clone(),
+        updater.clone(),
+      );
+      async move { super::login::<Msg>(&conn, &pw, &mailer, &site, updater, login, &password).await }
+    };
+
+    let user = login("alice", encrypt("secret")).await.unwrap().unwrap();
+    let identity = conn
+      .oidc_identity()
+      .get_identity(LDAP_PROVIDER, "alice-uuid")
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(identity.user_id, user);
+    assert_eq!(
+      conn.user().get_user_by_id(user).await.unwrap().email,
+      "alice@example.com"
+    );
+    assert_eq!(group_names(&conn, user).await, vec!["admins", "staff"]);
+
+    // linked users can only log in with the directory password
+    let err = login("alice@example.com", encrypt("wrong"))
+      .await
+      .unwrap_err();
+    assert_eq!(err.status, StatusCode::UNAUTHORIZED);
+    let again = login("alice@example.com", encrypt("secret")).await.unwrap();
+    assert_eq!(again, Some(user));
+
+    // unlinked local accounts keep using their own password
+    conn
+      .user()
+      .create_user(
+        "Local".into(),
+        "local@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    assert_eq!(
+      login("local@example.com", encrypt("secret")).await.unwrap(),
+      None
+    );
+
+    // a linked user removed from the directory
+    let gone = conn
+      .user()
+      .create_user(
+        "Gone".into(),
+        "gone@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    conn
+      .oidc_identity()
+      .link(gone, LDAP_PROVIDER, "gone-uuid")
+      .await
+      .unwrap();
+    let staff = conn.group().group_ids(&["staff".into()]).await.unwrap();
+    conn.group().add_user_to_groups(gone, staff).await.unwrap();
+    assert_eq!(group_names(&conn, gone).await, vec!["staff"]);
+    conn
+      .session()
+      .create_session(gone, None, None, None)
+      .await
+      .unwrap();
+
+    directory.update("cn=admins,ou=groups,dc=example,dc=com", "member", &[BOB]);
+    directory.update(ALICE, "cn", &["Alice Smith"]);
+    let config = settings.ldap().unwrap();
+    assert_eq!(sync_users(&conn, &config, &updater).await.unwrap(), 1);
+    assert_eq!(group_names(&conn, user).await, vec!["staff"]);
+    assert_eq!(
+      conn.user().get_user_by_id(user).await.unwrap().name,
+      "Alice Smith"
+    );
+    assert!(group_names(&conn, gone).await.is_empty());
+    assert!(
+      conn
+        .session()
+        .list_user_sessions(gone, None)
+        .await
+        .unwrap()
+        .is_empty()
+    );
+  }
+}
@@ -0,0 +1,94 @@
+use chrono::{Duration, Utc};
+use tokio::spawn;
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::{
+  backend::{auth::settings::LockoutSettings, config::SiteConfig, endpoints::mail::template},
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::Result,
+  mail::Mailer,
+};
+
+/// Reject the login if the account is locked or the delay after the last
+/// failed attempt has not passed yet. Has to run before the credentials are
+/// checked, so guesses during a lockout reveal nothing.
+pub async fn check_lockout(db: &Connection, user: Uuid) -> Result<()> {
+  let settings = db.settings().get_settings::<LockoutSettings>().await?;
+  let Some(policy) = settings.policy() else {
+    return Ok(());
+  };
+  let Some(attempts) = db.login_attempt().get(user).await? else {
+    return Ok(());
+  };
+
+  let now = Utc::now().naive_utc();
+  let locked = attempts.locked_until.is_some_and(|until| until > now);
+  let delayed = now < attempts.last_failed + Duration::seconds(policy.delay_after(attempts.failed));
+  if locked || delayed {
+    bail!(
+      TOO_MANY_REQUESTS,
+      "Too many failed login attempts, try again later"
+    );
+  }
+
+  Ok(())
+}
+
+/// Count a failed login and lock the account once the threshold is reached.
+/// The owner is notified by mail about the lockout.
+pub async fn record_failure(
+  db: &Connection,
+  mailer: &Mailer,
+  site: &SiteConfig,
+  user: Uuid,
+) -> Result<()> {
+  let settings = db.settings().get_settings::<LockoutSettings>().await?;
+  let Some(policy) = settings.policy() else {
+    return Ok(());
+  };
+
+  let now = Utc::now().naive_utc();
+  let window_start = now - Duration::seconds(policy.duration);
+  let failed = db
+    .login_attempt()
+    .record_failure(user, window_start)
+    .await?;
+  if failed < policy.threshold {
+    return Ok(());
+  }
+
+  let until = now + Duration::seconds(policy.duration);
+  db.login_attempt().lock(user, until).await?;
+  info!("Locked account {} after {} failed logins", user, failed);
+
+  // only the failure that triggers the lockout sends a mail
+  if failed == policy.threshold && mailer.is_active().await {
+    let user = db.user().get_user_by_id(user).await?;
+    let mailer = mailer.clone();
+    let link = site.site_url.to_string();
+    spawn(async move {
+      let until = until.format("%Y-%m-%d %H:%M:%S").to_string();
+      if let Err(e) = mailer
+        .send_mail(
+          user.name,
+          user.email.clone(),
+          "Account locked".to_string(),
+          template::account_locked(&until, &link),
+        )
+        .await
+      {
+        warn!("Failed to send lockout mail to {}: {:?}", user.email, e);
+      }
+    });
+  }
+
+  Ok(())
+}
+
+/// Forget earlier failures after a completed login.
+pub async fn record_success(db: &Connection, user: Uuid) -> Result<()> {
+  db.login_attempt().reset(user).await?;
+  Ok(())
+}
@@ -3,10 +3,17 @@ use aide::axum::routing::{ApiMethodRouter, post_with};
 use axum_extra::extract::CookieJar;
 use chrono::DateTime;
 use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Serialize;
 use tracing::debug;
+use url::Url;
 
 use crate::backend::auth::jwt_auth::JwtAuth;
-use crate::backend::auth::jwt_state::{JWT_COOKIE_NAME, JwtInvalidState, JwtState};
+use crate::backend::auth::jwt_state::{
+  JWT_COOKIE_NAME, JwtInvalidState, JwtState, REFRESH_COOKIE_NAME,
+};
+use crate::backend::auth::oidc::OidcState;
+use crate::backend::config::SiteConfig;
 use crate::backend::request::response::TokenRes;
 use crate::db::init::Connection;
 use crate::db::tables::ConnectionExt;
@@ -20,13 +27,23 @@ pub fn logout_route() -> ApiMethodRouter<()> {
   post_with(logout, |op| op.id("logout"))
 }
 
+#[derive(Serialize, JsonSchema, Debug)]
+struct LogoutResponse {
+  /// Set if the session was started with an OIDC provider that supports
+  /// RP-initiated logout, the client should navigate there to log out at the
+  /// provider as well.
+  redirect: Option<Url>,
+}
+
 async fn logout(
   auth: JwtAuth,
   db: Connection,
   mut cookies: CookieJar,
   state: JwtInvalidState,
   jwt: JwtState,
-) -> Result<(CookieJar, TokenRes)> {
+  oidc: OidcState,
+  site: SiteConfig,
+) -> Result<(CookieJar, TokenRes<LogoutResponse>)> {
   let cookie = cookies
     .get(JWT_COOKIE_NAME)
     .status_context(StatusCode::UNAUTHORIZED, "Missing auth cookie")?;
@@ -40,8 +57,23 @@ async fn logout(
     )
     .await?;
 
+  let mut redirect = None;
+  if let Some(sid) = auth.sid {
+    if let Some(provider) = db
+      .session()
+      .get_session(sid)
+      .await?
+      .and_then(|session| session.oidc_provider)
+    {
+      redirect = oidc.end_session_url(&provider, &site.site_url).await;
+    }
+    db.session().revoke_session(sid).await?;
+  }
+
   debug!("User logged out: {}", auth.user_id);
-  cookies = cookies.remove(jwt.create_cookie(JWT_COOKIE_NAME, String::new()));
+  cookies = cookies
+    .remove(jwt.create_cookie(JWT_COOKIE_NAME, String::new()))
+    .remove(jwt.create_cookie(REFRESH_COOKIE_NAME, String::new()));
 
-  Ok((cookies, TokenRes(())))
+  Ok((cookies, TokenRes(LogoutResponse { redirect })))
 }
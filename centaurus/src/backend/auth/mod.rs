@@ -22,39 +22,84 @@ use crate::{
       settings::AuthConfig,
     },
     config::Config,
-    endpoints::websocket::state::UpdateMessage,
+    endpoints::{audit, user::invitation::InvitationState, websocket::state::UpdateMessage},
     middleware::rate_limiter::RateLimiter,
+    store::Store,
   },
   db::{init::Connection, tables::ConnectionExt},
 };
 
+#[cfg(feature = "endpoints")]
+pub mod api_token;
+#[cfg(feature = "endpoints")]
+pub mod client;
 #[cfg(feature = "endpoints")]
 pub mod config;
+#[cfg(feature = "endpoints")]
+pub mod jwks;
 pub mod jwt;
 #[cfg(feature = "endpoints")]
 pub mod jwt_auth;
 #[cfg(feature = "endpoints")]
 pub mod jwt_state;
+#[cfg(feature = "ldap")]
+pub mod ldap;
+#[cfg(feature = "endpoints")]
+pub mod lockout;
 #[cfg(feature = "endpoints")]
 pub mod logout;
 #[cfg(feature = "endpoints")]
 pub mod oidc;
 #[cfg(feature = "endpoints")]
+pub mod pake;
+#[cfg(all(feature = "endpoints", feature = "webauthn"))]
+pub mod passkey;
+#[cfg(feature = "endpoints")]
 pub mod password;
 #[cfg(feature = "endpoints")]
+pub mod password_policy;
+#[cfg(feature = "endpoints")]
 pub mod permission;
+#[cfg(feature = "oidc_provider")]
+pub mod provider;
 pub mod pw_state;
+#[cfg(feature = "endpoints")]
+pub mod refresh;
+#[cfg(feature = "saml")]
+pub mod saml;
+#[cfg(feature = "endpoints")]
+pub mod session;
 pub mod settings;
 #[cfg(feature = "endpoints")]
 pub mod test_token;
+#[cfg(feature = "endpoints")]
+pub mod token;
+#[cfg(feature = "endpoints")]
+pub mod totp;
 
 #[cfg(feature = "endpoints")]
 pub fn router<T: UpdateMessage>(rate_limiter: &mut RateLimiter) -> BackendRouter {
   let router = BackendRouter::new()
-    .nest("/password", password::router(rate_limiter))
+    .nest("/password", password::router::<T>(rate_limiter))
+    .nest("/totp", totp::router(rate_limiter))
+    .nest("/client", client::router(rate_limiter))
     .nest("/logout", logout::router())
+    .nest("/refresh", refresh::router(rate_limiter))
+    .nest("/keys", jwks::router())
     .nest("/test_token", test_token::router());
 
+  #[cfg(feature = "webauthn")]
+  let router = router.nest("/passkey", passkey::router(rate_limiter));
+
+  #[cfg(feature = "saml")]
+  let router = router.nest("/saml", saml::router::<T>(rate_limiter));
+
+  #[cfg(feature = "ldap")]
+  let router = router.nest("/ldap", ldap::router(rate_limiter));
+
+  #[cfg(feature = "oidc_provider")]
+  let router = router.nest("/provider", provider::router(rate_limiter));
+
   #[cfg(feature = "avatar")]
   {
     router
@@ -66,19 +111,36 @@ pub fn router<T: UpdateMessage>(rate_limiter: &mut RateLimiter) -> BackendRouter
 }
 
 #[cfg(feature = "endpoints")]
-pub async fn state<C: Config>(router: BackendRouter, config: &C, db: &Connection) -> BackendRouter {
+pub async fn state<C: Config>(
+  router: BackendRouter,
+  config: &C,
+  db: &Connection,
+  #[cfg_attr(not(feature = "avatar"), allow(unused_variables))] store: &Store,
+) -> BackendRouter {
   #[cfg(feature = "avatar")]
   use crate::backend::auth::oidc::OidcState;
 
   let pw_state = init_pw_state(config.auth(), db).await;
   let jwt_state = JwtState::init(config.auth(), db).await;
+  if let Some(retention) = config.auth().auth_audit_retention {
+    audit::spawn_retention(db.clone(), retention);
+  }
   #[cfg(feature = "avatar")]
-  let oidc_state = OidcState::new(db, config.oidc()).await;
+  let oidc_state = OidcState::new(db, config.oidc(), store.clone()).await;
 
   let router = router
     .layer(Extension(pw_state))
     .layer(Extension(jwt_state))
-    .layer(Extension(JwtInvalidState::default()));
+    .layer(Extension(JwtInvalidState::default()))
+    .layer(Extension(InvitationState::init(
+      config.auth().auth_invite_expiration,
+    )));
+
+  #[cfg(feature = "webauthn")]
+  let router = router.layer(Extension(passkey::PasskeyState::init(
+    config.site(),
+    &config.auth().auth_issuer,
+  )));
 
   #[cfg(feature = "avatar")]
   {
@@ -112,8 +174,7 @@ pub async fn init_pw_state(config: &AuthConfig, db: &Connection) -> PasswordStat
     private_key
   };
 
-  let pepper = config.auth_pepper.as_bytes().to_vec();
-  PasswordState::init(pepper, key).await
+  PasswordState::init(config, key).await
 }
 
 #[cfg(all(test, feature = "endpoints"))]
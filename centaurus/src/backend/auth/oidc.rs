@@ -8,32 +8,42 @@ use crate::{
   backend::{
     BackendRouter,
     auth::{
+      jwt_auth::JwtAuth,
       jwt_state::JwtState,
-      settings::{OidcSettings, UserSettings},
+      session::ClientInfo,
+      settings::{DEFAULT_PROVIDER, OidcSettings, UserSettings},
     },
     config::SiteConfig,
-    endpoints::websocket::state::{UpdateMessage, Updater},
+    endpoints::{
+      audit::Audit,
+      user::invitation::{InvitationState, accept_invitation},
+      websocket::state::{UpdateMessage, Updater},
+    },
     middleware::rate_limiter::RateLimiter,
     request::redirect::Redirect,
+    store::Store,
   },
   bail,
   db::{init::Connection, tables::ConnectionExt},
-  error::{ErrorReportStatusExt, Result},
+  error::{ErrorReportExt, ErrorReportStatusExt, Result},
   overwrite_with_env_config,
 };
 use aide::OperationIo;
 use argon2::password_hash::SaltString;
 use axum::{
-  Extension, Json,
-  extract::{FromRequestParts, Query},
+  Extension, Form, Json,
+  extract::{FromRequestParts, Path, Query},
 };
 use axum_extra::extract::{CookieJar, cookie::Cookie};
 use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
-use dashmap::DashMap;
-use http::StatusCode;
+use chrono::{NaiveDateTime, Utc};
+use http::{
+  HeaderMap, StatusCode,
+  header::{CACHE_CONTROL, EXPIRES},
+};
 use jsonwebtoken::{
   DecodingKey, Validation,
-  jwk::{AlgorithmParameters, JwkSet},
+  jwk::{AlgorithmParameters, Jwk, JwkSet},
 };
 use rand::seq::IndexedRandom;
 use reqwest::{Client, redirect::Policy};
@@ -46,39 +56,96 @@ use url::Url;
 use uuid::Uuid;
 
 pub const OIDC_STATE: &str = "oidc_state";
+const OIDC_NONCE: &str = "oidc_nonce";
+const OIDC_LOGOUT_JTI: &str = "oidc_logout_jti";
+const STATE_TTL: Duration = Duration::from_secs(600);
+/// Logout tokens older than this are rejected, their `jti` is remembered as
+/// long to detect replays.
+const LOGOUT_TOKEN_TTL: Duration = Duration::from_secs(300);
+const BACKCHANNEL_LOGOUT_EVENT: &str = "http://schemas.openid.net/event/backchannel-logout";
+/// How often the discovery document and expired keys are fetched again.
+const DISCOVERY_REFRESH_INTERVAL: Duration = Duration::from_secs(3600);
+/// Used if the provider sends no cache headers with its keys.
+const JWKS_DEFAULT_TTL: Duration = Duration::from_secs(3600);
+const JWKS_MIN_TTL: Duration = Duration::from_secs(60);
+const JWKS_MAX_TTL: Duration = Duration::from_secs(86400);
+/// Minimum time between two fetches of the keys, so tokens with unknown `kid`s
+/// can not be used to flood the provider with requests.
+const JWKS_REFETCH_INTERVAL: Duration = Duration::from_secs(30);
 pub const SKIP_SETUP_ENV: &str = "SKIP_SETUP";
 pub const URL_SAFE_CHARS: &[u8] =
   b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789-._~";
 
 pub fn router<T: UpdateMessage>(rate_limiter: &mut RateLimiter) -> BackendRouter {
   #[cfg(feature = "openapi")]
-  use aide::axum::routing::get;
+  use aide::axum::routing::{get, post};
   #[cfg(not(feature = "openapi"))]
-  use axum::routing::get;
+  use axum::routing::{get, post};
 
+  // the routes without a provider use the default provider
   BackendRouter::new()
     .route("/url", get(oidc_url))
-    .layer(rate_limiter.create_limiter())
+    .route("/{provider}/url", get(provider_url))
+    .route("/{provider}/link", get(link_url))
+    .route("/{provider}/invite", get(invite_url))
+    .layer(rate_limiter.create_limiter("oidc"))
     .route("/callback", get(oidc_callback::<T>))
+    .route("/{provider}/callback", get(provider_callback::<T>))
+    .route("/{provider}/backchannel_logout", post(backchannel_logout))
 }
 
-type State = (Instant, Option<String>, Option<String>);
+/// Pending authorization request, kept in the [`Store`] under its state id.
+#[derive(Serialize, Deserialize, Debug)]
+struct PendingAuth {
+  provider: String,
+  code_verifier: Option<String>,
+  redirect_to: Option<String>,
+  /// Set if the subject should be linked to this user instead of logging in.
+  link: Option<Uuid>,
+  /// Set if the login accepts this invitation.
+  #[serde(default)]
+  invite: Option<Uuid>,
+}
 
 #[derive(Clone, FromRequestParts, Debug, OperationIo)]
 #[from_request(via(Extension))]
 pub struct OidcState {
-  config: Arc<Mutex<Option<OidcConfig>>>,
-  state: Arc<DashMap<Uuid, State>>,
-  nonce: Arc<DashMap<Uuid, Instant>>,
+  config: Arc<Mutex<Vec<OidcConfig>>>,
+  /// Errors of providers that could not be initialized, by name.
+  failed: Arc<Mutex<HashMap<String, String>>>,
+  store: Store,
+}
+
+/// A provider users can log in with.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, schemars::JsonSchema)]
+pub struct OidcProvider {
+  pub name: String,
+  pub display_name: Option<String>,
+}
+
+/// Health of the connection to a provider.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq, schemars::JsonSchema)]
+pub struct OidcProviderStatus {
+  pub name: String,
+  /// Whether the last request to the provider succeeded.
+  pub healthy: bool,
+  /// Last successful fetch of the discovery document or the keys.
+  pub last_refresh: Option<NaiveDateTime>,
+  pub last_error: Option<String>,
+  pub keys: usize,
 }
 
 #[derive(Debug, Clone)]
 struct OidcConfig {
+  name: String,
+  display_name: Option<String>,
   issuer: String,
   authorization_endpoint: Url,
   token_endpoint: Url,
   userinfo_endpoint: Url,
-  jwk_set: JwkSet,
+  end_session_endpoint: Option<Url>,
+  discovery_url: Url,
+  connection: Arc<Mutex<ProviderConnection>>,
   client_id: String,
   client_secret: String,
   client: Client,
@@ -89,6 +156,7 @@ struct OidcConfig {
   image_sync: bool,
   create_user: bool,
   pkce: bool,
+  link_by_email: bool,
 }
 
 #[derive(Deserialize, Debug)]
@@ -98,14 +166,28 @@ struct OidcConfiguration {
   token_endpoint: Url,
   userinfo_endpoint: Url,
   jwks_uri: Url,
+  end_session_endpoint: Option<Url>,
+}
+
+/// Keys and connection state of a provider, shared by all clones of its config.
+#[derive(Debug)]
+struct ProviderConnection {
+  jwks_uri: Url,
+  jwk_set: JwkSet,
+  /// The keys are fetched again on the next use after this.
+  jwks_expires: Instant,
+  /// Last attempt to fetch the keys, successful or not.
+  jwks_fetched: Instant,
+  last_refresh: NaiveDateTime,
+  last_error: Option<String>,
 }
 
 impl OidcState {
-  pub async fn new(db: &Connection, oidc: Option<&UserSettings>) -> Self {
+  pub async fn new(db: &Connection, oidc: Option<&UserSettings>, store: Store) -> Self {
     let state = Self {
-      config: Arc::new(Mutex::new(None)),
-      state: Arc::new(DashMap::new()),
-      nonce: Arc::new(DashMap::new()),
+      config: Arc::new(Mutex::new(Vec::new())),
+      failed: Arc::new(Mutex::new(HashMap::new())),
+      store,
     };
 
     let mut settings: UserSettings = db.settings().get_settings().await.unwrap_or_default();
@@ -117,7 +199,8 @@ impl OidcState {
       oidc_issuer,
       oidc_client_id,
       oidc_client_secret,
-      oidc_scopes,,
+      oidc_scopes,
+      oidc_providers,,
       oidc_enabled,
       oidc_group_sync,
       oidc_image_sync,
@@ -179,20 +262,29 @@ impl OidcState {
       info!("Could not skip setup, OIDC is not configured");
     }
 
+    // providers are independent, one that fails does not affect the others
+    for provider in settings
+      .oidc_providers()
+      .into_iter()
+      .filter(|p| p.name != DEFAULT_PROVIDER)
+    {
+      let state = state.clone();
+      spawn(async move {
+        if let Err(e) = state.try_init(&provider).await {
+          warn!(
+            "Failed to initialize OIDC provider {}: {:?}",
+            provider.name, e
+          );
+        }
+      });
+    }
+
     spawn({
       let state = state.clone();
       async move {
-        let cleanup_interval = Duration::from_secs(600);
-        let expiration_duration = Duration::from_secs(600);
         loop {
-          sleep(cleanup_interval).await;
-          let now = Instant::now();
-          state
-            .nonce
-            .retain(|_, &mut instant| now.duration_since(instant) < expiration_duration);
-          state
-            .state
-            .retain(|_, &mut (instant, _, _)| now.duration_since(instant) < expiration_duration);
+          sleep(DISCOVERY_REFRESH_INTERVAL).await;
+          state.refresh().await;
         }
       }
     });
@@ -200,28 +292,176 @@ impl OidcState {
     state
   }
 
+  /// Add the provider or replace the one with the same name.
   pub async fn try_init(&self, settings: &OidcSettings) -> Result<()> {
-    let config = OidcConfig::new(settings).await?;
+    let config = match OidcConfig::new(settings).await {
+      Ok(config) => config,
+      Err(e) => {
+        self
+          .failed
+          .lock()
+          .await
+          .insert(settings.name.clone(), e.to_string());
+        return Err(e);
+      }
+    };
+    self.failed.lock().await.remove(&config.name);
+
     let mut lock = self.config.lock().await;
-    *lock = Some(config);
+    if let Some(existing) = lock.iter_mut().find(|c| c.name == config.name) {
+      *existing = config;
+    } else {
+      lock.push(config);
+    }
+    Ok(())
+  }
+
+  /// Replace all providers. If one of them can not be initialized the current
+  /// ones are kept.
+  pub async fn configure(&self, settings: &[OidcSettings]) -> Result<()> {
+    let mut configs = Vec::with_capacity(settings.len());
+    for provider in settings {
+      let config = OidcConfig::new(provider).await.context(&format!(
+        "Failed to initialize OIDC provider {}",
+        provider.name
+      ))?;
+      configs.push(config);
+    }
+
+    *self.config.lock().await = configs;
+    self.failed.lock().await.clear();
     Ok(())
   }
 
   pub async fn deactivate(&self) {
     let mut lock = self.config.lock().await;
-    *lock = None;
+    lock.clear();
+    self.failed.lock().await.clear();
   }
 
   pub async fn is_enabled(&self) -> bool {
     let lock = self.config.lock().await;
-    lock.is_some()
+    !lock.is_empty()
+  }
+
+  pub async fn providers(&self) -> Vec<OidcProvider> {
+    let lock = self.config.lock().await;
+    lock
+      .iter()
+      .map(|c| OidcProvider {
+        name: c.name.clone(),
+        display_name: c.display_name.clone(),
+      })
+      .collect()
+  }
+
+  /// RP-initiated logout: where to send the user to end the session at the
+  /// provider as well, if it supports that.
+  pub async fn end_session_url(
+    &self,
+    provider: &str,
+    post_logout_redirect_uri: &Url,
+  ) -> Option<Url> {
+    self
+      .provider(provider)
+      .await?
+      .end_session_url(post_logout_redirect_uri)
+  }
+
+  /// Connection health of the configured providers and of those that could
+  /// not be initialized.
+  pub async fn status(&self) -> Vec<OidcProviderStatus> {
+    let configs = self.config.lock().await.clone();
+    let mut status = Vec::with_capacity(configs.len());
+    for config in &configs {
+      let connection = config.connection.lock().await;
+      status.push(OidcProviderStatus {
+        name: config.name.clone(),
+        healthy: connection.last_error.is_none(),
+        last_refresh: Some(connection.last_refresh),
+        last_error: connection.last_error.clone(),
+        keys: connection.jwk_set.keys.len(),
+      });
+    }
+
+    let failed = self.failed.lock().await;
+    for (name, error) in failed.iter() {
+      if configs.iter().all(|c| &c.name != name) {
+        status.push(OidcProviderStatus {
+          name: name.clone(),
+          healthy: false,
+          last_refresh: None,
+          last_error: Some(error.clone()),
+          keys: 0,
+        });
+      }
+    }
+
+    status
+  }
+
+  /// Fetch the discovery document of all providers again and their keys if
+  /// they expired. Failures are kept in the status of the provider, the
+  /// previous endpoints and keys stay in use.
+  pub async fn refresh(&self) {
+    let configs = self.config.lock().await.clone();
+    for config in configs {
+      let discovery = match fetch_configuration(&config.discovery_url).await {
+        Ok(discovery) => discovery,
+        Err(e) => {
+          warn!(
+            "Failed to refresh OIDC discovery of provider {}: {:?}",
+            config.name, e
+          );
+          config.connection.lock().await.last_error = Some(e.to_string());
+          continue;
+        }
+      };
+
+      let jwks_uri = {
+        let mut connection = config.connection.lock().await;
+        connection.last_refresh = Utc::now().naive_utc();
+        connection.last_error = None;
+        if connection.jwks_uri != discovery.jwks_uri {
+          connection.jwks_uri = discovery.jwks_uri.clone();
+          connection.jwks_expires = Instant::now();
+        }
+        (connection.jwks_expires <= Instant::now()).then(|| connection.start_jwks_refresh())
+      };
+      if let Some(jwks_uri) = jwks_uri {
+        let result = fetch_jwks(&jwks_uri).await;
+        config
+          .connection
+          .lock()
+          .await
+          .finish_jwks_refresh(&config.name, &jwks_uri, result);
+      }
+
+      let mut lock = self.config.lock().await;
+      // the provider could have been replaced in the meantime
+      if let Some(current) = lock
+        .iter_mut()
+        .find(|c| Arc::ptr_eq(&c.connection, &config.connection))
+      {
+        current.issuer = discovery.issuer;
+        current.authorization_endpoint = discovery.authorization_endpoint;
+        current.token_endpoint = discovery.token_endpoint;
+        current.userinfo_endpoint = discovery.userinfo_endpoint;
+        current.end_session_endpoint = discovery.end_session_endpoint;
+      }
+    }
+  }
+
+  async fn provider(&self, name: &str) -> Option<OidcConfig> {
+    let lock = self.config.lock().await;
+    lock.iter().find(|c| c.name == name).cloned()
   }
 }
 
 impl OidcConfig {
   async fn new(oidc_settings: &OidcSettings) -> Result<Self> {
-    let mut url = oidc_settings.issuer.clone();
-    url
+    let mut discovery_url = oidc_settings.issuer.clone();
+    discovery_url
       .path_segments_mut()
       .ok()
       .status_context(StatusCode::BAD_REQUEST, "Failed to add path to url")?
@@ -229,25 +469,9 @@ impl OidcConfig {
       .push(".well-known")
       .push("openid-configuration");
 
-    info!("Configuring OIDC with URL: {}", url);
-    let res = reqwest::get(url.clone()).await?;
-    if !res.status().is_success() {
-      let body = res.text().await.unwrap_or_default();
-      bail!(
-        "Failed to retrieve OIDC configuration from {}: {}",
-        url,
-        body
-      );
-    }
-    let config: OidcConfiguration = res.json().await?;
-
-    info!("Retrieving JWKs from: {}", config.jwks_uri);
-    let res = reqwest::get(config.jwks_uri.clone()).await?;
-    if !res.status().is_success() {
-      let body = res.text().await.unwrap_or_default();
-      bail!("Failed to retrieve JWKs from {}: {}", config.jwks_uri, body);
-    }
-    let jwk_set: JwkSet = res.json().await?;
+    info!("Configuring OIDC with URL: {}", discovery_url);
+    let config = fetch_configuration(&discovery_url).await?;
+    let (jwk_set, ttl) = fetch_jwks(&config.jwks_uri).await?;
 
     let client = Client::builder().redirect(Policy::none()).build()?;
     info!(
@@ -255,12 +479,24 @@ impl OidcConfig {
       config.issuer
     );
 
+    let now = Instant::now();
     Ok(Self {
+      name: oidc_settings.name.clone(),
+      display_name: oidc_settings.display_name.clone(),
       issuer: config.issuer,
       authorization_endpoint: config.authorization_endpoint,
       token_endpoint: config.token_endpoint,
       userinfo_endpoint: config.userinfo_endpoint,
-      jwk_set,
+      end_session_endpoint: config.end_session_endpoint,
+      discovery_url,
+      connection: Arc::new(Mutex::new(ProviderConnection {
+        jwks_uri: config.jwks_uri,
+        jwk_set,
+        jwks_expires: now + ttl,
+        jwks_fetched: now,
+        last_refresh: Utc::now().naive_utc(),
+        last_error: None,
+      })),
       client_id: oidc_settings.client_id.clone(),
       client_secret: oidc_settings.client_secret.clone(),
       client,
@@ -270,19 +506,127 @@ impl OidcConfig {
       image_sync: oidc_settings.image_sync,
       create_user: oidc_settings.create_user,
       pkce: oidc_settings.pkce,
+      link_by_email: oidc_settings.link_by_email,
     })
   }
 }
 
+/// The keys are fetched between [`ProviderConnection::start_jwks_refresh`] and
+/// [`ProviderConnection::finish_jwks_refresh`] without holding the lock, so a
+/// slow provider does not block logins with the keys that are still known.
+impl ProviderConnection {
+  /// Returns the url to fetch the keys from.
+  fn start_jwks_refresh(&mut self) -> Url {
+    self.jwks_fetched = Instant::now();
+    self.jwks_uri.clone()
+  }
+
+  fn finish_jwks_refresh(
+    &mut self,
+    provider: &str,
+    jwks_uri: &Url,
+    result: Result<(JwkSet, Duration)>,
+  ) {
+    // the discovery document could have moved the keys in the meantime
+    if &self.jwks_uri != jwks_uri {
+      return;
+    }
+
+    match result {
+      Ok((jwk_set, ttl)) => {
+        self.jwk_set = jwk_set;
+        self.jwks_expires = Instant::now() + ttl;
+        self.last_refresh = Utc::now().naive_utc();
+        self.last_error = None;
+      }
+      Err(e) => {
+        warn!(
+          "Failed to refresh JWKs of OIDC provider {}: {:?}",
+          provider, e
+        );
+        self.last_error = Some(e.to_string());
+      }
+    }
+  }
+}
+
+async fn fetch_configuration(url: &Url) -> Result<OidcConfiguration> {
+  let res = reqwest::get(url.clone()).await?;
+  if !res.status().is_success() {
+    let body = res.text().await.unwrap_or_default();
+    bail!(
+      "Failed to retrieve OIDC configuration from {}: {}",
+      url,
+      body
+    );
+  }
+
+  Ok(res.json().await?)
+}
+
+/// Fetch the keys of a provider and how long they may be cached.
+async fn fetch_jwks(url: &Url) -> Result<(JwkSet, Duration)> {
+  info!("Retrieving JWKs from: {}", url);
+  let res = reqwest::get(url.clone()).await?;
+  if !res.status().is_success() {
+    let body = res.text().await.unwrap_or_default();
+    bail!("Failed to retrieve JWKs from {}: {}", url, body);
+  }
+  let ttl = cache_ttl(res.headers());
+
+  Ok((res.json().await?, ttl))
+}
+
+/// How long a response may be cached according to its `Cache-Control` or
+/// `Expires` header, within sane bounds.
+fn cache_ttl(headers: &HeaderMap) -> Duration {
+  let ttl = if let Some(cache_control) = headers
+    .get(CACHE_CONTROL)
+    .and_then(|value| value.to_str().ok())
+  {
+    let mut ttl = None;
+    for directive in cache_control.split(',').map(str::trim) {
+      if directive.eq_ignore_ascii_case("no-cache") || directive.eq_ignore_ascii_case("no-store") {
+        ttl = Some(Duration::ZERO);
+        break;
+      }
+      if let Some((name, value)) = directive.split_once('=')
+        && name.trim().eq_ignore_ascii_case("max-age")
+        && let Ok(secs) = value.trim().trim_matches('"').parse()
+      {
+        ttl = Some(Duration::from_secs(secs));
+      }
+    }
+    ttl
+  } else {
+    None
+  };
+
+  let ttl = ttl.or_else(|| {
+    let expires = headers.get(EXPIRES)?.to_str().ok()?;
+    let expires = chrono::DateTime::parse_from_rfc2822(expires).ok()?;
+    Some(
+      (expires.with_timezone(&Utc) - Utc::now())
+        .to_std()
+        .unwrap_or_default(),
+    )
+  });
+
+  ttl
+    .unwrap_or(JWKS_DEFAULT_TTL)
+    .clamp(JWKS_MIN_TTL, JWKS_MAX_TTL)
+}
+
 impl OidcConfig {
-  async fn validate_jwk(&self, token: &str, nonce_map: &DashMap<Uuid, Instant>) -> Result<()> {
+  /// Verify the signature, issuer and audience of a token of the provider.
+  async fn decode_claims(&self, token: &str) -> Result<HashMap<String, serde_json::Value>> {
     let header = jsonwebtoken::decode_header(token)?;
 
     let Some(kid) = header.kid else {
       bail!(INTERNAL_SERVER_ERROR, "Missing kid in JWK header");
     };
 
-    let Some(jwk) = self.jwk_set.find(&kid) else {
+    let Some(jwk) = self.jwk(&kid).await else {
       bail!(INTERNAL_SERVER_ERROR, "JWK not found");
     };
 
@@ -308,21 +652,117 @@ impl OidcConfig {
       &validation,
     )?;
 
-    let Some(Some(Ok(nonce))) = data
-      .claims
+    Ok(data.claims)
+  }
+
+  /// The key with the `kid`. The keys are fetched again if they expired or the
+  /// provider might have rotated them, at most every
+  /// [`JWKS_REFETCH_INTERVAL`].
+  async fn jwk(&self, kid: &str) -> Option<Jwk> {
+    let jwks_uri = {
+      let mut connection = self.connection.lock().await;
+      let now = Instant::now();
+      ((connection.jwks_expires <= now || connection.jwk_set.find(kid).is_none())
+        && now.duration_since(connection.jwks_fetched) >= JWKS_REFETCH_INTERVAL)
+        .then(|| connection.start_jwks_refresh())
+    };
+    if let Some(jwks_uri) = jwks_uri {
+      debug!("Refreshing JWKs of OIDC provider {}", self.name);
+      let result = fetch_jwks(&jwks_uri).await;
+      self
+        .connection
+        .lock()
+        .await
+        .finish_jwks_refresh(&self.name, &jwks_uri, result);
+    }
+
+    self.connection.lock().await.jwk_set.find(kid).cloned()
+  }
+
+  /// Validate the id token and return its claims.
+  async fn validate_jwk(
+    &self,
+    token: &str,
+    store: &Store,
+  ) -> Result<HashMap<String, serde_json::Value>> {
+    let claims = self.decode_claims(token).await?;
+
+    let Some(Some(Ok(nonce))) = claims
       .get("nonce")
-      .map(|nonce| nonce.as_str().map(|nonce| nonce.parse()))
+      .map(|nonce| nonce.as_str().map(|nonce| nonce.parse::<Uuid>()))
     else {
       bail!(INTERNAL_SERVER_ERROR, "Missing nonce in JWK claims");
     };
-    if nonce_map.remove(&nonce).is_none() {
+    if store.take(OIDC_NONCE, &nonce.to_string()).await?.is_none() {
       bail!(INTERNAL_SERVER_ERROR, "Invalid nonce");
     }
 
-    Ok(())
+    Ok(claims)
+  }
+
+  /// Validate a back-channel logout token and return its claims.
+  async fn validate_logout_token(
+    &self,
+    token: &str,
+    store: &Store,
+  ) -> Result<HashMap<String, serde_json::Value>> {
+    let claims = self
+      .decode_claims(token)
+      .await
+      .status_context(StatusCode::BAD_REQUEST, "Invalid logout token")?;
+
+    let is_logout = claims
+      .get("events")
+      .and_then(|events| events.as_object())
+      .is_some_and(|events| events.contains_key(BACKCHANNEL_LOGOUT_EVENT));
+    // a nonce is forbidden so id tokens can not be used as logout tokens
+    if !is_logout || claims.contains_key("nonce") {
+      bail!(BAD_REQUEST, "Not a logout token");
+    }
+    if claim(&claims, "sub").is_none() && claim(&claims, "sid").is_none() {
+      bail!(BAD_REQUEST, "Logout token contains neither sub nor sid");
+    }
+
+    let Some(iat) = claims.get("iat").and_then(|iat| iat.as_i64()) else {
+      bail!(BAD_REQUEST, "Missing iat in logout token");
+    };
+    if chrono::Utc::now().timestamp() - iat > LOGOUT_TOKEN_TTL.as_secs() as i64 {
+      bail!(BAD_REQUEST, "Logout token expired");
+    }
+
+    let Some(jti) = claim(&claims, "jti") else {
+      bail!(BAD_REQUEST, "Missing jti in logout token");
+    };
+    // the counter is incremented atomically, only the first use sees 1
+    let key = format!("{}:{}", self.name, jti);
+    if store
+      .increment(OIDC_LOGOUT_JTI, &key, LOGOUT_TOKEN_TTL)
+      .await?
+      > 1
+    {
+      bail!(BAD_REQUEST, "Logout token has already been used");
+    }
+
+    Ok(claims)
+  }
+
+  fn end_session_url(&self, post_logout_redirect_uri: &Url) -> Option<Url> {
+    let mut url = self.end_session_endpoint.clone()?;
+    url
+      .query_pairs_mut()
+      .append_pair("client_id", &self.client_id)
+      .append_pair(
+        "post_logout_redirect_uri",
+        post_logout_redirect_uri.as_str(),
+      );
+    Some(url)
   }
 }
 
+fn claim<'c>(claims: &'c HashMap<String, serde_json::Value>, name: &str) -> Option<&'c str> {
+  claims.get(name).and_then(|value| value.as_str())
+}
+
 #[derive(Serialize)]
 #[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 struct OidcResponse {
@@ -338,14 +778,99 @@ struct OidcUrlQuery {
 async fn oidc_url(
   state: OidcState,
   jwt: JwtState,
+  cookies: CookieJar,
+  Query(OidcUrlQuery { redirect_to }): Query<OidcUrlQuery>,
+) -> Result<(CookieJar, Json<OidcResponse>)> {
+  start_auth(
+    &state,
+    &jwt,
+    cookies,
+    DEFAULT_PROVIDER,
+    redirect_to,
+    None,
+    None,
+  )
+  .await
+}
+
+async fn provider_url(
+  state: OidcState,
+  jwt: JwtState,
+  cookies: CookieJar,
+  Path(provider): Path<String>,
+  Query(OidcUrlQuery { redirect_to }): Query<OidcUrlQuery>,
+) -> Result<(CookieJar, Json<OidcResponse>)> {
+  start_auth(&state, &jwt, cookies, &provider, redirect_to, None, None).await
+}
+
+/// Like [`provider_url`], but the callback links the subject to the logged in
+/// user instead of logging in.
+async fn link_url(
+  auth: JwtAuth,
+  state: OidcState,
+  jwt: JwtState,
+  cookies: CookieJar,
+  Path(provider): Path<String>,
+  Query(OidcUrlQuery { redirect_to }): Query<OidcUrlQuery>,
+) -> Result<(CookieJar, Json<OidcResponse>)> {
+  start_auth(
+    &state,
+    &jwt,
+    cookies,
+    &provider,
+    redirect_to,
+    Some(auth.user_id),
+    None,
+  )
+  .await
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct InviteUrlQuery {
+  token: String,
+  redirect_to: Option<String>,
+}
+
+/// Like [`provider_url`], but the callback creates the invited user with the
+/// subject instead of looking up an existing one.
+async fn invite_url(
+  state: OidcState,
+  jwt: JwtState,
+  db: Connection,
+  invitations: InvitationState,
+  cookies: CookieJar,
+  Path(provider): Path<String>,
+  Query(InviteUrlQuery { token, redirect_to }): Query<InviteUrlQuery>,
+) -> Result<(CookieJar, Json<OidcResponse>)> {
+  let Some(invitation) = invitations.resolve(&db, &token).await? else {
+    bail!(NOT_FOUND, "Invitation not found or expired");
+  };
+
+  start_auth(
+    &state,
+    &jwt,
+    cookies,
+    &provider,
+    redirect_to,
+    None,
+    Some(invitation.id),
+  )
+  .await
+}
+
+async fn start_auth(
+  state: &OidcState,
+  jwt: &JwtState,
   mut cookies: CookieJar,
-  Query(OidcUrlQuery { mut redirect_to }): Query<OidcUrlQuery>,
+  provider: &str,
+  redirect_to: Option<String>,
+  link: Option<Uuid>,
+  invite: Option<Uuid>,
 ) -> Result<(CookieJar, Json<OidcResponse>)> {
-  let lock = state.config.lock().await;
-  let Some(config) = lock.clone() else {
+  let Some(config) = state.provider(provider).await else {
     bail!(BAD_REQUEST, "OIDC not configured");
   };
-  drop(lock);
 
   let state_id = Uuid::new_v4();
   let nonce = Uuid::new_v4();
@@ -383,37 +908,25 @@ async fn oidc_url(
     form.insert("scope", config.scope.join(" "));
   }
 
-  if let Some(redirect_to) = &mut redirect_to {
-    let dummy_base = Url::parse("http://localhost").unwrap();
-    match dummy_base.join(redirect_to) {
-      Ok(redirect_url) => {
-        if redirect_url.host_str() != Some("localhost") {
-          bail!("Invalid redirect url");
-        }
-
-        let path = redirect_url.path();
-        let query = redirect_url
-          .query()
-          .map(|q| format!("?{}", q))
-          .unwrap_or_default();
-        let fragment = redirect_url
-          .fragment()
-          .map(|f| format!("#{}", f))
-          .unwrap_or_default();
-        *redirect_to = format!("{}{}{}", path, query, fragment);
-      }
-      Err(_) => {
-        bail!("Invalid redirect url");
-      }
-    }
-  }
+  let redirect_to = redirect_to.as_deref().map(sanitize_redirect).transpose()?;
 
+  let pending = PendingAuth {
+    provider: config.name.clone(),
+    code_verifier,
+    redirect_to,
+    link,
+    invite,
+  };
   state
-    .state
-    .insert(state_id, (Instant::now(), code_verifier, redirect_to));
+    .store
+    .set_json(OIDC_STATE, &state_id.to_string(), &pending, STATE_TTL)
+    .await?;
   cookies = cookies.add(jwt.create_cookie(OIDC_STATE, state_id.to_string()));
 
-  state.nonce.insert(nonce, Instant::now());
+  state
+    .store
+    .set(OIDC_NONCE, &nonce.to_string(), String::new(), STATE_TTL)
+    .await?;
 
   let mut url = config.authorization_endpoint.clone();
   url.query_pairs_mut().extend_pairs(&form);
@@ -426,6 +939,28 @@ async fn oidc_url(
   ))
 }
 
+/// Reduce the target after the login to a same-origin ref (/path?query#frag).
+pub(crate) fn sanitize_redirect(redirect_to: &str) -> Result<String> {
+  let dummy_base = Url::parse("http://localhost").unwrap();
+  let Ok(redirect_url) = dummy_base.join(redirect_to) else {
+    bail!("Invalid redirect url");
+  };
+  if redirect_url.host_str() != Some("localhost") {
+    bail!("Invalid redirect url");
+  }
+
+  let path = redirect_url.path();
+  let query = redirect_url
+    .query()
+    .map(|q| format!("?{}", q))
+    .unwrap_or_default();
+  let fragment = redirect_url
+    .fragment()
+    .map(|f| format!("#{}", f))
+    .unwrap_or_default();
+  Ok(format!("{}{}{}", path, query, fragment))
+}
+
 #[derive(Deserialize, Clone)]
 #[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
 struct OidcCallbackQuery {
@@ -435,7 +970,7 @@ struct OidcCallbackQuery {
 }
 
 #[derive(Deserialize)]
-struct TokenRes {
+struct TokenEndpointRes {
   id_token: String,
 }
 
@@ -449,7 +984,34 @@ pub struct AuthInfo {
   pub extra: HashMap<String, serde_json::Value>,
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn oidc_callback<T: UpdateMessage>(
+  query: Query<OidcCallbackQuery>,
+  oidc_state: OidcState,
+  cookies: CookieJar,
+  db: Connection,
+  oidc_config: SiteConfig,
+  jwt: JwtState,
+  updater: Updater<T>,
+  client: ClientInfo,
+) -> Result<(CookieJar, Redirect)> {
+  provider_callback(
+    Path(DEFAULT_PROVIDER.to_string()),
+    query,
+    oidc_state,
+    cookies,
+    db,
+    oidc_config,
+    jwt,
+    updater,
+    client,
+  )
+  .await
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn provider_callback<T: UpdateMessage>(
+  Path(provider): Path<String>,
   Query(OidcCallbackQuery { code, state, error }): Query<OidcCallbackQuery>,
   oidc_state: OidcState,
   cookies: CookieJar,
@@ -457,29 +1019,37 @@ async fn oidc_callback<T: UpdateMessage>(
   oidc_config: SiteConfig,
   jwt: JwtState,
   updater: Updater<T>,
+  client: ClientInfo,
 ) -> Result<(CookieJar, Redirect)> {
   let (path, error, mut cookies) = check_code(
+    &provider,
     error,
     state,
     code,
     &db,
     cookies,
     &jwt,
-    &oidc_state.nonce,
     updater,
     &oidc_state,
+    &client,
   )
   .await?;
 
   cookies = cookies.remove(Cookie::from(OIDC_STATE));
 
+  Ok((cookies, login_redirect(oidc_config.site_url, &path, error)))
+}
+
+/// Redirect back to the site after an SSO login, `error` is appended to the
+/// query.
+pub(crate) fn login_redirect(site_url: Url, path: &str, error: Option<String>) -> Redirect {
   // `path` is a sanitized same-origin ref (/path?query#frag). Re-split it and
   // apply onto the trusted site_url via host-safe setters; set_path alone would
   // percent-encode the '?' and swallow the query/fragment.
-  let mut url = oidc_config.site_url;
+  let mut url = site_url;
   let target = Url::parse("http://localhost")
     .unwrap()
-    .join(&path)
+    .join(path)
     .unwrap_or_else(|_| Url::parse("http://localhost/").unwrap());
   url.set_path(target.path());
   url.set_fragment(target.fragment());
@@ -494,7 +1064,7 @@ async fn oidc_callback<T: UpdateMessage>(
   }
   url.set_query((!query.is_empty()).then_some(query.as_str()));
 
-  Ok((cookies, Redirect::found(url.to_string())))
+  Redirect::found(url.to_string())
 }
 
 #[derive(Deserialize)]
@@ -504,25 +1074,24 @@ struct TokenError {
 
 #[allow(clippy::too_many_arguments)]
 async fn check_code<T: UpdateMessage>(
+  provider: &str,
   error: Option<String>,
   state: Option<Uuid>,
   code: Option<String>,
   db: &Connection,
   mut cookies: CookieJar,
   jwt: &JwtState,
-  nonce_map: &DashMap<Uuid, Instant>,
   updater: Updater<T>,
   oidc_state: &OidcState,
+  client: &ClientInfo,
 ) -> Result<(String, Option<String>, CookieJar)> {
-  let lock = oidc_state.config.lock().await;
-  let Some(config) = lock.clone() else {
+  let Some(config) = oidc_state.provider(provider).await else {
     return Ok((
       "/login".to_string(),
       Some("oidc_not_configured".to_string()),
       cookies,
     ));
   };
-  drop(lock);
 
   if let Some(error) = error {
     return Ok(("/login".to_string(), Some(error), cookies));
@@ -536,7 +1105,17 @@ async fn check_code<T: UpdateMessage>(
     ));
   };
 
-  let Some((_, (_, code_verifier, redirect_to))) = oidc_state.state.remove(&state) else {
+  let Some(PendingAuth {
+    provider: pending_provider,
+    code_verifier,
+    redirect_to,
+    link,
+    invite,
+  }) = oidc_state
+    .store
+    .take_json(OIDC_STATE, &state.to_string())
+    .await?
+  else {
     return Ok((
       "/login".to_string(),
       Some("invalid_state".to_string()),
@@ -550,7 +1129,7 @@ async fn check_code<T: UpdateMessage>(
       cookies,
     ));
   };
-  if cookie.value() != state.to_string() {
+  if cookie.value() != state.to_string() || pending_provider != config.name {
     return Ok((
       "/login".to_string(),
       Some("invalid_state".to_string()),
@@ -596,8 +1175,11 @@ async fn check_code<T: UpdateMessage>(
     return Ok(("/login".to_string(), Some(error.error), cookies));
   }
 
-  let res: TokenRes = res.json().await?;
-  config.validate_jwk(&res.id_token, nonce_map).await?;
+  let res: TokenEndpointRes = res.json().await?;
+  let claims = config
+    .validate_jwk(&res.id_token, &oidc_state.store)
+    .await?;
+  let oidc_sid = claim(&claims, "sid").map(str::to_string);
   let token = res.id_token.clone();
 
   let req = config
@@ -620,11 +1202,81 @@ async fn check_code<T: UpdateMessage>(
 
   let redirect_to = redirect_to.unwrap_or("/".to_string());
 
-  if let Some(user) = db.user().resolve_oidc_user(&res.sub, &res.email).await? {
+  if let Some(invite) = invite {
+    let invitation = db
+      .invitation()
+      .get_invitation(invite)
+      .await?
+      .filter(|invitation| invitation.exp > Utc::now().naive_utc());
+    let Some(invitation) = invitation else {
+      return Ok((
+        "/login".to_string(),
+        Some("invalid_invitation".to_string()),
+        cookies,
+      ));
+    };
+    if subject_user(db, &config, &res.sub).await?.is_some() {
+      return Ok((
+        "/login".to_string(),
+        Some("identity_in_use".to_string()),
+        cookies,
+      ));
+    }
+    if db
+      .user()
+      .try_get_user_by_email(&invitation.email)
+      .await?
+      .is_some()
+    {
+      return Ok((
+        "/login".to_string(),
+        Some("user_exists".to_string()),
+        cookies,
+      ));
+    }
+
+    let is_default = config.name == DEFAULT_PROVIDER;
+    let audit = Audit::from_client(client);
+    let user = accept_invitation(
+      db,
+      &audit,
+      &updater,
+      invitation,
+      String::new(),
+      is_default.then(|| res.sub.clone()),
+    )
+    .await?;
+    if !is_default {
+      db.oidc_identity()
+        .link(user, &config.name, &res.sub)
+        .await?;
+    }
+    sync_oidc_user(user, &res, &config, db, token, updater).await?;
+
+    debug!("Accepted invitation through OIDC, created user: {}", user);
+    cookies = jwt
+      .create_oidc_login(db, cookies, user, client, config.name, oidc_sid)
+      .await?;
+
+    return Ok((redirect_to, None, cookies));
+  }
+
+  if let Some(user) = link {
+    if let Some(error) = link_identity(db, &config, user, &res.sub).await? {
+      return Ok((redirect_to, Some(error.to_string()), cookies));
+    }
+    debug!("Linked OIDC provider {} to user: {}", config.name, user);
+
+    return Ok((redirect_to, None, cookies));
+  }
+
+  if let Some(user) = resolve_user(db, &config, &res).await? {
     sync_oidc_user(user.id, &res, &config, db, token, updater).await?;
 
     debug!("OIDC user authenticated: {}", user.id);
-    cookies = cookies.add(jwt.create_token(user.id)?);
+    cookies = jwt
+      .create_oidc_login(db, cookies, user.id, client, config.name, oidc_sid)
+      .await?;
 
     return Ok((redirect_to, None, cookies));
   }
@@ -637,6 +1289,7 @@ async fn check_code<T: UpdateMessage>(
     ));
   }
 
+  let is_default = config.name == DEFAULT_PROVIDER;
   let user = db
     .user()
     .create_user(
@@ -645,9 +1298,14 @@ async fn check_code<T: UpdateMessage>(
       String::new(),
       SaltString::generate(OsRng {}).to_string(),
       true,
-      Some(res.sub.clone()),
+      is_default.then(|| res.sub.clone()),
     )
     .await?;
+  if !is_default {
+    db.oidc_identity()
+      .link(user, &config.name, &res.sub)
+      .await?;
+  }
   sync_oidc_user(user, &res, &config, db, token, updater).await?;
 
   if !db.setup().is_setup().await? || db.user().count_users().await? == 1 {
@@ -672,11 +1330,119 @@ async fn check_code<T: UpdateMessage>(
   }
 
   debug!("OIDC user authenticated: {}", user);
-  cookies = cookies.add(jwt.create_token(user)?);
+  cookies = jwt
+    .create_oidc_login(db, cookies, user, client, config.name, oidc_sid)
+    .await?;
 
   Ok((redirect_to, None, cookies))
 }
 
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct BackchannelLogout {
+  logout_token: String,
+}
+
+/// Back-channel logout, the provider posts a logout token to end the sessions
+/// of its session `sid` or, without one, of the subject.
+async fn backchannel_logout(
+  Path(provider): Path<String>,
+  state: OidcState,
+  db: Connection,
+  Form(BackchannelLogout { logout_token }): Form<BackchannelLogout>,
+) -> Result<()> {
+  let Some(config) = state.provider(&provider).await else {
+    bail!(BAD_REQUEST, "OIDC not configured");
+  };
+  let claims = config
+    .validate_logout_token(&logout_token, &state.store)
+    .await?;
+
+  let user = match claim(&claims, "sub") {
+    Some(sub) => subject_user(&db, &config, sub).await?,
+    None => None,
+  };
+  let revoked = db
+    .session()
+    .revoke_oidc_sessions(&config.name, user, claim(&claims, "sid"))
+    .await?;
+  debug!(
+    "Back-channel logout of provider {} revoked {} sessions",
+    config.name, revoked
+  );
+
+  Ok(())
+}
+
+async fn subject_user(db: &Connection, config: &OidcConfig, subject: &str) -> Result<Option<Uuid>> {
+  if config.name == DEFAULT_PROVIDER {
+    return Ok(
+      db.user()
+        .try_get_user_by_oidc_subject(subject)
+        .await?
+        .map(|user| user.id),
+    );
+  }
+
+  Ok(
+    db.oidc_identity()
+      .get_identity(&config.name, subject)
+      .await?
+      .map(|identity| identity.user_id),
+  )
+}
+
+/// The subject at the default provider is stored with the user, the ones at
+/// named providers in the identity table.
+async fn resolve_user(
+  db: &Connection,
+  config: &OidcConfig,
+  auth: &AuthInfo,
+) -> Result<Option<crate::db::entities::user::Model>> {
+  if config.name == DEFAULT_PROVIDER {
+    return db.user().resolve_oidc_user(&auth.sub, &auth.email).await;
+  }
+
+  db.oidc_identity()
+    .resolve_user(&config.name, &auth.sub, &auth.email, config.link_by_email)
+    .await
+}
+
+/// Returns the error for the redirect if the subject can not be linked.
+async fn link_identity(
+  db: &Connection,
+  config: &OidcConfig,
+  user: Uuid,
+  subject: &str,
+) -> Result<Option<&'static str>> {
+  if config.name == DEFAULT_PROVIDER {
+    if let Some(other) = db.user().try_get_user_by_oidc_subject(subject).await? {
+      return Ok((other.id != user).then_some("identity_in_use"));
+    }
+    if db.user().get_user_by_id(user).await?.oidc_subject.is_some() {
+      return Ok(Some("provider_already_linked"));
+    }
+    db.user()
+      .set_oidc_subject(user, subject.to_string())
+      .await?;
+    return Ok(None);
+  }
+
+  if let Some(identity) = db
+    .oidc_identity()
+    .get_identity(&config.name, subject)
+    .await?
+  {
+    return Ok((identity.user_id != user).then_some("identity_in_use"));
+  }
+  if db.oidc_identity().has_identity(user, &config.name).await? {
+    return Ok(Some("provider_already_linked"));
+  }
+  db.oidc_identity().link(user, &config.name, subject).await?;
+
+  Ok(None)
+}
+
 impl AuthInfo {
   pub fn groups(&self, group_claim: &str) -> Vec<String> {
     if let Some(groups) = self.extra.get(group_claim) {
@@ -738,8 +1504,18 @@ async fn sync_groups<T: UpdateMessage>(
     return Ok(());
   }
 
-  let groups = auth.groups(&config.group_claim);
-  let mut group_ids = db.group().group_ids(&groups).await?;
+  sync_user_groups(user, &auth.groups(&config.group_claim), db, updater).await
+}
+
+/// Replace the groups of the user with the ones named by the identity
+/// provider. The last admin always keeps the admin group.
+pub(crate) async fn sync_user_groups<T: UpdateMessage>(
+  user: Uuid,
+  groups: &[String],
+  db: &Connection,
+  updater: Updater<T>,
+) -> Result<()> {
+  let mut group_ids = db.group().group_ids(groups).await?;
 
   let Some(admin_group) = db.setup().get_admin_group_id().await? else {
     return Ok(());
@@ -792,6 +1568,7 @@ mod tests {
   use super::*;
   use crate::backend::auth::settings::AuthConfig;
   use crate::backend::endpoints::websocket::state::{UpdateMessage, UpdateState, Updater};
+  use crate::backend::store::Store;
   use crate::db::{config::DBConfig, init::connect_db, migrations::Migrator};
   use axum::response::IntoResponse;
   use axum::routing::{get, post};
@@ -799,7 +1576,10 @@ mod tests {
   use sea_orm_migration::MigratorTrait;
   use serde::{Deserialize, Serialize};
   use serde_json::json;
-  use std::sync::{Arc, Mutex};
+  use std::sync::{
+    Arc, Mutex,
+    atomic::{AtomicUsize, Ordering},
+  };
 
   #[derive(Serialize, Deserialize, Clone, Debug)]
   enum Msg {
@@ -863,6 +1643,8 @@ mod tests {
 
   fn settings(issuer: &str, pkce: bool) -> OidcSettings {
     OidcSettings {
+      name: DEFAULT_PROVIDER.into(),
+      display_name: None,
       issuer: Url::parse(issuer).unwrap(),
       client_id: "client".into(),
       client_secret: "secret".into(),
@@ -872,6 +1654,7 @@ mod tests {
       pkce,
       image_sync: false,
       create_user: false,
+      link_by_email: true,
     }
   }
 
@@ -881,13 +1664,32 @@ mod tests {
     conn
   }
 
+  /// State id and nonce from the authorize URL.
+  fn auth_params(resp: &OidcResponse) -> (Uuid, Uuid) {
+    let url = Url::parse(&resp.url).unwrap();
+    let q: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    (q["state"].parse().unwrap(), q["nonce"].parse().unwrap())
+  }
+
+  async fn pending(state: &OidcState, state_id: Uuid) -> Option<PendingAuth> {
+    state
+      .store
+      .get_json(OIDC_STATE, &state_id.to_string())
+      .await
+      .unwrap()
+  }
+
   struct SigningIdp {
     base: String,
     token_slot: Arc<Mutex<String>>,
     enc_key: jsonwebtoken::EncodingKey,
+    /// Served at the jwks endpoint, replace it to rotate the keys.
+    jwks: Arc<Mutex<serde_json::Value>>,
+    jwks_fetches: Arc<AtomicUsize>,
   }
 
-  async fn signing_idp(userinfo: serde_json::Value) -> SigningIdp {
+  /// A new RSA key with the `kid` and its JWK.
+  fn rsa_key(kid: &str) -> (jsonwebtoken::EncodingKey, serde_json::Value) {
     use jsonwebtoken::EncodingKey;
     use rsa::pkcs1::EncodeRsaPrivateKey;
     use rsa::pkcs8::LineEnding;
@@ -900,12 +1702,21 @@ mod tests {
     let e = BASE64_URL_SAFE_NO_PAD.encode(pub_key.e().to_bytes_be());
     let enc_key =
       EncodingKey::from_rsa_pem(priv_key.to_pkcs1_pem(LineEnding::LF).unwrap().as_bytes()).unwrap();
+    let jwk = json!({"kty":"RSA","kid":kid,"alg":"RS256","use":"sig","n":n,"e":e});
+    (enc_key, jwk)
+  }
+
+  async fn signing_idp(userinfo: serde_json::Value) -> SigningIdp {
+    let (enc_key, jwk) = rsa_key("test");
     let token_slot = Arc::new(Mutex::new(String::new()));
+    let jwks = Arc::new(Mutex::new(json!({ "keys": [jwk] })));
+    let jwks_fetches = Arc::new(AtomicUsize::new(0));
 
     let listener = tokio::net::TcpListener::bind("127.0.0.1:0").await.unwrap();
     let base = format!("http://127.0.0.1:{}", listener.local_addr().unwrap().port());
     let disco_base = base.clone();
-    let jwk = json!({"keys":[{"kty":"RSA","kid":"test","alg":"RS256","use":"sig","n":n,"e":e}]});
+    let served_jwks = jwks.clone();
+    let fetches = jwks_fetches.clone();
     let slot = token_slot.clone();
     let app = axum::Router::new()
       .route(
@@ -919,6 +1730,7 @@ mod tests {
               "token_endpoint": format!("{base}/token"),
               "userinfo_endpoint": format!("{base}/userinfo"),
               "jwks_uri": format!("{base}/jwks"),
+              "end_session_endpoint": format!("{base}/logout"),
             }))
           }
         }),
@@ -926,8 +1738,9 @@ mod tests {
       .route(
         "/jwks",
         get(move || {
-          let jwk = jwk.clone();
-          async move { axum::Json(jwk) }
+          fetches.fetch_add(1, Ordering::SeqCst);
+          let jwks = served_jwks.lock().unwrap().clone();
+          async move { ([(CACHE_CONTROL, "public, max-age=600")], axum::Json(jwks)) }
         }),
       )
       .route(
@@ -961,6 +1774,8 @@ mod tests {
       base,
       token_slot,
       enc_key,
+      jwks,
+      jwks_fetches,
     }
   }
 
@@ -974,6 +1789,8 @@ mod tests {
     use jsonwebtoken::{Algorithm, Header, encode};
 
     let oidc_settings = OidcSettings {
+      name: DEFAULT_PROVIDER.into(),
+      display_name: None,
       issuer: Url::parse(&idp.base).unwrap(),
       client_id: "client".into(),
       client_secret: "secret".into(),
@@ -983,12 +1800,13 @@ mod tests {
       pkce: false,
       image_sync: false,
       create_user,
+      link_by_email: true,
     };
 
-    let state = OidcState::new(conn, None).await;
+    let state = OidcState::new(conn, None, Store::memory()).await;
     state.try_init(&oidc_settings).await.unwrap();
     let jwt = JwtState::init(&AuthConfig::default(), conn).await;
-    let (cookies, _) = oidc_url(
+    let (cookies, axum::Json(resp)) = oidc_url(
       state.clone(),
       jwt.clone(),
       CookieJar::new(),
@@ -998,8 +1816,7 @@ mod tests {
     )
     .await
     .unwrap();
-    let state_id = *state.state.iter().next().unwrap().key();
-    let nonce = *state.nonce.iter().next().unwrap().key();
+    let (state_id, nonce) = auth_params(&resp);
 
     let exp = chrono::Utc::now().timestamp() + 3600;
     let claims = json!({
@@ -1013,7 +1830,7 @@ mod tests {
     header.kid = Some("test".into());
     *idp.token_slot.lock().unwrap() = encode(&header, &claims, &idp.enc_key).unwrap();
 
-    let updater: Updater<Msg> = UpdateState::<Msg>::init().await.1;
+    let updater: Updater<Msg> = UpdateState::<Msg>::init(&Store::memory()).await.unwrap().1;
     let out = oidc_callback::<Msg>(
       axum::extract::Query(OidcCallbackQuery {
         code: Some("auth-code".into()),
@@ -1026,6 +1843,90 @@ mod tests {
       SiteConfig::default(),
       jwt,
       updater,
+      ClientInfo::default(),
+    )
+    .await
+    .unwrap();
+
+    out
+      .into_response()
+      .headers()
+      .get(LOCATION)
+      .unwrap()
+      .to_str()
+      .unwrap()
+      .to_string()
+  }
+
+  fn named_settings(idp: &SigningIdp, name: &str) -> OidcSettings {
+    OidcSettings {
+      name: name.into(),
+      display_name: Some("Corp".into()),
+      pkce: false,
+      create_user: true,
+      link_by_email: false,
+      ..settings(&idp.base, false)
+    }
+  }
+
+  /// Runs the flow of a named provider, linking the subject to `link` if set.
+  async fn run_provider_callback(
+    conn: &Connection,
+    state: &OidcState,
+    idp: &SigningIdp,
+    provider: &str,
+    id_token_sub: &str,
+    link: Option<Uuid>,
+  ) -> String {
+    run_provider_flow(conn, state, idp, provider, id_token_sub, link, None).await
+  }
+
+  /// Like [`run_provider_callback`], accepting the invitation `invite` if set.
+  async fn run_provider_flow(
+    conn: &Connection,
+    state: &OidcState,
+    idp: &SigningIdp,
+    provider: &str,
+    id_token_sub: &str,
+    link: Option<Uuid>,
+    invite: Option<Uuid>,
+  ) -> String {
+    use jsonwebtoken::{Algorithm, Header, encode};
+
+    let jwt = JwtState::init(&AuthConfig::default(), conn).await;
+    let (cookies, axum::Json(resp)) =
+      start_auth(state, &jwt, CookieJar::new(), provider, None, link, invite)
+        .await
+        .unwrap();
+    let (state_id, nonce) = auth_params(&resp);
+
+    let claims = json!({
+      "iss": idp.base,
+      "aud": "client",
+      "sub": id_token_sub,
+      "sid": "idp-sid",
+      "nonce": nonce.to_string(),
+      "exp": chrono::Utc::now().timestamp() + 3600,
+    });
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("test".into());
+    *idp.token_slot.lock().unwrap() = encode(&header, &claims, &idp.enc_key).unwrap();
+
+    let updater: Updater<Msg> = UpdateState::<Msg>::init(&Store::memory()).await.unwrap().1;
+    let out = provider_callback::<Msg>(
+      Path(provider.to_string()),
+      Query(OidcCallbackQuery {
+        code: Some("auth-code".into()),
+        state: Some(state_id),
+        error: None,
+      }),
+      state.clone(),
+      cookies,
+      conn.clone(),
+      SiteConfig::default(),
+      jwt,
+      updater,
+      ClientInfo::default(),
     )
     .await
     .unwrap();
@@ -1040,11 +1941,54 @@ mod tests {
       .to_string()
   }
 
+  fn logout_token(idp: &SigningIdp, claims: serde_json::Value) -> String {
+    use jsonwebtoken::{Algorithm, Header, encode};
+
+    let mut token = json!({
+      "iss": idp.base,
+      "aud": "client",
+      "iat": chrono::Utc::now().timestamp(),
+      "exp": chrono::Utc::now().timestamp() + 120,
+      "jti": Uuid::new_v4().to_string(),
+      "events": {BACKCHANNEL_LOGOUT_EVENT: {}},
+    });
+    token
+      .as_object_mut()
+      .unwrap()
+      .extend(claims.as_object().unwrap().clone());
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("test".into());
+    encode(&header, &token, &idp.enc_key).unwrap()
+  }
+
+  /// Logs in with the named provider and returns the user and its session.
+  async fn provider_session(
+    conn: &Connection,
+    state: &OidcState,
+    idp: &SigningIdp,
+  ) -> (Uuid, Uuid) {
+    let group = conn.group().create_group("Admin".into()).await.unwrap();
+    conn.setup().set_admin_group_created(group).await.unwrap();
+    state.try_init(&named_settings(idp, "corp")).await.unwrap();
+
+    let loc = run_provider_callback(conn, state, idp, "corp", "corp-1", None).await;
+    assert!(!loc.contains("error="), "unexpected error redirect: {loc}");
+    let user = conn
+      .oidc_identity()
+      .get_identity("corp", "corp-1")
+      .await
+      .unwrap()
+      .unwrap()
+      .user_id;
+    let session = conn.session().list_user_sessions(user, None).await.unwrap()[0].uuid;
+    (user, session)
+  }
+
   #[tokio::test]
   async fn test_try_init_is_enabled_deactivate() {
     let base = mock_idp().await;
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
 
     assert!(!state.is_enabled().await);
     state.try_init(&settings(&base, true)).await.unwrap();
@@ -1056,7 +2000,7 @@ mod tests {
   #[tokio::test]
   async fn test_try_init_unreachable_issuer_errors() {
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     // Nothing listens on port 9 ⇒ discovery fails ⇒ state stays disabled.
     assert!(
       state
@@ -1065,13 +2009,120 @@ mod tests {
         .is_err()
     );
     assert!(!state.is_enabled().await);
+
+    let status = state.status().await;
+    assert_eq!(status.len(), 1);
+    assert_eq!(status[0].name, DEFAULT_PROVIDER);
+    assert!(!status[0].healthy);
+    assert!(status[0].last_error.is_some());
+  }
+
+  #[test]
+  fn test_cache_ttl_honors_headers() {
+    let headers = |name, value: &str| {
+      let mut headers = HeaderMap::new();
+      headers.insert(name, value.parse().unwrap());
+      headers
+    };
+
+    assert_eq!(cache_ttl(&HeaderMap::new()), JWKS_DEFAULT_TTL);
+    assert_eq!(
+      cache_ttl(&headers(CACHE_CONTROL, "public, max-age=600")),
+      Duration::from_secs(600)
+    );
+    // The bounds protect the provider and keep rotated keys from being used
+    // for too long.
+    assert_eq!(
+      cache_ttl(&headers(CACHE_CONTROL, "no-store, max-age=600")),
+      JWKS_MIN_TTL
+    );
+    assert_eq!(
+      cache_ttl(&headers(CACHE_CONTROL, "max-age=31536000")),
+      JWKS_MAX_TTL
+    );
+
+    let expires = (Utc::now() + chrono::Duration::seconds(7200)).to_rfc2822();
+    let ttl = cache_ttl(&headers(EXPIRES, &expires));
+    assert!(ttl > Duration::from_secs(7100) && ttl <= Duration::from_secs(7200));
+  }
+
+  #[tokio::test]
+  async fn test_unknown_kid_refetches_rotated_keys() {
+    use jsonwebtoken::{Algorithm, Header, encode};
+
+    let conn = db().await;
+    let idp = signing_idp(json!({})).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
+    state.try_init(&named_settings(&idp, "corp")).await.unwrap();
+    assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 1);
+
+    let (rotated_key, rotated_jwk) = rsa_key("rotated");
+    idp.jwks.lock().unwrap()["keys"]
+      .as_array_mut()
+      .unwrap()
+      .push(rotated_jwk);
+    let mut header = Header::new(Algorithm::RS256);
+    header.kid = Some("rotated".into());
+    let token = encode(
+      &header,
+      &json!({
+        "iss": idp.base,
+        "aud": "client",
+        "sub": "corp-1",
+        "exp": Utc::now().timestamp() + 3600,
+      }),
+      &rotated_key,
+    )
+    .unwrap();
+
+    let config = state.provider("corp").await.unwrap();
+    // The keys were just fetched, so the unknown kid does not trigger a fetch.
+    assert!(config.decode_claims(&token).await.is_err());
+    assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 1);
+
+    config.connection.lock().await.jwks_fetched -= JWKS_REFETCH_INTERVAL;
+    let claims = config.decode_claims(&token).await.unwrap();
+    assert_eq!(claim(&claims, "sub"), Some("corp-1"));
+    assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 2);
+
+    // Known keys are served from the cache.
+    config.decode_claims(&token).await.unwrap();
+    assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 2);
+    assert_eq!(state.status().await[0].keys, 2);
+  }
+
+  #[tokio::test]
+  async fn test_refresh_fetches_expired_keys() {
+    let conn = db().await;
+    let idp = signing_idp(json!({})).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
+    state.try_init(&named_settings(&idp, "corp")).await.unwrap();
+
+    // Keys that did not expire yet are kept.
+    state.refresh().await;
+    assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 1);
+
+    idp.jwks.lock().unwrap()["keys"]
+      .as_array_mut()
+      .unwrap()
+      .push(rsa_key("rotated").1);
+    let config = state.provider("corp").await.unwrap();
+    config.connection.lock().await.jwks_expires = Instant::now();
+    state.refresh().await;
+    assert_eq!(idp.jwks_fetches.load(Ordering::SeqCst), 2);
+
+    let status = state.status().await;
+    assert_eq!(status.len(), 1);
+    assert!(status[0].healthy);
+    assert_eq!(status[0].keys, 2);
+    assert!(status[0].last_refresh.is_some());
   }
 
   #[tokio::test]
   async fn test_oidc_url_builds_redirect_with_pkce() {
     let base = mock_idp().await;
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     state.try_init(&settings(&base, true)).await.unwrap();
 
     let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
@@ -1091,10 +2142,7 @@ mod tests {
     let q: HashMap<_, _> = url.query_pairs().into_owned().collect();
     assert_eq!(q.get("response_type").map(String::as_str), Some("code"));
     assert_eq!(q.get("client_id").map(String::as_str), Some("client"));
-    let state_id = *state.state.iter().next().unwrap().key();
-    let nonce = *state.nonce.iter().next().unwrap().key();
-    assert_eq!(q.get("state"), Some(&state_id.to_string()));
-    assert_eq!(q.get("nonce"), Some(&nonce.to_string()));
+    let (state_id, nonce) = auth_params(&resp);
     // pkce=true ⇒ challenge params present.
     assert!(q.contains_key("code_challenge"));
     assert_eq!(
@@ -1104,15 +2152,22 @@ mod tests {
     assert_eq!(q.get("scope").map(String::as_str), Some("openid profile"));
     assert!(cookies.get(OIDC_STATE).is_some());
     // A pending state + nonce were registered for the later callback.
-    assert_eq!(state.state.len(), 1);
-    assert_eq!(state.nonce.len(), 1);
+    assert!(pending(&state, state_id).await.is_some());
+    assert!(
+      state
+        .store
+        .get(OIDC_NONCE, &nonce.to_string())
+        .await
+        .unwrap()
+        .is_some()
+    );
   }
 
   #[tokio::test]
   async fn test_oidc_url_no_pkce_omits_challenge() {
     let base = mock_idp().await;
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     state.try_init(&settings(&base, false)).await.unwrap();
 
     let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
@@ -1130,13 +2185,15 @@ mod tests {
     assert!(!q.contains_key("code_challenge"));
     assert!(!q.contains_key("code_challenge_method"));
     // The stored verifier is None when pkce is disabled.
-    assert!(state.state.iter().next().unwrap().value().1.is_none());
+    let (state_id, _) = auth_params(&resp);
+    let pending = pending(&state, state_id).await.unwrap();
+    assert!(pending.code_verifier.is_none());
   }
 
   #[tokio::test]
   async fn test_oidc_url_unconfigured_is_bad_request() {
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
     let err = match oidc_url(
       state,
@@ -1157,11 +2214,11 @@ mod tests {
   async fn sanitize_redirect(redirect_to: Option<&str>) -> Result<Option<String>> {
     let base = mock_idp().await;
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     state.try_init(&settings(&base, true)).await.unwrap();
     let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
 
-    let _ = oidc_url(
+    let (_, axum::Json(resp)) = oidc_url(
       state.clone(),
       jwt,
       CookieJar::new(),
@@ -1171,7 +2228,8 @@ mod tests {
     )
     .await?;
 
-    Ok(state.state.iter().next().unwrap().value().2.clone())
+    let (state_id, _) = auth_params(&resp);
+    Ok(pending(&state, state_id).await.unwrap().redirect_to)
   }
 
   #[tokio::test]
@@ -1276,7 +2334,7 @@ mod tests {
     conn: &Connection,
   ) -> String {
     let jwt = JwtState::init(&AuthConfig::default(), conn).await;
-    let updater: Updater<Msg> = UpdateState::<Msg>::init().await.1;
+    let updater: Updater<Msg> = UpdateState::<Msg>::init(&Store::memory()).await.unwrap().1;
     let out = oidc_callback::<Msg>(
       axum::extract::Query(query),
       state,
@@ -1285,6 +2343,7 @@ mod tests {
       SiteConfig::default(),
       jwt,
       updater,
+      ClientInfo::default(),
     )
     .await
     .unwrap();
@@ -1301,7 +2360,7 @@ mod tests {
   #[tokio::test]
   async fn test_callback_unconfigured_redirects_with_error() {
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     let loc = callback_location(
       state,
       OidcCallbackQuery {
@@ -1320,7 +2379,7 @@ mod tests {
   async fn test_callback_provider_error_is_propagated() {
     let base = mock_idp().await;
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     state.try_init(&settings(&base, false)).await.unwrap();
 
     let loc = callback_location(
@@ -1341,7 +2400,7 @@ mod tests {
   async fn test_callback_missing_state_is_invalid() {
     let base = mock_idp().await;
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     state.try_init(&settings(&base, false)).await.unwrap();
 
     let loc = callback_location(
@@ -1443,6 +2502,8 @@ mod tests {
     conn.setup().set_admin_group_created(group).await.unwrap();
 
     let oidc_settings = OidcSettings {
+      name: DEFAULT_PROVIDER.into(),
+      display_name: None,
       issuer: Url::parse(&base).unwrap(),
       client_id: "client".into(),
       client_secret: "secret".into(),
@@ -1452,14 +2513,15 @@ mod tests {
       pkce: false,
       image_sync: false,
       create_user: true,
+      link_by_email: true,
     };
 
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     state.try_init(&oidc_settings).await.unwrap();
     let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
 
     // 1. Begin the flow: registers a state + nonce and sets the state cookie.
-    let (cookies, _) = oidc_url(
+    let (cookies, axum::Json(resp)) = oidc_url(
       state.clone(),
       jwt.clone(),
       CookieJar::new(),
@@ -1467,8 +2529,7 @@ mod tests {
     )
     .await
     .unwrap();
-    let state_id = *state.state.iter().next().unwrap().key();
-    let nonce = *state.nonce.iter().next().unwrap().key();
+    let (state_id, nonce) = auth_params(&resp);
 
     // 2. Mint an id_token carrying the matching nonce, signed by the IdP key.
     let exp = chrono::Utc::now().timestamp() + 3600;
@@ -1484,7 +2545,7 @@ mod tests {
     *token_slot.lock().unwrap() = encode(&header, &claims, &enc_key).unwrap();
 
     // 3. Complete the callback with the matching code + state + cookie.
-    let updater: Updater<Msg> = UpdateState::<Msg>::init().await.1;
+    let updater: Updater<Msg> = UpdateState::<Msg>::init(&Store::memory()).await.unwrap().1;
     let out = oidc_callback::<Msg>(
       axum::extract::Query(OidcCallbackQuery {
         code: Some("auth-code".into()),
@@ -1497,6 +2558,7 @@ mod tests {
       SiteConfig::default(),
       jwt,
       updater,
+      ClientInfo::default(),
     )
     .await
     .unwrap();
@@ -1838,10 +2900,10 @@ mod tests {
     });
 
     let conn = db().await;
-    let state = OidcState::new(&conn, None).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
     state.try_init(&settings(&base, false)).await.unwrap();
     let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
-    let (cookies, _) = oidc_url(
+    let (cookies, axum::Json(resp)) = oidc_url(
       state.clone(),
       jwt.clone(),
       CookieJar::new(),
@@ -1849,7 +2911,7 @@ mod tests {
     )
     .await
     .unwrap();
-    let state_id = *state.state.iter().next().unwrap().key();
+    let (state_id, _) = auth_params(&resp);
 
     let loc = callback_location(
       state,
@@ -1865,4 +2927,263 @@ mod tests {
     // The provider's structured error is surfaced to the login page.
     assert!(loc.contains("error=bad_grant"), "got {loc}");
   }
+
+  #[tokio::test]
+  async fn test_configure_lists_named_providers() {
+    let idp = signing_idp(json!({"sub": "s", "email": "e@example.com"})).await;
+    let conn = db().await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
+
+    state
+      .configure(&[settings(&idp.base, false), named_settings(&idp, "corp")])
+      .await
+      .unwrap();
+    let names: Vec<_> = state
+      .providers()
+      .await
+      .into_iter()
+      .map(|p| p.name)
+      .collect();
+    assert_eq!(
+      names,
+      vec![DEFAULT_PROVIDER.to_string(), "corp".to_string()]
+    );
+
+    // A provider that can not be initialized keeps the current ones.
+    let mut broken = settings("http://127.0.0.1:9", false);
+    broken.name = "broken".into();
+    assert!(state.configure(&[broken]).await.is_err());
+    assert_eq!(state.providers().await.len(), 2);
+  }
+
+  #[tokio::test]
+  async fn test_named_provider_creates_user_with_identity() {
+    let conn = db().await;
+    let group = conn.group().create_group("Admin".into()).await.unwrap();
+    conn.setup().set_admin_group_created(group).await.unwrap();
+    let idp = signing_idp(json!({
+      "sub": "corp-1",
+      "email": "corp@example.com",
+      "name": "Corp User"
+    }))
+    .await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
+    state.try_init(&named_settings(&idp, "corp")).await.unwrap();
+
+    let loc = run_provider_callback(&conn, &state, &idp, "corp", "corp-1", None).await;
+    assert!(!loc.contains("error="), "unexpected error redirect: {loc}");
+
+    let user = conn
+      .oidc_identity()
+      .resolve_user("corp", "corp-1", "", false)
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(user.email, "corp@example.com");
+    // The default provider subject stays free.
+    assert_eq!(user.oidc_subject, None);
+
+    // The state of one provider can not be redeemed at another one.
+    let mut other = named_settings(&idp, "other");
+    other.create_user = false;
+    state.try_init(&other).await.unwrap();
+    let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
+    let (cookies, axum::Json(resp)) =
+      start_auth(&state, &jwt, CookieJar::new(), "corp", None, None, None)
+        .await
+        .unwrap();
+    let (state_id, _) = auth_params(&resp);
+    let (_, error, _) = check_code::<Msg>(
+      "other",
+      None,
+      Some(state_id),
+      Some("auth-code".into()),
+      &conn,
+      cookies,
+      &jwt,
+      UpdateState::<Msg>::init(&Store::memory()).await.unwrap().1,
+      &state,
+      &ClientInfo::default(),
+    )
+    .await
+    .unwrap();
+    assert_eq!(error.as_deref(), Some("invalid_state"));
+  }
+
+  #[tokio::test]
+  async fn test_link_adds_identity_to_logged_in_user() {
+    let conn = db().await;
+    let user = conn
+      .user()
+      .create_user(
+        "local".into(),
+        "local@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    let idp = signing_idp(json!({
+      "sub": "corp-1",
+      "email": "someone-else@example.com",
+      "name": "Corp User"
+    }))
+    .await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
+    state.try_init(&named_settings(&idp, "corp")).await.unwrap();
+
+    let loc = run_provider_callback(&conn, &state, &idp, "corp", "corp-1", Some(user)).await;
+    assert!(!loc.contains("error="), "unexpected error redirect: {loc}");
+    assert!(
+      conn
+        .oidc_identity()
+        .has_identity(user, "corp")
+        .await
+        .unwrap()
+    );
+    // Linking does not change the profile of the user.
+    let model = conn.user().get_user_by_id(user).await.unwrap();
+    assert_eq!(model.email, "local@example.com");
+
+    // The subject can not be linked to a second user.
+    let other = conn
+      .user()
+      .create_user(
+        "other".into(),
+        "other@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    let loc = run_provider_callback(&conn, &state, &idp, "corp", "corp-1", Some(other)).await;
+    assert!(loc.contains("error=identity_in_use"), "{loc}");
+  }
+
+  #[tokio::test]
+  async fn test_invite_creates_user_with_invited_groups() {
+    let conn = db().await;
+    let idp = signing_idp(json!({
+      "sub": "corp-1",
+      "email": "invitee@corp.example",
+      "name": "Corp User"
+    }))
+    .await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
+    state
+      .try_init(&OidcSettings {
+        create_user: false,
+        ..named_settings(&idp, "corp")
+      })
+      .await
+      .unwrap();
+    let group = conn.group().create_group("staff".into()).await.unwrap();
+    let invite = conn
+      .invitation()
+      .create_invitation(
+        "Invitee".into(),
+        "invitee@example.com".into(),
+        "hash".into(),
+        vec![group],
+        Utc::now() + chrono::Duration::hours(1),
+      )
+      .await
+      .unwrap();
+
+    // Users can be created through an invitation even if the provider may not
+    // create them on its own.
+    let loc = run_provider_flow(&conn, &state, &idp, "corp", "corp-1", None, Some(invite)).await;
+    assert!(!loc.contains("error="), "unexpected error redirect: {loc}");
+    let user = conn
+      .oidc_identity()
+      .get_identity("corp", "corp-1")
+      .await
+      .unwrap()
+      .unwrap()
+      .user_id;
+    assert!(conn.group().is_in_group(group, user).await.unwrap());
+    assert!(conn.user().get_user_by_id(user).await.unwrap().oidc_user);
+    assert!(
+      conn
+        .invitation()
+        .get_invitation(invite)
+        .await
+        .unwrap()
+        .is_none()
+    );
+
+    // The invitation can only be used once.
+    let loc = run_provider_flow(&conn, &state, &idp, "corp", "corp-1", None, Some(invite)).await;
+    assert!(loc.contains("error=invalid_invitation"), "{loc}");
+  }
+
+  #[tokio::test]
+  async fn test_login_remembers_provider_session() {
+    let conn = db().await;
+    let idp =
+      signing_idp(json!({"sub": "corp-1", "email": "corp@example.com", "name": "Corp"})).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
+    let (_, session) = provider_session(&conn, &state, &idp).await;
+
+    let session = conn.session().get_session(session).await.unwrap().unwrap();
+    assert_eq!(session.oidc_provider.as_deref(), Some("corp"));
+    assert_eq!(session.oidc_sid.as_deref(), Some("idp-sid"));
+
+    let url = state
+      .end_session_url("corp", &Url::parse("https://app.example").unwrap())
+      .await
+      .unwrap();
+    assert!(url.as_str().starts_with(&format!("{}/logout?", idp.base)));
+    let query: HashMap<_, _> = url.query_pairs().into_owned().collect();
+    assert_eq!(query["client_id"], "client");
+    assert_eq!(query["post_logout_redirect_uri"], "https://app.example/");
+  }
+
+  #[tokio::test]
+  async fn test_backchannel_logout_revokes_sessions_of_subject() {
+    let conn = db().await;
+    let idp =
+      signing_idp(json!({"sub": "corp-1", "email": "corp@example.com", "name": "Corp"})).await;
+    let state = OidcState::new(&conn, None, Store::memory()).await;
+    let (user, session) = provider_session(&conn, &state, &idp).await;
+    let local = conn
+      .session()
+      .create_session(user, None, None, None)
+      .await
+      .unwrap();
+
+    let logout = |token: String| {
+      backchannel_logout(
+        Path("corp".to_string()),
+        state.clone(),
+        conn.clone(),
+        Form(BackchannelLogout {
+          logout_token: token,
+        }),
+      )
+    };
+
+    // An id token is not accepted as logout token.
+    let id_token = logout_token(&idp, json!({"sub": "corp-1", "nonce": "n"}));
+    assert!(logout(id_token).await.is_err());
+    let stale = logout_token(
+      &idp,
+      json!({"sub": "corp-1", "iat": chrono::Utc::now().timestamp() - 3600}),
+    );
+    assert!(logout(stale).await.is_err());
+    assert!(conn.session().get_session(session).await.unwrap().is_some());
+
+    let token = logout_token(&idp, json!({"sub": "corp-1"}));
+    logout(token.clone()).await.unwrap();
+    assert!(conn.session().get_session(session).await.unwrap().is_none());
+    // Only sessions started with the provider are affected.
+    assert!(conn.session().get_session(local).await.unwrap().is_some());
+
+    // A logout token can only be used once.
+    assert!(logout(token).await.is_err());
+  }
 }
@@ -0,0 +1,456 @@
+//! Password login without sending the password, as an alternative to the RSA
+//! transport encryption of [`super::password`]. This is SRP-6a (RFC 5054) with
+//! the 2048 bit group, SHA-256 as `H` and argon2id as the password derivation:
+//!
+//! - `x = argon2id(password, salt)`, m = 19456 KiB, t = 2, p = 1, 32 bytes
+//! - `v = g^x`, stored by the server as the verifier
+//! - `k = H(N | PAD(g))`, `u = H(PAD(A) | PAD(B))`
+//! - `K = H(PAD(S))`, `M1 = H(PAD(A) | PAD(B) | K)`, `M2 = H(PAD(A) | M1 | K)`
+//!
+//! All numbers are sent as base64 of their big endian bytes. The client
+//! computes the verifier as well, so the password never reaches the server.
+
+use std::{sync::LazyLock, time::Duration};
+
+use aide::axum::routing::{ApiMethodRouter, post_with};
+use axum::Json;
+use axum_extra::extract::CookieJar;
+use base64::prelude::*;
+use http::StatusCode;
+use num_bigint::BigUint;
+use serde::{Deserialize, Serialize};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    auth::{
+      jwt_state::JwtState,
+      lockout::{check_lockout, record_failure},
+      password::{LoginResponse, finish_login},
+      pw_state::PasswordState,
+      session::ClientInfo,
+    },
+    config::SiteConfig,
+    request::response::TokenRes,
+    store::Store,
+  },
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::{ErrorReportStatusExt, Result},
+  mail::Mailer,
+};
+
+/// Prime of the 2048 bit group from RFC 5054 appendix A.
+const N_HEX: &[u8] = b"AC6BDB41324A9A9BF166DE5E1389582FAF72B6651987EE07FC3192943DB56050\
+A37329CBB4A099ED8193E0757767A13DD52312AB4B03310DCD7F48A9DA04FD50\
+E8083969EDB767B0CF6095179A163AB3661A05FBD5FAAAE82918A9962F0B93B8\
+55F97993EC975EEAA80D740ADBF4FF747359D041D5C33EA71D281E446B14773B\
+CA97B43A23FB801676BD207A436C6481F1D2B9078717461A5B9D32E688F87748\
+544523B524B0D57D5EA77A2775D2ECFA032CFBDBF52FB3786160279004E57AE6\
+AF874E7303CE53299CCC041C7BC308D82A5698F3A8D0C38271AE35F8E9DBFBB6\
+94B5C803D89F7AE435DE236D525F54759B65E372FCD68EF20FA7111F9E4AFF73";
+const N_LEN: usize = 256;
+const SALT_LEN: usize = 16;
+const PAKE_SESSION: &str = "pake_session";
+const SESSION_TTL: Duration = Duration::from_secs(60);
+
+struct Group {
+  n: BigUint,
+  g: BigUint,
+  k: BigUint,
+}
+
+static GROUP: LazyLock<Group> = LazyLock::new(|| {
+  let n = BigUint::parse_bytes(N_HEX, 16).expect("static prime is valid");
+  let g = BigUint::from(2u32);
+  let k = BigUint::from_bytes_be(&hash(&[&n.to_bytes_be(), &pad(&g)]));
+  Group { n, g, k }
+});
+
+pub fn start_route() -> ApiMethodRouter<()> {
+  post_with(start, |op| op.id("startPakeLogin"))
+}
+
+pub fn finish_route() -> ApiMethodRouter<()> {
+  post_with(finish, |op| op.id("finishPakeLogin"))
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct PakeStartReq {
+  email: String,
+  /// Public ephemeral value `A` of the client.
+  a: String,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct PakeStartRes {
+  session: Uuid,
+  salt: String,
+  /// Public ephemeral value `B` of the server.
+  b: String,
+}
+
+#[derive(Serialize, Deserialize)]
+struct PendingLogin {
+  /// `None` for the fake challenge of accounts without PAKE.
+  user: Option<Uuid>,
+  a: String,
+  b: String,
+  secret: String,
+}
+
+/// First step of a PAKE login, answers the value `A` of the client with the
+/// salt and the value `B` of the server. Unknown accounts and accounts without
+/// PAKE get a challenge no proof matches, so this does not reveal either.
+async fn start(
+  db: Connection,
+  store: Store,
+  pw: PasswordState,
+  Json(req): Json<PakeStartReq>,
+) -> Result<Json<PakeStartRes>> {
+  let a_pub = decode_number(&req.a)?;
+  if &a_pub % &GROUP.n == BigUint::ZERO {
+    bail!(BAD_REQUEST, "Invalid client value");
+  }
+
+  let user = db
+    .user()
+    .try_get_user_by_email(&req.email)
+    .await?
+    .filter(|user| !user.service_account);
+  let verifier = match &user {
+    Some(user) => {
+      check_lockout(&db, user.id).await?;
+      db.pake_verifier().get_verifier(user.id).await?
+    }
+    None => None,
+  };
+  let (user, salt, verifier) = match (user, verifier) {
+    (Some(user), Some(verifier)) => (
+      Some(user.id),
+      verifier.salt,
+      decode_number(&verifier.verifier)?,
+    ),
+    _ => (None, fake_salt(&pw.pepper, &req.email), random_number()),
+  };
+
+  let (secret, b_pub) = server_challenge(&verifier);
+  let session = Uuid::new_v4();
+  let pending = PendingLogin {
+    user,
+    a: req.a,
+    b: encode_number(&b_pub),
+    secret: encode_number(&secret),
+  };
+  store
+    .set_json(PAKE_SESSION, &session.to_string(), &pending, SESSION_TTL)
+    .await?;
+
+  Ok(Json(PakeStartRes {
+    session,
+    salt,
+    b: pending.b,
+  }))
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct PakeFinishReq {
+  session: Uuid,
+  /// Proof `M1` of the client.
+  proof: String,
+}
+
+#[derive(Serialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct PakeLoginResponse {
+  #[serde(flatten)]
+  login: LoginResponse,
+  /// Proof `M2` of the server, the client should abort if it does not match.
+  proof: String,
+}
+
+/// Second step of a PAKE login. Each session from [`start`] can only be used
+/// once.
+#[allow(clippy::too_many_arguments)]
+async fn finish(
+  jwt: JwtState,
+  db: Connection,
+  store: Store,
+  mailer: Mailer,
+  site: SiteConfig,
+  cookies: CookieJar,
+  client: ClientInfo,
+  Json(req): Json<PakeFinishReq>,
+) -> Result<(CookieJar, TokenRes<PakeLoginResponse>)> {
+  let Some(pending) = store
+    .take_json::<PendingLogin>(PAKE_SESSION, &req.session.to_string())
+    .await?
+  else {
+    bail!(UNAUTHORIZED, "Login session expired");
+  };
+  let Some(user) = pending.user else {
+    bail!(UNAUTHORIZED, "Invalid email or password");
+  };
+  check_lockout(&db, user).await?;
+  let Some(verifier) = db.pake_verifier().get_verifier(user).await? else {
+    bail!(UNAUTHORIZED, "Invalid email or password");
+  };
+
+  let proofs = server_proofs(
+    &decode_number(&pending.a)?,
+    &decode_number(&pending.b)?,
+    &decode_number(&pending.secret)?,
+    &decode_number(&verifier.verifier)?,
+  );
+  let client_proof = BASE64_STANDARD
+    .decode(&req.proof)
+    .status(StatusCode::BAD_REQUEST)?;
+  let Some((_, proof)) = proofs.filter(|(expected, _)| eq(expected, &client_proof)) else {
+    record_failure(&db, &mailer, &site, user).await?;
+    bail!(UNAUTHORIZED, "Invalid email or password");
+  };
+
+  let (cookies, login) = finish_login(&db, &jwt, cookies, user, &client).await?;
+  debug!("PAKE login proof accepted for user: {}", user);
+
+  Ok((
+    cookies,
+    TokenRes(PakeLoginResponse {
+      login,
+      proof: BASE64_STANDARD.encode(proof),
+    }),
+  ))
+}
+
+/// Checks a salt and verifier computed by the client before they are stored
+/// by [`crate::db::tables::pake_verifier::PakeVerifierTable`].
+pub fn validate_verifier(salt: &str, verifier: &str) -> Result<()> {
+  let salt = BASE64_STANDARD
+    .decode(salt)
+    .status(StatusCode::BAD_REQUEST)?;
+  if salt.len() < SALT_LEN {
+    bail!(BAD_REQUEST, "Salt is too short");
+  }
+  let verifier = decode_number(verifier)?;
+  if verifier <= BigUint::from(1u32) || verifier >= GROUP.n {
+    bail!(BAD_REQUEST, "Invalid verifier");
+  }
+
+  Ok(())
+}
+
+/// Salt shown for accounts without PAKE, stable per email so repeated logins
+/// look like the ones of an account with a verifier.
+fn fake_salt(pepper: &[u8], email: &str) -> String {
+  let salt = hash(&[b"pake-salt", pepper, email.to_lowercase().as_bytes()]);
+  BASE64_STANDARD.encode(&salt[..SALT_LEN])
+}
+
+fn random_number() -> BigUint {
+  BigUint::from_bytes_be(&rand::random::<[u8; 32]>())
+}
+
+/// Random secret `b` and `B = k*v + g^b`.
+fn server_challenge(verifier: &BigUint) -> (BigUint, BigUint) {
+  let secret = random_number();
+  let b_pub = (&GROUP.k * verifier + GROUP.g.modpow(&secret, &GROUP.n)) % &GROUP.n;
+  (secret, b_pub)
+}
+
+/// The expected proof of the client and the proof of the server, `None` if the
+/// client values are unusable.
+fn server_proofs(
+  a_pub: &BigUint,
+  b_pub: &BigUint,
+  secret: &BigUint,
+  verifier: &BigUint,
+) -> Option<(Vec<u8>, Vec<u8>)> {
+  if a_pub % &GROUP.n == BigUint::ZERO {
+    return None;
+  }
+  let u = scrambler(a_pub, b_pub)?;
+  let s = (a_pub * verifier.modpow(&u, &GROUP.n)).modpow(secret, &GROUP.n);
+  Some(proofs(a_pub, b_pub, &s))
+}
+
+fn scrambler(a_pub: &BigUint, b_pub: &BigUint) -> Option<BigUint> {
+  let u = BigUint::from_bytes_be(&hash(&[&pad(a_pub), &pad(b_pub)]));
+  (u != BigUint::ZERO).then_some(u)
+}
+
+fn proofs(a_pub: &BigUint, b_pub: &BigUint, s: &BigUint) -> (Vec<u8>, Vec<u8>) {
+  let key = hash(&[&pad(s)]);
+  let client = hash(&[&pad(a_pub), &pad(b_pub), &key]);
+  let server = hash(&[&pad(a_pub), &client, &key]);
+  (client, server)
+}
+
+fn hash(parts: &[&[u8]]) -> Vec<u8> {
+  let mut hasher = Sha256::new();
+  for part in parts {
+    hasher.update(part);
+  }
+  hasher.finalize().to_vec()
+}
+
+fn pad(n: &BigUint) -> Vec<u8> {
+  let bytes = n.to_bytes_be();
+  let mut padded = vec![0; N_LEN.saturating_sub(bytes.len())];
+  padded.extend(bytes);
+  padded
+}
+
+/// Compares without returning early, so the time does not depend on where the
+/// first difference is.
+fn eq(a: &[u8], b: &[u8]) -> bool {
+  a.len() == b.len() && a.iter().zip(b).fold(0, |acc, (a, b)| acc | (a ^ b)) == 0
+}
+
+fn encode_number(n: &BigUint) -> String {
+  BASE64_STANDARD.encode(n.to_bytes_be())
+}
+
+fn decode_number(n: &str) -> Result<BigUint> {
+  let bytes = BASE64_STANDARD.decode(n).status(StatusCode::BAD_REQUEST)?;
+  Ok(BigUint::from_bytes_be(&bytes))
+}
+
+/// The client side of the exchange, only used to test the server.
+#[cfg(test)]
+pub(crate) mod client {
+  use argon2::{Algorithm, Argon2, Params, Version};
+
+  use super::*;
+
+  const ARGON2_MEMORY: u32 = 19456;
+  const ARGON2_ITERATIONS: u32 = 2;
+  const ARGON2_PARALLELISM: u32 = 1;
+
+  /// Random salt and the verifier `v = g^x` for the password, base64 encoded.
+  pub fn register(password: &str) -> (String, String) {
+    let salt = BASE64_STANDARD.encode(rand::random::<[u8; SALT_LEN]>());
+    let x = derive_secret(password, &salt);
+    let verifier = encode_number(&GROUP.g.modpow(&x, &GROUP.n));
+    (salt, verifier)
+  }
+
+  fn derive_secret(password: &str, salt: &str) -> BigUint {
+    let salt = BASE64_STANDARD.decode(salt).unwrap();
+    let params = Params::new(
+      ARGON2_MEMORY,
+      ARGON2_ITERATIONS,
+      ARGON2_PARALLELISM,
+      Some(32),
+    )
+    .unwrap();
+    let mut x = [0u8; 32];
+    Argon2::new(Algorithm::Argon2id, Version::V0x13, params)
+      .hash_password_into(password.as_bytes(), &salt, &mut x)
+      .unwrap();
+
+    BigUint::from_bytes_be(&x)
+  }
+
+  /// Random secret `a` and `A = g^a`, base64 encoded.
+  pub fn start() -> (BigUint, String) {
+    let secret = random_number();
+    let a_pub = GROUP.g.modpow(&secret, &GROUP.n);
+    (secret, encode_number(&a_pub))
+  }
+
+  /// Proofs `M1` and `M2` for the response of the server, base64 encoded.
+  pub fn proofs(
+    password: &str,
+    salt: &str,
+    secret: &BigUint,
+    a_pub: &str,
+    b_pub: &str,
+  ) -> (String, String) {
+    let a_pub = decode_number(a_pub).unwrap();
+    let b_pub = decode_number(b_pub).unwrap();
+    let u = scrambler(&a_pub, &b_pub).unwrap();
+    let x = derive_secret(password, salt);
+
+    let kgx = (&GROUP.k * GROUP.g.modpow(&x, &GROUP.n)) % &GROUP.n;
+    let base = (&b_pub + &GROUP.n - kgx) % &GROUP.n;
+    let s = base.modpow(&(secret + u * x), &GROUP.n);
+    let (client, server) = super::proofs(&a_pub, &b_pub, &s);
+
+    (
+      BASE64_STANDARD.encode(client),
+      BASE64_STANDARD.encode(server),
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Whether both sides accept the proof of the other one.
+  fn exchange(password: &str, registered: &str) -> bool {
+    let (salt, verifier) = client::register(registered);
+    let verifier = decode_number(&verifier).unwrap();
+
+    let (a, a_pub) = client::start();
+    let (b, b_pub) = server_challenge(&verifier);
+    let (client_proof, server_proof) =
+      client::proofs(password, &salt, &a, &a_pub, &encode_number(&b_pub));
+
+    let (expected, proof) =
+      server_proofs(&decode_number(&a_pub).unwrap(), &b_pub, &b, &verifier).unwrap();
+    BASE64_STANDARD.encode(expected) == client_proof
+      && BASE64_STANDARD.encode(proof) == server_proof
+  }
+
+  #[test]
+  fn test_group_is_rfc_5054() {
+    assert_eq!(GROUP.n.bits(), 2048);
+    assert_eq!(pad(&GROUP.n).len(), N_LEN);
+    assert_eq!(pad(&GROUP.g).len(), N_LEN);
+  }
+
+  #[test]
+  fn test_exchange_agrees_on_proofs() {
+    assert!(exchange("Corr3ct-horse", "Corr3ct-horse"));
+    assert!(!exchange("wrong", "Corr3ct-horse"));
+  }
+
+  #[test]
+  fn test_rejects_zero_client_value() {
+    let verifier = BigUint::from(4u32);
+    let (b, b_pub) = server_challenge(&verifier);
+    assert!(server_proofs(&BigUint::ZERO, &b_pub, &b, &verifier).is_none());
+    assert!(server_proofs(&GROUP.n, &b_pub, &b, &verifier).is_none());
+  }
+
+  #[test]
+  fn test_validate_verifier() {
+    let (salt, verifier) = client::register("Corr3ct-horse");
+    assert!(validate_verifier(&salt, &verifier).is_ok());
+    assert!(validate_verifier("c2FsdA==", &verifier).is_err());
+    assert!(validate_verifier(&salt, &encode_number(&BigUint::from(1u32))).is_err());
+    assert!(validate_verifier(&salt, &encode_number(&GROUP.n)).is_err());
+  }
+
+  #[test]
+  fn test_fake_salt_is_stable_per_email() {
+    let salt = fake_salt(b"pepper", "a@example.com");
+    assert_eq!(salt, fake_salt(b"pepper", "A@example.com"));
+    assert_ne!(salt, fake_salt(b"pepper", "b@example.com"));
+    assert_ne!(salt, fake_salt(b"other", "a@example.com"));
+    assert_eq!(BASE64_STANDARD.decode(salt).unwrap().len(), SALT_LEN);
+  }
+
+  #[test]
+  fn test_eq() {
+    assert!(eq(b"abc", b"abc"));
+    assert!(!eq(b"abc", b"abd"));
+    assert!(!eq(b"abc", b"ab"));
+  }
+}
@@ -0,0 +1,396 @@
+use std::{sync::Arc, time::Duration};
+
+use aide::{
+  OperationIo,
+  axum::routing::{ApiMethodRouter, post_with},
+};
+use axum::{Extension, Json, extract::FromRequestParts};
+use axum_extra::extract::CookieJar;
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use http::StatusCode;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::debug;
+use uuid::Uuid;
+use webauthn_rs_core::{
+  WebauthnCore,
+  proto::{
+    AttestationConveyancePreference, AuthenticationState, COSEAlgorithm, Credential,
+    PublicKeyCredential, RegisterPublicKeyCredential, RegistrationState, UserVerificationPolicy,
+  },
+};
+
+use crate::{
+  backend::{
+    BackendRouter,
+    auth::{
+      jwt_auth::JwtAuth,
+      jwt_state::{JwtState, MFA_COOKIE_NAME},
+      password::LoginResponse,
+      session::ClientInfo,
+    },
+    config::SiteConfig,
+    middleware::rate_limiter::RateLimiter,
+    request::response::TokenRes,
+    store::Store,
+  },
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::{ErrorReportStatusExt, Result},
+};
+
+/// How long a started registration or authentication can be finished.
+const CEREMONY_TIMEOUT: Duration = Duration::from_secs(300);
+const REGISTRATION_NAMESPACE: &str = "passkey_registration";
+const AUTHENTICATION_NAMESPACE: &str = "passkey_authentication";
+
+pub fn router(rate_limiter: &mut RateLimiter) -> BackendRouter {
+  BackendRouter::new()
+    .api_route("/login/start", start_login_route())
+    .api_route("/login/finish", finish_login_route())
+    .api_route("/mfa/start", start_mfa_route())
+    .api_route("/mfa/finish", finish_mfa_route())
+    .layer(rate_limiter.create_limiter("passkey"))
+    .api_route("/register/start", start_register_route())
+    .api_route("/register/finish", finish_register_route())
+}
+
+pub fn start_register_route() -> ApiMethodRouter<()> {
+  post_with(start_register, |op| op.id("startPasskeyRegistration"))
+}
+
+pub fn finish_register_route() -> ApiMethodRouter<()> {
+  post_with(finish_register, |op| op.id("finishPasskeyRegistration"))
+}
+
+pub fn start_login_route() -> ApiMethodRouter<()> {
+  post_with(start_login, |op| op.id("startPasskeyLogin"))
+}
+
+pub fn finish_login_route() -> ApiMethodRouter<()> {
+  post_with(finish_login, |op| op.id("finishPasskeyLogin"))
+}
+
+pub fn start_mfa_route() -> ApiMethodRouter<()> {
+  post_with(start_mfa, |op| op.id("startPasskeyMfa"))
+}
+
+pub fn finish_mfa_route() -> ApiMethodRouter<()> {
+  post_with(finish_mfa, |op| op.id("finishPasskeyMfa"))
+}
+
+/// Started ceremony, kept in the [`Store`] for [`CEREMONY_TIMEOUT`] so it can
+/// be finished on any replica.
+#[derive(Serialize, Deserialize)]
+struct Ceremony<S> {
+  state: S,
+  /// User the ceremony was started for, `None` for a password-less login where
+  /// the user is only known from the passkey.
+  user: Option<Uuid>,
+}
+
+#[derive(Clone, FromRequestParts, OperationIo)]
+#[from_request(via(Extension))]
+pub struct PasskeyState {
+  webauthn: Arc<WebauthnCore>,
+}
+
+impl PasskeyState {
+  /// The relying party is the host of the site url, which also is the only
+  /// allowed origin.
+  pub fn init(site: &SiteConfig, rp_name: &str) -> Self {
+    let rp_id = site.site_url.host_str().unwrap_or("localhost");
+    let webauthn = WebauthnCore::new_unsafe_experts_only(
+      rp_name,
+      rp_id,
+      vec![site.site_url.clone()],
+      CEREMONY_TIMEOUT,
+      None,
+      None,
+    );
+
+    Self {
+      webauthn: Arc::new(webauthn),
+    }
+  }
+
+  async fn start_authentication(
+    &self,
+    store: &Store,
+    credentials: Vec<Credential>,
+    policy: UserVerificationPolicy,
+    user: Option<Uuid>,
+  ) -> Result<AuthenticationStart> {
+    let builder = self
+      .webauthn
+      .new_challenge_authenticate_builder(credentials, Some(policy))?;
+    let (options, state) = self.webauthn.generate_challenge_authenticate(builder)?;
+
+    let ceremony = Uuid::new_v4();
+    store
+      .set_json(
+        AUTHENTICATION_NAMESPACE,
+        &ceremony.to_string(),
+        &Ceremony { state, user },
+        CEREMONY_TIMEOUT,
+      )
+      .await?;
+
+    Ok(AuthenticationStart {
+      ceremony,
+      options: serde_json::to_value(options)?,
+    })
+  }
+
+  /// Verify the assertion and return the owner of the passkey that was used.
+  async fn finish_authentication(
+    &self,
+    db: &Connection,
+    store: &Store,
+    req: FinishAuthentication,
+    user: Option<Uuid>,
+  ) -> Result<Uuid> {
+    let Some(ceremony) = store
+      .take_json::<Ceremony<AuthenticationState>>(
+        AUTHENTICATION_NAMESPACE,
+        &req.ceremony.to_string(),
+      )
+      .await?
+    else {
+      bail!(UNAUTHORIZED, "Unknown passkey ceremony");
+    };
+    // a second factor ceremony can not be used for a password-less login and
+    // the other way around
+    if ceremony.user != user {
+      bail!(UNAUTHORIZED, "Unknown passkey ceremony");
+    }
+
+    let response: PublicKeyCredential =
+      serde_json::from_value(req.credential).status(StatusCode::BAD_REQUEST)?;
+    let credential_id = BASE64_URL_SAFE_NO_PAD.encode(response.get_credential_id());
+    let Some(passkey) = db.passkey().get_by_credential_id(&credential_id).await? else {
+      bail!(UNAUTHORIZED, "Unknown passkey");
+    };
+    if user.is_some_and(|user| user != passkey.user_id) {
+      bail!(UNAUTHORIZED, "Unknown passkey");
+    }
+    if response
+      .get_user_unique_id()
+      .is_some_and(|handle| handle != passkey.user_id.as_bytes())
+    {
+      bail!(UNAUTHORIZED, "Unknown passkey");
+    }
+
+    let mut credential: Credential = serde_json::from_str(&passkey.credential)?;
+    let mut state = ceremony.state;
+    state.set_allowed_credentials(vec![credential.clone()]);
+    let result = self.webauthn.authenticate_credential(&response, &state)?;
+
+    if result.counter() > credential.counter {
+      credential.counter = result.counter();
+    }
+    credential.backup_state = result.backup_state();
+    db.passkey()
+      .mark_used(passkey.id, serde_json::to_string(&credential)?)
+      .await?;
+
+    Ok(passkey.user_id)
+  }
+}
+
+fn encode_credential_id(credential: &Credential) -> String {
+  let id: &[u8] = credential.cred_id.as_ref();
+  BASE64_URL_SAFE_NO_PAD.encode(id)
+}
+
+async fn start_register(
+  auth: JwtAuth,
+  db: Connection,
+  state: PasskeyState,
+  store: Store,
+) -> Result<Json<Value>> {
+  let user = db.user().get_user_by_id(auth.user_id).await?;
+  let exclude = db
+    .passkey()
+    .get_user_passkeys(user.id)
+    .await?
+    .iter()
+    .map(|p| Ok(serde_json::from_str::<Credential>(&p.credential)?.cred_id))
+    .collect::<Result<Vec<_>>>()?;
+
+  let builder = state
+    .webauthn
+    .new_challenge_register_builder(user.id.as_bytes(), &user.email, &user.name)?
+    .attestation(AttestationConveyancePreference::None)
+    .credential_algorithms(COSEAlgorithm::secure_algs())
+    .require_resident_key(true)
+    .user_verification_policy(UserVerificationPolicy::Required)
+    .exclude_credentials(Some(exclude));
+  let (options, registration) = state.webauthn.generate_challenge_register(builder)?;
+
+  store
+    .set_json(
+      REGISTRATION_NAMESPACE,
+      &user.id.to_string(),
+      &Ceremony {
+        state: registration,
+        user: Some(user.id),
+      },
+      CEREMONY_TIMEOUT,
+    )
+    .await?;
+
+  Ok(Json(serde_json::to_value(options)?))
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct FinishRegistration {
+  name: String,
+  /// `RegisterPublicKeyCredential` returned by `navigator.credentials.create`.
+  credential: Value,
+  /// Require the passkey after the password. Otherwise it is only used for
+  /// password-less logins.
+  #[serde(default)]
+  second_factor: bool,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct PasskeyCreated {
+  uuid: Uuid,
+}
+
+async fn finish_register(
+  auth: JwtAuth,
+  db: Connection,
+  state: PasskeyState,
+  store: Store,
+  Json(req): Json<FinishRegistration>,
+) -> Result<Json<PasskeyCreated>> {
+  let Some(ceremony) = store
+    .take_json::<Ceremony<RegistrationState>>(REGISTRATION_NAMESPACE, &auth.user_id.to_string())
+    .await?
+  else {
+    bail!(NOT_FOUND, "No passkey registration in progress");
+  };
+
+  let response: RegisterPublicKeyCredential =
+    serde_json::from_value(req.credential).status(StatusCode::BAD_REQUEST)?;
+  let credential = state
+    .webauthn
+    .register_credential(&response, &ceremony.state, None)?;
+
+  let name = match req.name.trim() {
+    "" => "Passkey".to_string(),
+    name => name.to_string(),
+  };
+  let uuid = db
+    .passkey()
+    .create_passkey(
+      auth.user_id,
+      name,
+      encode_credential_id(&credential),
+      serde_json::to_string(&credential)?,
+      req.second_factor,
+    )
+    .await?;
+  debug!("Passkey registered for user: {}", auth.user_id);
+
+  Ok(Json(PasskeyCreated { uuid }))
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct AuthenticationStart {
+  ceremony: Uuid,
+  /// `RequestChallengeResponse` for `navigator.credentials.get`.
+  options: Value,
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct FinishAuthentication {
+  ceremony: Uuid,
+  /// `PublicKeyCredential` returned by `navigator.credentials.get`.
+  credential: Value,
+}
+
+/// Password-less login, the browser offers every passkey it has for the site.
+async fn start_login(state: PasskeyState, store: Store) -> Result<Json<AuthenticationStart>> {
+  Ok(Json(
+    state
+      .start_authentication(&store, Vec::new(), UserVerificationPolicy::Required, None)
+      .await?,
+  ))
+}
+
+async fn finish_login(
+  jwt: JwtState,
+  db: Connection,
+  state: PasskeyState,
+  store: Store,
+  mut cookies: CookieJar,
+  client: ClientInfo,
+  Json(req): Json<FinishAuthentication>,
+) -> Result<(CookieJar, TokenRes<LoginResponse>)> {
+  let user = state.finish_authentication(&db, &store, req, None).await?;
+
+  cookies = jwt.create_login(&db, cookies, user, &client).await?;
+  debug!("User logged in with passkey: {}", user);
+
+  Ok((cookies, TokenRes(LoginResponse::new(user))))
+}
+
+/// Passkey as second factor after a password login, see [`MFA_COOKIE_NAME`].
+async fn start_mfa(
+  jwt: JwtState,
+  db: Connection,
+  state: PasskeyState,
+  store: Store,
+  cookies: CookieJar,
+) -> Result<Json<AuthenticationStart>> {
+  let user = jwt.validate_mfa_token(&cookies).await?;
+  let credentials = db
+    .passkey()
+    .get_user_passkeys(user)
+    .await?
+    .iter()
+    .map(|p| Ok(serde_json::from_str::<Credential>(&p.credential)?))
+    .collect::<Result<Vec<_>>>()?;
+  if credentials.is_empty() {
+    bail!(BAD_REQUEST, "No passkeys registered");
+  }
+
+  Ok(Json(
+    state
+      .start_authentication(
+        &store,
+        credentials,
+        UserVerificationPolicy::Preferred,
+        Some(user),
+      )
+      .await?,
+  ))
+}
+
+async fn finish_mfa(
+  jwt: JwtState,
+  db: Connection,
+  state: PasskeyState,
+  store: Store,
+  mut cookies: CookieJar,
+  client: ClientInfo,
+  Json(req): Json<FinishAuthentication>,
+) -> Result<(CookieJar, TokenRes<LoginResponse>)> {
+  let user = jwt.validate_mfa_token(&cookies).await?;
+  state
+    .finish_authentication(&db, &store, req, Some(user))
+    .await?;
+
+  cookies = cookies.remove(jwt.create_cookie(MFA_COOKIE_NAME, String::new()));
+  cookies = jwt.create_login(&db, cookies, user, &client).await?;
+  debug!("User logged in with passkey as second factor: {}", user);
+
+  Ok((cookies, TokenRes(LoginResponse::new(user))))
+}
@@ -7,23 +7,33 @@ use uuid::Uuid;
 
 use crate::backend::BackendRouter;
 use crate::backend::auth::jwt_state::JwtState;
-use crate::backend::auth::pw_state::PasswordState;
+use crate::backend::auth::lockout::{check_lockout, record_failure, record_success};
+use crate::backend::auth::pake;
+use crate::backend::auth::pw_state::{PasswordState, Verified};
+use crate::backend::auth::session::ClientInfo;
+use crate::backend::config::SiteConfig;
+use crate::backend::endpoints::websocket::state::UpdateMessage;
+#[cfg(feature = "ldap")]
+use crate::backend::endpoints::websocket::state::Updater;
 use crate::backend::middleware::rate_limiter::RateLimiter;
 use crate::backend::request::response::TokenRes;
 use crate::bail;
 use crate::db::init::Connection;
 use crate::db::tables::ConnectionExt;
 use crate::error::Result;
+use crate::mail::Mailer;
 
-pub fn router(rate_limiter: &mut RateLimiter) -> BackendRouter {
+pub fn router<T: UpdateMessage>(rate_limiter: &mut RateLimiter) -> BackendRouter {
   BackendRouter::new()
-    .api_route("/", authenticate_route())
-    .layer(rate_limiter.create_limiter())
+    .api_route("/", authenticate_route::<T>())
+    .api_route("/pake/start", pake::start_route())
+    .api_route("/pake/finish", pake::finish_route())
+    .layer(rate_limiter.create_limiter("password"))
     .api_route("/", key_route())
 }
 
-pub fn authenticate_route() -> ApiMethodRouter<()> {
-  post_with(authenticate, |op| op.id("authenticate"))
+pub fn authenticate_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(authenticate::<T>, |op| op.id("authenticate"))
 }
 
 pub fn key_route() -> ApiMethodRouter<()> {
@@ -47,29 +57,116 @@ struct LoginReq {
   password: String,
 }
 
+#[derive(Serialize, Debug, Clone, Copy, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "lowercase")]
+pub enum MfaMethod {
+  Totp,
+  Passkey,
+}
+
 #[derive(Serialize, Debug)]
 #[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
-struct LoginResponse {
-  user: Uuid,
+pub struct LoginResponse {
+  pub user: Uuid,
+  /// If not empty the password was correct, but the login has to be completed
+  /// with one of these second factors.
+  pub mfa: Vec<MfaMethod>,
+}
+
+impl LoginResponse {
+  pub fn new(user: Uuid) -> Self {
+    Self {
+      user,
+      mfa: Vec::new(),
+    }
+  }
+}
+
+/// Second factors the user has set up. Passkeys only count if they were
+/// registered as second factor.
+pub async fn mfa_methods(db: &Connection, user: Uuid) -> Result<Vec<MfaMethod>> {
+  let mut methods = Vec::new();
+  if db.totp().is_enabled(user).await? {
+    methods.push(MfaMethod::Totp);
+  }
+  #[cfg(feature = "webauthn")]
+  if db.passkey().has_second_factor(user).await? {
+    methods.push(MfaMethod::Passkey);
+  }
+
+  Ok(methods)
 }
 
-async fn authenticate(
+#[allow(clippy::too_many_arguments)]
+#[cfg_attr(not(feature = "ldap"), allow(clippy::extra_unused_type_parameters))]
+async fn authenticate<T: UpdateMessage>(
   state: PasswordState,
   jwt: JwtState,
   db: Connection,
-  mut cookies: CookieJar,
+  mailer: Mailer,
+  site: SiteConfig,
+  cookies: CookieJar,
+  client: ClientInfo,
+  #[cfg(feature = "ldap")] updater: Updater<T>,
   Json(req): Json<LoginReq>,
 ) -> Result<(CookieJar, TokenRes<LoginResponse>)> {
+  #[cfg(feature = "ldap")]
+  if let Some(user) = crate::backend::auth::ldap::login(
+    &db,
+    &state,
+    &mailer,
+    &site,
+    updater,
+    &req.email,
+    &req.password,
+  )
+  .await?
+  {
+    let (cookies, login) = finish_login(&db, &jwt, cookies, user, &client).await?;
+    return Ok((cookies, TokenRes(login)));
+  }
+
   let user = db.user().get_user_by_email(&req.email).await?;
-  let hash = state.pw_hash(&user.salt, &req.password)?;
+  if user.service_account {
+    bail!(UNAUTHORIZED, "Invalid email or password");
+  }
+  check_lockout(&db, user.id).await?;
 
-  if hash != user.password {
+  let Verified::Valid { rehash } = state.pw_verify(&user.salt, &user.password, &req.password)?
+  else {
+    record_failure(&db, &mailer, &site, user.id).await?;
     bail!(UNAUTHORIZED, "Invalid email or password");
+  };
+  if let Some(hash) = rehash {
+    db.user().rehash_password(user.id, hash).await?;
+    debug!("Rehashed password of user: {}", user.id);
+  }
+
+  let (cookies, login) = finish_login(&db, &jwt, cookies, user.id, &client).await?;
+  Ok((cookies, TokenRes(login)))
+}
+
+/// Issue a session after the password was verified, or only the partial token
+/// of [`JwtState::create_mfa_token`] if a second factor is required.
+pub async fn finish_login(
+  db: &Connection,
+  jwt: &JwtState,
+  mut cookies: CookieJar,
+  user: Uuid,
+  client: &ClientInfo,
+) -> Result<(CookieJar, LoginResponse)> {
+  let mfa = mfa_methods(db, user).await?;
+  if !mfa.is_empty() {
+    cookies = cookies.add(jwt.create_mfa_token(user)?);
+    debug!("User needs second factor: {}", user);
+
+    return Ok((cookies, LoginResponse { user, mfa }));
   }
 
-  let cookie = jwt.create_token(user.id)?;
-  cookies = cookies.add(cookie);
-  debug!("User logged in: {}", user.id);
+  record_success(db, user).await?;
+  cookies = jwt.create_login(db, cookies, user, client).await?;
+  debug!("User logged in: {}", user);
 
-  Ok((cookies, TokenRes(LoginResponse { user: user.id })))
+  Ok((cookies, LoginResponse::new(user)))
 }
@@ -0,0 +1,198 @@
+use std::{io::ErrorKind, path::Path};
+
+use http::StatusCode;
+use serde::Serialize;
+use sha1::{Digest, Sha1};
+use tokio::fs;
+
+use crate::{
+  backend::auth::{
+    pw_state::{PasswordState, Verified},
+    settings::{PasswordPolicy, PasswordPolicySettings},
+  },
+  db::{entities::user, init::Connection, tables::ConnectionExt},
+  error::{ErrorBody, ErrorReport, Result},
+};
+
+/// Why a password was rejected, sent to the client as
+/// `{"violations": [{"reason": ...}]}` with status 422.
+#[derive(Serialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[serde(tag = "reason", rename_all = "snake_case")]
+pub enum PolicyViolation {
+  TooShort { min_length: usize },
+  MissingLowercase,
+  MissingUppercase,
+  MissingDigit,
+  MissingSymbol,
+  Reused { history: u32 },
+  Breached,
+}
+
+/// Check a new plaintext password against the configured policy. Reuse is
+/// only checked if the `user` already exists.
+pub async fn check_password(
+  db: &Connection,
+  state: &PasswordState,
+  user: Option<&user::Model>,
+  password: &str,
+) -> Result<()> {
+  let policy = db
+    .settings()
+    .get_settings::<PasswordPolicySettings>()
+    .await?
+    .policy();
+  let mut violations = rule_violations(&policy, password);
+
+  if policy.breach_check
+    && let Some(corpus) = &state.breach_corpus
+    && is_breached(corpus, password).await?
+  {
+    violations.push(PolicyViolation::Breached);
+  }
+
+  if let Some(user) = user
+    && policy.history > 0
+    && is_reused(db, state, user, password, policy.history).await?
+  {
+    violations.push(PolicyViolation::Reused {
+      history: policy.history,
+    });
+  }
+
+  if violations.is_empty() {
+    return Ok(());
+  }
+
+  Err(ErrorReport::new(
+    eyre::Report::new(ErrorBody(serde_json::json!({ "violations": violations }))),
+    StatusCode::UNPROCESSABLE_ENTITY,
+  ))
+}
+
+fn rule_violations(policy: &PasswordPolicy, password: &str) -> Vec<PolicyViolation> {
+  let mut violations = Vec::new();
+
+  if password.chars().count() < policy.min_length {
+    violations.push(PolicyViolation::TooShort {
+      min_length: policy.min_length,
+    });
+  }
+  if policy.require_lowercase && !password.chars().any(char::is_lowercase) {
+    violations.push(PolicyViolation::MissingLowercase);
+  }
+  if policy.require_uppercase && !password.chars().any(char::is_uppercase) {
+    violations.push(PolicyViolation::MissingUppercase);
+  }
+  if policy.require_digit && !password.chars().any(|c| c.is_ascii_digit()) {
+    violations.push(PolicyViolation::MissingDigit);
+  }
+  if policy.require_symbol && password.chars().all(char::is_alphanumeric) {
+    violations.push(PolicyViolation::MissingSymbol);
+  }
+
+  violations
+}
+
+/// Look the SHA-1 of the password up in the file of its 5 character prefix,
+/// the same range split the k-anonymity API of breach databases uses.
+async fn is_breached(corpus: &Path, password: &str) -> Result<bool> {
+  let hash: String = Sha1::digest(password.as_bytes())
+    .iter()
+    .map(|byte| format!("{:02X}", byte))
+    .collect();
+  let (prefix, suffix) = hash.split_at(5);
+
+  let range = match fs::read_to_string(corpus.join(prefix)).await {
+    Ok(range) => range,
+    Err(e) if e.kind() == ErrorKind::NotFound => return Ok(false),
+    Err(e) => return Err(e.into()),
+  };
+
+  Ok(range.lines().any(|line| {
+    line
+      .split(':')
+      .next()
+      .is_some_and(|entry| entry.trim().eq_ignore_ascii_case(suffix))
+  }))
+}
+
+/// Compare with the current and the `history - 1` previous hashes.
+async fn is_reused(
+  db: &Connection,
+  state: &PasswordState,
+  user: &user::Model,
+  password: &str,
+  history: u32,
+) -> Result<bool> {
+  let mut hashes = vec![user.password.clone()];
+  hashes.extend(
+    db.password_history()
+      .recent(user.id, (history - 1).into())
+      .await?,
+  );
+
+  for hash in hashes {
+    if state.pw_verify_raw(&user.salt, &hash, password)? != Verified::Invalid {
+      return Ok(true);
+    }
+  }
+
+  Ok(false)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_rule_violations() {
+    let policy = PasswordPolicySettings {
+      password_min_length: Some(8),
+      password_require_lowercase: Some(true),
+      password_require_uppercase: Some(true),
+      password_require_digit: Some(true),
+      password_require_symbol: Some(true),
+      ..Default::default()
+    }
+    .policy();
+
+    assert_eq!(
+      rule_violations(&policy, "abc"),
+      vec![
+        PolicyViolation::TooShort { min_length: 8 },
+        PolicyViolation::MissingUppercase,
+        PolicyViolation::MissingDigit,
+        PolicyViolation::MissingSymbol,
+      ]
+    );
+    assert!(rule_violations(&policy, "Corr3ct-horse").is_empty());
+    // Length counts characters, not bytes.
+    let policy = PasswordPolicySettings {
+      password_min_length: Some(8),
+      ..Default::default()
+    }
+    .policy();
+    assert_eq!(
+      rule_violations(&policy, "äöüäöüä"),
+      vec![PolicyViolation::TooShort { min_length: 8 }]
+    );
+    // Without settings nothing beyond the breach check is enforced.
+    assert!(rule_violations(&PasswordPolicySettings::default().policy(), "x").is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_breach_corpus_lookup() {
+    let corpus = tempfile::tempdir().unwrap();
+    // SHA-1 of "password" is 5BAA61E4C9B93F3F0682250B6CF8331B7EE68FD8
+    std::fs::write(
+      corpus.path().join("5BAA6"),
+      "003D68EB55068C33ACE09247EE4C639306B:3\r\n1E4C9B93F3F0682250B6CF8331B7EE68FD8:9545824\r\n",
+    )
+    .unwrap();
+
+    assert!(is_breached(corpus.path(), "password").await.unwrap());
+    // Missing range files mean the password is unknown.
+    assert!(!is_breached(corpus.path(), "Corr3ct-horse").await.unwrap());
+  }
+}
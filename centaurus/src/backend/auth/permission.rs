@@ -1,3 +1,4 @@
+use axum::extract::{FromRequestParts, RawPathParams};
 use http::request::Parts;
 use uuid::Uuid;
 
@@ -12,6 +13,11 @@ pub trait Permission {
     ""
   }
 
+  /// Permissions that holding this one grants as well.
+  fn implies() -> Vec<&'static str> {
+    Vec::new()
+  }
+
   fn check(db: &Connection, user: Uuid, _parts: &Parts) -> impl Future<Output = Result<()>> + Send {
     async move {
       // Empty permission means no permission required
@@ -30,26 +36,138 @@ pub trait Permission {
   }
 }
 
+/// Make an application defined permission and its implications known, so
+/// wildcards and implied permissions resolve to it. Built-in permissions are
+/// always known.
+pub fn register_permission<P: Permission>() {
+  crate::db::permission::register_permission(P::name(), &P::implies());
+}
+
+pub(crate) fn builtin_permissions() -> Vec<(&'static str, Vec<&'static str>)> {
+  #[cfg_attr(not(feature = "scim"), allow(unused_mut))]
+  let mut permissions = vec![
+    (SettingsView::name(), SettingsView::implies()),
+    (SettingsEdit::name(), SettingsEdit::implies()),
+    (GroupView::name(), GroupView::implies()),
+    (GroupEdit::name(), GroupEdit::implies()),
+    (UserView::name(), UserView::implies()),
+    (UserEdit::name(), UserEdit::implies()),
+    (AclView::name(), AclView::implies()),
+    (AclEdit::name(), AclEdit::implies()),
+    (AuditView::name(), AuditView::implies()),
+  ];
+  #[cfg(feature = "scim")]
+  permissions.push((ScimProvision::name(), ScimProvision::implies()));
+  #[cfg(feature = "oidc_provider")]
+  permissions.push((OidcClientEdit::name(), OidcClientEdit::implies()));
+
+  permissions
+}
+
 pub fn permissions() -> Vec<&'static str> {
-  vec![
+  #[cfg_attr(not(feature = "scim"), allow(unused_mut))]
+  let mut permissions = vec![
     SettingsView::name(),
     SettingsEdit::name(),
     GroupView::name(),
     GroupEdit::name(),
     UserView::name(),
     UserEdit::name(),
-  ]
+    AclView::name(),
+    AclEdit::name(),
+    AuditView::name(),
+  ];
+  #[cfg(feature = "scim")]
+  permissions.push(ScimProvision::name());
+  #[cfg(feature = "oidc_provider")]
+  permissions.push(OidcClientEdit::name());
+
+  permissions
+}
+
+/// Check used by `resource_permission!`. A group permission grants access to
+/// every resource, otherwise the user needs a grant for the resource whose id
+/// is taken from the path parameter `param`.
+pub async fn check_resource(
+  db: &Connection,
+  user: Uuid,
+  parts: &Parts,
+  permission: &str,
+  resource_type: &str,
+  param: &str,
+) -> Result<()> {
+  if db.group().user_hash_permissions(user, permission).await? {
+    return Ok(());
+  }
+
+  let mut parts = parts.clone();
+  let Ok(params) = RawPathParams::from_request_parts(&mut parts, &()).await else {
+    bail!(FORBIDDEN, "insufficient permissions");
+  };
+  let Some((_, resource_id)) = params.iter().find(|(name, _)| *name == param) else {
+    bail!(FORBIDDEN, "insufficient permissions");
+  };
+
+  if !db
+    .acl()
+    .user_has_access(user, resource_type, resource_id, permission)
+    .await?
+  {
+    bail!(FORBIDDEN, "insufficient permissions");
+  }
+
+  Ok(())
 }
 
+/// Declare a permission, optionally with the permissions it implies:
+/// `permission!(SettingsEdit, "settings:edit", implies = [SettingsView]);`
 #[macro_export]
 macro_rules! permission {
-  ($type:ident, $name:literal) => {
+  ($type:ident, $name:literal $(, implies = [$($implied:ty),* $(,)?])?) => {
     pub struct $type;
 
     impl $crate::backend::auth::permission::Permission for $type {
       fn name() -> &'static str {
         $name
       }
+
+      fn implies() -> Vec<&'static str> {
+        vec![$($(<$implied as $crate::backend::auth::permission::Permission>::name()),*)?]
+      }
+    }
+  };
+}
+
+/// Declare a permission that can also be granted on single resources through
+/// the acl table, the resource id is read from the named path parameter:
+/// `resource_permission!(ProjectEdit, "project:edit", resource = "project", param = "id");`
+#[macro_export]
+macro_rules! resource_permission {
+  (
+    $type:ident,
+    $name:literal,
+    resource = $resource:literal,
+    param = $param:literal
+    $(, implies = [$($implied:ty),* $(,)?])?
+  ) => {
+    pub struct $type;
+
+    impl $crate::backend::auth::permission::Permission for $type {
+      fn name() -> &'static str {
+        $name
+      }
+
+      fn implies() -> Vec<&'static str> {
+        vec![$($(<$implied as $crate::backend::auth::permission::Permission>::name()),*)?]
+      }
+
+      fn check(
+        db: &$crate::db::init::Connection,
+        user: ::uuid::Uuid,
+        parts: &$crate::http::request::Parts,
+      ) -> impl Future<Output = $crate::error::Result<()>> + Send {
+        $crate::backend::auth::permission::check_resource(db, user, parts, $name, $resource, $param)
+      }
     }
   };
 }
@@ -59,15 +177,30 @@ permission!(NoPerm, "");
 
 // Settings
 permission!(SettingsView, "settings:view");
-permission!(SettingsEdit, "settings:edit");
+permission!(SettingsEdit, "settings:edit", implies = [SettingsView]);
 
 // Groups
 permission!(GroupView, "group:view");
-permission!(GroupEdit, "group:edit");
+permission!(GroupEdit, "group:edit", implies = [GroupView]);
 
 // Users
 permission!(UserView, "user:view");
-permission!(UserEdit, "user:edit");
+permission!(UserEdit, "user:edit", implies = [UserView]);
+
+// Resource grants
+permission!(AclView, "acl:view");
+permission!(AclEdit, "acl:edit", implies = [AclView]);
+
+// Audit log
+permission!(AuditView, "audit:view");
+
+// Provisioning through SCIM
+#[cfg(feature = "scim")]
+permission!(ScimProvision, "scim:provision");
+
+// Clients of the OIDC provider
+#[cfg(feature = "oidc_provider")]
+permission!(OidcClientEdit, "oidc_client:edit");
 
 #[cfg(test)]
 mod tests {
@@ -94,7 +227,9 @@ mod tests {
     let perms = permissions();
     assert!(perms.contains(&"settings:view"));
     assert!(perms.contains(&"user:edit"));
-    assert_eq!(perms.len(), 6);
+    let optional =
+      usize::from(cfg!(feature = "scim")) + usize::from(cfg!(feature = "oidc_provider"));
+    assert_eq!(perms.len(), 9 + optional);
     // NoPerm has an empty name.
     assert_eq!(NoPerm::name(), "");
   }
@@ -153,4 +288,49 @@ mod tests {
       .unwrap();
     assert!(UserView::check(&conn, uid, &parts).await.is_ok());
   }
+
+  #[tokio::test]
+  async fn test_wildcard_and_implied_permissions() {
+    let conn = db().await;
+    let parts = empty_parts();
+    let uid = conn
+      .user()
+      .create_user(
+        "u".into(),
+        "u@x.com".into(),
+        "h".into(),
+        "s".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    let group = conn.group().create_group("g".into()).await.unwrap();
+    conn
+      .group()
+      .add_permissions_to_group(group, vec!["user:*".into(), "settings:edit".into()])
+      .await
+      .unwrap();
+    conn
+      .group()
+      .add_users_to_group(group, vec![uid])
+      .await
+      .unwrap();
+
+    assert!(UserEdit::check(&conn, uid, &parts).await.is_ok());
+    assert!(SettingsView::check(&conn, uid, &parts).await.is_ok());
+    assert!(GroupView::check(&conn, uid, &parts).await.is_err());
+
+    let effective = conn.group().get_user_permissions(uid).await.unwrap();
+    for perm in [
+      "user:*",
+      "user:view",
+      "user:edit",
+      "settings:edit",
+      "settings:view",
+    ] {
+      assert!(effective.contains(&perm.to_string()), "missing {perm}");
+    }
+    assert!(!effective.contains(&"group:view".to_string()));
+  }
 }
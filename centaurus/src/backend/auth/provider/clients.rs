@@ -0,0 +1,292 @@
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with, put_with};
+use axum::Json;
+use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    BackendRouter,
+    auth::{
+      jwt_auth::JwtAuth,
+      permission::OidcClientEdit,
+      token::{generate_token, hash_token},
+    },
+    endpoints::audit::{Audit, AuditEvent},
+  },
+  bail,
+  db::{
+    init::Connection,
+    tables::{
+      ConnectionExt,
+      oidc_client::{ConsentInfo, OidcClientInfo},
+    },
+  },
+  error::Result,
+};
+
+pub fn router() -> BackendRouter {
+  BackendRouter::new()
+    .api_route("/clients", list_clients_route())
+    .api_route("/clients", create_client_route())
+    .api_route("/clients", edit_client_route())
+    .api_route("/clients", delete_client_route())
+    .api_route("/clients/secret", rotate_client_secret_route())
+    .api_route("/consents", list_consents_route())
+    .api_route("/consents", revoke_consent_route())
+}
+
+pub fn list_clients_route() -> ApiMethodRouter<()> {
+  get_with(list_clients, |op| op.id("listOidcClients"))
+}
+
+pub fn create_client_route() -> ApiMethodRouter<()> {
+  post_with(create_client, |op| op.id("createOidcClient"))
+}
+
+pub fn edit_client_route() -> ApiMethodRouter<()> {
+  put_with(edit_client, |op| op.id("editOidcClient"))
+}
+
+pub fn delete_client_route() -> ApiMethodRouter<()> {
+  delete_with(delete_client, |op| op.id("deleteOidcClient"))
+}
+
+pub fn rotate_client_secret_route() -> ApiMethodRouter<()> {
+  post_with(rotate_client_secret, |op| op.id("rotateOidcClientSecret"))
+}
+
+pub fn list_consents_route() -> ApiMethodRouter<()> {
+  get_with(list_consents, |op| op.id("listOidcConsents"))
+}
+
+pub fn revoke_consent_route() -> ApiMethodRouter<()> {
+  delete_with(revoke_consent, |op| op.id("revokeOidcConsent"))
+}
+
+/// Redirect uris have to be absolute and without fragment, RFC 6749 section
+/// 3.1.2.
+fn check_client(name: &str, redirect_uris: &[String]) -> Result<()> {
+  if name.trim().is_empty() {
+    bail!(BAD_REQUEST, "Name cannot be empty");
+  }
+  if redirect_uris.is_empty() {
+    bail!(BAD_REQUEST, "At least one redirect uri is required");
+  }
+  for uri in redirect_uris {
+    let Ok(url) = Url::parse(uri) else {
+      bail!(BAD_REQUEST, "Invalid redirect uri {}", uri);
+    };
+    if url.fragment().is_some() || uri.contains(char::is_whitespace) {
+      bail!(BAD_REQUEST, "Invalid redirect uri {}", uri);
+    }
+  }
+
+  Ok(())
+}
+
+async fn list_clients(
+  _auth: JwtAuth<OidcClientEdit>,
+  db: Connection,
+) -> Result<Json<Vec<OidcClientInfo>>> {
+  Ok(Json(db.oidc_client().list_clients().await?))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct CreateClient {
+  name: String,
+  redirect_uris: Vec<String>,
+  /// Public clients, e.g. single page apps, get no secret.
+  public: bool,
+  #[serde(default)]
+  skip_consent: bool,
+}
+
+#[derive(Serialize, Debug, JsonSchema)]
+struct ClientCredentials {
+  client_id: Uuid,
+  /// Only returned once, the server only keeps a hash.
+  client_secret: Option<String>,
+}
+
+async fn create_client(
+  auth: JwtAuth<OidcClientEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<CreateClient>,
+) -> Result<Json<ClientCredentials>> {
+  check_client(&req.name, &req.redirect_uris)?;
+
+  let client_secret = (!req.public).then(|| generate_token(48));
+  let txn = db.begin().await?;
+  let client_id = txn
+    .oidc_client()
+    .create_client(
+      req.name.trim().to_string(),
+      client_secret.as_deref().map(hash_token),
+      req.redirect_uris,
+      req.skip_consent,
+    )
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "oidc_client.create")
+        .target("oidc_client", client_id)
+        .after(
+          &txn
+            .oidc_client()
+            .get_client(client_id)
+            .await?
+            .map(OidcClientInfo::from),
+        ),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(Json(ClientCredentials {
+    client_id,
+    client_secret,
+  }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct EditClient {
+  uuid: Uuid,
+  name: String,
+  redirect_uris: Vec<String>,
+  skip_consent: bool,
+}
+
+async fn edit_client(
+  auth: JwtAuth<OidcClientEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<EditClient>,
+) -> Result<()> {
+  check_client(&req.name, &req.redirect_uris)?;
+
+  let Some(before) = db.oidc_client().get_client(req.uuid).await? else {
+    bail!(NOT_FOUND, "Client not found");
+  };
+  let txn = db.begin().await?;
+  txn
+    .oidc_client()
+    .edit_client(
+      req.uuid,
+      req.name.trim().to_string(),
+      req.redirect_uris,
+      req.skip_consent,
+    )
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "oidc_client.edit")
+        .target("oidc_client", req.uuid)
+        .before(&OidcClientInfo::from(before))
+        .after(
+          &txn
+            .oidc_client()
+            .get_client(req.uuid)
+            .await?
+            .map(OidcClientInfo::from),
+        ),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ClientRequest {
+  uuid: Uuid,
+}
+
+async fn delete_client(
+  auth: JwtAuth<OidcClientEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<ClientRequest>,
+) -> Result<()> {
+  let Some(before) = db.oidc_client().get_client(req.uuid).await? else {
+    bail!(NOT_FOUND, "Client not found");
+  };
+  let txn = db.begin().await?;
+  txn.oidc_client().delete_client(req.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "oidc_client.delete")
+        .target("oidc_client", req.uuid)
+        .before(&OidcClientInfo::from(before)),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(())
+}
+
+async fn rotate_client_secret(
+  auth: JwtAuth<OidcClientEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<ClientRequest>,
+) -> Result<Json<ClientCredentials>> {
+  let Some(client) = db.oidc_client().get_client(req.uuid).await? else {
+    bail!(NOT_FOUND, "Client not found");
+  };
+  if client.secret_hash.is_none() {
+    bail!(BAD_REQUEST, "Public clients have no secret");
+  }
+
+  let client_secret = generate_token(48);
+  let txn = db.begin().await?;
+  txn
+    .oidc_client()
+    .set_secret(req.uuid, hash_token(&client_secret))
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "oidc_client.rotate_secret").target("oidc_client", req.uuid),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(Json(ClientCredentials {
+    client_id: req.uuid,
+    client_secret: Some(client_secret),
+  }))
+}
+
+/// Clients the user allowed to access their account.
+async fn list_consents(auth: JwtAuth, db: Connection) -> Result<Json<Vec<ConsentInfo>>> {
+  Ok(Json(db.oidc_client().list_consents(auth.user_id).await?))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct RevokeConsent {
+  client: Uuid,
+}
+
+/// The client has to ask for consent again on its next authorization request.
+/// Tokens it already holds stay valid until they expire.
+async fn revoke_consent(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<RevokeConsent>,
+) -> Result<()> {
+  if !db
+    .oidc_client()
+    .revoke_consent(auth.user_id, req.client)
+    .await?
+  {
+    bail!(NOT_FOUND, "Consent not found");
+  }
+
+  Ok(())
+}
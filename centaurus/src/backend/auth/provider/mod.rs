@@ -0,0 +1,636 @@
+//! OpenID Connect provider for downstream applications, enabled with the
+//! `oidc_provider` feature. Clients use the authorization code flow with PKCE,
+//! the tokens are signed with the [`JwtState`] keys published at
+//! [`JWKS_PATH`]. Clients validate the issuer of the discovery document, so
+//! `auth_issuer` has to be the public url of the site, e.g.
+//! `https://auth.example.com`.
+//!
+//! Tokens issued to clients carry an `aud` claim and are rejected by the own
+//! api, see [`AUDIENCE_CLAIM`].
+
+use std::{collections::HashMap, time::Duration};
+
+use axum::{
+  Form, Json, Router,
+  extract::{Path, Query, RawQuery},
+  routing::get,
+};
+use axum_extra::{
+  TypedHeader,
+  headers::{
+    Authorization,
+    authorization::{Basic, Bearer},
+  },
+};
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use chrono::Utc;
+use http::{StatusCode, header};
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+use sha2::{Digest, Sha256};
+use tracing::debug;
+use url::{Url, form_urlencoded};
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    BackendRouter,
+    auth::{
+      jwks::JWKS_PATH,
+      jwt_auth::JwtAuth,
+      jwt_state::{AUDIENCE_CLAIM, JwtState},
+      oidc::login_redirect,
+      token::{generate_token, hash_token},
+    },
+    config::SiteConfig,
+    middleware::rate_limiter::RateLimiter,
+    request::redirect::Redirect,
+    store::Store,
+  },
+  bail,
+  db::{entities::oidc_client, init::Connection, tables::ConnectionExt},
+  error::{ErrorBody, ErrorReport, Result},
+};
+
+pub mod clients;
+
+pub const DISCOVERY_PATH: &str = "/.well-known/openid-configuration";
+const PROVIDER_PATH: &str = "api/auth/provider";
+/// Page of the frontend asking the user for consent, it receives the id of
+/// the pending request as `request`.
+const CONSENT_PAGE: &str = "/consent";
+const CODE_NAMESPACE: &str = "oidc_provider_code";
+const REQUEST_NAMESPACE: &str = "oidc_provider_request";
+const CODE_TTL: Duration = Duration::from_secs(60);
+const REQUEST_TTL: Duration = Duration::from_secs(600);
+const SCOPE_OPENID: &str = "openid";
+/// `groups` adds the group names and the effective permissions of the user.
+pub const SCOPES: [&str; 4] = [SCOPE_OPENID, "profile", "email", "groups"];
+
+pub fn router(rate_limiter: &mut RateLimiter) -> BackendRouter {
+  use aide::axum::routing::{get, post};
+
+  BackendRouter::new()
+    .route("/token", post(token))
+    .layer(rate_limiter.create_limiter("oidc_provider"))
+    .route("/authorize", get(authorize))
+    .route("/consent/{request}", get(consent_info))
+    .route("/consent", post(consent))
+    .route("/userinfo", get(userinfo).post(userinfo))
+    .merge(clients::router())
+}
+
+/// Served outside of `/api` next to the JWKS.
+pub fn well_known_router() -> Router {
+  Router::new().route(DISCOVERY_PATH, get(discovery))
+}
+
+async fn discovery(jwt: JwtState, site: SiteConfig) -> Result<Json<Value>> {
+  let endpoint = |name: &str| site.site_url.join(&format!("{PROVIDER_PATH}/{name}"));
+
+  Ok(Json(json!({
+    "issuer": jwt.iss,
+    "authorization_endpoint": endpoint("authorize")?,
+    "token_endpoint": endpoint("token")?,
+    "userinfo_endpoint": endpoint("userinfo")?,
+    "jwks_uri": site.site_url.join(JWKS_PATH.trim_start_matches('/'))?,
+    "response_types_supported": ["code"],
+    "grant_types_supported": ["authorization_code"],
+    "subject_types_supported": ["public"],
+    "id_token_signing_alg_values_supported": ["RS256"],
+    "scopes_supported": SCOPES,
+    "claims_supported": [
+      "sub", "iss", "aud", "exp", "iat", "nonce", "name", "email", "groups", "permissions",
+    ],
+    "code_challenge_methods_supported": ["S256"],
+    "token_endpoint_auth_methods_supported": ["client_secret_basic", "client_secret_post", "none"],
+  })))
+}
+
+#[derive(Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct AuthorizeQuery {
+  response_type: String,
+  client_id: Uuid,
+  redirect_uri: String,
+  scope: String,
+  state: Option<String>,
+  nonce: Option<String>,
+  code_challenge: Option<String>,
+  code_challenge_method: Option<String>,
+  /// Only `none` is supported, which fails instead of asking the user.
+  prompt: Option<String>,
+}
+
+/// Authorization request waiting for the consent of the user, kept in the
+/// [`Store`] under a random id.
+#[derive(Serialize, Deserialize, Debug)]
+struct PendingRequest {
+  user: Uuid,
+  client: Uuid,
+  redirect_uri: String,
+  scopes: Vec<String>,
+  state: Option<String>,
+  nonce: Option<String>,
+  code_challenge: String,
+}
+
+/// Issued code, kept in the [`Store`] under its hash until it is redeemed.
+#[derive(Serialize, Deserialize, Debug)]
+struct AuthorizationCode {
+  user: Uuid,
+  client: Uuid,
+  redirect_uri: String,
+  scopes: Vec<String>,
+  nonce: Option<String>,
+  code_challenge: String,
+}
+
+/// Url back to the client, `params` are appended to the query.
+fn client_redirect(redirect_uri: &str, params: &[(&str, Option<&str>)]) -> Result<String> {
+  let mut url = Url::parse(redirect_uri)?;
+  {
+    let mut query = url.query_pairs_mut();
+    for (name, value) in params {
+      if let Some(value) = value {
+        query.append_pair(name, value);
+      }
+    }
+  }
+  Ok(url.to_string())
+}
+
+fn error_url(redirect_uri: &str, error: &str, state: Option<&str>) -> Result<String> {
+  client_redirect(redirect_uri, &[("error", Some(error)), ("state", state)])
+}
+
+/// Supported scopes of the request, in the order of [`SCOPES`].
+fn requested_scopes(scope: &str) -> Vec<String> {
+  let requested: Vec<_> = scope.split_whitespace().collect();
+  SCOPES
+    .iter()
+    .filter(|scope| requested.contains(scope))
+    .map(|scope| scope.to_string())
+    .collect()
+}
+
+fn verify_pkce(code_verifier: &str, code_challenge: &str) -> bool {
+  let mut hasher = Sha256::new();
+  hasher.update(code_verifier.as_bytes());
+  BASE64_URL_SAFE_NO_PAD.encode(hasher.finalize()) == code_challenge
+}
+
+async fn get_client(db: &Connection, id: Uuid) -> Result<oidc_client::Model> {
+  let Some(client) = db.oidc_client().get_client(id).await? else {
+    bail!(BAD_REQUEST, "Unknown client");
+  };
+  Ok(client)
+}
+
+/// Validates the request and either redirects back to the client with a code,
+/// to the login page or to the consent page. Errors are only reported to the
+/// client once the redirect uri is known to belong to it.
+async fn authorize(
+  auth: Option<JwtAuth>,
+  db: Connection,
+  store: Store,
+  site: SiteConfig,
+  RawQuery(raw_query): RawQuery,
+  Query(query): Query<AuthorizeQuery>,
+) -> Result<Redirect> {
+  let client = get_client(&db, query.client_id).await?;
+  if !client.redirect_uris().contains(&query.redirect_uri) {
+    bail!(BAD_REQUEST, "Invalid redirect uri");
+  }
+
+  let error = |error: &str| {
+    error_url(&query.redirect_uri, error, query.state.as_deref()).map(Redirect::found)
+  };
+  if query.response_type != "code" {
+    return error("unsupported_response_type");
+  }
+  let scopes = requested_scopes(&query.scope);
+  if !scopes.iter().any(|scope| scope == SCOPE_OPENID) {
+    return error("invalid_scope");
+  }
+  let Some(code_challenge) = query.code_challenge.clone() else {
+    return error("invalid_request");
+  };
+  if query.code_challenge_method.as_deref() != Some("S256") {
+    return error("invalid_request");
+  }
+  let prompt_none = query.prompt.as_deref() == Some("none");
+
+  let Some(auth) = auth else {
+    if prompt_none {
+      return error("login_required");
+    }
+    // the login page returns to this request afterwards
+    let request = format!(
+      "/{PROVIDER_PATH}/authorize?{}",
+      raw_query.unwrap_or_default()
+    );
+    let login = format!(
+      "/login?{}",
+      form_urlencoded::Serializer::new(String::new())
+        .append_pair("redirect_to", &request)
+        .finish()
+    );
+    return Ok(login_redirect(site.site_url, &login, None));
+  };
+  if !db.user().get_user_by_id(auth.user_id).await?.active {
+    return error("access_denied");
+  }
+
+  let pending = PendingRequest {
+    user: auth.user_id,
+    client: client.id,
+    redirect_uri: query.redirect_uri.clone(),
+    scopes,
+    state: query.state.clone(),
+    nonce: query.nonce.clone(),
+    code_challenge,
+  };
+
+  let consented = db
+    .oidc_client()
+    .get_consent(auth.user_id, client.id)
+    .await?;
+  if client.skip_consent || pending.scopes.iter().all(|scope| consented.contains(scope)) {
+    return Ok(Redirect::found(issue_code(&store, pending).await?));
+  }
+  if prompt_none {
+    return error("consent_required");
+  }
+
+  let request = Uuid::new_v4();
+  store
+    .set_json(
+      REQUEST_NAMESPACE,
+      &request.to_string(),
+      &pending,
+      REQUEST_TTL,
+    )
+    .await?;
+  let consent = format!(
+    "{CONSENT_PAGE}?{}",
+    form_urlencoded::Serializer::new(String::new())
+      .append_pair("request", &request.to_string())
+      .finish()
+  );
+  Ok(login_redirect(site.site_url, &consent, None))
+}
+
+/// Url back to the client with a new code.
+async fn issue_code(store: &Store, pending: PendingRequest) -> Result<String> {
+  let code = generate_token(48);
+  let stored = AuthorizationCode {
+    user: pending.user,
+    client: pending.client,
+    redirect_uri: pending.redirect_uri,
+    scopes: pending.scopes,
+    nonce: pending.nonce,
+    code_challenge: pending.code_challenge,
+  };
+  store
+    .set_json(CODE_NAMESPACE, &hash_token(&code), &stored, CODE_TTL)
+    .await?;
+
+  client_redirect(
+    &stored.redirect_uri,
+    &[("code", Some(&code)), ("state", pending.state.as_deref())],
+  )
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct ConsentPath {
+  request: Uuid,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct ConsentInfo {
+  client: Uuid,
+  client_name: String,
+  scopes: Vec<String>,
+}
+
+/// What the consent page shows the user.
+async fn consent_info(
+  auth: JwtAuth,
+  db: Connection,
+  store: Store,
+  Path(path): Path<ConsentPath>,
+) -> Result<Json<ConsentInfo>> {
+  let pending: Option<PendingRequest> = store
+    .get_json(REQUEST_NAMESPACE, &path.request.to_string())
+    .await?;
+  let Some(pending) = pending.filter(|pending| pending.user == auth.user_id) else {
+    bail!(NOT_FOUND, "Authorization request not found");
+  };
+  let client = get_client(&db, pending.client).await?;
+
+  Ok(Json(ConsentInfo {
+    client: client.id,
+    client_name: client.name,
+    scopes: pending.scopes,
+  }))
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct ConsentReq {
+  request: Uuid,
+  approve: bool,
+}
+
+#[derive(Serialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct ConsentResponse {
+  /// Where the browser continues, back to the client in both cases.
+  redirect: String,
+}
+
+async fn consent(
+  auth: JwtAuth,
+  db: Connection,
+  store: Store,
+  Json(req): Json<ConsentReq>,
+) -> Result<Json<ConsentResponse>> {
+  let key = req.request.to_string();
+  let pending: Option<PendingRequest> = store.get_json(REQUEST_NAMESPACE, &key).await?;
+  if pending.is_none_or(|pending| pending.user != auth.user_id) {
+    bail!(NOT_FOUND, "Authorization request not found");
+  }
+  let Some(pending) = store
+    .take_json::<PendingRequest>(REQUEST_NAMESPACE, &key)
+    .await?
+  else {
+    bail!(NOT_FOUND, "Authorization request not found");
+  };
+
+  let redirect = if req.approve {
+    let mut scopes = db
+      .oidc_client()
+      .get_consent(auth.user_id, pending.client)
+      .await?;
+    for scope in &pending.scopes {
+      if !scopes.contains(scope) {
+        scopes.push(scope.clone());
+      }
+    }
+    db.oidc_client()
+      .grant_consent(auth.user_id, pending.client, scopes)
+      .await?;
+    issue_code(&store, pending).await?
+  } else {
+    error_url(
+      &pending.redirect_uri,
+      "access_denied",
+      pending.state.as_deref(),
+    )?
+  };
+
+  Ok(Json(ConsentResponse { redirect }))
+}
+
+/// Error of the token endpoint in the format of RFC 6749, section 5.2.
+fn token_error(status: StatusCode, error: &str, description: &str) -> ErrorReport {
+  ErrorReport::new(
+    eyre::Report::new(ErrorBody(json!({
+      "error": error,
+      "error_description": description,
+    }))),
+    status,
+  )
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct TokenReq {
+  grant_type: String,
+  code: String,
+  redirect_uri: String,
+  code_verifier: String,
+  /// Only needed if the client does not authenticate with basic auth.
+  client_id: Option<Uuid>,
+  client_secret: Option<String>,
+}
+
+#[derive(Serialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct TokenResponse {
+  access_token: String,
+  token_type: &'static str,
+  expires_in: i64,
+  id_token: String,
+  scope: String,
+}
+
+/// Authenticates the client with basic auth or the form and redeems the code.
+async fn token(
+  jwt: JwtState,
+  db: Connection,
+  store: Store,
+  basic: Option<TypedHeader<Authorization<Basic>>>,
+  Form(req): Form<TokenReq>,
+) -> Result<([(header::HeaderName, &'static str); 1], Json<TokenResponse>)> {
+  if req.grant_type != "authorization_code" {
+    return Err(token_error(
+      StatusCode::BAD_REQUEST,
+      "unsupported_grant_type",
+      "Only the authorization code grant is supported",
+    ));
+  }
+
+  let (client_id, client_secret) = match &basic {
+    Some(TypedHeader(Authorization(basic))) => (
+      basic.username().parse().ok(),
+      Some(basic.password().to_string()),
+    ),
+    None => (req.client_id, req.client_secret.clone()),
+  };
+  let invalid_client = || {
+    token_error(
+      StatusCode::UNAUTHORIZED,
+      "invalid_client",
+      "Client authentication failed",
+    )
+  };
+  let Some(client) = client_id else {
+    return Err(invalid_client());
+  };
+  let Some(client) = db.oidc_client().get_client(client).await? else {
+    return Err(invalid_client());
+  };
+  if let Some(secret_hash) = &client.secret_hash
+    && client_secret.as_deref().map(hash_token).as_ref() != Some(secret_hash)
+  {
+    return Err(invalid_client());
+  }
+
+  let invalid_grant =
+    |description| token_error(StatusCode::BAD_REQUEST, "invalid_grant", description);
+  let Some(code) = store
+    .take_json::<AuthorizationCode>(CODE_NAMESPACE, &hash_token(&req.code))
+    .await?
+  else {
+    return Err(invalid_grant("Invalid or expired code"));
+  };
+  if code.client != client.id || code.redirect_uri != req.redirect_uri {
+    return Err(invalid_grant("Code was issued for another client"));
+  }
+  if !verify_pkce(&req.code_verifier, &code.code_challenge) {
+    return Err(invalid_grant("Invalid code verifier"));
+  }
+  if !db.user().get_user_by_id(code.user).await?.active {
+    return Err(invalid_grant("Account is deactivated"));
+  }
+
+  let scope = code.scopes.join(" ");
+  let iat = json!(Utc::now().timestamp());
+  let access_token = jwt.create_raw_token_custom(
+    code.user,
+    HashMap::from([
+      (AUDIENCE_CLAIM.to_string(), json!(client.id)),
+      ("client_id".to_string(), json!(client.id)),
+      ("scope".to_string(), json!(scope)),
+      ("iat".to_string(), iat.clone()),
+    ]),
+  )?;
+
+  let mut claims = user_claims(&db, code.user, &code.scopes).await?;
+  claims.insert(AUDIENCE_CLAIM.to_string(), json!(client.id));
+  claims.insert("iat".to_string(), iat);
+  if let Some(nonce) = code.nonce {
+    claims.insert("nonce".to_string(), json!(nonce));
+  }
+  let id_token = jwt.create_raw_token_custom(code.user, claims)?;
+  debug!(
+    "Issued tokens of user {} to client {}",
+    code.user, client.id
+  );
+
+  Ok((
+    [(header::CACHE_CONTROL, "no-store")],
+    Json(TokenResponse {
+      access_token,
+      token_type: "Bearer",
+      expires_in: jwt.exp,
+      id_token,
+      scope,
+    }),
+  ))
+}
+
+/// Claims about the user the scopes grant access to.
+async fn user_claims(
+  db: &Connection,
+  user: Uuid,
+  scopes: &[String],
+) -> Result<HashMap<String, Value>> {
+  let has_scope = |name: &str| scopes.iter().any(|scope| scope == name);
+  let info = db.user().get_user_by_id(user).await?;
+  let mut claims = HashMap::new();
+
+  if has_scope("profile") {
+    claims.insert("name".to_string(), json!(info.name));
+  }
+  if has_scope("email") {
+    claims.insert("email".to_string(), json!(info.email));
+  }
+  if has_scope("groups") {
+    let groups: Vec<_> = db
+      .user()
+      .get_user_groups(user)
+      .await?
+      .into_iter()
+      .map(|group| group.name)
+      .collect();
+    claims.insert("groups".to_string(), json!(groups));
+    claims.insert(
+      "permissions".to_string(),
+      json!(db.group().get_user_permissions(user).await?),
+    );
+  }
+
+  Ok(claims)
+}
+
+/// Accepts access tokens issued by [`token`], which are the only ones carrying
+/// a `client_id` claim.
+async fn userinfo(
+  jwt: JwtState,
+  db: Connection,
+  TypedHeader(Authorization(bearer)): TypedHeader<Authorization<Bearer>>,
+) -> Result<Json<HashMap<String, Value>>> {
+  let Ok(claims) = jwt.validate_token(bearer.token()).await else {
+    bail!(UNAUTHORIZED, "Invalid access token");
+  };
+  let client = claims
+    .additional_claims
+    .get("client_id")
+    .and_then(Value::as_str)
+    .and_then(|client| client.parse().ok());
+  let Some(client) = client else {
+    bail!(UNAUTHORIZED, "Invalid access token");
+  };
+  if db.oidc_client().get_client(client).await?.is_none() {
+    bail!(UNAUTHORIZED, "Client has been removed");
+  }
+  if !db.user().get_user_by_id(claims.sub).await?.active {
+    bail!(UNAUTHORIZED, "Account is deactivated");
+  }
+
+  let scopes: Vec<_> = claims
+    .additional_claims
+    .get("scope")
+    .and_then(Value::as_str)
+    .unwrap_or_default()
+    .split_whitespace()
+    .map(str::to_string)
+    .collect();
+  let mut info = user_claims(&db, claims.sub, &scopes).await?;
+  info.insert("sub".to_string(), json!(claims.sub));
+
+  Ok(Json(info))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_requested_scopes() {
+    assert_eq!(
+      requested_scopes("email  openid unknown"),
+      vec!["openid", "email"]
+    );
+    assert!(requested_scopes("").is_empty());
+  }
+
+  #[test]
+  fn test_verify_pkce() {
+    // example of RFC 7636, appendix B
+    let verifier = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+    assert!(verify_pkce(
+      verifier,
+      "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+    ));
+    assert!(!verify_pkce(
+      "other",
+      "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM"
+    ));
+  }
+
+  #[test]
+  fn test_client_redirect_keeps_query() {
+    let redirect = client_redirect(
+      "https://app.example/cb?tenant=a",
+      &[("code", Some("abc")), ("state", None)],
+    )
+    .unwrap();
+    assert_eq!(redirect, "https://app.example/cb?tenant=a&code=abc");
+  }
+}
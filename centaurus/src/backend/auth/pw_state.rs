@@ -1,15 +1,18 @@
+use std::{collections::BTreeMap, path::PathBuf};
+
 use argon2::{
-  Argon2,
-  password_hash::{PasswordHasher, SaltString},
+  Algorithm, Argon2, KeyId, Params, ParamsBuilder, Version,
+  password_hash::{PasswordHash, PasswordHasher, PasswordVerifier, SaltString},
 };
 use axum::{Extension, extract::FromRequestParts};
 use base64::prelude::*;
 use rsa::{
   Pkcs1v15Encrypt, RsaPrivateKey, RsaPublicKey, pkcs1::EncodeRsaPublicKey, pkcs8::LineEnding,
+  rand_core::OsRng,
 };
-use tracing::instrument;
+use tracing::{instrument, warn};
 
-use crate::error::Result;
+use crate::{backend::auth::settings::AuthConfig, error::Result};
 
 #[derive(Clone, FromRequestParts)]
 #[cfg_attr(feature = "openapi", derive(aide::OperationIo))]
@@ -17,7 +20,23 @@ use crate::error::Result;
 pub struct PasswordState {
   key: RsaPrivateKey,
   pub pub_key: String,
+  /// Pepper of hashes from before the pepper version was recorded.
   pub pepper: Vec<u8>,
+  peppers: BTreeMap<u32, Vec<u8>>,
+  params: Params,
+  /// See [`AuthConfig::auth_breach_corpus`].
+  pub breach_corpus: Option<PathBuf>,
+}
+
+/// Outcome of [`PasswordState::pw_verify`].
+#[derive(Debug, PartialEq, Eq)]
+pub enum Verified {
+  Invalid,
+  /// `rehash` is a new hash of the password if the stored one uses outdated
+  /// parameters or pepper.
+  Valid {
+    rehash: Option<String>,
+  },
 }
 
 impl PasswordState {
@@ -25,8 +44,9 @@ impl PasswordState {
     Ok(self.key.decrypt(Pkcs1v15Encrypt, message)?)
   }
 
+  /// Decrypt a password the client encrypted with [`Self::pub_key`].
   #[instrument(skip(self, password))]
-  pub fn pw_hash(&self, salt: &str, password: &str) -> Result<String> {
+  pub fn decrypt_password(&self, password: &str) -> Result<String> {
     use http::StatusCode;
 
     use crate::error::ErrorReportStatusExt;
@@ -38,33 +58,150 @@ impl PasswordState {
       .key
       .decrypt(Pkcs1v15Encrypt, &bytes)
       .status(StatusCode::BAD_REQUEST)?;
-    let password = String::from_utf8_lossy(&pw_bytes).to_string();
+    Ok(String::from_utf8_lossy(&pw_bytes).to_string())
+  }
 
-    self.pw_hash_raw(salt, &password)
+  #[instrument(skip(self, password))]
+  pub fn pw_hash(&self, password: &str) -> Result<String> {
+    let password = self.decrypt_password(password)?;
+    self.pw_hash_raw(&password)
   }
 
+  /// PHC string with a random salt, the configured cost and the newest pepper,
+  /// whose version is stored as `keyid`.
   #[instrument(skip(self, password))]
-  pub fn pw_hash_raw(&self, salt: &str, password: &str) -> Result<String> {
-    hash_secret(&self.pepper, salt, password.as_bytes())
+  pub fn pw_hash_raw(&self, password: &str) -> Result<String> {
+    let (version, pepper) = self.current_pepper();
+    let params = ParamsBuilder::new()
+      .m_cost(self.params.m_cost())
+      .t_cost(self.params.t_cost())
+      .p_cost(self.params.p_cost())
+      .keyid(KeyId::new(version.to_string().as_bytes())?)
+      .build()?;
+    let argon2 = Argon2::new_with_secret(pepper, Algorithm::Argon2id, Version::V0x13, params)?;
+    let salt = SaltString::generate(OsRng);
+
+    Ok(
+      argon2
+        .hash_password(password.as_bytes(), &salt)?
+        .to_string(),
+    )
   }
 
-  pub async fn init(pepper: Vec<u8>, key: RsaPrivateKey) -> Self {
+  /// Check an encrypted password against the stored `hash`. `salt` is only
+  /// used for hashes from before the pepper version was recorded.
+  #[instrument(skip(self, hash, password))]
+  pub fn pw_verify(&self, salt: &str, hash: &str, password: &str) -> Result<Verified> {
+    let password = self.decrypt_password(password)?;
+    self.pw_verify_raw(salt, hash, &password)
+  }
+
+  #[instrument(skip(self, hash, password))]
+  pub fn pw_verify_raw(&self, salt: &str, hash: &str, password: &str) -> Result<Verified> {
+    let Ok(parsed) = PasswordHash::new(hash) else {
+      warn!("Stored password hash is not a PHC string");
+      return Ok(Verified::Invalid);
+    };
+    let params = Params::try_from(&parsed)?;
+
+    let valid = if params.keyid().is_empty() {
+      hash_secret(&self.pepper, salt, password.as_bytes())? == hash
+    } else {
+      let Some(pepper) = std::str::from_utf8(params.keyid())
+        .ok()
+        .and_then(|version| version.parse().ok())
+        .and_then(|version: u32| self.peppers.get(&version))
+      else {
+        warn!("Password hash uses an unknown pepper version");
+        return Ok(Verified::Invalid);
+      };
+      Argon2::new_with_secret(
+        pepper,
+        Algorithm::Argon2id,
+        Version::V0x13,
+        Params::default(),
+      )?
+      .verify_password(password.as_bytes(), &parsed)
+      .is_ok()
+    };
+
+    if !valid {
+      return Ok(Verified::Invalid);
+    }
+
+    let rehash = if self.needs_rehash(&parsed, &params) {
+      Some(self.pw_hash_raw(password)?)
+    } else {
+      None
+    };
+    Ok(Verified::Valid { rehash })
+  }
+
+  fn needs_rehash(&self, hash: &PasswordHash, params: &Params) -> bool {
+    let (version, _) = self.current_pepper();
+
+    hash.algorithm != Algorithm::Argon2id.ident()
+      || hash.version != Some(Version::V0x13.into())
+      || params.m_cost() != self.params.m_cost()
+      || params.t_cost() != self.params.t_cost()
+      || params.p_cost() != self.params.p_cost()
+      || params.keyid() != version.to_string().as_bytes()
+  }
+
+  fn current_pepper(&self) -> (u32, &[u8]) {
+    let (version, pepper) = self
+      .peppers
+      .last_key_value()
+      .expect("pepper version 0 always exists");
+    (*version, pepper)
+  }
+
+  pub async fn init(config: &AuthConfig, key: RsaPrivateKey) -> Self {
     let pub_key = RsaPublicKey::from(&key)
       .to_pkcs1_pem(LineEnding::CRLF)
       .expect("Failed to export Rsa Public Key");
 
+    let pepper = config.auth_pepper.as_bytes().to_vec();
     if pepper.len() > 32 {
       panic!("Pepper is longer than 32 characters");
     }
 
+    if config
+      .auth_peppers
+      .keys()
+      .any(|version| version.to_string().len() > Params::MAX_KEYID_LEN)
+    {
+      panic!("Pepper versions have at most 8 digits");
+    }
+    let mut peppers = BTreeMap::from([(0, pepper.clone())]);
+    peppers.extend(
+      config
+        .auth_peppers
+        .iter()
+        .map(|(version, pepper)| (*version, pepper.as_bytes().to_vec())),
+    );
+
+    let params = Params::new(
+      config.auth_hash_memory,
+      config.auth_hash_iterations,
+      config.auth_hash_parallelism,
+      None,
+    )
+    .expect("Invalid password hash parameters");
+
     Self {
       key,
       pub_key,
       pepper,
+      peppers,
+      params,
+      breach_corpus: config.auth_breach_corpus.clone(),
     }
   }
 }
 
+/// Hash from before the pepper version was recorded, the pepper is appended
+/// to the salt.
 pub fn hash_secret(pepper: &[u8], salt: &str, passphrase: &[u8]) -> Result<String> {
   let mut salt = BASE64_STANDARD_NO_PAD.decode(salt)?;
   salt.extend_from_slice(pepper);
@@ -81,13 +218,27 @@ pub fn hash_secret(pepper: &[u8], salt: &str, passphrase: &[u8]) -> Result<Strin
 #[cfg(test)]
 mod tests {
   use super::*;
-  use rsa::rand_core::OsRng;
 
-  #[tokio::test]
-  async fn test_pw_state_init() {
+  fn config(pepper: &str) -> AuthConfig {
+    AuthConfig {
+      auth_pepper: pepper.into(),
+      // cheap parameters to keep the tests fast
+      auth_hash_memory: 64,
+      auth_hash_iterations: 1,
+      auth_hash_parallelism: 1,
+      ..Default::default()
+    }
+  }
+
+  async fn state(config: &AuthConfig) -> PasswordState {
     let mut rng = OsRng;
     let key = RsaPrivateKey::new(&mut rng, 512).unwrap();
-    let state = PasswordState::init(vec![1, 2, 3], key).await;
+    PasswordState::init(config, key).await
+  }
+
+  #[tokio::test]
+  async fn test_pw_state_init() {
+    let state = state(&config("pepper")).await;
     assert!(state.pub_key.contains("BEGIN RSA PUBLIC KEY"));
   }
 
@@ -114,7 +265,7 @@ mod tests {
     let mut rng = OsRng;
     let key = RsaPrivateKey::new(&mut rng, 512).unwrap();
     let pub_key = RsaPublicKey::from(&key);
-    let state = PasswordState::init(vec![1, 2, 3], key).await;
+    let state = PasswordState::init(&config("pepper"), key).await;
 
     let ciphertext = pub_key
       .encrypt(&mut rng, Pkcs1v15Encrypt, b"secret-message")
@@ -123,29 +274,91 @@ mod tests {
   }
 
   #[tokio::test]
-  async fn test_pw_hash_matches_raw() {
+  async fn test_pw_hash_verifies_encrypted_password() {
     let mut rng = OsRng;
     let key = RsaPrivateKey::new(&mut rng, 512).unwrap();
     let pub_key = RsaPublicKey::from(&key);
-    let state = PasswordState::init(vec![7], key).await;
+    let state = PasswordState::init(&config("pepper"), key).await;
 
     // pw_hash decrypts a base64'd RSA-encrypted password; the resulting hash
-    // must equal hashing the plaintext directly.
+    // must verify against the plaintext.
     let ciphertext = pub_key
       .encrypt(&mut rng, Pkcs1v15Encrypt, b"hunter2")
       .unwrap();
     let encoded = BASE64_STANDARD.encode(&ciphertext);
 
-    let from_encrypted = state.pw_hash("c2FsdHNhbHQ", &encoded).unwrap();
-    let from_raw = state.pw_hash_raw("c2FsdHNhbHQ", "hunter2").unwrap();
-    assert_eq!(from_encrypted, from_raw);
+    let hash = state.pw_hash(&encoded).unwrap();
+    assert!(hash.starts_with("$argon2id$v=19$m=64,t=1,p=1,keyid="));
+    assert_eq!(
+      state.pw_verify("", &hash, &encoded).unwrap(),
+      Verified::Valid { rehash: None }
+    );
+    assert_eq!(
+      state.pw_verify_raw("", &hash, "hunter3").unwrap(),
+      Verified::Invalid
+    );
+    // Every hash has its own salt.
+    assert_ne!(hash, state.pw_hash_raw("hunter2").unwrap());
   }
 
   #[tokio::test]
   async fn test_pw_hash_rejects_invalid_base64() {
-    let mut rng = OsRng;
-    let key = RsaPrivateKey::new(&mut rng, 512).unwrap();
-    let state = PasswordState::init(vec![1], key).await;
-    assert!(state.pw_hash("c2FsdHNhbHQ", "!!!not base64!!!").is_err());
+    let state = state(&config("pepper")).await;
+    assert!(state.pw_hash("!!!not base64!!!").is_err());
+  }
+
+  #[tokio::test]
+  async fn test_legacy_hash_is_rehashed() {
+    let state = state(&config("pepper")).await;
+    let legacy = hash_secret(b"pepper", "c2FsdHNhbHQ", b"hunter2").unwrap();
+
+    let Verified::Valid {
+      rehash: Some(rehash),
+    } = state
+      .pw_verify_raw("c2FsdHNhbHQ", &legacy, "hunter2")
+      .unwrap()
+    else {
+      panic!("legacy hash should verify and be replaced");
+    };
+    assert_eq!(
+      state.pw_verify_raw("", &rehash, "hunter2").unwrap(),
+      Verified::Valid { rehash: None }
+    );
+    assert_eq!(
+      state
+        .pw_verify_raw("c2FsdHNhbHQ", &legacy, "hunter3")
+        .unwrap(),
+      Verified::Invalid
+    );
+  }
+
+  #[tokio::test]
+  async fn test_rotated_pepper_and_cost_trigger_rehash() {
+    let old = state(&config("pepper")).await;
+    let hash = old.pw_hash_raw("hunter2").unwrap();
+
+    let mut rotated = config("pepper");
+    rotated.auth_peppers.insert(1, "new-pepper".into());
+    let rotated = state(&rotated).await;
+    let Verified::Valid {
+      rehash: Some(rehash),
+    } = rotated.pw_verify_raw("", &hash, "hunter2").unwrap()
+    else {
+      panic!("hash with old pepper should verify and be replaced");
+    };
+    assert!(rehash.contains("keyid=MQ"));
+    // The old pepper is needed to verify the new hash.
+    assert_eq!(
+      old.pw_verify_raw("", &rehash, "hunter2").unwrap(),
+      Verified::Invalid
+    );
+
+    let mut costly = config("pepper");
+    costly.auth_hash_iterations = 2;
+    let costly = state(&costly).await;
+    assert!(matches!(
+      costly.pw_verify_raw("", &hash, "hunter2").unwrap(),
+      Verified::Valid { rehash: Some(_) }
+    ));
   }
 }
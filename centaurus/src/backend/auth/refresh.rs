@@ -0,0 +1,52 @@
+use aide::axum::routing::{ApiMethodRouter, post_with};
+use axum_extra::extract::CookieJar;
+use http::StatusCode;
+use serde::Serialize;
+use tracing::debug;
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    BackendRouter,
+    auth::jwt_state::{JwtState, REFRESH_COOKIE_NAME},
+    middleware::rate_limiter::RateLimiter,
+    request::response::TokenRes,
+    store::Store,
+  },
+  db::init::Connection,
+  error::{ErrorReportStatusExt, Result},
+};
+
+pub fn router(rate_limiter: &mut RateLimiter) -> BackendRouter {
+  BackendRouter::new()
+    .api_route("/", refresh_route())
+    .layer(rate_limiter.create_limiter("refresh"))
+}
+
+pub fn refresh_route() -> ApiMethodRouter<()> {
+  post_with(refresh, |op| op.id("refresh"))
+}
+
+#[derive(Serialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct RefreshResponse {
+  user: Uuid,
+}
+
+async fn refresh(
+  jwt: JwtState,
+  db: Connection,
+  store: Store,
+  cookies: CookieJar,
+) -> Result<(CookieJar, TokenRes<RefreshResponse>)> {
+  let token = cookies
+    .get(REFRESH_COOKIE_NAME)
+    .status_context(StatusCode::UNAUTHORIZED, "Missing refresh token")?
+    .value()
+    .to_string();
+
+  let (user, cookies) = jwt.refresh(&db, &store, cookies, &token).await?;
+  debug!("Refreshed session for user: {}", user);
+
+  Ok((cookies, TokenRes(RefreshResponse { user })))
+}
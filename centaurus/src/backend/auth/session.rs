@@ -0,0 +1,128 @@
+use std::convert::Infallible;
+
+use aide::OperationIo;
+use axum::extract::FromRequestParts;
+use http::{header::USER_AGENT, request::Parts};
+
+use crate::backend::request::client_ip::client_ip;
+
+/// Client details recorded for a new session.
+#[derive(Debug, Clone, Default, OperationIo)]
+pub struct ClientInfo {
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+}
+
+impl ClientInfo {
+  pub fn device(&self) -> Option<String> {
+    self.user_agent.as_deref().and_then(device_name)
+  }
+}
+
+impl<S: Sync> FromRequestParts<S> for ClientInfo {
+  type Rejection = Infallible;
+
+  async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+    let ip = client_ip(parts).map(|ip| ip.to_string());
+    let user_agent = parts
+      .headers
+      .get(USER_AGENT)
+      .and_then(|v| v.to_str().ok())
+      .map(|v| v.to_string());
+
+    Ok(ClientInfo { ip, user_agent })
+  }
+}
+
+/// Short human readable description like "Firefox on Linux".
+pub fn device_name(user_agent: &str) -> Option<String> {
+  // order matters, e.g. Edge and Chrome both contain "Chrome"
+  const BROWSERS: &[(&str, &str)] = &[
+    ("Edg/", "Edge"),
+    ("OPR/", "Opera"),
+    ("Firefox/", "Firefox"),
+    ("Chrome/", "Chrome"),
+    ("Safari/", "Safari"),
+  ];
+  const SYSTEMS: &[(&str, &str)] = &[
+    ("Android", "Android"),
+    ("iPhone", "iOS"),
+    ("iPad", "iPadOS"),
+    ("Windows", "Windows"),
+    ("Mac OS X", "macOS"),
+    ("Linux", "Linux"),
+  ];
+
+  let find = |list: &[(&str, &'static str)]| {
+    list
+      .iter()
+      .find(|(needle, _)| user_agent.contains(needle))
+      .map(|(_, name)| *name)
+  };
+
+  match (find(BROWSERS), find(SYSTEMS)) {
+    (Some(browser), Some(system)) => Some(format!("{browser} on {system}")),
+    (Some(name), None) | (None, Some(name)) => Some(name.to_string()),
+    (None, None) => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::backend::request::client_ip::TrustedProxies;
+  use axum::{body::Body, extract::ConnectInfo};
+  use std::net::SocketAddr;
+
+  #[test]
+  fn test_device_name() {
+    assert_eq!(
+      device_name("Mozilla/5.0 (X11; Linux x86_64; rv:128.0) Gecko/20100101 Firefox/128.0")
+        .as_deref(),
+      Some("Firefox on Linux")
+    );
+    assert_eq!(
+      device_name(
+        "Mozilla/5.0 (Windows NT 10.0; Win64; x64) AppleWebKit/537.36 (KHTML, like Gecko) Chrome/120.0.0.0 Safari/537.36 Edg/120.0.0.0"
+      )
+      .as_deref(),
+      Some("Edge on Windows")
+    );
+    assert_eq!(device_name("curl/8.0").as_deref(), None);
+  }
+
+  #[tokio::test]
+  async fn test_client_info_uses_forwarded_header_of_trusted_proxy() {
+    let (mut parts, _) = http::Request::builder()
+      .extension(ConnectInfo(SocketAddr::from(([192, 168, 1, 5], 1234))))
+      .extension(TrustedProxies::new(vec!["192.168.0.0/16".parse().unwrap()]))
+      .header("x-forwarded-for", "10.0.0.1, 10.0.0.2")
+      .header("x-real-ip", "10.0.0.3")
+      .header(USER_AGENT, "curl/8.0")
+      .body(Body::empty())
+      .unwrap()
+      .into_parts();
+
+    let info = ClientInfo::from_request_parts(&mut parts, &())
+      .await
+      .unwrap();
+    assert_eq!(info.ip.as_deref(), Some("10.0.0.2"));
+    assert_eq!(info.user_agent.as_deref(), Some("curl/8.0"));
+  }
+
+  #[tokio::test]
+  async fn test_client_info_ignores_headers_of_other_peers() {
+    let (mut parts, _) = http::Request::builder()
+      .extension(ConnectInfo(SocketAddr::from(([192, 168, 1, 5], 1234))))
+      .header("x-forwarded-for", "10.0.0.1")
+      .body(Body::empty())
+      .unwrap()
+      .into_parts();
+
+    let info = ClientInfo::from_request_parts(&mut parts, &())
+      .await
+      .unwrap();
+    assert_eq!(info.ip.as_deref(), Some("192.168.1.5"));
+    assert!(info.user_agent.is_none());
+  }
+}
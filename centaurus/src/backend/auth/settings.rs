@@ -1,4 +1,4 @@
-use std::convert::Infallible;
+use std::{collections::BTreeMap, convert::Infallible, path::PathBuf};
 
 use axum::{
   Extension,
@@ -25,6 +25,33 @@ pub struct UserSettings {
   pub oidc_pkce: Option<bool>,
   pub sso_instant_redirect: Option<bool>,
   pub sso_create_user: Option<bool>,
+  /// Further providers next to the one configured by the `oidc_*` fields,
+  /// which is available as [`DEFAULT_PROVIDER`].
+  pub oidc_providers: Option<Vec<OidcProviderSettings>>,
+}
+
+/// Name of the provider configured by the `oidc_*` fields of [`UserSettings`].
+pub const DEFAULT_PROVIDER: &str = "default";
+
+#[derive(Serialize, Deserialize, Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct OidcProviderSettings {
+  /// Used in the routes of the provider, e.g. `/oidc/{name}/url`.
+  pub name: String,
+  pub display_name: Option<String>,
+  pub enabled: Option<bool>,
+  pub issuer: Url,
+  pub client_id: String,
+  pub client_secret: Option<String>,
+  pub scopes: Option<String>,
+  pub group_sync: Option<bool>,
+  pub group_claim: Option<String>,
+  pub image_sync: Option<bool>,
+  pub pkce: Option<bool>,
+  pub create_user: Option<bool>,
+  /// Link the first login to an existing user with the same email. Only enable
+  /// this for providers that verify email addresses.
+  pub link_by_email: Option<bool>,
 }
 
 impl<S: Send + Sync> OptionalFromRequestParts<S> for UserSettings {
@@ -51,12 +78,9 @@ impl UserSettings {
         &self.oidc_client_secret,
       )
     {
-      let scopes = self
-        .oidc_scopes
-        .clone()
-        .map(|s| s.split(" ").map(|s| s.to_string()).collect())
-        .unwrap_or_else(|| vec!["openid".to_string()]);
       Some(OidcSettings {
+        name: DEFAULT_PROVIDER.to_string(),
+        display_name: None,
         issuer: issuer.clone(),
         client_id: client_id.clone(),
         client_secret: client_secret.clone(),
@@ -66,18 +90,66 @@ impl UserSettings {
           .oidc_group_claim
           .clone()
           .unwrap_or_else(|| "groups".to_string()),
-        scopes,
+        scopes: parse_scopes(self.oidc_scopes.as_deref()),
         create_user: self.sso_create_user.unwrap_or(false),
         pkce: self.oidc_pkce.unwrap_or(false),
+        link_by_email: true,
       })
     } else {
       None
     }
   }
+
+  /// Every enabled provider, starting with [`DEFAULT_PROVIDER`].
+  pub fn oidc_providers(&self) -> Vec<OidcSettings> {
+    let mut providers: Vec<_> = self.oidc_settings().into_iter().collect();
+    providers.extend(
+      self
+        .oidc_providers
+        .iter()
+        .flatten()
+        .filter_map(OidcProviderSettings::oidc_settings),
+    );
+    providers
+  }
+}
+
+impl OidcProviderSettings {
+  pub fn oidc_settings(&self) -> Option<OidcSettings> {
+    if !self.enabled.unwrap_or(true) {
+      return None;
+    }
+
+    Some(OidcSettings {
+      name: self.name.clone(),
+      display_name: self.display_name.clone(),
+      issuer: self.issuer.clone(),
+      client_id: self.client_id.clone(),
+      client_secret: self.client_secret.clone()?,
+      scopes: parse_scopes(self.scopes.as_deref()),
+      group_sync: self.group_sync.unwrap_or(false),
+      group_claim: self
+        .group_claim
+        .clone()
+        .unwrap_or_else(|| "groups".to_string()),
+      pkce: self.pkce.unwrap_or(true),
+      image_sync: self.image_sync.unwrap_or(false),
+      create_user: self.create_user.unwrap_or(false),
+      link_by_email: self.link_by_email.unwrap_or(false),
+    })
+  }
+}
+
+fn parse_scopes(scopes: Option<&str>) -> Vec<String> {
+  scopes
+    .map(|s| s.split(" ").map(|s| s.to_string()).collect())
+    .unwrap_or_else(|| vec!["openid".to_string()])
 }
 
 #[derive(Debug, Clone)]
 pub struct OidcSettings {
+  pub name: String,
+  pub display_name: Option<String>,
   pub issuer: Url,
   pub client_id: String,
   pub client_secret: String,
@@ -87,13 +159,370 @@ pub struct OidcSettings {
   pub pkce: bool,
   pub image_sync: bool,
   pub create_user: bool,
+  pub link_by_email: bool,
+}
+
+/// Per account protection against password guessing. Repeated failed logins
+/// delay the next attempt, reaching the threshold locks the account.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "db", derive(crate::Settings))]
+#[cfg_attr(feature = "db", settings(id = 5))]
+pub struct LockoutSettings {
+  pub lockout_enabled: Option<bool>,
+  /// Failed logins after which the account is locked.
+  pub lockout_threshold: Option<u32>,
+  /// Seconds the account stays locked. Failures older than this are forgotten.
+  pub lockout_duration: Option<i64>,
+  /// Seconds to wait after the second failed login, doubled with every further
+  /// failure.
+  pub lockout_delay: Option<i64>,
+}
+
+impl LockoutSettings {
+  pub fn policy(&self) -> Option<LockoutPolicy> {
+    if !self.lockout_enabled.unwrap_or(true) {
+      return None;
+    }
+
+    Some(LockoutPolicy {
+      threshold: self.lockout_threshold.unwrap_or(5).max(1) as i32,
+      duration: self.lockout_duration.unwrap_or(60 * 15).max(0),
+      delay: self.lockout_delay.unwrap_or(1).max(0),
+    })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LockoutPolicy {
+  pub threshold: i32,
+  pub duration: i64,
+  pub delay: i64,
+}
+
+impl LockoutPolicy {
+  /// Seconds to wait after `failed` failed logins, never longer than a lockout.
+  /// A single typo does not cause a delay.
+  pub fn delay_after(&self, failed: i32) -> i64 {
+    if failed <= 1 {
+      return 0;
+    }
+
+    let factor = 1i64 << (failed - 2).min(32);
+    self.delay.saturating_mul(factor).min(self.duration)
+  }
+}
+
+/// Requirements for new passwords. Unset fields use the defaults of
+/// [`PasswordPolicy`].
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "db", derive(crate::Settings))]
+#[cfg_attr(feature = "db", settings(id = 6))]
+pub struct PasswordPolicySettings {
+  pub password_min_length: Option<u32>,
+  pub password_require_lowercase: Option<bool>,
+  pub password_require_uppercase: Option<bool>,
+  pub password_require_digit: Option<bool>,
+  pub password_require_symbol: Option<bool>,
+  /// Number of previous passwords that can not be used again, including the
+  /// current one.
+  pub password_history: Option<u32>,
+  /// Reject passwords found in the breach corpus, if one is configured.
+  pub password_breach_check: Option<bool>,
+}
+
+impl PasswordPolicySettings {
+  pub fn policy(&self) -> PasswordPolicy {
+    PasswordPolicy {
+      min_length: self.password_min_length.unwrap_or(0) as usize,
+      require_lowercase: self.password_require_lowercase.unwrap_or(false),
+      require_uppercase: self.password_require_uppercase.unwrap_or(false),
+      require_digit: self.password_require_digit.unwrap_or(false),
+      require_symbol: self.password_require_symbol.unwrap_or(false),
+      history: self.password_history.unwrap_or(0),
+      breach_check: self.password_breach_check.unwrap_or(true),
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PasswordPolicy {
+  pub min_length: usize,
+  pub require_lowercase: bool,
+  pub require_uppercase: bool,
+  pub require_digit: bool,
+  pub require_symbol: bool,
+  pub history: u32,
+  pub breach_check: bool,
+}
+
+/// SAML 2.0 identity provider users can log in with. Users are created and
+/// linked like with a named OIDC provider.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "db", derive(crate::Settings))]
+#[cfg_attr(feature = "db", settings(id = 7))]
+pub struct SamlSettings {
+  pub saml_enabled: Option<bool>,
+  /// Entity id of the identity provider, the issuer of its responses.
+  pub saml_idp_entity_id: Option<String>,
+  /// Single sign-on service of the identity provider.
+  pub saml_idp_sso_url: Option<Url>,
+  /// Certificate the identity provider signs with, PEM or base64 DER.
+  pub saml_idp_certificate: Option<String>,
+  /// Binding the authentication request is sent with, redirect by default.
+  pub saml_binding: Option<SamlBinding>,
+  /// Entity id of this service provider, the metadata URL by default.
+  pub saml_sp_entity_id: Option<String>,
+  /// Attribute with the email of the user, the name id is used if missing.
+  pub saml_email_attribute: Option<String>,
+  /// Attribute with the display name of the user, the email by default.
+  pub saml_name_attribute: Option<String>,
+  pub saml_group_sync: Option<bool>,
+  pub saml_group_attribute: Option<String>,
+  /// Group attribute values mapped to group names. If set, values without a
+  /// mapping are ignored.
+  pub saml_group_mapping: Option<BTreeMap<String, String>>,
+  pub saml_create_user: Option<bool>,
+  /// Link the first login to an existing user with the same email. Only enable
+  /// this for identity providers that verify email addresses.
+  pub saml_link_by_email: Option<bool>,
+}
+
+#[derive(Serialize, Deserialize, Debug, Clone, Copy, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[serde(rename_all = "snake_case")]
+pub enum SamlBinding {
+  #[default]
+  Redirect,
+  Post,
+}
+
+impl SamlSettings {
+  pub fn saml(&self) -> Option<SamlConfig> {
+    if !self.saml_enabled.unwrap_or(false) {
+      return None;
+    }
+    let (Some(idp_entity_id), Some(idp_sso_url), Some(idp_certificate)) = (
+      &self.saml_idp_entity_id,
+      &self.saml_idp_sso_url,
+      &self.saml_idp_certificate,
+    ) else {
+      return None;
+    };
+
+    Some(SamlConfig {
+      idp_entity_id: idp_entity_id.clone(),
+      idp_sso_url: idp_sso_url.clone(),
+      idp_certificate: idp_certificate.clone(),
+      binding: self.saml_binding.unwrap_or_default(),
+      sp_entity_id: self.saml_sp_entity_id.clone(),
+      email_attribute: self
+        .saml_email_attribute
+        .clone()
+        .unwrap_or_else(|| "email".to_string()),
+      name_attribute: self
+        .saml_name_attribute
+        .clone()
+        .unwrap_or_else(|| "name".to_string()),
+      group_sync: self.saml_group_sync.unwrap_or(false),
+      group_attribute: self
+        .saml_group_attribute
+        .clone()
+        .unwrap_or_else(|| "groups".to_string()),
+      group_mapping: self.saml_group_mapping.clone(),
+      create_user: self.saml_create_user.unwrap_or(false),
+      link_by_email: self.saml_link_by_email.unwrap_or(false),
+    })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SamlConfig {
+  pub idp_entity_id: String,
+  pub idp_sso_url: Url,
+  pub idp_certificate: String,
+  pub binding: SamlBinding,
+  pub sp_entity_id: Option<String>,
+  pub email_attribute: String,
+  pub name_attribute: String,
+  pub group_sync: bool,
+  pub group_attribute: String,
+  pub group_mapping: Option<BTreeMap<String, String>>,
+  pub create_user: bool,
+  pub link_by_email: bool,
+}
+
+impl SamlConfig {
+  /// Group names for the values of the group attribute.
+  pub fn groups(&self, values: &[String]) -> Vec<String> {
+    map_groups(self.group_mapping.as_ref(), values)
+  }
+}
+
+fn map_groups(mapping: Option<&BTreeMap<String, String>>, values: &[String]) -> Vec<String> {
+  match mapping {
+    Some(mapping) => values
+      .iter()
+      .filter_map(|value| mapping.get(value).cloned())
+      .collect(),
+    None => values.to_vec(),
+  }
+}
+
+/// LDAP directory, e.g. Active Directory, users can log in to with the password
+/// flow. Users are created and linked like with a named OIDC provider.
+#[derive(Serialize, Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[cfg_attr(feature = "db", derive(crate::Settings))]
+#[cfg_attr(feature = "db", settings(id = 8))]
+pub struct LdapSettings {
+  pub ldap_enabled: Option<bool>,
+  /// `ldap://` or `ldaps://` url of the server.
+  pub ldap_url: Option<Url>,
+  /// Upgrade `ldap://` connections with StartTLS.
+  pub ldap_starttls: Option<bool>,
+  /// Account users and groups are searched with, anonymous if missing.
+  pub ldap_bind_dn: Option<String>,
+  pub ldap_bind_password: Option<String>,
+  pub ldap_user_base_dn: Option<String>,
+  /// Filter for the entry of a login, `{login}` is replaced with the escaped
+  /// login. `(mail={login})` by default.
+  pub ldap_user_filter: Option<String>,
+  /// Attribute that identifies users across renames, e.g. `entryUUID` or
+  /// `objectGUID`. The DN is used if missing.
+  pub ldap_id_attribute: Option<String>,
+  pub ldap_email_attribute: Option<String>,
+  pub ldap_name_attribute: Option<String>,
+  pub ldap_group_sync: Option<bool>,
+  /// Base of the group search, the user base by default.
+  pub ldap_group_base_dn: Option<String>,
+  /// Filter for the groups of a user, `{dn}` is replaced with the escaped DN
+  /// of the user. `(member={dn})` by default.
+  pub ldap_group_filter: Option<String>,
+  pub ldap_group_name_attribute: Option<String>,
+  /// Directory group names mapped to group names. If set, groups without a
+  /// mapping are ignored.
+  pub ldap_group_mapping: Option<BTreeMap<String, String>>,
+  /// Seconds between the group syncs of all LDAP users, 0 disables them.
+  pub ldap_sync_interval: Option<u64>,
+  pub ldap_create_user: Option<bool>,
+  /// Link the first login to an existing local user with the same email.
+  pub ldap_link_by_email: Option<bool>,
+}
+
+impl LdapSettings {
+  pub fn ldap(&self) -> Option<LdapConfig> {
+    if !self.ldap_enabled.unwrap_or(false) {
+      return None;
+    }
+    let (Some(url), Some(user_base_dn)) = (&self.ldap_url, &self.ldap_user_base_dn) else {
+      return None;
+    };
+
+    Some(LdapConfig {
+      url: url.clone(),
+      starttls: self.ldap_starttls.unwrap_or(false),
+      bind_dn: self.ldap_bind_dn.clone(),
+      bind_password: self.ldap_bind_password.clone(),
+      user_base_dn: user_base_dn.clone(),
+      user_filter: self
+        .ldap_user_filter
+        .clone()
+        .unwrap_or_else(|| "(mail={login})".to_string()),
+      id_attribute: self.ldap_id_attribute.clone(),
+      email_attribute: self
+        .ldap_email_attribute
+        .clone()
+        .unwrap_or_else(|| "mail".to_string()),
+      name_attribute: self
+        .ldap_name_attribute
+        .clone()
+        .unwrap_or_else(|| "cn".to_string()),
+      group_sync: self.ldap_group_sync.unwrap_or(false),
+      group_base_dn: self
+        .ldap_group_base_dn
+        .clone()
+        .unwrap_or_else(|| user_base_dn.clone()),
+      group_filter: self
+        .ldap_group_filter
+        .clone()
+        .unwrap_or_else(|| "(member={dn})".to_string()),
+      group_name_attribute: self
+        .ldap_group_name_attribute
+        .clone()
+        .unwrap_or_else(|| "cn".to_string()),
+      group_mapping: self.ldap_group_mapping.clone(),
+      sync_interval: self.ldap_sync_interval.unwrap_or(60 * 60),
+      create_user: self.ldap_create_user.unwrap_or(false),
+      link_by_email: self.ldap_link_by_email.unwrap_or(false),
+    })
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LdapConfig {
+  pub url: Url,
+  pub starttls: bool,
+  pub bind_dn: Option<String>,
+  pub bind_password: Option<String>,
+  pub user_base_dn: String,
+  pub user_filter: String,
+  pub id_attribute: Option<String>,
+  pub email_attribute: String,
+  pub name_attribute: String,
+  pub group_sync: bool,
+  pub group_base_dn: String,
+  pub group_filter: String,
+  pub group_name_attribute: String,
+  pub group_mapping: Option<BTreeMap<String, String>>,
+  pub sync_interval: u64,
+  pub create_user: bool,
+  pub link_by_email: bool,
+}
+
+impl LdapConfig {
+  /// Group names for the names of the directory groups.
+  pub fn groups(&self, values: &[String]) -> Vec<String> {
+    map_groups(self.group_mapping.as_ref(), values)
+  }
 }
 
 #[derive(Serialize, Deserialize, Debug, Clone)]
+#[serde(default)]
 pub struct AuthConfig {
+  /// Pepper version 0.
   pub auth_pepper: String,
+  /// Further peppers by version. New hashes use the highest version, hashes
+  /// with older ones still verify and are replaced on the next login.
+  pub auth_peppers: BTreeMap<u32, String>,
+  /// Argon2 memory cost in KiB. Hashes with other costs are replaced on the
+  /// next login.
+  pub auth_hash_memory: u32,
+  pub auth_hash_iterations: u32,
+  pub auth_hash_parallelism: u32,
   pub auth_issuer: String,
   pub auth_jwt_expiration: i64,
+  /// Lifetime of a refresh token. Every refresh issues a new one, so sessions
+  /// stay alive as long as they are used at least once in this window.
+  pub auth_refresh_expiration: i64,
+  /// Seconds a rotated refresh token still returns the token that replaced it,
+  /// so tabs refreshing at the same time don't count as reuse.
+  pub auth_refresh_grace: i64,
+  /// Seconds after which a new JWT signing key is generated. `None` disables
+  /// scheduled rotation.
+  pub auth_jwt_key_rotation: Option<i64>,
+  /// Seconds audit log entries are kept. `None` keeps them forever.
+  pub auth_audit_retention: Option<i64>,
+  /// Seconds a password reset link stays valid.
+  pub auth_reset_expiration: i64,
+  /// Seconds an invitation link stays valid.
+  pub auth_invite_expiration: i64,
+  /// Directory with breached password hashes in the k-anonymity format: one
+  /// file per uppercase 5 character SHA-1 prefix, containing `SUFFIX:COUNT`
+  /// lines.
+  pub auth_breach_corpus: Option<PathBuf>,
 }
 
 impl Default for AuthConfig {
@@ -101,7 +530,18 @@ impl Default for AuthConfig {
     Self {
       auth_issuer: "centaurus_auth".to_string(),
       auth_pepper: "__CENTAURUS_PEPPER__".to_string(),
-      auth_jwt_expiration: 60 * 60 * 24 * 7, // 7 days
+      auth_peppers: BTreeMap::new(),
+      auth_hash_memory: argon2::Params::DEFAULT_M_COST,
+      auth_hash_iterations: argon2::Params::DEFAULT_T_COST,
+      auth_hash_parallelism: argon2::Params::DEFAULT_P_COST,
+      auth_jwt_expiration: 60 * 15,                   // 15 minutes
+      auth_refresh_expiration: 60 * 60 * 24 * 30,     // 30 days
+      auth_refresh_grace: 5,                          // 5 seconds
+      auth_jwt_key_rotation: Some(60 * 60 * 24 * 30), // 30 days
+      auth_audit_retention: Some(60 * 60 * 24 * 365), // 1 year
+      auth_reset_expiration: 60 * 60,                 // 1 hour
+      auth_invite_expiration: 60 * 60 * 24 * 7,       // 7 days
+      auth_breach_corpus: None,
     }
   }
 }
@@ -116,6 +556,83 @@ mod tests {
     assert_eq!(config.auth_issuer, "centaurus_auth");
   }
 
+  #[test]
+  fn test_saml_settings() {
+    let mut settings = SamlSettings {
+      saml_enabled: Some(true),
+      saml_idp_entity_id: Some("https://idp.example".to_string()),
+      saml_idp_sso_url: Some(Url::parse("https://idp.example/sso").unwrap()),
+      ..Default::default()
+    };
+    // Without a certificate responses can not be verified.
+    assert!(settings.saml().is_none());
+
+    settings.saml_idp_certificate = Some("cert".to_string());
+    let saml = settings.saml().unwrap();
+    assert_eq!(saml.binding, SamlBinding::Redirect);
+    assert_eq!(saml.email_attribute, "email");
+    assert_eq!(
+      saml.groups(&["a".to_string(), "b".to_string()]),
+      vec!["a", "b"]
+    );
+
+    settings.saml_group_mapping = Some(BTreeMap::from([("a".to_string(), "Admins".to_string())]));
+    let saml = settings.saml().unwrap();
+    assert_eq!(
+      saml.groups(&["a".to_string(), "b".to_string()]),
+      vec!["Admins"]
+    );
+
+    settings.saml_enabled = Some(false);
+    assert!(settings.saml().is_none());
+  }
+
+  #[test]
+  fn test_ldap_settings() {
+    let mut settings = LdapSettings {
+      ldap_enabled: Some(true),
+      ldap_url: Some(Url::parse("ldap://ldap.example.com").unwrap()),
+      ..Default::default()
+    };
+    // Without a base users can not be searched.
+    assert!(settings.ldap().is_none());
+
+    settings.ldap_user_base_dn = Some("ou=people,dc=example,dc=com".to_string());
+    let ldap = settings.ldap().unwrap();
+    assert_eq!(ldap.user_filter, "(mail={login})");
+    assert_eq!(ldap.group_base_dn, "ou=people,dc=example,dc=com");
+    assert_eq!(ldap.sync_interval, 3600);
+
+    settings.ldap_group_mapping = Some(BTreeMap::from([(
+      "admins".to_string(),
+      "Admins".to_string(),
+    )]));
+    let ldap = settings.ldap().unwrap();
+    assert_eq!(
+      ldap.groups(&["admins".to_string(), "staff".to_string()]),
+      vec!["Admins"]
+    );
+
+    settings.ldap_enabled = None;
+    assert!(settings.ldap().is_none());
+  }
+
+  #[test]
+  fn test_lockout_policy_defaults() {
+    let policy = LockoutSettings::default().policy().unwrap();
+    assert_eq!(policy.threshold, 5);
+    assert_eq!(policy.delay_after(1), 0);
+    assert_eq!(policy.delay_after(2), 1);
+    assert_eq!(policy.delay_after(4), 4);
+    assert_eq!(policy.delay_after(40), policy.duration);
+
+    let disabled = LockoutSettings {
+      lockout_enabled: Some(false),
+      ..Default::default()
+    };
+    assert!(disabled.policy().is_none());
+  }
+
   #[test]
   fn test_oidc_settings_none() {
     let settings = UserSettings::default();
@@ -136,4 +653,51 @@ mod tests {
     assert_eq!(oidc.issuer.as_str(), "http://issuer.com/");
     assert_eq!(oidc.client_id, "client");
   }
+
+  #[test]
+  fn test_oidc_providers() {
+    let provider = OidcProviderSettings {
+      name: "github".into(),
+      display_name: None,
+      enabled: None,
+      issuer: Url::parse("http://github.example").unwrap(),
+      client_id: "client".into(),
+      client_secret: Some("secret".into()),
+      scopes: Some("openid email".into()),
+      group_sync: None,
+      group_claim: None,
+      image_sync: None,
+      pkce: None,
+      create_user: None,
+      link_by_email: None,
+    };
+    let settings = UserSettings {
+      oidc_enabled: Some(true),
+      oidc_issuer: Some(Url::parse("http://issuer.com").unwrap()),
+      oidc_client_id: Some("client".to_string()),
+      oidc_client_secret: Some("secret".to_string()),
+      oidc_providers: Some(vec![
+        provider.clone(),
+        OidcProviderSettings {
+          name: "disabled".into(),
+          enabled: Some(false),
+          ..provider.clone()
+        },
+        OidcProviderSettings {
+          name: "no_secret".into(),
+          client_secret: None,
+          ..provider
+        },
+      ]),
+      ..Default::default()
+    };
+
+    let providers = settings.oidc_providers();
+    let names: Vec<_> = providers.iter().map(|p| p.name.as_str()).collect();
+    assert_eq!(names, vec![DEFAULT_PROVIDER, "github"]);
+    // Named providers default to PKCE and do not trust emails.
+    assert!(providers[1].pkce);
+    assert!(!providers[1].link_by_email);
+    assert_eq!(providers[1].scopes, vec!["openid", "email"]);
+  }
 }
@@ -0,0 +1,41 @@
+use base64::{Engine, prelude::BASE64_URL_SAFE_NO_PAD};
+use rand::seq::IndexedRandom;
+use sha2::{Digest, Sha256};
+
+use crate::backend::auth::oidc::URL_SAFE_CHARS;
+
+/// Random url safe token for opaque secrets like refresh tokens.
+pub fn generate_token(len: usize) -> String {
+  let mut rng = rand::rng();
+  (0..len)
+    .map(|_| *URL_SAFE_CHARS.choose(&mut rng).unwrap() as char)
+    .collect()
+}
+
+/// Opaque tokens are high entropy, so a plain SHA-256 is enough to store them.
+pub fn hash_token(token: &str) -> String {
+  let mut hasher = Sha256::new();
+  hasher.update(token.as_bytes());
+  BASE64_URL_SAFE_NO_PAD.encode(hasher.finalize())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn test_generate_token_is_url_safe_and_unique() {
+    let a = generate_token(64);
+    let b = generate_token(64);
+    assert_eq!(a.len(), 64);
+    assert!(a.bytes().all(|c| URL_SAFE_CHARS.contains(&c)));
+    assert_ne!(a, b);
+  }
+
+  #[test]
+  fn test_hash_token_is_deterministic() {
+    assert_eq!(hash_token("token"), hash_token("token"));
+    assert_ne!(hash_token("token"), hash_token("other"));
+    assert_ne!(hash_token("token"), "token");
+  }
+}
@@ -0,0 +1,304 @@
+use aide::axum::routing::{ApiMethodRouter, post_with};
+use axum::Json;
+use axum_extra::extract::CookieJar;
+use hmac::{Hmac, KeyInit, Mac};
+use http::StatusCode;
+use rand::seq::IndexedRandom;
+use serde::Deserialize;
+use sha1::Sha1;
+use tracing::debug;
+use url::Url;
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    BackendRouter,
+    auth::{
+      jwt_state::{JwtState, MFA_COOKIE_NAME},
+      lockout::{check_lockout, record_failure, record_success},
+      password::LoginResponse,
+      session::ClientInfo,
+      token::hash_token,
+    },
+    config::SiteConfig,
+    middleware::rate_limiter::RateLimiter,
+    request::response::TokenRes,
+  },
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::{ErrorReportStatusExt, Result},
+  mail::Mailer,
+};
+
+const TOTP_DIGITS: u32 = 6;
+const TOTP_PERIOD: i64 = 30;
+/// Codes of the previous and next time step are accepted to allow for clock drift.
+const TOTP_SKEW: i64 = 1;
+const SECRET_LEN: usize = 20;
+pub const RECOVERY_CODE_COUNT: usize = 10;
+const RECOVERY_CODE_CHARS: &[u8] = b"abcdefghijkmnpqrstuvwxyz23456789";
+const BASE32_ALPHABET: &[u8] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZ234567";
+
+pub fn router(rate_limiter: &mut RateLimiter) -> BackendRouter {
+  BackendRouter::new()
+    .api_route("/", verify_totp_route())
+    .layer(rate_limiter.create_limiter("totp"))
+}
+
+pub fn verify_totp_route() -> ApiMethodRouter<()> {
+  post_with(verify_totp, |op| op.id("verifyTotp"))
+}
+
+#[derive(Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+struct TotpLoginReq {
+  code: String,
+}
+
+/// Second step of a password login for users with TOTP enabled. Exchanges the
+/// partial token from [`MFA_COOKIE_NAME`] and a code for a real session.
+async fn verify_totp(
+  jwt: JwtState,
+  db: Connection,
+  mailer: Mailer,
+  site: SiteConfig,
+  mut cookies: CookieJar,
+  client: ClientInfo,
+  Json(req): Json<TotpLoginReq>,
+) -> Result<(CookieJar, TokenRes<LoginResponse>)> {
+  let user = jwt.validate_mfa_token(&cookies).await?;
+  check_lockout(&db, user).await?;
+  if !check_second_factor(&db, user, &req.code).await? {
+    record_failure(&db, &mailer, &site, user).await?;
+    bail!(UNAUTHORIZED, "Invalid code");
+  }
+
+  record_success(&db, user).await?;
+  cookies = cookies.remove(jwt.create_cookie(MFA_COOKIE_NAME, String::new()));
+  cookies = jwt.create_login(&db, cookies, user, &client).await?;
+  debug!("User logged in with second factor: {}", user);
+
+  Ok((cookies, TokenRes(LoginResponse::new(user))))
+}
+
+/// Accepts either a current TOTP code or one of the unused recovery codes of the
+/// user. Both can only be used once.
+pub async fn check_second_factor(db: &Connection, user: Uuid, code: &str) -> Result<bool> {
+  let Some(totp) = db.totp().get_totp(user).await? else {
+    return Ok(false);
+  };
+  if !totp.enabled {
+    return Ok(false);
+  }
+
+  let code = code.trim();
+  if code.len() == TOTP_DIGITS as usize && code.bytes().all(|c| c.is_ascii_digit()) {
+    let Some(step) = verify_code(&totp.secret, code, chrono::Utc::now().timestamp())? else {
+      return Ok(false);
+    };
+    return db.totp().use_step(user, step).await;
+  }
+
+  db.totp()
+    .use_recovery_code(user, &hash_recovery_code(code))
+    .await
+}
+
+/// New random secret, base32 encoded as expected by authenticator apps.
+pub fn generate_secret() -> String {
+  base32_encode(&rand::random::<[u8; SECRET_LEN]>())
+}
+
+pub fn otpauth_uri(issuer: &str, account: &str, secret: &str) -> String {
+  let mut url = Url::parse("otpauth://totp/").expect("static url is valid");
+  url.set_path(&format!("{issuer}:{account}"));
+  url
+    .query_pairs_mut()
+    .append_pair("secret", secret)
+    .append_pair("issuer", issuer)
+    .append_pair("algorithm", "SHA1")
+    .append_pair("digits", &TOTP_DIGITS.to_string())
+    .append_pair("period", &TOTP_PERIOD.to_string());
+  url.to_string()
+}
+
+/// Returns the time step the code belongs to, if it is valid at `now`.
+pub fn verify_code(secret: &str, code: &str, now: i64) -> Result<Option<i64>> {
+  let key = base32_decode(secret).status_context(
+    StatusCode::INTERNAL_SERVER_ERROR,
+    "Stored TOTP secret is invalid",
+  )?;
+  let Ok(code) = code.parse::<u32>() else {
+    return Ok(None);
+  };
+
+  let current = now / TOTP_PERIOD;
+  for step in current - TOTP_SKEW..=current + TOTP_SKEW {
+    if code_at(&key, step)? == code {
+      return Ok(Some(step));
+    }
+  }
+
+  Ok(None)
+}
+
+/// RFC 6238 code for the given time step.
+fn code_at(key: &[u8], step: i64) -> Result<u32> {
+  let mut mac = Hmac::<Sha1>::new_from_slice(key)?;
+  mac.update(&step.to_be_bytes());
+  let hash = mac.finalize().into_bytes();
+
+  let offset = (hash[hash.len() - 1] & 0x0f) as usize;
+  let binary = u32::from_be_bytes([
+    hash[offset] & 0x7f,
+    hash[offset + 1],
+    hash[offset + 2],
+    hash[offset + 3],
+  ]);
+
+  Ok(binary % 10u32.pow(TOTP_DIGITS))
+}
+
+#[cfg(test)]
+pub(crate) fn current_code(secret: &str) -> String {
+  let key = base32_decode(secret).unwrap();
+  let step = chrono::Utc::now().timestamp() / TOTP_PERIOD;
+  format!("{:06}", code_at(&key, step).unwrap())
+}
+
+/// Codes in the form `xxxxx-xxxxx`, only the hashes are stored.
+pub fn generate_recovery_codes() -> Vec<String> {
+  let mut rng = rand::rng();
+  (0..RECOVERY_CODE_COUNT)
+    .map(|_| {
+      let code: String = (0..10)
+        .map(|_| *RECOVERY_CODE_CHARS.choose(&mut rng).unwrap() as char)
+        .collect();
+      format!("{}-{}", &code[..5], &code[5..])
+    })
+    .collect()
+}
+
+pub fn hash_recovery_code(code: &str) -> String {
+  hash_token(&normalize_recovery_code(code))
+}
+
+fn normalize_recovery_code(code: &str) -> String {
+  code
+    .chars()
+    .filter(|c| c.is_ascii_alphanumeric())
+    .map(|c| c.to_ascii_lowercase())
+    .collect()
+}
+
+fn base32_encode(data: &[u8]) -> String {
+  let mut out = String::with_capacity(data.len().div_ceil(5) * 8);
+  let mut buffer = 0u32;
+  let mut bits = 0;
+
+  for &byte in data {
+    buffer = (buffer << 8) | byte as u32;
+    bits += 8;
+    while bits >= 5 {
+      bits -= 5;
+      out.push(BASE32_ALPHABET[((buffer >> bits) & 0x1f) as usize] as char);
+    }
+  }
+  if bits > 0 {
+    out.push(BASE32_ALPHABET[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+  }
+
+  out
+}
+
+fn base32_decode(data: &str) -> Option<Vec<u8>> {
+  let mut out = Vec::with_capacity(data.len() * 5 / 8);
+  let mut buffer = 0u32;
+  let mut bits = 0;
+
+  for c in data.bytes().filter(|c| *c != b'=') {
+    let value = BASE32_ALPHABET
+      .iter()
+      .position(|a| *a == c.to_ascii_uppercase())?;
+    buffer = (buffer << 5) | value as u32;
+    bits += 5;
+    if bits >= 8 {
+      bits -= 8;
+      out.push((buffer >> bits) as u8);
+    }
+  }
+
+  Some(out)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  // RFC 6238 appendix B, SHA1 secret "12345678901234567890"
+  const RFC_SECRET: &str = "GEZDGNBVGY3TQOJQGEZDGNBVGY3TQOJQ";
+
+  #[test]
+  fn test_base32_roundtrip() {
+    assert_eq!(base32_encode(b"12345678901234567890"), RFC_SECRET);
+    assert_eq!(
+      base32_decode(RFC_SECRET).unwrap(),
+      b"12345678901234567890".to_vec()
+    );
+    assert_eq!(base32_encode(b"f"), "MY");
+    assert_eq!(base32_decode("MY======").unwrap(), b"f".to_vec());
+    assert!(base32_decode("not base32!").is_none());
+  }
+
+  #[test]
+  fn test_code_matches_rfc_vectors() {
+    let key = base32_decode(RFC_SECRET).unwrap();
+    // 8 digit vectors from the RFC truncated to 6 digits
+    assert_eq!(code_at(&key, 59 / TOTP_PERIOD).unwrap(), 287082);
+    assert_eq!(code_at(&key, 1111111109 / TOTP_PERIOD).unwrap(), 81804);
+    assert_eq!(code_at(&key, 1234567890 / TOTP_PERIOD).unwrap(), 5924);
+  }
+
+  #[test]
+  fn test_verify_code_allows_skew() {
+    let now = 1_700_000_000;
+    let key = base32_decode(RFC_SECRET).unwrap();
+    let previous = code_at(&key, now / TOTP_PERIOD - 1).unwrap();
+    let stale = code_at(&key, now / TOTP_PERIOD - 2).unwrap();
+
+    assert_eq!(
+      verify_code(RFC_SECRET, &format!("{previous:06}"), now).unwrap(),
+      Some(now / TOTP_PERIOD - 1)
+    );
+    assert_eq!(
+      verify_code(RFC_SECRET, &format!("{stale:06}"), now).unwrap(),
+      None
+    );
+    assert_eq!(verify_code(RFC_SECRET, "abcdef", now).unwrap(), None);
+  }
+
+  #[test]
+  fn test_otpauth_uri() {
+    let uri = otpauth_uri("Centaurus", "user@example.com", "ABC");
+    assert!(uri.starts_with("otpauth://totp/Centaurus:user@example.com?"));
+    assert!(uri.contains("secret=ABC"));
+    assert!(uri.contains("issuer=Centaurus"));
+  }
+
+  #[test]
+  fn test_recovery_codes() {
+    let codes = generate_recovery_codes();
+    assert_eq!(codes.len(), RECOVERY_CODE_COUNT);
+    assert!(codes.iter().all(|c| c.len() == 11 && &c[5..6] == "-"));
+    assert_eq!(
+      hash_recovery_code(&codes[0]),
+      hash_recovery_code(&codes[0].to_uppercase().replace('-', " "))
+    );
+  }
+
+  #[test]
+  fn test_generate_secret_decodes_to_full_length() {
+    let secret = generate_secret();
+    assert_eq!(base32_decode(&secret).unwrap().len(), SECRET_LEN);
+  }
+}
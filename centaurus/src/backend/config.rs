@@ -1,10 +1,14 @@
+use std::collections::BTreeMap;
+
 #[cfg(feature = "config_site")]
 use axum::{Extension, extract::FromRequestParts};
+use ipnet::IpNet;
 use serde::{Deserialize, Serialize};
 use tracing::level_filters::LevelFilter;
 
 #[cfg(feature = "auth")]
 use crate::backend::auth::settings::{AuthConfig, UserSettings};
+use crate::backend::store::StoreBackend;
 #[cfg(feature = "mail")]
 use crate::mail::MailSettings;
 use crate::serde::{de_str, se_str};
@@ -18,6 +22,16 @@ pub struct BaseConfig {
   pub log_level: LevelFilter,
 
   pub allowed_origins: String,
+
+  /// Has to be `database` when running multiple replicas.
+  #[serde(default)]
+  pub ephemeral_store: StoreBackend,
+
+  /// Reverse proxies whose `X-Forwarded-For` and `X-Real-IP` headers are
+  /// believed, e.g. `10.0.0.0/8`. Other clients are identified by their peer
+  /// address, which [`run_app`](crate::backend::init::run_app) provides.
+  #[serde(default)]
+  pub trusted_proxies: Vec<IpNet>,
 }
 
 impl Default for BaseConfig {
@@ -26,6 +40,8 @@ impl Default for BaseConfig {
       port: 8000,
       log_level: LevelFilter::INFO,
       allowed_origins: "".to_string(),
+      ephemeral_store: StoreBackend::Memory,
+      trusted_proxies: Vec::new(),
     }
   }
 }
@@ -38,6 +54,54 @@ pub struct MetricsConfig {
   pub extra_labels: Vec<(String, String)>,
 }
 
+/// Named rate limit policies, picked with
+/// [`RateLimiter::create_limiter`](crate::backend::middleware::rate_limiter::RateLimiter::create_limiter).
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default)]
+pub struct RateLimitConfig {
+  /// Policies that are not listed here fall back to the `default` policy,
+  /// or [`RateLimitPolicy::default`] if that is missing too.
+  #[serde(default)]
+  pub rate_limit_policies: BTreeMap<String, RateLimitPolicy>,
+  /// Requests from these networks are never limited, e.g. `10.0.0.0/8`. The
+  /// client address is resolved as described at [`BaseConfig::trusted_proxies`].
+  #[serde(default)]
+  pub rate_limit_allowlist: Vec<IpNet>,
+}
+
+#[derive(Deserialize, Serialize, Clone, Debug, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct RateLimitPolicy {
+  /// Milliseconds until one request of the burst is replenished.
+  pub period_ms: u64,
+  pub burst_size: u32,
+  #[serde(default)]
+  pub key: RateLimitKey,
+}
+
+impl Default for RateLimitPolicy {
+  fn default() -> Self {
+    Self {
+      period_ms: 10_000,
+      burst_size: 20,
+      key: RateLimitKey::Ip,
+    }
+  }
+}
+
+/// What requests share a quota. Requests that cannot be keyed by user or
+/// token, e.g. because they are not authenticated, are keyed by ip.
+#[derive(
+  Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum RateLimitKey {
+  #[default]
+  Ip,
+  /// The subject of the JWT.
+  User,
+  /// The personal API token, falls back to the user for JWTs.
+  ApiToken,
+}
+
 pub trait Config: Clone + Send + Sync + 'static {
   fn base(&self) -> &BaseConfig;
   #[cfg(feature = "metrics")]
@@ -54,6 +118,9 @@ pub trait Config: Clone + Send + Sync + 'static {
   fn mail(&self) -> Option<&MailSettings> {
     None
   }
+  fn rate_limit(&self) -> Option<&RateLimitConfig> {
+    None
+  }
 }
 
 #[cfg(feature = "config_site")]
@@ -86,6 +153,24 @@ mod tests {
     assert_eq!(config.log_level, LevelFilter::INFO);
   }
 
+  #[test]
+  fn test_rate_limit_config_deserialize() {
+    let config: RateLimitConfig = serde_json::from_value(serde_json::json!({
+      "rate_limit_policies": {
+        "password": { "period_ms": 1000, "burst_size": 5, "key": "user" },
+      },
+      "rate_limit_allowlist": ["10.0.0.0/8"],
+    }))
+    .unwrap();
+
+    let policy = &config.rate_limit_policies["password"];
+    assert_eq!(policy.burst_size, 5);
+    assert_eq!(policy.key, RateLimitKey::User);
+    assert!(
+      config.rate_limit_allowlist[0].contains(&"10.1.2.3".parse::<std::net::IpAddr>().unwrap())
+    );
+  }
+
   #[cfg(feature = "config_site")]
   #[test]
   fn test_site_config_default() {
@@ -0,0 +1,164 @@
+use aide::axum::ApiRouter;
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with};
+use axum::{Json, extract::Path};
+use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::backend::auth::jwt_auth::JwtAuth;
+use crate::backend::auth::permission::{AclEdit, AclView};
+use crate::backend::endpoints::audit::{Audit, AuditEvent};
+use crate::bail;
+use crate::db::init::Connection;
+use crate::db::permission::has_permission;
+use crate::db::tables::ConnectionExt;
+use crate::db::tables::acl::{AclInfo, AclSubject};
+use crate::error::Result;
+
+pub fn router() -> ApiRouter {
+  ApiRouter::new()
+    .api_route("/", grant_access_route())
+    .api_route("/", revoke_access_route())
+    .api_route("/{resource_type}/{resource_id}", list_grants_route())
+}
+
+pub fn list_grants_route() -> ApiMethodRouter<()> {
+  get_with(list_grants, |op| op.id("listGrants"))
+}
+
+pub fn grant_access_route() -> ApiMethodRouter<()> {
+  post_with(grant_access, |op| op.id("grantAccess"))
+}
+
+pub fn revoke_access_route() -> ApiMethodRouter<()> {
+  delete_with(revoke_access, |op| op.id("revokeAccess"))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ResourcePath {
+  resource_type: String,
+  resource_id: String,
+}
+
+async fn list_grants(
+  _auth: JwtAuth<AclView>,
+  db: Connection,
+  Path(path): Path<ResourcePath>,
+) -> Result<Json<Vec<AclInfo>>> {
+  Ok(Json(
+    db.acl()
+      .list_resource_grants(&path.resource_type, &path.resource_id)
+      .await?,
+  ))
+}
+
+/// Nobody can hand out access they do not have themselves.
+async fn check_editor(db: &Connection, editor: Uuid, permission: &str) -> Result<()> {
+  let permissions = db.group().get_user_permissions(editor).await?;
+  if !has_permission(&permissions, permission) {
+    bail!(
+      FORBIDDEN,
+      "Cannot grant permissions that the editor does not have"
+    );
+  }
+
+  Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct GrantRequest {
+  resource_type: String,
+  resource_id: String,
+  permission: String,
+  subject: AclSubject,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct GrantResponse {
+  uuid: Uuid,
+}
+
+async fn grant_access(
+  auth: JwtAuth<AclEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<GrantRequest>,
+) -> Result<Json<GrantResponse>> {
+  if req.resource_type.trim().is_empty()
+    || req.resource_id.trim().is_empty()
+    || req.permission.trim().is_empty()
+  {
+    bail!(
+      BAD_REQUEST,
+      "Resource type, resource id and permission are required"
+    );
+  }
+  check_editor(&db, auth.user_id, &req.permission).await?;
+
+  match req.subject {
+    AclSubject::User(user) => {
+      if db.user().user_info(user).await?.is_none() {
+        bail!(NOT_FOUND, "User not found");
+      }
+    }
+    AclSubject::Group(group) => {
+      if db.group().group_info(group).await?.is_none() {
+        bail!(NOT_FOUND, "Group not found");
+      }
+    }
+  }
+
+  let txn = db.begin().await?;
+  let uuid = txn
+    .acl()
+    .grant(
+      &req.resource_type,
+      &req.resource_id,
+      &req.permission,
+      req.subject,
+    )
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "acl.grant")
+        .target("acl", uuid)
+        .after(&txn.acl().get_grant(uuid).await?),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(Json(GrantResponse { uuid }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct RevokeRequest {
+  uuid: Uuid,
+}
+
+async fn revoke_access(
+  auth: JwtAuth<AclEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<RevokeRequest>,
+) -> Result<()> {
+  let Some(grant) = db.acl().get_grant(req.uuid).await? else {
+    bail!(NOT_FOUND, "Grant not found");
+  };
+  check_editor(&db, auth.user_id, &grant.permission).await?;
+
+  let txn = db.begin().await?;
+  txn.acl().revoke(req.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "acl.revoke")
+        .target("acl", req.uuid)
+        .before(&grant),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(())
+}
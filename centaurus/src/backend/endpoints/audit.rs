@@ -0,0 +1,219 @@
+use std::convert::Infallible;
+
+use aide::OperationIo;
+use aide::axum::ApiRouter;
+use aide::axum::routing::{ApiMethodRouter, get_with};
+use axum::{
+  Json,
+  extract::{FromRequestParts, Query},
+};
+use chrono::{Duration, NaiveDateTime, Utc};
+use http::request::Parts;
+use schemars::JsonSchema;
+use sea_orm::DatabaseTransaction;
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tokio::{spawn, time::sleep};
+use tracing::{info, warn};
+use uuid::Uuid;
+
+use crate::backend::auth::jwt_auth::JwtAuth;
+use crate::backend::auth::permission::AuditView;
+use crate::backend::auth::session::ClientInfo;
+use crate::db::init::Connection;
+use crate::db::tables::ConnectionExt;
+use crate::db::tables::audit_log::{AuditFilter, AuditPage, NewAuditEntry};
+use crate::error::Result;
+
+const RETENTION_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60 * 60);
+const DEFAULT_PAGE_SIZE: u64 = 50;
+const REDACTED: &str = "<redacted>";
+/// Fields whose name contains one of these are redacted.
+const SECRET_FRAGMENTS: &[&str] = &["password", "secret", "token", "hash", "salt", "verifier"];
+
+pub fn router() -> ApiRouter {
+  ApiRouter::new().api_route("/", list_audit_log_route())
+}
+
+pub fn list_audit_log_route() -> ApiMethodRouter<()> {
+  get_with(list_audit_log, |op| op.id("listAuditLog"))
+}
+
+/// A change to record with [`Audit::record`].
+///
+/// `audit.record(&txn, AuditEvent::new(auth.user_id, "group.edit").target("group", id).before(&old).after(&new))`
+#[derive(Debug, Clone)]
+pub struct AuditEvent(NewAuditEntry);
+
+impl AuditEvent {
+  pub fn new(actor: impl Into<Option<Uuid>>, action: impl Into<String>) -> Self {
+    Self(NewAuditEntry {
+      actor: actor.into(),
+      action: action.into(),
+      ..Default::default()
+    })
+  }
+
+  pub fn target(mut self, target_type: impl Into<String>, target_id: impl ToString) -> Self {
+    self.0.target_type = Some(target_type.into());
+    self.0.target_id = Some(target_id.to_string());
+    self
+  }
+
+  pub fn before<T: Serialize>(mut self, value: &T) -> Self {
+    self.0.before = snapshot(value);
+    self
+  }
+
+  pub fn after<T: Serialize>(mut self, value: &T) -> Self {
+    self.0.after = snapshot(value);
+    self
+  }
+}
+
+fn snapshot<T: Serialize>(value: &T) -> Option<Value> {
+  let mut value = serde_json::to_value(value).ok()?;
+  redact(&mut value);
+  Some(value)
+}
+
+/// Secrets never end up in the log, only whether they were set.
+fn redact(value: &mut Value) {
+  match value {
+    Value::Object(map) => {
+      for (key, value) in map.iter_mut() {
+        let key = key.to_lowercase();
+        if SECRET_FRAGMENTS.iter().any(|f| key.contains(f)) && !value.is_null() {
+          *value = Value::String(REDACTED.into());
+        } else {
+          redact(value);
+        }
+      }
+    }
+    Value::Array(values) => values.iter_mut().for_each(redact),
+    _ => (),
+  }
+}
+
+/// Writes entries to the audit log, tagged with the ip of the current request.
+/// Handlers apply their change and record it in one transaction, so neither is
+/// saved without the other.
+#[derive(Clone, OperationIo)]
+pub struct Audit {
+  ip: Option<String>,
+}
+
+impl<S: Sync> FromRequestParts<S> for Audit {
+  type Rejection = Infallible;
+
+  async fn from_request_parts(
+    parts: &mut Parts,
+    state: &S,
+  ) -> std::result::Result<Self, Self::Rejection> {
+    let client = ClientInfo::from_request_parts(parts, state).await?;
+
+    Ok(Self { ip: client.ip })
+  }
+}
+
+impl Audit {
+  /// For handlers that already extracted the client, e.g. the OIDC callback.
+  pub fn from_client(client: &ClientInfo) -> Self {
+    Self {
+      ip: client.ip.clone(),
+    }
+  }
+
+  /// Record the event in the transaction that applies the change, it is only
+  /// saved once the transaction is committed.
+  pub async fn record(&self, txn: &DatabaseTransaction, event: AuditEvent) -> Result<()> {
+    let mut entry = event.0;
+    entry.ip = self.ip.clone();
+    txn.audit_log().record(entry).await?;
+
+    Ok(())
+  }
+}
+
+/// Periodically remove entries older than `retention` seconds.
+pub fn spawn_retention(db: Connection, retention: i64) {
+  spawn(async move {
+    loop {
+      let cutoff = Utc::now().naive_utc() - Duration::seconds(retention);
+      match db.audit_log().remove_older_than(cutoff).await {
+        Ok(0) => (),
+        Ok(removed) => info!("Removed {} expired audit log entries", removed),
+        Err(e) => warn!("Failed to remove expired audit log entries: {:?}", e),
+      }
+      sleep(RETENTION_INTERVAL).await;
+    }
+  });
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct AuditLogQuery {
+  actor: Option<Uuid>,
+  /// Exact action, or a prefix ending with `*`.
+  action: Option<String>,
+  target_type: Option<String>,
+  target_id: Option<String>,
+  from: Option<NaiveDateTime>,
+  to: Option<NaiveDateTime>,
+  /// Zero based.
+  #[serde(default)]
+  page: u64,
+  per_page: Option<u64>,
+}
+
+async fn list_audit_log(
+  _auth: JwtAuth<AuditView>,
+  db: Connection,
+  Query(query): Query<AuditLogQuery>,
+) -> Result<Json<AuditPage>> {
+  let filter = AuditFilter {
+    actor: query.actor,
+    action: query.action,
+    target_type: query.target_type,
+    target_id: query.target_id,
+    from: query.from,
+    to: query.to,
+  };
+  let per_page = query.per_page.unwrap_or(DEFAULT_PAGE_SIZE);
+
+  Ok(Json(
+    db.audit_log().query(&filter, query.page, per_page).await?,
+  ))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  #[test]
+  fn test_snapshot_redacts_secrets() {
+    let value = snapshot(&json!({
+      "name": "smtp",
+      "smtp_password": "hunter2",
+      "oidc_client_secret": null,
+      "client_secret": "s3cret",
+      "token_hash": "abc",
+      "refresh_token": "xyz",
+      "nested": [{ "Password": "x" }],
+    }))
+    .unwrap();
+
+    assert_eq!(
+      value,
+      json!({
+        "name": "smtp",
+        "smtp_password": REDACTED,
+        "oidc_client_secret": null,
+        "client_secret": REDACTED,
+        "token_hash": REDACTED,
+        "refresh_token": REDACTED,
+        "nested": [{ "Password": REDACTED }],
+      })
+    );
+  }
+}
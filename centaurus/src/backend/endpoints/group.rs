@@ -2,14 +2,17 @@ use aide::axum::ApiRouter;
 use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with, put_with};
 use axum::{Json, extract::Path};
 use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::backend::auth::jwt_auth::JwtAuth;
 use crate::backend::auth::permission::{GroupEdit, GroupView};
+use crate::backend::endpoints::audit::{Audit, AuditEvent};
 use crate::backend::endpoints::websocket::state::{UpdateMessage, Updater};
 use crate::bail;
 use crate::db::init::Connection;
+use crate::db::permission::has_permission;
 use crate::db::tables::ConnectionExt;
 use crate::db::tables::group::{GroupDetails, GroupInfo, SimpleUserInfo};
 use crate::error::Result;
@@ -106,9 +109,10 @@ struct GroupCreateResponse {
 }
 
 async fn create_group<T: UpdateMessage>(
-  _auth: JwtAuth<GroupEdit>,
+  auth: JwtAuth<GroupEdit>,
   db: Connection,
   updater: Updater<T>,
+  audit: Audit,
   Json(data): Json<CreateGroupRequest>,
 ) -> Result<Json<GroupCreateResponse>> {
   if data.name.trim().is_empty() {
@@ -119,7 +123,17 @@ async fn create_group<T: UpdateMessage>(
     bail!(CONFLICT, "A group with this name already exists");
   }
 
-  let group_id = db.group().create_group(data.name).await?;
+  let txn = db.begin().await?;
+  let group_id = txn.group().create_group(data.name).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "group.create")
+        .target("group", group_id)
+        .after(&txn.group().group_info(group_id).await?),
+    )
+    .await?;
+  txn.commit().await?;
   updater.broadcast(T::group(group_id)).await;
 
   Ok(Json(GroupCreateResponse { uuid: group_id }))
@@ -131,9 +145,10 @@ struct DeleteGroupRequest {
 }
 
 async fn delete_group<T: UpdateMessage>(
-  _auth: JwtAuth<GroupEdit>,
+  auth: JwtAuth<GroupEdit>,
   db: Connection,
   updater: Updater<T>,
+  audit: Audit,
   Json(data): Json<DeleteGroupRequest>,
 ) -> Result<()> {
   if let Some(admin_group) = db.setup().get_admin_group_id().await?
@@ -143,7 +158,18 @@ async fn delete_group<T: UpdateMessage>(
   }
 
   let users = db.group().get_group_users_ids(data.uuid).await?;
-  db.group().delete_group(data.uuid).await?;
+  let before = db.group().group_info(data.uuid).await?;
+  let txn = db.begin().await?;
+  txn.group().delete_group(data.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "group.delete")
+        .target("group", data.uuid)
+        .before(&before),
+    )
+    .await?;
+  txn.commit().await?;
 
   updater.broadcast(T::group(data.uuid)).await;
   for user_id in users {
@@ -165,6 +191,7 @@ async fn edit_group<T: UpdateMessage>(
   auth: JwtAuth<GroupEdit>,
   db: Connection,
   updater: Updater<T>,
+  audit: Audit,
   Json(data): Json<EditGroupRequest>,
 ) -> Result<()> {
   if data.name.trim().is_empty() {
@@ -185,7 +212,7 @@ async fn edit_group<T: UpdateMessage>(
   if group
     .permissions
     .iter()
-    .any(|perm| !user_permissions.contains(perm))
+    .any(|perm| !has_permission(&user_permissions, perm))
   {
     bail!(
       FORBIDDEN,
@@ -195,7 +222,7 @@ async fn edit_group<T: UpdateMessage>(
   if data
     .permissions
     .iter()
-    .any(|perm| !user_permissions.contains(perm))
+    .any(|perm| !has_permission(&user_permissions, perm))
   {
     bail!(
       FORBIDDEN,
@@ -219,7 +246,9 @@ async fn edit_group<T: UpdateMessage>(
 
   let old_users = db.group().get_group_users_ids(data.uuid).await?;
 
-  db.group()
+  let txn = db.begin().await?;
+  txn
+    .group()
     .edit_group(
       data.uuid,
       data.name,
@@ -227,6 +256,16 @@ async fn edit_group<T: UpdateMessage>(
       data.users.clone(),
     )
     .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "group.edit")
+        .target("group", data.uuid)
+        .before(&group)
+        .after(&txn.group().group_info(data.uuid).await?),
+    )
+    .await?;
+  txn.commit().await?;
 
   updater.broadcast(T::group(data.uuid)).await;
 
@@ -6,27 +6,45 @@
 //! axum testing example. Authentication is performed by minting JWTs directly
 //! through [`JwtState`] and replaying them as bearer tokens.
 
+use std::collections::HashMap;
+use std::net::SocketAddr;
+
 use aide::axum::ApiRouter;
-use axum::{Extension, Router, body::Body};
+use axum::{Extension, Router, body::Body, extract::ConnectInfo};
+use axum_extra::extract::cookie::Cookie;
 use base64::prelude::*;
-use http::{Method, Request, StatusCode, header::CONTENT_TYPE};
+use http::{
+  Method, Request, StatusCode,
+  header::{CONTENT_TYPE, SET_COOKIE},
+};
+use num_bigint::BigUint;
 use rsa::{Pkcs1v15Encrypt, RsaPublicKey, pkcs1::DecodeRsaPublicKey, rand_core::OsRng};
 use serde::{Deserialize, Serialize};
 use serde_json::{Value, json};
 use tower::ServiceExt;
 use uuid::Uuid;
 
-use crate::backend::auth::jwt_state::{JWT_COOKIE_NAME, JwtInvalidState, JwtState};
+use crate::backend::auth::api_token::API_TOKEN_PREFIX;
+use crate::backend::auth::jwt_auth::JwtAuth;
+use crate::backend::auth::jwt_state::{
+  JWT_COOKIE_NAME, JwtInvalidState, JwtState, MFA_COOKIE_NAME, REFRESH_COOKIE_NAME,
+};
 use crate::backend::auth::oidc::OidcState;
+use crate::backend::auth::pake;
 use crate::backend::auth::permission::permissions;
-use crate::backend::auth::pw_state::PasswordState;
-use crate::backend::auth::settings::{AuthConfig, UserSettings};
+use crate::backend::auth::pw_state::{PasswordState, hash_secret};
+use crate::backend::auth::settings::{
+  AuthConfig, LockoutSettings, PasswordPolicySettings, UserSettings,
+};
+use crate::backend::auth::totp::current_code;
 use crate::backend::config::SiteConfig;
 use crate::backend::endpoints::mail::state::ResetPasswordState;
 use crate::backend::endpoints::user::email::EmailChangeState;
+use crate::backend::endpoints::user::invitation::InvitationState;
 use crate::backend::endpoints::websocket::state::UpdateState;
-use crate::backend::endpoints::{group, mail, settings, setup, user, websocket};
+use crate::backend::endpoints::{acl, audit, group, mail, settings, setup, user, websocket};
 use crate::backend::middleware::rate_limiter::RateLimiter;
+use crate::backend::store::Store;
 use crate::backend::{self, auth};
 use crate::db::config::DBConfig;
 use crate::db::init::{Connection, connect_db};
@@ -51,8 +69,21 @@ enum TestMsg {
   Permissions,
 }
 
+crate::resource_permission!(
+  ProjectEdit,
+  "project:edit",
+  resource = "project",
+  param = "id"
+);
+
 const SALT: &str = "c2FsdHNhbHQ"; // base64 (no pad) of "saltsalt"
 
+/// Peer address of the test requests, as axum's `into_make_service_with_connect_info`
+/// would insert it.
+fn peer() -> ConnectInfo<SocketAddr> {
+  ConnectInfo(SocketAddr::from(([127, 0, 0, 1], 0)))
+}
+
 struct TestApp {
   app: Router,
   conn: Connection,
@@ -63,16 +94,20 @@ struct TestApp {
 
 impl TestApp {
   async fn new() -> Self {
+    Self::with_config(AuthConfig::default()).await
+  }
+
+  async fn with_config(auth_config: AuthConfig) -> Self {
     let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
     Migrator::up(&*conn, None).await.unwrap();
 
-    let auth_config = AuthConfig::default();
     let jwt = JwtState::init(&auth_config, &conn).await;
     let pw = auth::init_pw_state(&auth_config, &conn).await;
     let pw_pub = RsaPublicKey::from_pkcs1_pem(&pw.pub_key).unwrap();
 
-    let (update_state, updater) = UpdateState::<TestMsg>::init().await;
-    let oidc = OidcState::new(&conn, None).await;
+    let store = Store::memory();
+    let (update_state, updater) = UpdateState::<TestMsg>::init(&store).await.unwrap();
+    let oidc = OidcState::new(&conn, None, store.clone()).await;
     let mailer = Mailer::new(MailSettings::default()).await;
 
     let mut rl = RateLimiter::default();
@@ -84,7 +119,16 @@ impl TestApp {
       .nest("/mail", mail::router(&mut rl))
       .nest("/auth", auth::router::<TestMsg>(&mut rl))
       .nest("/ws", websocket::router::<TestMsg>())
-      .merge(backend::endpoints::health::router())
+      .nest("/acl", acl::router())
+      .nest("/audit", audit::router())
+      .route(
+        "/project/{id}",
+        axum::routing::get(|_: JwtAuth<ProjectEdit>| async {}),
+      )
+      .merge(backend::endpoints::health::router());
+    #[cfg(feature = "scim")]
+    let api = api.nest("/scim", backend::endpoints::scim::router::<TestMsg>());
+    let api = api
       .layer(Extension(conn.clone()))
       .layer(Extension(jwt.clone()))
       .layer(Extension(pw.clone()))
@@ -92,8 +136,10 @@ impl TestApp {
       .layer(Extension(oidc))
       .layer(Extension(update_state))
       .layer(Extension(updater))
-      .layer(Extension(EmailChangeState::init()))
-      .layer(Extension(ResetPasswordState::default()))
+      .layer(Extension(EmailChangeState::init(store.clone())))
+      .layer(Extension(ResetPasswordState::init(&conn, 3600)))
+      .layer(Extension(InvitationState::init(3600)))
+      .layer(Extension(store))
       .layer(Extension(mailer))
       .layer(Extension(SiteConfig::default()))
       .layer(Extension(UserSettings::default()))
@@ -115,7 +161,7 @@ impl TestApp {
   }
 
   fn token(&self, user: Uuid) -> String {
-    self.jwt.create_raw_token(user).unwrap()
+    self.jwt.create_service_token(user).unwrap()
   }
 
   /// base64(RSA-encrypted) password, the wire format the handlers expect.
@@ -158,11 +204,35 @@ impl TestApp {
     uid
   }
 
+  /// Create a user that only holds `user:edit`.
+  async fn editor_user(&self, name: &str) -> Uuid {
+    let group = self
+      .conn
+      .group()
+      .create_group("Editors".into())
+      .await
+      .unwrap();
+    self
+      .conn
+      .group()
+      .add_permissions_to_group(group, vec!["user:edit".into()])
+      .await
+      .unwrap();
+    let uid = self.local_user(name, "password").await;
+    self
+      .conn
+      .group()
+      .add_users_to_group(group, vec![uid])
+      .await
+      .unwrap();
+    uid
+  }
+
   /// Create a local (non-OIDC) user whose stored hash matches `plain` when sent
   /// through the login flow (i.e. encrypted on the wire).
   async fn local_user(&self, name: &str, plain: &str) -> Uuid {
     let enc = self.encrypt(plain);
-    let hash = self.pw.pw_hash(SALT, &enc).unwrap();
+    let hash = self.pw.pw_hash(&enc).unwrap();
     self
       .conn
       .user()
@@ -185,10 +255,7 @@ impl TestApp {
     token: Option<&str>,
     body: Option<Value>,
   ) -> (StatusCode, Value) {
-    let mut builder = Request::builder()
-      .method(method)
-      .uri(uri)
-      .header("x-real-ip", "127.0.0.1");
+    let mut builder = Request::builder().method(method).uri(uri).extension(peer());
     if let Some(t) = token {
       builder = builder.header("authorization", format!("Bearer {t}"));
     }
@@ -208,6 +275,59 @@ impl TestApp {
     let value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
     (status, value)
   }
+
+  /// Like [`Self::send`] but authenticates with cookies and returns the cookies
+  /// set by the response.
+  async fn send_cookies(
+    &self,
+    method: Method,
+    uri: &str,
+    cookies: &HashMap<String, String>,
+    body: Option<Value>,
+  ) -> (StatusCode, HashMap<String, String>, Value) {
+    let cookie_header = cookies
+      .iter()
+      .map(|(k, v)| format!("{k}={v}"))
+      .collect::<Vec<_>>()
+      .join("; ");
+    let builder = Request::builder()
+      .method(method)
+      .uri(uri)
+      .extension(peer())
+      .header("cookie", cookie_header);
+    let req = match &body {
+      Some(v) => builder
+        .header(CONTENT_TYPE, "application/json")
+        .body(Body::from(v.to_string()))
+        .unwrap(),
+      None => builder.body(Body::empty()).unwrap(),
+    };
+
+    let resp = self.app.clone().oneshot(req).await.unwrap();
+    let status = resp.status();
+    let set_cookies = resp
+      .headers()
+      .get_all(SET_COOKIE)
+      .iter()
+      .filter_map(|v| Cookie::parse(v.to_str().ok()?.to_string()).ok())
+      .map(|c| (c.name().to_string(), c.value().to_string()))
+      .collect();
+    let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+      .await
+      .unwrap();
+    let value = serde_json::from_slice(&bytes).unwrap_or(Value::Null);
+    (status, set_cookies, value)
+  }
+
+  /// Log in through the password flow and return the session cookies.
+  async fn login(&self, email: &str, plain: &str) -> HashMap<String, String> {
+    let body = json!({"email": email, "password": self.encrypt(plain)});
+    let (status, cookies, _) = self
+      .send_cookies(Method::POST, "/auth/password", &HashMap::new(), Some(body))
+      .await;
+    assert_eq!(status, StatusCode::OK);
+    cookies
+  }
 }
 
 // ---------------------------------------------------------------------------
@@ -379,6 +499,175 @@ async fn auth_login_success_and_failures() {
   assert_eq!(status, StatusCode::BAD_REQUEST);
 }
 
+#[tokio::test]
+async fn auth_login_rehashes_legacy_password() {
+  let app = TestApp::new().await;
+  let legacy = hash_secret(&app.pw.pepper, SALT, b"s3cret").unwrap();
+  let uid = app
+    .conn
+    .user()
+    .create_user(
+      "legacy".into(),
+      "legacy@example.com".into(),
+      legacy.clone(),
+      SALT.into(),
+      false,
+      None,
+    )
+    .await
+    .unwrap();
+
+  app.login("legacy@example.com", "s3cret").await;
+  let stored = app.conn.user().get_user_by_id(uid).await.unwrap().password;
+  assert_ne!(stored, legacy);
+  assert!(stored.contains("keyid="));
+
+  // The new hash keeps working and is not replaced again.
+  app.login("legacy@example.com", "s3cret").await;
+  let again = app.conn.user().get_user_by_id(uid).await.unwrap().password;
+  assert_eq!(again, stored);
+}
+
+impl TestApp {
+  async fn pake_start(&self, email: &str) -> (StatusCode, Value, BigUint, String) {
+    let (secret, a) = pake::client::start();
+    let (status, body) = self
+      .send(
+        Method::POST,
+        "/auth/password/pake/start",
+        None,
+        Some(json!({"email": email, "a": a})),
+      )
+      .await;
+    (status, body, secret, a)
+  }
+}
+
+/// Runs a PAKE login that has to fail at the proof, not at the start.
+async fn assert_pake_fails(app: &TestApp, email: &str, password: &str) {
+  let (status, body, secret, a) = app.pake_start(email).await;
+  assert_eq!(status, StatusCode::OK);
+  let (proof, _) = pake::client::proofs(
+    password,
+    body["salt"].as_str().unwrap(),
+    &secret,
+    &a,
+    body["b"].as_str().unwrap(),
+  );
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/auth/password/pake/finish",
+      None,
+      Some(json!({"session": body["session"], "proof": proof})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn auth_pake_login_without_sending_password() {
+  use pake::client;
+
+  let app = TestApp::new().await;
+  let uid = app.local_user("pake", "s3cret").await;
+  let token = app.token(uid);
+
+  // Not set up yet, the challenge looks the same but no proof matches.
+  assert_pake_fails(&app, "pake@example.com", "s3cret").await;
+  assert_pake_fails(&app, "nobody@example.com", "s3cret").await;
+  let (_, first, ..) = app.pake_start("nobody@example.com").await;
+  let (_, second, ..) = app.pake_start("nobody@example.com").await;
+  assert_eq!(first["salt"], second["salt"]);
+
+  let (salt, verifier) = client::register("s3cret");
+  let old_password = app.encrypt("s3cret");
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/pake",
+      Some(&token),
+      Some(json!({"old_password": old_password, "salt": salt, "verifier": "AQ=="})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  // A session alone is not enough to set a verifier.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/pake",
+      Some(&token),
+      Some(json!({"old_password": app.encrypt("wrong"), "salt": salt, "verifier": verifier})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/pake",
+      Some(&token),
+      Some(json!({"old_password": old_password, "salt": salt, "verifier": verifier})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (_, body) = app
+    .send(Method::GET, "/user/account/pake", Some(&token), None)
+    .await;
+  assert_eq!(body["enabled"], json!(true));
+
+  // Wrong password gives a wrong proof.
+  let (status, body, secret, a) = app.pake_start("pake@example.com").await;
+  assert_eq!(status, StatusCode::OK);
+  let salt = body["salt"].as_str().unwrap();
+  let (proof, _) = client::proofs("wrong", salt, &secret, &a, body["b"].as_str().unwrap());
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/auth/password/pake/finish",
+      None,
+      Some(json!({"session": body["session"], "proof": proof})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  let (_, body, secret, a) = app.pake_start("pake@example.com").await;
+  let salt = body["salt"].as_str().unwrap();
+  let (proof, server_proof) =
+    client::proofs("s3cret", salt, &secret, &a, body["b"].as_str().unwrap());
+  let finish = json!({"session": body["session"], "proof": proof});
+  let (status, res) = app
+    .send(
+      Method::POST,
+      "/auth/password/pake/finish",
+      None,
+      Some(finish.clone()),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(res["user"], json!(uid.to_string()));
+  assert_eq!(res["proof"], json!(server_proof));
+
+  // Sessions are single use.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/auth/password/pake/finish",
+      None,
+      Some(finish),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  // A password change invalidates the verifier.
+  app
+    .conn
+    .user()
+    .update_user_password(uid, app.pw.pw_hash_raw("n3w").unwrap())
+    .await
+    .unwrap();
+  assert_pake_fails(&app, "pake@example.com", "s3cret").await;
+}
+
 #[tokio::test]
 async fn auth_test_token_reflects_validity() {
   let app = TestApp::new().await;
@@ -416,7 +705,7 @@ async fn auth_logout_invalidates_token() {
   let req = Request::builder()
     .method(Method::POST)
     .uri("/auth/logout")
-    .header("x-real-ip", "127.0.0.1")
+    .extension(peer())
     .header("authorization", format!("Bearer {token}"))
     .header("cookie", format!("{JWT_COOKIE_NAME}={token}"))
     .body(Body::empty())
@@ -443,583 +732,2845 @@ async fn auth_logout_without_cookie_is_unauthorized() {
   assert_eq!(status, StatusCode::UNAUTHORIZED);
 }
 
-// ---------------------------------------------------------------------------
-// user/info
-// ---------------------------------------------------------------------------
-
 #[tokio::test]
-async fn user_info_requires_auth() {
-  let app = TestApp::new().await;
-  // No credentials at all ⇒ the token cannot be located ⇒ 400.
-  let (status, _) = app.send(Method::GET, "/user/info", None, None).await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
+async fn auth_refresh_rotates_and_detects_reuse() {
+  let app = TestApp::with_config(AuthConfig {
+    auth_refresh_grace: 0,
+    ..Default::default()
+  })
+  .await;
+  let uid = app.local_user("rf", "pw").await;
+  let login = app.login("rf@example.com", "pw").await;
+  assert!(login.contains_key(JWT_COOKIE_NAME));
+  let first = login[REFRESH_COOKIE_NAME].clone();
+
+  // Refreshing yields a new access token and a rotated refresh token.
+  let (status, cookies, body) = app
+    .send_cookies(Method::POST, "/auth/refresh", &login, None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["user"], json!(uid.to_string()));
+  let second = cookies[REFRESH_COOKIE_NAME].clone();
+  assert_ne!(first, second);
+  let claims = app
+    .jwt
+    .validate_token(&cookies[JWT_COOKIE_NAME])
+    .await
+    .unwrap();
+  assert_eq!(claims.sub, uid);
 
-  // A syntactically present but bogus bearer token ⇒ 401.
-  let (status, _) = app
-    .send(Method::GET, "/user/info", Some("not-a-real-jwt"), None)
+  // Replaying the first refresh token is rejected...
+  let (status, _, _) = app
+    .send_cookies(Method::POST, "/auth/refresh", &login, None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  // ...and revokes the whole family, including the rotated token.
+  let (status, _, _) = app
+    .send_cookies(Method::POST, "/auth/refresh", &cookies, None)
     .await;
   assert_eq!(status, StatusCode::UNAUTHORIZED);
 }
 
 #[tokio::test]
-async fn user_info_returns_profile() {
+async fn auth_refresh_concurrent_requests_share_successor() {
   let app = TestApp::new().await;
-  let uid = app.local_user("ui", "pw").await;
-  let token = app.token(uid);
-  let (status, body) = app
-    .send(Method::GET, "/user/info", Some(&token), None)
+  app.local_user("rc", "pw").await;
+  let login = app.login("rc@example.com", "pw").await;
+
+  // Two tabs refreshing with the same token both stay logged in...
+  let (first, second) = tokio::join!(
+    app.send_cookies(Method::POST, "/auth/refresh", &login, None),
+    app.send_cookies(Method::POST, "/auth/refresh", &login, None),
+  );
+  assert_eq!(first.0, StatusCode::OK);
+  assert_eq!(second.0, StatusCode::OK);
+  assert_eq!(first.1[REFRESH_COOKIE_NAME], second.1[REFRESH_COOKIE_NAME]);
+  assert_ne!(first.1[REFRESH_COOKIE_NAME], login[REFRESH_COOKIE_NAME]);
+
+  // ...and the shared successor keeps working.
+  let (status, _, _) = app
+    .send_cookies(Method::POST, "/auth/refresh", &second.1, None)
     .await;
   assert_eq!(status, StatusCode::OK);
-  assert_eq!(body["email"], json!("ui@example.com"));
-  assert_eq!(body["oidc_user"], json!(false));
 }
 
 #[tokio::test]
-async fn user_avatar_missing_is_not_found() {
+async fn auth_refresh_without_cookie_is_unauthorized() {
   let app = TestApp::new().await;
-  let uid = app.local_user("av", "pw").await;
-  let token = app.token(uid);
-  let other = Uuid::now_v7();
-  let (status, _) = app
-    .send(
-      Method::GET,
-      &format!("/user/info/avatar/{other}"),
-      Some(&token),
-      None,
-    )
+  let (status, _, _) = app
+    .send_cookies(Method::POST, "/auth/refresh", &HashMap::new(), None)
     .await;
-  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn auth_logout_revokes_refresh_token() {
+  let app = TestApp::new().await;
+  app.local_user("lr", "pw").await;
+  let login = app.login("lr@example.com", "pw").await;
+
+  let (status, cookies, body) = app
+    .send_cookies(Method::POST, "/auth/logout", &login, None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  // Password sessions have no provider to log out at.
+  assert!(body["redirect"].is_null());
+  // Both cookies are cleared on the client.
+  assert_eq!(cookies[REFRESH_COOKIE_NAME], "");
+  assert_eq!(cookies[JWT_COOKIE_NAME], "");
+
+  let (status, _, _) = app
+    .send_cookies(Method::POST, "/auth/refresh", &login, None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
 }
 
 // ---------------------------------------------------------------------------
-// user/account
+// sessions
 // ---------------------------------------------------------------------------
 
 #[tokio::test]
-async fn account_update_name() {
+async fn sessions_list_and_revoke_others() {
   let app = TestApp::new().await;
-  let uid = app.local_user("acc", "pw").await;
-  let token = app.token(uid);
+  app.local_user("se", "pw").await;
+  let first = app.login("se@example.com", "pw").await;
+  let second = app.login("se@example.com", "pw").await;
 
-  let (status, _) = app
-    .send(
+  let (status, _, body) = app
+    .send_cookies(Method::GET, "/user/account/sessions", &second, None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let sessions = body.as_array().unwrap();
+  assert_eq!(sessions.len(), 2);
+  assert_eq!(
+    sessions
+      .iter()
+      .filter(|s| s["current"] == json!(true))
+      .count(),
+    1
+  );
+
+  let (status, _, _) = app
+    .send_cookies(
       Method::POST,
-      "/user/account/update",
-      Some(&token),
-      Some(json!({"username":"  "})),
+      "/user/account/sessions/revoke_others",
+      &second,
+      None,
     )
     .await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(status, StatusCode::OK);
 
-  let (status, _) = app
-    .send(
-      Method::POST,
-      "/user/account/update",
-      Some(&token),
-      Some(json!({"username":"renamed"})),
+  // The other session's access and refresh tokens stop working immediately.
+  let (status, _, _) = app
+    .send_cookies(Method::GET, "/user/info", &first, None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  let (status, _, _) = app
+    .send_cookies(Method::POST, "/auth/refresh", &first, None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  // The caller's own session survives.
+  let (status, _, _) = app
+    .send_cookies(Method::GET, "/user/info", &second, None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn sessions_revoke_single_only_own() {
+  let app = TestApp::new().await;
+  let owner = app.local_user("so", "pw").await;
+  app.local_user("sx", "pw").await;
+  let own = app.login("so@example.com", "pw").await;
+  let foreign = app.login("sx@example.com", "pw").await;
+
+  let sid = app
+    .jwt
+    .validate_token(&own[JWT_COOKIE_NAME])
+    .await
+    .unwrap()
+    .sid
+    .unwrap();
+
+  // Another user cannot revoke the session.
+  let (status, _, _) = app
+    .send_cookies(
+      Method::DELETE,
+      "/user/account/sessions",
+      &foreign,
+      Some(json!({"uuid": sid})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+
+  let (status, _, _) = app
+    .send_cookies(
+      Method::DELETE,
+      "/user/account/sessions",
+      &own,
+      Some(json!({"uuid": sid})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
-  assert_eq!(
-    app.conn.user().get_user_by_id(uid).await.unwrap().name,
-    "renamed"
+  assert!(
+    app
+      .conn
+      .session()
+      .list_user_sessions(owner, None)
+      .await
+      .unwrap()
+      .is_empty()
   );
 }
 
 #[tokio::test]
-async fn account_change_password_paths() {
+async fn management_revoke_user_sessions() {
   let app = TestApp::new().await;
-  let uid = app.local_user("pwch", "old-pw").await;
-  let token = app.token(uid);
-  let old = app.encrypt("old-pw");
-  let new = app.encrypt("new-pw");
+  let admin = app.admin_user("sa").await;
+  let target = app.local_user("st", "pw").await;
+  let target_login = app.login("st@example.com", "pw").await;
+  let admin_token = app.token(admin);
 
-  // Wrong old password.
+  // Requires user:edit.
+  let target_token = app.token(target);
   let (status, _) = app
     .send(
-      Method::POST,
-      "/user/account/password",
-      Some(&token),
-      Some(json!({"old_password":app.encrypt("bad"),"new_password":new})),
+      Method::DELETE,
+      "/user/management/sessions",
+      Some(&target_token),
+      Some(json!({"uuid": target})),
     )
     .await;
   assert_eq!(status, StatusCode::FORBIDDEN);
 
-  // Correct rotation.
+  // Editors can't log out users with more permissions.
+  let editor = app.editor_user("se").await;
   let (status, _) = app
     .send(
-      Method::POST,
-      "/user/account/password",
-      Some(&token),
-      Some(json!({"old_password":old,"new_password":new})),
+      Method::DELETE,
+      "/user/management/sessions",
+      Some(&app.token(editor)),
+      Some(json!({"uuid": admin})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management/sessions",
+      Some(&admin_token),
+      Some(json!({"uuid": target})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
+
+  let (status, _, _) = app
+    .send_cookies(Method::GET, "/user/info", &target_login, None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
 }
 
 // ---------------------------------------------------------------------------
-// user/management
+// totp
 // ---------------------------------------------------------------------------
 
-#[tokio::test]
-async fn management_requires_permission() {
-  let app = TestApp::new().await;
-  // A plain user without UserView cannot list users.
-  let uid = app.local_user("plain", "pw").await;
-  let token = app.token(uid);
-  let (status, _) = app
-    .send(Method::GET, "/user/management", Some(&token), None)
-    .await;
-  assert_eq!(status, StatusCode::FORBIDDEN);
+impl TestApp {
+  /// Enroll the user in TOTP and return the secret and the recovery codes.
+  async fn enable_totp(&self, cookies: &HashMap<String, String>) -> (String, Vec<String>) {
+    let (status, _, setup) = self
+      .send_cookies(Method::POST, "/user/account/totp", cookies, None)
+      .await;
+    assert_eq!(status, StatusCode::OK);
+    let secret = setup["secret"].as_str().unwrap().to_string();
+    assert!(
+      setup["uri"]
+        .as_str()
+        .unwrap()
+        .starts_with("otpauth://totp/")
+    );
+
+    let (status, _, body) = self
+      .send_cookies(
+        Method::POST,
+        "/user/account/totp/confirm",
+        cookies,
+        Some(json!({"code": current_code(&secret)})),
+      )
+      .await;
+    assert_eq!(status, StatusCode::OK);
+    let codes = body["recovery_codes"]
+      .as_array()
+      .unwrap()
+      .iter()
+      .map(|c| c.as_str().unwrap().to_string())
+      .collect();
+
+    (secret, codes)
+  }
 }
 
 #[tokio::test]
-async fn management_crud_flow() {
+async fn totp_login_requires_second_step() {
   let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
-  let token = app.token(admin);
+  let user = app.local_user("tf", "pw").await;
+  let cookies = app.login("tf@example.com", "pw").await;
+  let (_, codes) = app.enable_totp(&cookies).await;
 
-  // List users (admin only at first).
-  let (status, body) = app
-    .send(Method::GET, "/user/management", Some(&token), None)
+  let (status, _, body) = app
+    .send_cookies(Method::GET, "/user/account/totp", &cookies, None)
     .await;
   assert_eq!(status, StatusCode::OK);
-  assert_eq!(body.as_array().unwrap().len(), 1);
+  assert_eq!(body["enabled"], json!(true));
+  assert_eq!(body["recovery_codes"], json!(codes.len()));
 
-  // mailActive / groups / users-simple.
-  let (status, body) = app
-    .send(Method::GET, "/user/management/mail", Some(&token), None)
+  // The password step only yields a partial token.
+  let login = json!({"email": "tf@example.com", "password": app.encrypt("pw")});
+  let (status, partial, body) = app
+    .send_cookies(Method::POST, "/auth/password", &HashMap::new(), Some(login))
     .await;
   assert_eq!(status, StatusCode::OK);
-  assert_eq!(body["active"], json!(false));
-  let (status, _) = app
-    .send(Method::GET, "/user/management/groups", Some(&token), None)
+  assert_eq!(body["mfa"], json!(["totp"]));
+  assert!(!partial.contains_key(JWT_COOKIE_NAME));
+  let mfa = partial[MFA_COOKIE_NAME].clone();
+
+  // The partial token is not accepted as a normal token.
+  let (status, _) = app.send(Method::GET, "/user/info", Some(&mfa), None).await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+
+  let (status, _, _) = app
+    .send_cookies(
+      Method::POST,
+      "/auth/totp",
+      &partial,
+      Some(json!({"code": "000000x"})),
+    )
     .await;
-  assert_eq!(status, StatusCode::OK);
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
 
-  // Create a user (mailer inactive ⇒ password required, encrypted).
-  let pw = app.encrypt("created-pw");
-  let (status, body) = app
-    .send(
+  // A recovery code completes the login, but only once.
+  let (status, full, body) = app
+    .send_cookies(
       Method::POST,
-      "/user/management",
-      Some(&token),
-      Some(json!({"name":"created","email":"created@example.com","password":pw})),
+      "/auth/totp",
+      &partial,
+      Some(json!({"code": codes[0]})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
-  let new_id = body["uuid"].as_str().unwrap().to_string();
+  assert_eq!(body["user"], json!(user));
+  let (status, _, _) = app
+    .send_cookies(Method::GET, "/user/info", &full, None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, _, _) = app
+    .send_cookies(
+      Method::POST,
+      "/auth/totp",
+      &partial,
+      Some(json!({"code": codes[0]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn totp_verify_without_partial_token_is_unauthorized() {
+  let app = TestApp::new().await;
+  let user = app.local_user("tn", "pw").await;
+  let token = app.token(user);
 
-  // Duplicate email conflicts.
   let (status, _) = app
     .send(
       Method::POST,
-      "/user/management",
+      "/auth/totp",
       Some(&token),
-      Some(json!({"name":"created2","email":"created@example.com","password":pw})),
+      Some(json!({"code": "123456"})),
     )
     .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn totp_disable_and_admin_reset() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("ta").await;
+  let user = app.local_user("tr", "pw").await;
+  let cookies = app.login("tr@example.com", "pw").await;
+  let (_, codes) = app.enable_totp(&cookies).await;
+
+  // Enrolling twice is rejected.
+  let (status, _, _) = app
+    .send_cookies(Method::POST, "/user/account/totp", &cookies, None)
+    .await;
   assert_eq!(status, StatusCode::CONFLICT);
 
-  // Empty name rejected.
+  // Disabling requires a valid code.
+  let (status, _, _) = app
+    .send_cookies(
+      Method::DELETE,
+      "/user/account/totp",
+      &cookies,
+      Some(json!({"code": "wrong"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  let (status, _, _) = app
+    .send_cookies(
+      Method::DELETE,
+      "/user/account/totp",
+      &cookies,
+      Some(json!({"code": codes[1]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(!app.conn.totp().is_enabled(user).await.unwrap());
+
+  app.enable_totp(&cookies).await;
+  // Editors can't reset the second factor of users with more permissions.
+  let editor = app.editor_user("tr_editor").await;
   let (status, _) = app
     .send(
-      Method::POST,
-      "/user/management",
-      Some(&token),
-      Some(json!({"name":" ","email":"x@example.com","password":pw})),
+      Method::DELETE,
+      "/user/management/totp",
+      Some(&app.token(editor)),
+      Some(json!({"uuid": admin})),
     )
     .await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
-
-  // Missing password (mailer inactive) rejected.
+  assert_eq!(status, StatusCode::FORBIDDEN);
   let (status, _) = app
     .send(
-      Method::POST,
-      "/user/management",
-      Some(&token),
-      Some(json!({"name":"nopw","email":"nopw@example.com","password":null})),
+      Method::DELETE,
+      "/user/management/totp",
+      Some(&app.token(admin)),
+      Some(json!({"uuid": user})),
     )
     .await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(status, StatusCode::OK);
+  assert!(!app.conn.totp().is_enabled(user).await.unwrap());
 
-  // User info by id.
-  let (status, _) = app
+  // Without TOTP the password login completes in one step again.
+  let cookies = app.login("tr@example.com", "pw").await;
+  assert!(cookies.contains_key(JWT_COOKIE_NAME));
+}
+
+// ---------------------------------------------------------------------------
+// passkeys
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn passkeys_manage_own_only() {
+  let app = TestApp::new().await;
+  let owner = app.local_user("po", "pw").await;
+  let other = app.local_user("px", "pw").await;
+  let id = app
+    .conn
+    .passkey()
+    .create_passkey(owner, "Laptop".into(), "cred".into(), "{}".into(), false)
+    .await
+    .unwrap();
+  let owner_token = app.token(owner);
+  let other_token = app.token(other);
+
+  let (status, body) = app
     .send(
       Method::GET,
-      &format!("/user/management/{new_id}"),
-      Some(&token),
+      "/user/account/passkeys",
+      Some(&owner_token),
       None,
     )
     .await;
   assert_eq!(status, StatusCode::OK);
-  let (status, _) = app
+  assert_eq!(body[0]["name"], json!("Laptop"));
+
+  let (status, body) = app
     .send(
       Method::GET,
-      &format!("/user/management/{}", Uuid::now_v7()),
-      Some(&token),
+      "/user/account/passkeys",
+      Some(&other_token),
       None,
     )
     .await;
-  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body, json!([]));
 
-  // Edit user (rename, no groups).
   let (status, _) = app
     .send(
       Method::PUT,
-      "/user/management",
-      Some(&token),
-      Some(json!({"uuid":new_id,"name":"edited","groups":[]})),
+      "/user/account/passkeys",
+      Some(&other_token),
+      Some(json!({"uuid": id, "name": "Mine"})),
     )
     .await;
-  assert_eq!(status, StatusCode::OK);
-
-  // Reset the created user's password (mailer inactive).
+  assert_eq!(status, StatusCode::NOT_FOUND);
   let (status, _) = app
     .send(
       Method::PUT,
-      "/user/management/password",
-      Some(&token),
-      Some(json!({"uuid":new_id,"new_password":app.encrypt("reset-pw")})),
+      "/user/account/passkeys",
+      Some(&owner_token),
+      Some(json!({"uuid": id, "name": " "})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  let (status, _) = app
+    .send(
+      Method::PUT,
+      "/user/account/passkeys",
+      Some(&owner_token),
+      Some(json!({"uuid": id, "name": "Phone"})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
 
-  // Delete the created user.
   let (status, _) = app
     .send(
       Method::DELETE,
-      "/user/management",
-      Some(&token),
-      Some(json!({"uuid":new_id})),
+      "/user/account/passkeys",
+      Some(&other_token),
+      Some(json!({"uuid": id})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/account/passkeys",
+      Some(&owner_token),
+      Some(json!({"uuid": id})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
+  assert!(!app.conn.passkey().has_passkeys(owner).await.unwrap());
 }
 
 #[tokio::test]
-async fn management_cannot_delete_last_admin() {
+async fn passkeys_only_required_as_second_factor_if_registered_as_one() {
   let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
-  let token = app.token(admin);
-  let (status, _) = app
-    .send(
-      Method::DELETE,
-      "/user/management",
-      Some(&token),
-      Some(json!({"uuid":admin.to_string()})),
+  let user = app.local_user("pm", "pw").await;
+  let login = || json!({"email": "pm@example.com", "password": app.encrypt("pw")});
+
+  // A passkey for password-less logins does not change the password login.
+  app
+    .conn
+    .passkey()
+    .create_passkey(user, "Laptop".into(), "cred".into(), "{}".into(), false)
+    .await
+    .unwrap();
+  let (status, cookies, body) = app
+    .send_cookies(
+      Method::POST,
+      "/auth/password",
+      &HashMap::new(),
+      Some(login()),
     )
     .await;
-  assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["mfa"], json!([]));
+  assert!(cookies.contains_key(JWT_COOKIE_NAME));
+
+  app
+    .conn
+    .passkey()
+    .create_passkey(user, "Key".into(), "cred2".into(), "{}".into(), true)
+    .await
+    .unwrap();
+  let (status, cookies, body) = app
+    .send_cookies(
+      Method::POST,
+      "/auth/password",
+      &HashMap::new(),
+      Some(login()),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["mfa"], json!(["passkey"]));
+  assert!(!cookies.contains_key(JWT_COOKIE_NAME));
 }
 
 #[tokio::test]
-async fn management_convert_oidc_user() {
+async fn identities_unlink_keeps_a_login_method() {
   let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
-  let token = app.token(admin);
-
-  // An OIDC user can be converted to local; a local one cannot.
-  let oidc_uid = app
+  let uid = app
     .conn
     .user()
     .create_user(
-      "oidc".into(),
-      "oidc@example.com".into(),
-      "h".into(),
+      "sso".into(),
+      "sso@example.com".into(),
+      String::new(),
       SALT.into(),
       true,
-      None,
+      Some("default-sub".into()),
     )
     .await
     .unwrap();
+  app
+    .conn
+    .oidc_identity()
+    .link(uid, "corp", "corp-sub")
+    .await
+    .unwrap();
+  let token = app.token(uid);
+
+  let (status, body) = app
+    .send(Method::GET, "/user/account/identities", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body[0]["provider"], json!("default"));
+  assert_eq!(body[1]["provider"], json!("corp"));
+
   let (status, _) = app
     .send(
-      Method::PUT,
-      "/user/management/convert-oidc",
+      Method::DELETE,
+      "/user/account/identities",
       Some(&token),
-      Some(json!({"uuid":oidc_uid.to_string(),"new_password":app.encrypt("pw")})),
+      Some(json!({"provider": "github"})),
     )
     .await;
-  assert_eq!(status, StatusCode::OK);
-  assert!(
-    !app
-      .conn
-      .user()
-      .get_user_by_id(oidc_uid)
-      .await
-      .unwrap()
-      .oidc_user
-  );
-
+  assert_eq!(status, StatusCode::NOT_FOUND);
   let (status, _) = app
     .send(
-      Method::PUT,
-      "/user/management/convert-oidc",
+      Method::DELETE,
+      "/user/account/identities",
       Some(&token),
-      Some(json!({"uuid":oidc_uid.to_string(),"new_password":app.encrypt("pw")})),
+      Some(json!({"provider": "default"})),
     )
     .await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
-}
-
-// A genuinely valid 1x1 PNG, base64-encoded.
-#[cfg(feature = "avatar")]
-const PNG_1X1: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAAEElEQVR4AQEFAPr/AAoUHv8BpAE8tOS4KAAAAABJRU5ErkJggg==";
-
-#[cfg(feature = "avatar")]
-#[tokio::test]
-async fn account_avatar_update_and_invalid() {
-  let app = TestApp::new().await;
-  let uid = app.local_user("avup", "pw").await;
-  let token = app.token(uid);
+  assert_eq!(status, StatusCode::OK);
+  let user = app.conn.user().get_user_by_id(uid).await.unwrap();
+  assert_eq!(user.oidc_subject, None);
 
-  // A valid PNG is decoded, resized to 128x128, re-encoded to WebP and stored.
+  // Without a password the last identity can not be removed.
   let (status, _) = app
     .send(
-      Method::POST,
-      "/user/account/avatar",
+      Method::DELETE,
+      "/user/account/identities",
       Some(&token),
-      Some(json!({"avatar": PNG_1X1})),
+      Some(json!({"provider": "corp"})),
     )
     .await;
-  assert_eq!(status, StatusCode::OK);
+  assert_eq!(status, StatusCode::CONFLICT);
   assert!(
     app
       .conn
-      .user()
-      .get_user_avatar(uid)
+      .oidc_identity()
+      .has_identity(uid, "corp")
       .await
       .unwrap()
-      .is_some()
   );
+}
 
-  // Garbage that is not valid base64 is rejected as a bad request (not a 500).
-  let (status, _) = app
+// ---------------------------------------------------------------------------
+// api tokens
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn api_token_is_limited_to_its_permissions() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("tokens").await;
+  let jwt = app.token(admin);
+
+  let (status, body) = app
     .send(
       Method::POST,
-      "/user/account/avatar",
-      Some(&token),
-      Some(json!({"avatar": "!!!not base64!!!"})),
+      "/user/account/tokens",
+      Some(&jwt),
+      Some(json!({"name": "ci", "permissions": ["user:view"]})),
     )
     .await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(status, StatusCode::OK);
+  let token = body["token"].as_str().unwrap().to_string();
+  assert!(token.starts_with(API_TOKEN_PREFIX));
+
+  let (status, _) = app
+    .send(Method::GET, "/user/management", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  // Routes of the own account need the account scope.
+  let (status, _) = app
+    .send(Method::GET, "/user/info", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  // The owner may view groups, the token may not.
+  let (status, _) = app.send(Method::GET, "/group", Some(&token), None).await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
 
-  // Valid base64 that is not a decodable image is also a bad request.
   let (status, _) = app
     .send(
       Method::POST,
-      "/user/account/avatar",
+      "/user/account/tokens",
       Some(&token),
-      Some(json!({"avatar": BASE64_STANDARD.encode("not an image")})),
+      Some(json!({"name": "nested", "permissions": []})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, list) = app
+    .send(Method::GET, "/user/account/tokens", Some(&jwt), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(list[0]["permissions"], json!(["user:view"]));
+  assert!(!list[0]["last_used"].is_null());
+
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/account/tokens",
+      Some(&jwt),
+      Some(json!({"uuid": body["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(Method::GET, "/user/info", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn api_token_account_scope() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("scoped_tokens", "pw").await;
+  let jwt = app.token(uid);
+
+  // Every user may grant the account scope, it is not a group permission.
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/user/account/tokens",
+      Some(&jwt),
+      Some(json!({"name": "cli", "permissions": ["account:self"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let token = body["token"].as_str().unwrap().to_string();
+
+  let (status, info) = app
+    .send(Method::GET, "/user/info", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(info["uuid"], json!(uid));
+  let (status, _) = app
+    .send(Method::GET, "/user/management", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn api_token_cannot_exceed_owner_permissions() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("plain_tokens", "pw").await;
+  let jwt = app.token(uid);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/tokens",
+      Some(&jwt),
+      Some(json!({"name": "ci", "permissions": ["user:edit"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/tokens",
+      Some(&jwt),
+      Some(json!({"name": "ci", "permissions": [], "exp": "2000-01-01T00:00:00Z"})),
     )
     .await;
   assert_eq!(status, StatusCode::BAD_REQUEST);
 }
 
 // ---------------------------------------------------------------------------
-// user/email change flows
+// service accounts
 // ---------------------------------------------------------------------------
 
 #[tokio::test]
-async fn management_change_email_invariants() {
+async fn service_account_client_credentials_flow() {
   let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
+  let admin = app.admin_user("svc_admin").await;
   let token = app.token(admin);
-  let target = app.local_user("target", "pw").await;
+  let group = app.conn.group().create_group("Bots".into()).await.unwrap();
+  app
+    .conn
+    .group()
+    .add_permissions_to_group(group, vec!["user:view".into()])
+    .await
+    .unwrap();
 
-  // Empty email rejected (mailer is inactive so the handler proceeds past the
-  // active-mailer guard).
-  let (status, _) = app
+  let (status, created) = app
     .send(
       Method::POST,
-      "/user/management/email",
+      "/user/management/service_accounts",
       Some(&token),
-      Some(json!({"uuid":target.to_string(),"new_email":""})),
+      Some(json!({"name": "ci", "groups": [group]})),
     )
     .await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(status, StatusCode::OK);
+  let client_id = created["client_id"].clone();
 
-  // Valid change succeeds and is persisted (lower-cased).
+  // Service accounts are not mixed into the human user list.
+  let (_, users) = app
+    .send(Method::GET, "/user/management", Some(&token), None)
+    .await;
+  assert!(users.as_array().unwrap().iter().all(|u| u["name"] != "ci"));
+  let (_, accounts) = app
+    .send(
+      Method::GET,
+      "/user/management/service_accounts",
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(accounts[0]["uuid"], client_id);
+
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/auth/client",
+      None,
+      Some(json!({"client_id": client_id, "client_secret": created["client_secret"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let access = body["access_token"].as_str().unwrap().to_string();
   let (status, _) = app
+    .send(Method::GET, "/user/management", Some(&access), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app.send(Method::GET, "/group", Some(&access), None).await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, rotated) = app
     .send(
       Method::POST,
-      "/user/management/email",
+      "/user/management/service_accounts/secret",
       Some(&token),
-      Some(json!({"uuid":target.to_string(),"new_email":"Moved@Example.com"})),
+      Some(json!({"uuid": client_id})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
-  assert_eq!(
-    app.conn.user().get_user_by_id(target).await.unwrap().email,
-    "moved@example.com"
-  );
-
-  // Changing to an address already in use conflicts.
+  // Tokens issued for the old secret are revoked with it.
+  let (status, _) = app
+    .send(Method::GET, "/user/management", Some(&access), None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
   let (status, _) = app
     .send(
       Method::POST,
-      "/user/management/email",
-      Some(&token),
-      Some(json!({"uuid":target.to_string(),"new_email":"admin@example.com"})),
+      "/auth/client",
+      None,
+      Some(json!({"client_id": client_id, "client_secret": created["client_secret"]})),
     )
     .await;
-  assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/auth/client",
+      None,
+      Some(json!({"client_id": client_id, "client_secret": rotated["client_secret"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
 }
 
 #[tokio::test]
-async fn account_email_change_confirm_without_request_is_not_found() {
+async fn service_account_endpoints_reject_human_users() {
   let app = TestApp::new().await;
-  let uid = app.local_user("ec", "pw").await;
-  let token = app.token(uid);
+  let admin = app.admin_user("svc_admin2").await;
+  let token = app.token(admin);
+
   let (status, _) = app
     .send(
-      Method::POST,
-      "/user/account/email_change_confirm",
+      Method::DELETE,
+      "/user/management/service_accounts",
       Some(&token),
-      Some(json!({"new_code":"000000","old_code":"000000"})),
+      Some(json!({"uuid": admin})),
     )
     .await;
   assert_eq!(status, StatusCode::NOT_FOUND);
-}
 
-// ---------------------------------------------------------------------------
-// permission invariants
-// ---------------------------------------------------------------------------
+  // A human user has no client secret to authenticate with.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/auth/client",
+      None,
+      Some(json!({"client_id": admin, "client_secret": "password"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
 
 #[tokio::test]
-async fn edit_user_cannot_grant_unheld_permissions() {
+async fn service_account_editor_needs_account_permissions() {
   let app = TestApp::new().await;
-  app.admin_user("admin").await; // sets up the admin group
+  let admin = app.admin_user("svc_admin3").await;
+  let admin_group = app
+    .conn
+    .setup()
+    .get_admin_group_id()
+    .await
+    .unwrap()
+    .unwrap();
+  let (status, created) = app
+    .send(
+      Method::POST,
+      "/user/management/service_accounts",
+      Some(&app.token(admin)),
+      Some(json!({"name": "root-bot", "groups": [admin_group]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
 
   // An editor that only holds `user:edit`.
-  let editor = app.local_user("editor", "pw").await;
-  let editor_group = app
+  let editor = app.local_user("svc_editor", "pw").await;
+  let editors = app
     .conn
     .group()
-    .create_group("editors".into())
+    .create_group("Editors".into())
     .await
     .unwrap();
   app
     .conn
     .group()
-    .add_permissions_to_group(editor_group, vec!["user:edit".into()])
+    .add_permissions_to_group(editors, vec!["user:edit".into()])
     .await
     .unwrap();
   app
     .conn
     .group()
-    .add_users_to_group(editor_group, vec![editor])
+    .add_users_to_group(editors, vec![editor])
     .await
     .unwrap();
+  let editor_token = app.token(editor);
 
-  // A privileged group the editor must not be able to assign.
-  let priv_group = app.conn.group().create_group("priv".into()).await.unwrap();
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/service_accounts/secret",
+      Some(&editor_token),
+      Some(json!({"uuid": created["client_id"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management/service_accounts",
+      Some(&editor_token),
+      Some(json!({"uuid": created["client_id"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  // The old secret still works.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/auth/client",
+      None,
+      Some(json!({"client_id": created["client_id"], "client_secret": created["client_secret"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+// ---------------------------------------------------------------------------
+// acl
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn acl_grant_allows_single_resource() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("acl_admin").await;
+  // The admin needs the resource permission to hand it out.
+  let admin_group = app
+    .conn
+    .setup()
+    .get_admin_group_id()
+    .await
+    .unwrap()
+    .unwrap();
   app
     .conn
     .group()
-    .add_permissions_to_group(priv_group, vec!["settings:edit".into()])
+    .add_permissions_to_group(admin_group, vec!["project:edit".into()])
     .await
     .unwrap();
+  let admin_token = app.token(admin);
+  let user = app.local_user("acl_user", "pw").await;
+  let token = app.token(user);
 
-  let victim = app.local_user("victim", "pw").await;
-  let token = app.token(editor);
+  let (status, _) = app
+    .send(Method::GET, "/project/42", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/acl",
+      Some(&admin_token),
+      Some(json!({
+        "resource_type": "project",
+        "resource_id": "42",
+        "permission": "project:edit",
+        "subject": {"type": "user", "uuid": user},
+      })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, _) = app
+    .send(Method::GET, "/project/42", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(Method::GET, "/project/43", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  // The group permission covers every project.
+  let (status, _) = app
+    .send(Method::GET, "/project/43", Some(&admin_token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, grants) = app
+    .send(Method::GET, "/acl/project/42", Some(&admin_token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(grants[0]["subject"]["uuid"], json!(user));
+
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/acl",
+      Some(&admin_token),
+      Some(json!({"uuid": body["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(Method::GET, "/project/42", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn acl_cannot_grant_unheld_permission() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("acl_admin2").await;
+  let token = app.token(admin);
+  let user = app.local_user("acl_user2", "pw").await;
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/acl",
+      Some(&token),
+      Some(json!({
+        "resource_type": "project",
+        "resource_id": "42",
+        "permission": "project:edit",
+        "subject": {"type": "user", "uuid": user},
+      })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+// ---------------------------------------------------------------------------
+// audit log
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn audit_log_records_admin_changes() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("audit_admin").await;
+  let token = app.token(admin);
+
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/group",
+      Some(&token),
+      Some(json!({"name": "Editors"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let group = body["uuid"].clone();
+
+  let (status, _) = app
+    .send(
+      Method::PUT,
+      "/group",
+      Some(&token),
+      Some(json!({
+        "uuid": group,
+        "name": "Writers",
+        "permissions": ["group:view"],
+        "users": [],
+      })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, page) = app
+    .send(Method::GET, "/audit?action=group.*", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(page["total"], json!(2));
+  let edit = &page["entries"][0];
+  assert_eq!(edit["action"], json!("group.edit"));
+  assert_eq!(edit["actor"], json!(admin));
+  assert_eq!(edit["target_id"], group);
+  assert_eq!(edit["ip"], json!("127.0.0.1"));
+  assert_eq!(edit["before"]["name"], json!("Editors"));
+  assert_eq!(edit["after"]["name"], json!("Writers"));
+
+  let (status, page) = app
+    .send(
+      Method::GET,
+      "/audit?action=group.create&per_page=1&page=1",
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(page["total"], json!(1));
+  assert_eq!(page["entries"], json!([]));
+}
+
+#[tokio::test]
+async fn audit_log_redacts_secrets() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("audit_secret").await;
+  let token = app.token(admin);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/settings/mail",
+      Some(&token),
+      Some(json!({"smtp_enabled": false, "smtp_password": "hunter2"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (_, page) = app
+    .send(
+      Method::GET,
+      "/audit?action=settings.mail",
+      Some(&token),
+      None,
+    )
+    .await;
+  let entry = &page["entries"][0];
+  assert_eq!(entry["before"]["smtp_password"], Value::Null);
+  assert_eq!(entry["after"]["smtp_password"], json!("<redacted>"));
+  assert!(!page.to_string().contains("hunter2"));
+}
+
+#[tokio::test]
+async fn audit_log_requires_permission() {
+  let app = TestApp::new().await;
+  let user = app.local_user("audit_user", "pw").await;
+
+  let (status, _) = app
+    .send(Method::GET, "/audit", Some(&app.token(user)), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+// ---------------------------------------------------------------------------
+// account lockout
+// ---------------------------------------------------------------------------
+
+impl TestApp {
+  async fn login_status(&self, email: &str, plain: &str) -> StatusCode {
+    let body = json!({"email": email, "password": self.encrypt(plain)});
+    self
+      .send(Method::POST, "/auth/password", None, Some(body))
+      .await
+      .0
+  }
+}
+
+#[tokio::test]
+async fn lockout_after_threshold_and_admin_unlock() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("lock_admin").await;
+  let uid = app.local_user("locked", "s3cret").await;
+  app
+    .conn
+    .settings()
+    .save_settings(&LockoutSettings {
+      lockout_threshold: Some(3),
+      lockout_delay: Some(0),
+      ..Default::default()
+    })
+    .await
+    .unwrap();
+
+  for _ in 0..3 {
+    let status = app.login_status("locked@example.com", "wrong").await;
+    assert_eq!(status, StatusCode::UNAUTHORIZED);
+  }
+  // Even the correct password is rejected while locked.
+  let status = app.login_status("locked@example.com", "s3cret").await;
+  assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+
+  let token = app.token(admin);
+  let (_, info) = app
+    .send(
+      Method::GET,
+      &format!("/user/management/{uid}"),
+      Some(&token),
+      None,
+    )
+    .await;
+  assert!(!info["locked_until"].is_null());
+
+  // Editors can't unlock users with more permissions.
+  let editor = app.editor_user("lock_editor").await;
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/unlock",
+      Some(&app.token(editor)),
+      Some(json!({"uuid": admin})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/unlock",
+      Some(&token),
+      Some(json!({"uuid": uid})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let status = app.login_status("locked@example.com", "s3cret").await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn lockout_delays_repeated_failures() {
+  let app = TestApp::new().await;
+  app.local_user("slow", "s3cret").await;
+
+  // A single typo is not delayed, the second failure is.
+  let status = app.login_status("slow@example.com", "wrong").await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  let status = app.login_status("slow@example.com", "wrong").await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  let status = app.login_status("slow@example.com", "s3cret").await;
+  assert_eq!(status, StatusCode::TOO_MANY_REQUESTS);
+}
+
+#[tokio::test]
+async fn lockout_settings_validation() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("lock_settings").await;
+  let token = app.token(admin);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/settings/lockout",
+      Some(&token),
+      Some(json!({"lockout_enabled": true, "lockout_threshold": 0})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/settings/lockout",
+      Some(&token),
+      Some(json!({"lockout_enabled": false})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (_, body) = app
+    .send(Method::GET, "/settings/lockout", Some(&token), None)
+    .await;
+  assert_eq!(body["lockout_enabled"], json!(false));
+}
+
+// ---------------------------------------------------------------------------
+// user/info
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn user_info_requires_auth() {
+  let app = TestApp::new().await;
+  // No credentials at all ⇒ the token cannot be located ⇒ 400.
+  let (status, _) = app.send(Method::GET, "/user/info", None, None).await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // A syntactically present but bogus bearer token ⇒ 401.
+  let (status, _) = app
+    .send(Method::GET, "/user/info", Some("not-a-real-jwt"), None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+}
+
+#[tokio::test]
+async fn user_info_returns_profile() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("ui", "pw").await;
+  let token = app.token(uid);
+  let (status, body) = app
+    .send(Method::GET, "/user/info", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["email"], json!("ui@example.com"));
+  assert_eq!(body["oidc_user"], json!(false));
+}
+
+#[tokio::test]
+async fn user_avatar_missing_is_not_found() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("av", "pw").await;
+  let token = app.token(uid);
+  let other = Uuid::now_v7();
+  let (status, _) = app
+    .send(
+      Method::GET,
+      &format!("/user/info/avatar/{other}"),
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+// ---------------------------------------------------------------------------
+// user/account
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn account_update_name() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("acc", "pw").await;
+  let token = app.token(uid);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/update",
+      Some(&token),
+      Some(json!({"username":"  "})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/update",
+      Some(&token),
+      Some(json!({"username":"renamed"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(
+    app.conn.user().get_user_by_id(uid).await.unwrap().name,
+    "renamed"
+  );
+}
+
+#[tokio::test]
+async fn account_change_password_paths() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("pwch", "old-pw").await;
+  let token = app.token(uid);
+  let old = app.encrypt("old-pw");
+  let new = app.encrypt("new-pw");
+
+  // Wrong old password.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/password",
+      Some(&token),
+      Some(json!({"old_password":app.encrypt("bad"),"new_password":new})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  // Correct rotation.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/password",
+      Some(&token),
+      Some(json!({"old_password":old,"new_password":new})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn account_change_password_enforces_policy() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("pwpol", "Old-passw0rd").await;
+  let token = app.token(uid);
+  app
+    .conn
+    .settings()
+    .save_settings(&PasswordPolicySettings {
+      password_min_length: Some(10),
+      password_require_digit: Some(true),
+      password_history: Some(1),
+      ..Default::default()
+    })
+    .await
+    .unwrap();
+
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/user/account/password",
+      Some(&token),
+      Some(json!({
+        "old_password": app.encrypt("Old-passw0rd"),
+        "new_password": app.encrypt("short"),
+      })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+  assert_eq!(
+    body["violations"],
+    json!([
+      {"reason": "too_short", "min_length": 10},
+      {"reason": "missing_digit"},
+    ])
+  );
+
+  // The current password counts towards the history.
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/user/account/password",
+      Some(&token),
+      Some(json!({
+        "old_password": app.encrypt("Old-passw0rd"),
+        "new_password": app.encrypt("Old-passw0rd"),
+      })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+  assert_eq!(
+    body["violations"],
+    json!([{"reason": "reused", "history": 1}])
+  );
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/password",
+      Some(&token),
+      Some(json!({
+        "old_password": app.encrypt("Old-passw0rd"),
+        "new_password": app.encrypt("New-passw0rd"),
+      })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+// ---------------------------------------------------------------------------
+// user/management
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn management_requires_permission() {
+  let app = TestApp::new().await;
+  // A plain user without UserView cannot list users.
+  let uid = app.local_user("plain", "pw").await;
+  let token = app.token(uid);
+  let (status, _) = app
+    .send(Method::GET, "/user/management", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+#[tokio::test]
+async fn management_crud_flow() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+
+  // List users (admin only at first).
+  let (status, body) = app
+    .send(Method::GET, "/user/management", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body.as_array().unwrap().len(), 1);
+
+  // mailActive / groups / users-simple.
+  let (status, body) = app
+    .send(Method::GET, "/user/management/mail", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(body["active"], json!(false));
+  let (status, _) = app
+    .send(Method::GET, "/user/management/groups", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  // Create a user (mailer inactive ⇒ password required, encrypted).
+  let pw = app.encrypt("created-pw");
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/user/management",
+      Some(&token),
+      Some(json!({"name":"created","email":"created@example.com","password":pw})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let new_id = body["uuid"].as_str().unwrap().to_string();
+
+  // Duplicate email conflicts.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management",
+      Some(&token),
+      Some(json!({"name":"created2","email":"created@example.com","password":pw})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CONFLICT);
+
+  // Empty name rejected.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management",
+      Some(&token),
+      Some(json!({"name":" ","email":"x@example.com","password":pw})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // Missing password (mailer inactive) rejected.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management",
+      Some(&token),
+      Some(json!({"name":"nopw","email":"nopw@example.com","password":null})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // User info by id.
+  let (status, _) = app
+    .send(
+      Method::GET,
+      &format!("/user/management/{new_id}"),
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(
+      Method::GET,
+      &format!("/user/management/{}", Uuid::now_v7()),
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+
+  // Edit user (rename, no groups).
+  let (status, _) = app
+    .send(
+      Method::PUT,
+      "/user/management",
+      Some(&token),
+      Some(json!({"uuid":new_id,"name":"edited","groups":[]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  // Reset the created user's password (mailer inactive).
+  let (status, _) = app
+    .send(
+      Method::PUT,
+      "/user/management/password",
+      Some(&token),
+      Some(json!({"uuid":new_id,"new_password":app.encrypt("reset-pw")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  // Delete the created user.
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management",
+      Some(&token),
+      Some(json!({"uuid":new_id})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn management_cannot_delete_last_admin() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management",
+      Some(&token),
+      Some(json!({"uuid":admin.to_string()})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn management_convert_oidc_user() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+
+  // An OIDC user can be converted to local; a local one cannot.
+  let oidc_uid = app
+    .conn
+    .user()
+    .create_user(
+      "oidc".into(),
+      "oidc@example.com".into(),
+      "h".into(),
+      SALT.into(),
+      true,
+      None,
+    )
+    .await
+    .unwrap();
+  let (status, _) = app
+    .send(
+      Method::PUT,
+      "/user/management/convert-oidc",
+      Some(&token),
+      Some(json!({"uuid":oidc_uid.to_string(),"new_password":app.encrypt("pw")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(
+    !app
+      .conn
+      .user()
+      .get_user_by_id(oidc_uid)
+      .await
+      .unwrap()
+      .oidc_user
+  );
+
+  let (status, _) = app
+    .send(
+      Method::PUT,
+      "/user/management/convert-oidc",
+      Some(&token),
+      Some(json!({"uuid":oidc_uid.to_string(),"new_password":app.encrypt("pw")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn management_passwords_enforce_policy() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+  let uid = app.local_user("target", "Old-passw0rd").await;
+  let oidc_uid = app
+    .conn
+    .user()
+    .create_user(
+      "oidc".into(),
+      "oidc@example.com".into(),
+      "h".into(),
+      SALT.into(),
+      true,
+      None,
+    )
+    .await
+    .unwrap();
+  app
+    .conn
+    .settings()
+    .save_settings(&PasswordPolicySettings {
+      password_min_length: Some(10),
+      ..Default::default()
+    })
+    .await
+    .unwrap();
+
+  for (path, uuid) in [
+    ("/user/management/password", uid),
+    ("/user/management/convert-oidc", oidc_uid),
+  ] {
+    let (status, body) = app
+      .send(
+        Method::PUT,
+        path,
+        Some(&token),
+        Some(json!({"uuid": uuid, "new_password": app.encrypt("short")})),
+      )
+      .await;
+    assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+    assert_eq!(
+      body["violations"],
+      json!([{"reason": "too_short", "min_length": 10}])
+    );
+    let (status, _) = app
+      .send(
+        Method::PUT,
+        path,
+        Some(&token),
+        Some(json!({"uuid": uuid, "new_password": app.encrypt("long-enough")})),
+      )
+      .await;
+    assert_eq!(status, StatusCode::OK);
+  }
+
+  // The password of a new user is set by the admin as well.
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/user/management",
+      Some(&token),
+      Some(json!({"name": "new", "email": "new@example.com", "password": app.encrypt("short")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+  assert_eq!(
+    body["violations"],
+    json!([{"reason": "too_short", "min_length": 10}])
+  );
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management",
+      Some(&token),
+      Some(
+        json!({"name": "new", "email": "new@example.com", "password": app.encrypt("long-enough")}),
+      ),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+// A genuinely valid 1x1 PNG, base64-encoded.
+#[cfg(feature = "avatar")]
+const PNG_1X1: &str = "iVBORw0KGgoAAAANSUhEUgAAAAEAAAABCAYAAAAfFcSJAAAAEElEQVR4AQEFAPr/AAoUHv8BpAE8tOS4KAAAAABJRU5ErkJggg==";
+
+#[cfg(feature = "avatar")]
+#[tokio::test]
+async fn account_avatar_update_and_invalid() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("avup", "pw").await;
+  let token = app.token(uid);
+
+  // A valid PNG is decoded, resized to 128x128, re-encoded to WebP and stored.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/avatar",
+      Some(&token),
+      Some(json!({"avatar": PNG_1X1})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(
+    app
+      .conn
+      .user()
+      .get_user_avatar(uid)
+      .await
+      .unwrap()
+      .is_some()
+  );
+
+  // Garbage that is not valid base64 is rejected as a bad request (not a 500).
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/avatar",
+      Some(&token),
+      Some(json!({"avatar": "!!!not base64!!!"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // Valid base64 that is not a decodable image is also a bad request.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/avatar",
+      Some(&token),
+      Some(json!({"avatar": BASE64_STANDARD.encode("not an image")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// ---------------------------------------------------------------------------
+// user/invitations
+// ---------------------------------------------------------------------------
+
+fn invite_token(link: &Value) -> String {
+  let link = url::Url::parse(link.as_str().unwrap()).unwrap();
+  assert_eq!(link.path(), "/invite");
+  link
+    .query_pairs()
+    .find(|(key, _)| key == "token")
+    .unwrap()
+    .1
+    .to_string()
+}
+
+#[tokio::test]
+async fn invitations_create_validate_and_accept() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("inv_admin").await;
+  let token = app.token(admin);
+  let staff = app.conn.group().create_group("Staff".into()).await.unwrap();
+  app
+    .conn
+    .group()
+    .add_permissions_to_group(staff, vec!["user:view".into()])
+    .await
+    .unwrap();
+
+  let (status, created) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"name": "Invitee", "email": "invitee@example.com", "groups": [staff]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  // Without an active mail service the link is handed out by the admin.
+  let invite = invite_token(&created["link"]);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"name": "Again", "email": "invitee@example.com", "groups": []})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"name": "Admin", "email": "inv_admin@example.com", "groups": []})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"name": "X", "email": "x@example.com", "groups": [Uuid::new_v4()]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // Editors can only hand out permissions they hold themselves.
+  let editor = app.local_user("inv_editor", "pw").await;
+  let editors = app
+    .conn
+    .group()
+    .create_group("Editors".into())
+    .await
+    .unwrap();
+  app
+    .conn
+    .group()
+    .add_permissions_to_group(editors, vec!["user:edit".into()])
+    .await
+    .unwrap();
+  app
+    .conn
+    .group()
+    .add_users_to_group(editors, vec![editor])
+    .await
+    .unwrap();
+  let admin_group = app
+    .conn
+    .setup()
+    .get_admin_group_id()
+    .await
+    .unwrap()
+    .unwrap();
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations",
+      Some(&app.token(editor)),
+      Some(json!({"name": "X", "email": "x@example.com", "groups": [admin_group]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, list) = app
+    .send(
+      Method::GET,
+      "/user/management/invitations",
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(list.as_array().unwrap().len(), 1);
+  assert_eq!(list[0]["email"], "invitee@example.com");
+  assert_eq!(list[0]["groups"][0]["name"], "Staff");
+  assert_eq!(list[0]["expired"], false);
+
+  let (status, info) = app
+    .send(
+      Method::POST,
+      "/user/invitation/info",
+      None,
+      Some(json!({"token": invite})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(info["name"], "Invitee");
+  assert_eq!(info["email"], "invitee@example.com");
+
+  // Sending the invitation again invalidates the earlier link.
+  let (status, resent) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations/resend",
+      Some(&token),
+      Some(json!({"uuid": created["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/invitation/info",
+      None,
+      Some(json!({"token": invite})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  let invite = invite_token(&resent["link"]);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/invitation/accept",
+      None,
+      Some(json!({"token": invite, "password": app.encrypt("invited-pw")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  // The user picked their own password and got the invited groups.
+  app.login("invitee@example.com", "invited-pw").await;
+  let user = app
+    .conn
+    .user()
+    .get_user_by_email("invitee@example.com")
+    .await
+    .unwrap();
+  assert_eq!(user.name, "Invitee");
+  assert!(app.conn.group().is_in_group(staff, user.id).await.unwrap());
+  let (_, list) = app
+    .send(
+      Method::GET,
+      "/user/management/invitations",
+      Some(&token),
+      None,
+    )
+    .await;
+  assert!(list.as_array().unwrap().is_empty());
+  let (status, audit) = app
+    .send(
+      Method::GET,
+      "/audit?action=invitation.accept",
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(audit["entries"][0]["actor"], json!(user.id));
+
+  // Links can only be used once.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/invitation/accept",
+      None,
+      Some(json!({"token": invite, "password": app.encrypt("invited-pw")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+#[tokio::test]
+async fn invitations_revoke() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("inv_admin").await;
+  let token = app.token(admin);
+
+  let (_, created) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"name": "Invitee", "email": "invitee@example.com", "groups": []})),
+    )
+    .await;
+  let invite = invite_token(&created["link"]);
+
+  // Only user editors manage invitations.
+  let plain = app.local_user("inv_plain", "pw").await;
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management/invitations",
+      Some(&app.token(plain)),
+      Some(json!({"uuid": created["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"uuid": created["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"uuid": created["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/invitation/accept",
+      None,
+      Some(json!({"token": invite, "password": app.encrypt("invited-pw")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert!(
+    app
+      .conn
+      .user()
+      .try_get_user_by_email("invitee@example.com")
+      .await
+      .unwrap()
+      .is_none()
+  );
+}
+
+#[tokio::test]
+async fn invitations_resend_and_revoke_check_permissions() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("inv_admin").await;
+  let token = app.token(admin);
+  let admin_group = app
+    .conn
+    .setup()
+    .get_admin_group_id()
+    .await
+    .unwrap()
+    .unwrap();
+  let (_, privileged) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"name": "Admin", "email": "admin2@example.com", "groups": [admin_group]})),
+    )
+    .await;
+  let (_, plain) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations",
+      Some(&token),
+      Some(json!({"name": "Plain", "email": "plain@example.com", "groups": []})),
+    )
+    .await;
+
+  // An editor that only holds `user:edit`.
+  let editor = app.local_user("inv_editor", "pw").await;
+  let editors = app
+    .conn
+    .group()
+    .create_group("Editors".into())
+    .await
+    .unwrap();
+  app
+    .conn
+    .group()
+    .add_permissions_to_group(editors, vec!["user:edit".into()])
+    .await
+    .unwrap();
+  app
+    .conn
+    .group()
+    .add_users_to_group(editors, vec![editor])
+    .await
+    .unwrap();
+  let editor_token = app.token(editor);
+
+  // A fresh link to the admin group would grant the editor admin rights.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations/resend",
+      Some(&editor_token),
+      Some(json!({"uuid": privileged["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management/invitations",
+      Some(&editor_token),
+      Some(json!({"uuid": privileged["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+  let (status, list) = app
+    .send(
+      Method::GET,
+      "/user/management/invitations",
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(list.as_array().unwrap().len(), 2);
+
+  let (status, resent) = app
+    .send(
+      Method::POST,
+      "/user/management/invitations/resend",
+      Some(&editor_token),
+      Some(json!({"uuid": plain["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_ne!(resent["link"], plain["link"]);
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/user/management/invitations",
+      Some(&editor_token),
+      Some(json!({"uuid": plain["uuid"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+// ---------------------------------------------------------------------------
+// user/email change flows
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn management_change_email_invariants() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+  let target = app.local_user("target", "pw").await;
+
+  // Empty email rejected (mailer is inactive so the handler proceeds past the
+  // active-mailer guard).
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/email",
+      Some(&token),
+      Some(json!({"uuid":target.to_string(),"new_email":""})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // Valid change succeeds and is persisted (lower-cased).
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/email",
+      Some(&token),
+      Some(json!({"uuid":target.to_string(),"new_email":"Moved@Example.com"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(
+    app.conn.user().get_user_by_id(target).await.unwrap().email,
+    "moved@example.com"
+  );
+
+  // Changing to an address already in use conflicts.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/management/email",
+      Some(&token),
+      Some(json!({"uuid":target.to_string(),"new_email":"admin@example.com"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[tokio::test]
+async fn account_email_change_confirm_without_request_is_not_found() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("ec", "pw").await;
+  let token = app.token(uid);
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/user/account/email_change_confirm",
+      Some(&token),
+      Some(json!({"new_code":"000000","old_code":"000000"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+}
+
+// ---------------------------------------------------------------------------
+// permission invariants
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn edit_user_cannot_grant_unheld_permissions() {
+  let app = TestApp::new().await;
+  app.admin_user("admin").await; // sets up the admin group
+
+  // An editor that only holds `user:edit`.
+  let editor = app.local_user("editor", "pw").await;
+  let editor_group = app
+    .conn
+    .group()
+    .create_group("editors".into())
+    .await
+    .unwrap();
+  app
+    .conn
+    .group()
+    .add_permissions_to_group(editor_group, vec!["user:edit".into()])
+    .await
+    .unwrap();
+  app
+    .conn
+    .group()
+    .add_users_to_group(editor_group, vec![editor])
+    .await
+    .unwrap();
+
+  // A privileged group the editor must not be able to assign.
+  let priv_group = app.conn.group().create_group("priv".into()).await.unwrap();
+  app
+    .conn
+    .group()
+    .add_permissions_to_group(priv_group, vec!["settings:edit".into()])
+    .await
+    .unwrap();
+
+  let victim = app.local_user("victim", "pw").await;
+  let token = app.token(editor);
+
+  let (status, _) = app
+    .send(
+      Method::PUT,
+      "/user/management",
+      Some(&token),
+      Some(json!({"uuid":victim.to_string(),"name":"v","groups":[priv_group.to_string()]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+// ---------------------------------------------------------------------------
+// group
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn group_crud_flow() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+
+  // Create.
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/group",
+      Some(&token),
+      Some(json!({"name":"team"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let gid = body["uuid"].as_str().unwrap().to_string();
+
+  // Duplicate name.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/group",
+      Some(&token),
+      Some(json!({"name":"team"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CONFLICT);
+
+  // Empty name.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/group",
+      Some(&token),
+      Some(json!({"name":"  "})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // List + info + users-simple.
+  let (status, _) = app.send(Method::GET, "/group", Some(&token), None).await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(Method::GET, &format!("/group/{gid}"), Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(Method::GET, "/group/users", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  // Edit (no privileged permissions involved).
+  let (status, _) = app
+    .send(
+      Method::PUT,
+      "/group",
+      Some(&token),
+      Some(json!({"uuid":gid,"name":"team2","permissions":[],"users":[]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  // Delete.
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/group",
+      Some(&token),
+      Some(json!({"uuid":gid})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn group_cannot_delete_admin_group() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+  let admin_group = app
+    .conn
+    .setup()
+    .get_admin_group_id()
+    .await
+    .unwrap()
+    .unwrap();
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/group",
+      Some(&token),
+      Some(json!({"uuid":admin_group.to_string()})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// ---------------------------------------------------------------------------
+// settings
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn settings_user_get_and_save() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+
+  let (status, body) = app
+    .send(Method::GET, "/settings/user", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  // Client secret is never returned.
+  assert!(body["settings"]["oidc_client_secret"].is_null());
+  // No provider is configured, so there is no connection to report on.
+  assert_eq!(body["oidc_status"], json!([]));
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/settings/user",
+      Some(&token),
+      Some(json!({"oidc_enabled":false})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  // Named providers can not shadow the default provider.
+  let provider = json!({
+    "name": "default",
+    "issuer": "http://127.0.0.1:9",
+    "client_id": "client",
+    "client_secret": "secret",
+    "enabled": false
+  });
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/settings/user",
+      Some(&token),
+      Some(json!({"oidc_providers": [provider]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+#[tokio::test]
+async fn settings_mail_get_and_save() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+
+  let (status, body) = app
+    .send(Method::GET, "/settings/mail", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert!(body["settings"]["smtp_password"].is_null());
+
+  // Saving disabled SMTP settings deactivates the mailer.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/settings/mail",
+      Some(&token),
+      Some(json!({"smtp_enabled":false})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn settings_requires_permission() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("plain", "pw").await;
+  let token = app.token(uid);
+  let (status, _) = app
+    .send(Method::GET, "/settings/user", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+}
+
+// ---------------------------------------------------------------------------
+// mail endpoints
+// ---------------------------------------------------------------------------
+
+#[tokio::test]
+async fn mail_reset_endpoints_are_timing_safe() {
+  let app = TestApp::new().await;
+  // Both endpoints always return 200 regardless of input to avoid leaking
+  // whether an account exists.
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/mail/reset/send",
+      None,
+      Some(json!({"email":"ghost@example.com"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/mail/reset/confirm",
+      None,
+      Some(json!({"token":"nope","new_password":"x"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+}
+
+#[tokio::test]
+async fn mail_reset_rejected_password_keeps_link() {
+  let app = TestApp::new().await;
+  let uid = app.local_user("reset", "Old-passw0rd").await;
+  app
+    .conn
+    .settings()
+    .save_settings(&PasswordPolicySettings {
+      password_history: Some(1),
+      ..Default::default()
+    })
+    .await
+    .unwrap();
+  let state = ResetPasswordState::init(&app.conn, 3600);
+  let token = state.generate_token(&app.conn, uid).await.unwrap();
+  let old = app.conn.user().get_user_by_id(uid).await.unwrap();
+
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/mail/reset/confirm",
+      None,
+      Some(json!({"token": token, "new_password": app.encrypt("Old-passw0rd")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNPROCESSABLE_ENTITY);
+  assert_eq!(
+    body["violations"],
+    json!([{"reason": "reused", "history": 1}])
+  );
+  assert_eq!(
+    state.token_user(&app.conn, &token).await.unwrap(),
+    Some(uid)
+  );
+
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/mail/reset/confirm",
+      None,
+      Some(json!({"token": token, "new_password": app.encrypt("New-passw0rd")})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  // The reset itself runs in the background.
+  for _ in 0..50 {
+    let user = app.conn.user().get_user_by_id(uid).await.unwrap();
+    if user.password != old.password {
+      assert_eq!(
+        app.login_status("reset@example.com", "New-passw0rd").await,
+        StatusCode::OK
+      );
+      return;
+    }
+    tokio::time::sleep(std::time::Duration::from_millis(100)).await;
+  }
+  panic!("password was not reset");
+}
+
+#[tokio::test]
+async fn mail_test_requires_active_mailer() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("admin").await;
+  let token = app.token(admin);
+  // Mailer is inactive ⇒ sending fails with a 400 ("not configured").
+  let (status, _) = app
+    .send(Method::POST, "/mail/test", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+}
+
+// ---------------------------------------------------------------------------
+// scim
+// ---------------------------------------------------------------------------
+
+#[cfg(feature = "scim")]
+async fn scim_token(app: &TestApp, admin: Uuid) -> String {
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/user/account/tokens",
+      Some(&app.token(admin)),
+      Some(json!({"name": "scim", "permissions": ["scim:provision"]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  body["token"].as_str().unwrap().to_string()
+}
+
+#[cfg(feature = "scim")]
+#[tokio::test]
+async fn scim_users_provisioning_flow() {
+  let app = TestApp::new().await;
+  let admin = app.admin_user("scim_admin").await;
+  let token = scim_token(&app, admin).await;
+
+  // Users without the permission are rejected.
+  let plain = app.local_user("scim_plain", "pw").await;
+  let (status, _) = app
+    .send(Method::GET, "/scim/Users", Some(&app.token(plain)), None)
+    .await;
+  assert_eq!(status, StatusCode::FORBIDDEN);
+
+  let (status, created) = app
+    .send(
+      Method::POST,
+      "/scim/Users",
+      Some(&token),
+      Some(json!({
+        "schemas": ["urn:ietf:params:scim:schemas:core:2.0:User"],
+        "userName": "Alice@Example.com",
+        "externalId": "ext-1",
+        "name": {"givenName": "Alice", "familyName": "Smith"},
+        "active": true,
+      })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CREATED);
+  assert_eq!(created["userName"], "alice@example.com");
+  assert_eq!(created["displayName"], "Alice Smith");
+  assert_eq!(created["externalId"], "ext-1");
+  let id = created["id"].as_str().unwrap().to_string();
+
+  // Duplicates are reported in the SCIM error format.
+  let (status, body) = app
+    .send(
+      Method::POST,
+      "/scim/Users",
+      Some(&token),
+      Some(json!({"userName": "other@example.com", "externalId": "ext-1"})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(body["scimType"], "uniqueness");
+  assert_eq!(body["status"], "409");
+
+  let (status, list) = app
+    .send(
+      Method::GET,
+      "/scim/Users?filter=userName%20eq%20%22ALICE%40example.com%22",
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(list["totalResults"], 1);
+  assert_eq!(list["Resources"][0]["id"], id);
+
+  let (status, _) = app
+    .send(
+      Method::GET,
+      "/scim/Users?filter=userName%20zz%20%22x%22",
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  // Entra style PATCH: renames and deactivates.
+  let (status, patched) = app
+    .send(
+      Method::PATCH,
+      &format!("/scim/Users/{id}"),
+      Some(&token),
+      Some(json!({
+        "schemas": ["urn:ietf:params:scim:api:messages:2.0:PatchOp"],
+        "Operations": [
+          {"op": "Replace", "path": "displayName", "value": "Alice Jones"},
+          {"op": "Replace", "path": "active", "value": "False"},
+        ],
+      })),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(patched["displayName"], "Alice Jones");
+  assert_eq!(patched["active"], false);
+
+  let uid: Uuid = id.parse().unwrap();
+  let user = app.conn.user().get_user_by_id(uid).await.unwrap();
+  assert_eq!(user.name, "Alice Jones");
+  assert!(!user.active);
+
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      &format!("/scim/Users/{id}"),
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::NO_CONTENT);
+  let (status, body) = app
+    .send(
+      Method::GET,
+      &format!("/scim/Users/{id}"),
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  assert_eq!(body["detail"], "User not found");
+
+  // The last admin cannot be deactivated.
+  let (status, _) = app
+    .send(
+      Method::PATCH,
+      &format!("/scim/Users/{admin}"),
+      Some(&token),
+      Some(json!({"Operations": [{"op": "replace", "value": {"active": false}}]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::CONFLICT);
+}
+
+#[cfg(feature = "scim")]
+#[tokio::test]
+async fn scim_deactivated_owner_token_is_rejected() {
+  let app = TestApp::new().await;
+  app.admin_user("scim_owner").await;
+  let second = app.local_user("scim_second", "pw").await;
+  let admin_group = app
+    .conn
+    .setup()
+    .get_admin_group_id()
+    .await
+    .unwrap()
+    .unwrap();
+  app
+    .conn
+    .group()
+    .add_users_to_group(admin_group, vec![second])
+    .await
+    .unwrap();
+  let token = scim_token(&app, second).await;
+
+  let (status, _) = app
+    .send(Method::GET, "/scim/Users", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+
+  app.conn.user().set_active(second, false).await.unwrap();
+  let (status, _) = app
+    .send(Method::GET, "/scim/Users", Some(&token), None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
 
+  let enc = app.encrypt("pw");
   let (status, _) = app
     .send(
-      Method::PUT,
-      "/user/management",
-      Some(&token),
-      Some(json!({"uuid":victim.to_string(),"name":"v","groups":[priv_group.to_string()]})),
+      Method::POST,
+      "/auth/password",
+      None,
+      Some(json!({"email": "scim_second@example.com", "password": enc})),
     )
     .await;
   assert_eq!(status, StatusCode::FORBIDDEN);
 }
 
-// ---------------------------------------------------------------------------
-// group
-// ---------------------------------------------------------------------------
-
+#[cfg(feature = "scim")]
 #[tokio::test]
-async fn group_crud_flow() {
+async fn scim_groups_membership() {
   let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
-  let token = app.token(admin);
+  let admin = app.admin_user("scim_groups").await;
+  let token = scim_token(&app, admin).await;
+  let alice = app.local_user("scim_alice", "pw").await;
+  let bob = app.local_user("scim_bob", "pw").await;
 
-  // Create.
-  let (status, body) = app
+  let (status, created) = app
     .send(
       Method::POST,
-      "/group",
+      "/scim/Groups",
       Some(&token),
-      Some(json!({"name":"team"})),
+      Some(json!({"displayName": "Staff", "members": [{"value": alice.to_string()}]})),
     )
     .await;
-  assert_eq!(status, StatusCode::OK);
-  let gid = body["uuid"].as_str().unwrap().to_string();
+  assert_eq!(status, StatusCode::CREATED);
+  assert_eq!(created["members"][0]["value"], alice.to_string());
+  let id = created["id"].as_str().unwrap().to_string();
 
-  // Duplicate name.
-  let (status, _) = app
+  let (status, body) = app
     .send(
       Method::POST,
-      "/group",
+      "/scim/Groups",
       Some(&token),
-      Some(json!({"name":"team"})),
+      Some(json!({"displayName": "Staff"})),
     )
     .await;
   assert_eq!(status, StatusCode::CONFLICT);
+  assert_eq!(body["scimType"], "uniqueness");
 
-  // Empty name.
-  let (status, _) = app
+  let (status, patched) = app
     .send(
-      Method::POST,
-      "/group",
+      Method::PATCH,
+      &format!("/scim/Groups/{id}"),
       Some(&token),
-      Some(json!({"name":"  "})),
+      Some(json!({"Operations": [
+        {"op": "add", "path": "members", "value": [{"value": bob.to_string()}]},
+        {"op": "remove", "path": format!("members[value eq \"{alice}\"]")},
+      ]})),
     )
     .await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
-
-  // List + info + users-simple.
-  let (status, _) = app.send(Method::GET, "/group", Some(&token), None).await;
-  assert_eq!(status, StatusCode::OK);
-  let (status, _) = app
-    .send(Method::GET, &format!("/group/{gid}"), Some(&token), None)
-    .await;
-  assert_eq!(status, StatusCode::OK);
-  let (status, _) = app
-    .send(Method::GET, "/group/users", Some(&token), None)
-    .await;
   assert_eq!(status, StatusCode::OK);
+  assert_eq!(patched["members"].as_array().unwrap().len(), 1);
+  assert_eq!(patched["members"][0]["value"], bob.to_string());
 
-  // Edit (no privileged permissions involved).
-  let (status, _) = app
+  let (status, list) = app
     .send(
-      Method::PUT,
-      "/group",
+      Method::GET,
+      "/scim/Groups?filter=displayName%20sw%20%22st%22",
       Some(&token),
-      Some(json!({"uuid":gid,"name":"team2","permissions":[],"users":[]})),
+      None,
     )
     .await;
   assert_eq!(status, StatusCode::OK);
+  assert_eq!(list["totalResults"], 1);
 
-  // Delete.
-  let (status, _) = app
+  let (status, user) = app
     .send(
-      Method::DELETE,
-      "/group",
+      Method::GET,
+      &format!("/scim/Users/{bob}"),
       Some(&token),
-      Some(json!({"uuid":gid})),
+      None,
     )
     .await;
   assert_eq!(status, StatusCode::OK);
-}
+  assert_eq!(user["groups"][0]["display"], "Staff");
 
-#[tokio::test]
-async fn group_cannot_delete_admin_group() {
-  let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
-  let token = app.token(admin);
+  // The admin group keeps its last member and cannot be deleted.
   let admin_group = app
     .conn
     .setup()
@@ -1027,120 +3578,375 @@ async fn group_cannot_delete_admin_group() {
     .await
     .unwrap()
     .unwrap();
+  let (status, _) = app
+    .send(
+      Method::PATCH,
+      &format!("/scim/Groups/{admin_group}"),
+      Some(&token),
+      Some(json!({"Operations": [{"op": "remove", "path": "members"}]})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_ACCEPTABLE);
   let (status, _) = app
     .send(
       Method::DELETE,
-      "/group",
+      &format!("/scim/Groups/{admin_group}"),
       Some(&token),
-      Some(json!({"uuid":admin_group.to_string()})),
+      None,
     )
     .await;
   assert_eq!(status, StatusCode::BAD_REQUEST);
+
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      &format!("/scim/Groups/{id}"),
+      Some(&token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::NO_CONTENT);
 }
 
 // ---------------------------------------------------------------------------
-// settings
+// oidc provider
 // ---------------------------------------------------------------------------
 
+/// RFC 7636 appendix B.
+#[cfg(feature = "oidc_provider")]
+const PKCE_VERIFIER: &str = "dBjftJeZ4CVP-mB92K27uhbUJU1p1r_wW1gFWFOEjXk";
+#[cfg(feature = "oidc_provider")]
+const PKCE_CHALLENGE: &str = "E9Melhoa2OwvFrEMTJguCHaoeK1t8URWbuGJSstw-cM";
+
+#[cfg(feature = "oidc_provider")]
+impl TestApp {
+  /// Send a GET and return the status with the `location` header.
+  async fn redirect(&self, uri: &str, token: Option<&str>) -> (StatusCode, String) {
+    let mut builder = Request::builder().uri(uri).extension(peer());
+    if let Some(t) = token {
+      builder = builder.header("authorization", format!("Bearer {t}"));
+    }
+    let resp = self
+      .app
+      .clone()
+      .oneshot(builder.body(Body::empty()).unwrap())
+      .await
+      .unwrap();
+    let location = resp
+      .headers()
+      .get(http::header::LOCATION)
+      .and_then(|v| v.to_str().ok())
+      .unwrap_or_default()
+      .to_string();
+    (resp.status(), location)
+  }
+
+  /// Redeem a code at the token endpoint with basic client authentication.
+  async fn redeem_code(
+    &self,
+    client_id: &str,
+    client_secret: &str,
+    code: &str,
+    verifier: &str,
+  ) -> (StatusCode, Value) {
+    let form = url::form_urlencoded::Serializer::new(String::new())
+      .append_pair("grant_type", "authorization_code")
+      .append_pair("code", code)
+      .append_pair("redirect_uri", "https://wiki.example/cb")
+      .append_pair("code_verifier", verifier)
+      .finish();
+    let basic = BASE64_STANDARD.encode(format!("{client_id}:{client_secret}"));
+    let req = Request::builder()
+      .method(Method::POST)
+      .uri("/auth/provider/token")
+      .extension(peer())
+      .header("authorization", format!("Basic {basic}"))
+      .header(CONTENT_TYPE, "application/x-www-form-urlencoded")
+      .body(Body::from(form))
+      .unwrap();
+    let resp = self.app.clone().oneshot(req).await.unwrap();
+    let status = resp.status();
+    let bytes = axum::body::to_bytes(resp.into_body(), usize::MAX)
+      .await
+      .unwrap();
+    (
+      status,
+      serde_json::from_slice(&bytes).unwrap_or(Value::Null),
+    )
+  }
+}
+
+#[cfg(feature = "oidc_provider")]
+fn query_param(url: &str, name: &str) -> Option<String> {
+  url::Url::parse(url)
+    .unwrap()
+    .query_pairs()
+    .find(|(key, _)| key == name)
+    .map(|(_, value)| value.to_string())
+}
+
+#[cfg(feature = "oidc_provider")]
+fn authorize_uri(client_id: &str, extra: &str) -> String {
+  format!(
+    "/auth/provider/authorize?response_type=code&client_id={client_id}\
+     &redirect_uri=https%3A%2F%2Fwiki.example%2Fcb&scope=openid%20email%20groups\
+     &state=xyz&nonce=n1&code_challenge={PKCE_CHALLENGE}&code_challenge_method=S256{extra}"
+  )
+}
+
+#[cfg(feature = "oidc_provider")]
 #[tokio::test]
-async fn settings_user_get_and_save() {
+async fn oidc_provider_authorization_code_flow() {
   let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
-  let token = app.token(admin);
+  let admin = app.admin_user("op_admin").await;
+  let user = app.local_user("op_user", "pw").await;
+  let group = app.conn.group().create_group("Staff".into()).await.unwrap();
+  app
+    .conn
+    .group()
+    .add_permissions_to_group(group, vec!["user:view".into()])
+    .await
+    .unwrap();
+  app
+    .conn
+    .group()
+    .add_users_to_group(group, vec![user])
+    .await
+    .unwrap();
 
-  let (status, body) = app
-    .send(Method::GET, "/settings/user", Some(&token), None)
+  // Only client administrators can register clients.
+  let body = json!({"name": "Wiki", "redirect_uris": ["https://wiki.example/cb"], "public": false});
+  let (status, _) = app
+    .send(
+      Method::POST,
+      "/auth/provider/clients",
+      Some(&app.token(user)),
+      Some(body.clone()),
+    )
     .await;
-  assert_eq!(status, StatusCode::OK);
-  // Client secret is never returned.
-  assert!(body["settings"]["oidc_client_secret"].is_null());
-
+  assert_eq!(status, StatusCode::FORBIDDEN);
   let (status, _) = app
     .send(
       Method::POST,
-      "/settings/user",
-      Some(&token),
-      Some(json!({"oidc_enabled":false})),
+      "/auth/provider/clients",
+      Some(&app.token(admin)),
+      Some(
+        json!({"name": "Wiki", "redirect_uris": ["https://wiki.example/cb#x"], "public": false}),
+      ),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  let (status, creds) = app
+    .send(
+      Method::POST,
+      "/auth/provider/clients",
+      Some(&app.token(admin)),
+      Some(body),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
-}
+  let client_id = creds["client_id"].as_str().unwrap().to_string();
+  let secret = creds["client_secret"].as_str().unwrap().to_string();
 
-#[tokio::test]
-async fn settings_mail_get_and_save() {
-  let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
-  let token = app.token(admin);
+  // Unregistered redirect uris are not redirected to.
+  let (status, _) = app
+    .redirect(
+      &authorize_uri(&client_id, "").replace("wiki.example", "evil.example"),
+      Some(&app.token(user)),
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
 
-  let (status, body) = app
-    .send(Method::GET, "/settings/mail", Some(&token), None)
+  // Anonymous requests go through the login page and come back.
+  let (status, location) = app.redirect(&authorize_uri(&client_id, ""), None).await;
+  assert_eq!(status, StatusCode::FOUND);
+  let back = query_param(&location, "redirect_to").unwrap();
+  assert!(location.starts_with("http://localhost:8000/login?"));
+  assert!(back.starts_with("/api/auth/provider/authorize?response_type=code"));
+
+  // Without earlier consent the user is asked first.
+  let (_, location) = app
+    .redirect(
+      &authorize_uri(&client_id, "&prompt=none"),
+      Some(&app.token(user)),
+    )
     .await;
-  assert_eq!(status, StatusCode::OK);
-  assert!(body["settings"]["smtp_password"].is_null());
+  assert_eq!(query_param(&location, "error").unwrap(), "consent_required");
+  assert_eq!(query_param(&location, "state").unwrap(), "xyz");
+  let (status, location) = app
+    .redirect(&authorize_uri(&client_id, ""), Some(&app.token(user)))
+    .await;
+  assert_eq!(status, StatusCode::FOUND);
+  assert!(location.starts_with("http://localhost:8000/consent?"));
+  let request = query_param(&location, "request").unwrap();
 
-  // Saving disabled SMTP settings deactivates the mailer.
+  // The request belongs to the user that made it.
   let (status, _) = app
+    .send(
+      Method::GET,
+      &format!("/auth/provider/consent/{request}"),
+      Some(&app.token(admin)),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::NOT_FOUND);
+  let (status, info) = app
+    .send(
+      Method::GET,
+      &format!("/auth/provider/consent/{request}"),
+      Some(&app.token(user)),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(info["client_name"], "Wiki");
+  assert_eq!(info["scopes"], json!(["openid", "email", "groups"]));
+
+  let (status, approved) = app
     .send(
       Method::POST,
-      "/settings/mail",
-      Some(&token),
-      Some(json!({"smtp_enabled":false})),
+      "/auth/provider/consent",
+      Some(&app.token(user)),
+      Some(json!({"request": request, "approve": true})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
-}
+  let redirect = approved["redirect"].as_str().unwrap();
+  assert!(redirect.starts_with("https://wiki.example/cb?"));
+  assert_eq!(query_param(redirect, "state").unwrap(), "xyz");
+  let code = query_param(redirect, "code").unwrap();
 
-#[tokio::test]
-async fn settings_requires_permission() {
-  let app = TestApp::new().await;
-  let uid = app.local_user("plain", "pw").await;
-  let token = app.token(uid);
-  let (status, _) = app
-    .send(Method::GET, "/settings/user", Some(&token), None)
+  // Client authentication is checked before the code is spent.
+  let (status, body) = app
+    .redeem_code(&client_id, "wrong", &code, PKCE_VERIFIER)
     .await;
-  assert_eq!(status, StatusCode::FORBIDDEN);
-}
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  assert_eq!(body["error"], "invalid_client");
 
-// ---------------------------------------------------------------------------
-// mail endpoints
-// ---------------------------------------------------------------------------
+  let (status, tokens) = app
+    .redeem_code(&client_id, &secret, &code, PKCE_VERIFIER)
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  assert_eq!(tokens["token_type"], "Bearer");
+  assert_eq!(tokens["scope"], "openid email groups");
+  let id_token = app
+    .jwt
+    .validate_token(tokens["id_token"].as_str().unwrap())
+    .await
+    .unwrap();
+  assert_eq!(id_token.sub, user);
+  assert_eq!(id_token.additional_claims["aud"], json!(client_id));
+  assert_eq!(id_token.additional_claims["nonce"], "n1");
+  assert_eq!(id_token.additional_claims["email"], "op_user@example.com");
+  assert_eq!(id_token.additional_claims["groups"], json!(["Staff"]));
+  assert!(!id_token.additional_claims.contains_key("name"));
+
+  // Codes can only be redeemed once.
+  let (status, body) = app
+    .redeem_code(&client_id, &secret, &code, PKCE_VERIFIER)
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(body["error"], "invalid_grant");
 
-#[tokio::test]
-async fn mail_reset_endpoints_are_timing_safe() {
-  let app = TestApp::new().await;
-  // Both endpoints always return 200 regardless of input to avoid leaking
-  // whether an account exists.
+  // Access tokens are for the userinfo endpoint only.
+  let access_token = tokens["access_token"].as_str().unwrap();
+  let (status, _) = app
+    .send(Method::GET, "/user/info", Some(access_token), None)
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
   let (status, _) = app
     .send(
-      Method::POST,
-      "/mail/reset/send",
+      Method::GET,
+      "/auth/provider/userinfo",
+      Some(tokens["id_token"].as_str().unwrap()),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
+  let (status, info) = app
+    .send(
+      Method::GET,
+      "/auth/provider/userinfo",
+      Some(access_token),
       None,
-      Some(json!({"email":"ghost@example.com"})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
+  assert_eq!(info["sub"], user.to_string());
+  assert_eq!(info["email"], "op_user@example.com");
+  assert!(
+    info["permissions"]
+      .as_array()
+      .unwrap()
+      .contains(&json!("user:view"))
+  );
 
-  let (status, _) = app
+  // Consent is remembered, a wrong verifier still spends nothing useful.
+  let (_, location) = app
+    .redirect(
+      &authorize_uri(&client_id, "&prompt=none"),
+      Some(&app.token(user)),
+    )
+    .await;
+  let code = query_param(&location, "code").unwrap();
+  let (status, body) = app
+    .redeem_code(
+      &client_id,
+      &secret,
+      &code,
+      "a-different-verifier-of-enough-length-000000",
+    )
+    .await;
+  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(body["error"], "invalid_grant");
+
+  // Users can revoke their consent.
+  let (status, consents) = app
     .send(
-      Method::POST,
-      "/mail/reset/confirm",
+      Method::GET,
+      "/auth/provider/consents",
+      Some(&app.token(user)),
       None,
-      Some(json!({"token":"nope","new_password":"x"})),
     )
     .await;
   assert_eq!(status, StatusCode::OK);
-}
+  assert_eq!(consents[0]["client_name"], "Wiki");
+  let (status, _) = app
+    .send(
+      Method::DELETE,
+      "/auth/provider/consents",
+      Some(&app.token(user)),
+      Some(json!({"client": client_id})),
+    )
+    .await;
+  assert_eq!(status, StatusCode::OK);
+  let (_, location) = app
+    .redirect(
+      &authorize_uri(&client_id, "&prompt=none"),
+      Some(&app.token(user)),
+    )
+    .await;
+  assert_eq!(query_param(&location, "error").unwrap(), "consent_required");
 
-#[tokio::test]
-async fn mail_test_requires_active_mailer() {
-  let app = TestApp::new().await;
-  let admin = app.admin_user("admin").await;
-  let token = app.token(admin);
-  // Mailer is inactive ⇒ sending fails with a 400 ("not configured").
+  // Removing the client invalidates its access tokens.
   let (status, _) = app
-    .send(Method::POST, "/mail/test", Some(&token), None)
+    .send(
+      Method::DELETE,
+      "/auth/provider/clients",
+      Some(&app.token(admin)),
+      Some(json!({"uuid": client_id})),
+    )
     .await;
-  assert_eq!(status, StatusCode::BAD_REQUEST);
+  assert_eq!(status, StatusCode::OK);
+  let (status, _) = app
+    .send(
+      Method::GET,
+      "/auth/provider/userinfo",
+      Some(access_token),
+      None,
+    )
+    .await;
+  assert_eq!(status, StatusCode::UNAUTHORIZED);
 }
 
 // ---------------------------------------------------------------------------
@@ -1165,7 +3971,9 @@ async fn websocket_delivers_targeted_update() {
   let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
   Migrator::up(&*conn, None).await.unwrap();
   let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
-  let (update_state, updater) = UpdateState::<TestMsg>::init().await;
+  let (update_state, updater) = UpdateState::<TestMsg>::init(&Store::memory())
+    .await
+    .unwrap();
 
   let uid = conn
     .user()
@@ -1179,7 +3987,7 @@ async fn websocket_delivers_targeted_update() {
     )
     .await
     .unwrap();
-  let token = jwt.create_raw_token(uid).unwrap();
+  let token = jwt.create_service_token(uid).unwrap();
 
   let router = websocket::router::<TestMsg>()
     .layer(Extension(conn.clone()))
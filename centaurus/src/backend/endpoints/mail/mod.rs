@@ -19,7 +19,7 @@ pub fn router(rate_limiter: &mut RateLimiter) -> ApiRouter {
   ApiRouter::new()
     .nest("/reset", reset::router())
     .nest("/test", test::router())
-    .layer(rate_limiter.create_limiter())
+    .layer(rate_limiter.create_limiter("mail"))
 }
 
 pub async fn state<C: Config>(router: ApiRouter, db: &Connection, config: &C) -> ApiRouter {
@@ -40,7 +40,7 @@ pub async fn state<C: Config>(router: ApiRouter, db: &Connection, config: &C) ->
   );
 
   let mailer = Mailer::new(settings).await;
-  let password_reset_state = ResetPasswordState::default();
+  let password_reset_state = ResetPasswordState::init(db, config.auth().auth_reset_expiration);
 
   router
     .layer(Extension(mailer))
@@ -1,6 +1,6 @@
 use crate::{
   backend::{
-    auth::pw_state::PasswordState,
+    auth::{password_policy::check_password, pw_state::PasswordState},
     config::SiteConfig,
     endpoints::mail::{state::ResetPasswordState, template},
   },
@@ -54,7 +54,21 @@ async fn send_reset_link(
       return;
     }
 
-    let token = state.generate_token(user.email.clone()).await;
+    if user.service_account {
+      warn!("Password reset requested for service account: {}", email);
+      return;
+    }
+
+    let token = match state.generate_token(&db, user.id).await {
+      Ok(token) => token,
+      Err(e) => {
+        warn!(
+          "Failed to store password reset token for {}: {:?}",
+          email, e
+        );
+        return;
+      }
+    };
 
     let mut reset_link = config.site_url.clone();
     if let Ok(segments) = &mut reset_link.path_segments_mut() {
@@ -94,33 +108,51 @@ async fn reset_password(
     token,
     new_password,
   }): Json<ResetPasswordPayload>,
-) -> Result<(), ()> {
+) -> crate::error::Result<()> {
+  // The password is checked before the token is used up, so a rejected
+  // password does not burn the link
+  let new_password = pw.decrypt_password(&new_password);
+  if let Ok(new_password) = &new_password {
+    let user = match state.token_user(&db, &token).await? {
+      Some(user_id) => db.user().get_user_by_id(user_id).await.ok(),
+      None => None,
+    };
+    check_password(&db, &pw, user.as_ref(), new_password).await?;
+  }
+
   // Do reset async to avoid exposing timing information
   spawn(async move {
-    let Some(email) = state.validate_token(&token).await else {
+    let Ok(Some(user_id)) = state.consume_token(&db, &token).await else {
       warn!("Invalid or expired password reset token used");
       return;
     };
 
-    let Some(user) = db.user().get_user_by_email(&email).await.ok() else {
-      warn!("Password reset attempted for non-existent email: {}", email);
+    let Ok(user) = db.user().get_user_by_id(user_id).await else {
+      warn!(
+        "Password reset attempted for non-existent user: {}",
+        user_id
+      );
+      return;
+    };
+
+    let Ok(new_password) = new_password else {
+      warn!("Failed to decrypt new password for {}", user.email);
       return;
     };
 
-    let Ok(hashed_password) = pw.pw_hash(&user.salt, &new_password) else {
-      warn!("Failed to hash new password for {}", email);
+    let Ok(hashed_password) = pw.pw_hash_raw(&new_password) else {
+      warn!("Failed to hash new password for {}", user.email);
       return;
     };
 
+    // also revokes all other reset tokens of the user
     if let Err(e) = db
       .user()
       .update_user_password(user.id, hashed_password)
       .await
     {
-      warn!("Failed to update password for {}: {:?}", email, e);
+      warn!("Failed to update password for {}: {:?}", user.email, e);
     }
-
-    state.invalidate_token(&email).await;
   });
 
   Ok(())
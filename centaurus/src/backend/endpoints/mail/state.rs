@@ -1,80 +1,114 @@
-use std::{
-  sync::Arc,
-  thread::{sleep, spawn},
-  time::{Duration, Instant},
-};
+use std::time::Duration;
 
 use aide::OperationIo;
 use axum::{Extension, extract::FromRequestParts};
-use dashmap::DashMap;
+use chrono::Utc;
+use tokio::{spawn, time::sleep};
+use tracing::warn;
 use uuid::Uuid;
 
+use crate::{
+  backend::auth::token::{generate_token, hash_token},
+  db::{init::Connection, tables::ConnectionExt},
+  error::Result,
+};
+
+const TOKEN_LENGTH: usize = 64;
+const CLEANUP_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+/// Reset tokens are only stored as hashes and can be used once.
 #[derive(FromRequestParts, Clone, OperationIo)]
 #[from_request(via(Extension))]
 pub struct ResetPasswordState {
-  tokens: Arc<DashMap<String, (String, Instant)>>,
+  expiration: i64,
 }
 
 impl ResetPasswordState {
-  pub async fn generate_token(&self, email: String) -> String {
-    let token = Uuid::new_v4().to_string();
-    self.tokens.insert(token.clone(), (email, Instant::now()));
-    token
-  }
-
-  pub async fn validate_token(&self, token: &str) -> Option<String> {
-    self.tokens.get(token).map(|entry| entry.value().clone().0)
-  }
-
-  pub async fn invalidate_token(&self, email: &str) {
-    self.tokens.remove(email);
-  }
-}
-
-impl Default for ResetPasswordState {
-  fn default() -> Self {
-    let map = Arc::new(DashMap::new());
-
+  /// Tokens are valid for `expiration` seconds. Expired ones are removed in
+  /// the background.
+  pub fn init(db: &Connection, expiration: i64) -> Self {
     spawn({
-      let map = map.clone();
-      move || {
+      let db = db.clone();
+      async move {
         loop {
-          sleep(Duration::from_secs(600));
-          let now = Instant::now();
-          map.retain(|_, &mut (_, timestamp)| {
-            now.duration_since(timestamp) < Duration::from_secs(3600)
-          });
+          sleep(CLEANUP_INTERVAL).await;
+          if let Err(e) = db.password_reset().remove_expired().await {
+            warn!("Failed to remove expired password reset tokens: {:?}", e);
+          }
         }
       }
     });
 
-    ResetPasswordState { tokens: map }
+    Self { expiration }
+  }
+
+  pub async fn generate_token(&self, db: &Connection, user_id: Uuid) -> Result<String> {
+    let token = generate_token(TOKEN_LENGTH);
+    let exp = Utc::now() + chrono::Duration::seconds(self.expiration);
+    db.password_reset()
+      .create_token(user_id, hash_token(&token), exp)
+      .await?;
+    Ok(token)
+  }
+
+  /// The user the token was issued for, the token stays valid.
+  pub async fn token_user(&self, db: &Connection, token: &str) -> Result<Option<Uuid>> {
+    db.password_reset().get_token_user(&hash_token(token)).await
+  }
+
+  /// The user the token was issued for. The token can not be used again.
+  pub async fn consume_token(&self, db: &Connection, token: &str) -> Result<Option<Uuid>> {
+    db.password_reset().consume_token(&hash_token(token)).await
   }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::db::{config::DBConfig, init::connect_db, migrations::Migrator};
+  use sea_orm_migration::MigratorTrait;
 
   #[tokio::test]
   async fn test_token_lifecycle() {
-    let state = ResetPasswordState {
-      tokens: Arc::new(DashMap::new()),
-    };
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    let state = ResetPasswordState::init(&conn, 3600);
 
-    let token = state.generate_token("user@example.com".into()).await;
-    // A freshly generated token resolves back to its email.
+    let token = state.generate_token(&conn, user).await.unwrap();
+    // Only the hash is persisted.
+    assert!(
+      conn
+        .password_reset()
+        .consume_token(&token)
+        .await
+        .unwrap()
+        .is_none()
+    );
+    // A freshly generated token resolves back to its user, but only once.
+    assert_eq!(state.token_user(&conn, &token).await.unwrap(), Some(user));
     assert_eq!(
-      state.validate_token(&token).await,
-      Some("user@example.com".to_string())
+      state.consume_token(&conn, &token).await.unwrap(),
+      Some(user)
     );
+    assert_eq!(state.consume_token(&conn, &token).await.unwrap(), None);
     // Unknown tokens resolve to None.
-    assert_eq!(state.validate_token("missing").await, None);
+    assert_eq!(state.consume_token(&conn, "missing").await.unwrap(), None);
 
-    // Invalidation is keyed by the stored email.
-    state.invalidate_token("user@example.com").await;
-    // (The token map is keyed by token, so invalidating by email only removes a
-    // matching entry; the original token remains until expiry.)
-    assert!(state.validate_token(&token).await.is_some());
+    // Expired tokens are rejected.
+    let expired = ResetPasswordState::init(&conn, -1);
+    let token = expired.generate_token(&conn, user).await.unwrap();
+    assert_eq!(state.consume_token(&conn, &token).await.unwrap(), None);
   }
 }
@@ -88,6 +88,36 @@ pub fn confirm_code(code: &String, old: bool, link: &str) -> String {
   )
 }
 
+pub fn account_locked(until: &str, link: &str) -> String {
+  format!(
+    r#"
+  <!DOCTYPE html>
+  <html lang="en">
+    <head>
+      <meta charset="UTF-8">
+      <meta name="viewport" content="width=device-width, initial-scale=1.0">
+      <title>Account Locked</title>
+    </head>
+    <body>
+      <div style="display: flex; flex-direction: column;">
+        <header style="padding: 1rem; display: flex; flex-direction: column; align-items: center; justify-content: center;">
+          <h2 style="margin: 0;">Account Locked</h2>
+          <p style="margin: 0;">Your account was locked after too many failed login attempts</p>
+        </header>
+        <div style="display: flex; align-items: center; justify-content: center; flex-direction: column;">
+          <p>You can log in again after {until} UTC.</p>
+          <p>If this was not you, consider changing your password and contact an administrator.</p>
+        </div>
+        <footer style="display: flex; align-items: center; justify-content: center;">
+          <p>Mail send from <a href="{link}">{link}</a></p>
+        </footer>
+      </div>
+    </body>
+  </html>
+  "#
+  )
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -116,4 +146,11 @@ mod tests {
     let new = confirm_code(&"654321".to_string(), false, "https://app");
     assert!(new.contains("your new email"));
   }
+
+  #[test]
+  fn test_account_locked_embeds_time() {
+    let html = account_locked("2026-01-01 12:00:00", "https://app");
+    assert!(html.contains("Account Locked"));
+    assert!(html.contains("2026-01-01 12:00:00"));
+  }
 }
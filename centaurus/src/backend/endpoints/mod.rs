@@ -1,8 +1,14 @@
 #[cfg(feature = "endpoints")]
+pub mod acl;
+#[cfg(feature = "endpoints")]
+pub mod audit;
+#[cfg(feature = "endpoints")]
 pub mod group;
 pub mod health;
 #[cfg(feature = "endpoints")]
 pub mod mail;
+#[cfg(feature = "scim")]
+pub mod scim;
 #[cfg(feature = "endpoints")]
 pub mod settings;
 #[cfg(feature = "endpoints")]
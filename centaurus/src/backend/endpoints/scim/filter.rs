@@ -0,0 +1,389 @@
+//! Filters of SCIM list requests (RFC 7644, section 3.4.2.2). They are
+//! evaluated on the JSON representation of the resources, attribute names and
+//! string values compare case-insensitively.
+
+use serde_json::Value;
+
+use crate::{
+  backend::endpoints::scim::{error, get},
+  error::Result,
+};
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Filter {
+  Present(String),
+  Compare(String, CompareOp, Value),
+  /// `emails[type eq "work"]`, a multi-valued attribute with an element
+  /// matching the inner filter.
+  ValuePath(String, Box<Filter>),
+  And(Box<Filter>, Box<Filter>),
+  Or(Box<Filter>, Box<Filter>),
+  Not(Box<Filter>),
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CompareOp {
+  Eq,
+  Ne,
+  Co,
+  Sw,
+  Ew,
+  Gt,
+  Ge,
+  Lt,
+  Le,
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Token {
+  Open,
+  Close,
+  OpenBracket,
+  CloseBracket,
+  Word(String),
+  Str(String),
+}
+
+impl Filter {
+  pub fn parse(filter: &str) -> Result<Self> {
+    let mut parser = Parser {
+      tokens: tokenize(filter)?,
+      pos: 0,
+    };
+    let filter = parser.or()?;
+    if parser.pos != parser.tokens.len() {
+      return Err(invalid("Unexpected trailing input"));
+    }
+
+    Ok(filter)
+  }
+
+  pub fn matches(&self, resource: &Value) -> bool {
+    match self {
+      Filter::Present(path) => values(resource, path).into_iter().any(|value| match value {
+        Value::Null => false,
+        Value::String(s) => !s.is_empty(),
+        _ => true,
+      }),
+      Filter::Compare(path, op, expected) => values(resource, path)
+        .into_iter()
+        .any(|value| compare(value, *op, expected)),
+      Filter::ValuePath(path, inner) => values(resource, path)
+        .into_iter()
+        .any(|value| inner.matches(value)),
+      Filter::And(a, b) => a.matches(resource) && b.matches(resource),
+      Filter::Or(a, b) => a.matches(resource) || b.matches(resource),
+      Filter::Not(inner) => !inner.matches(resource),
+    }
+  }
+}
+
+/// Strips the schema URN of a fully qualified attribute, e.g.
+/// `urn:ietf:params:scim:schemas:core:2.0:User:userName`.
+pub fn attribute_name(path: &str) -> &str {
+  path.rsplit(':').next().unwrap_or(path)
+}
+
+/// All values at the dotted path, elements of multi-valued attributes are
+/// flattened.
+fn values<'a>(resource: &'a Value, path: &str) -> Vec<&'a Value> {
+  let mut current = vec![resource];
+  for name in attribute_name(path).split('.') {
+    current = current
+      .into_iter()
+      .flat_map(|value| match value {
+        Value::Array(items) => items.iter().collect(),
+        value => vec![value],
+      })
+      .filter_map(|value| get(value, name))
+      .collect();
+  }
+
+  current
+    .into_iter()
+    .flat_map(|value| match value {
+      Value::Array(items) => items.iter().collect(),
+      value => vec![value],
+    })
+    .collect()
+}
+
+fn compare(value: &Value, op: CompareOp, expected: &Value) -> bool {
+  match (value, expected) {
+    (Value::String(value), Value::String(expected)) => {
+      let value = value.to_lowercase();
+      let expected = expected.to_lowercase();
+      match op {
+        CompareOp::Eq => value == expected,
+        CompareOp::Ne => value != expected,
+        CompareOp::Co => value.contains(&expected),
+        CompareOp::Sw => value.starts_with(&expected),
+        CompareOp::Ew => value.ends_with(&expected),
+        CompareOp::Gt => value > expected,
+        CompareOp::Ge => value >= expected,
+        CompareOp::Lt => value < expected,
+        CompareOp::Le => value <= expected,
+      }
+    }
+    (Value::Bool(value), Value::Bool(expected)) => match op {
+      CompareOp::Eq => value == expected,
+      CompareOp::Ne => value != expected,
+      _ => false,
+    },
+    (Value::Number(value), Value::Number(expected)) => {
+      let (Some(value), Some(expected)) = (value.as_f64(), expected.as_f64()) else {
+        return false;
+      };
+      match op {
+        CompareOp::Eq => value == expected,
+        CompareOp::Ne => value != expected,
+        CompareOp::Gt => value > expected,
+        CompareOp::Ge => value >= expected,
+        CompareOp::Lt => value < expected,
+        CompareOp::Le => value <= expected,
+        _ => false,
+      }
+    }
+    (value, Value::Null) => match op {
+      CompareOp::Eq => value.is_null(),
+      CompareOp::Ne => !value.is_null(),
+      _ => false,
+    },
+    _ => false,
+  }
+}
+
+fn invalid(detail: &str) -> crate::error::ErrorReport {
+  error(http::StatusCode::BAD_REQUEST, Some("invalidFilter"), detail)
+}
+
+fn tokenize(filter: &str) -> Result<Vec<Token>> {
+  let mut tokens = Vec::new();
+  let mut chars = filter.char_indices().peekable();
+
+  while let Some((start, c)) = chars.next() {
+    match c {
+      c if c.is_whitespace() => {}
+      '(' => tokens.push(Token::Open),
+      ')' => tokens.push(Token::Close),
+      '[' => tokens.push(Token::OpenBracket),
+      ']' => tokens.push(Token::CloseBracket),
+      '"' => {
+        let mut escaped = false;
+        let mut end = None;
+        for (i, c) in chars.by_ref() {
+          match c {
+            '\\' if !escaped => escaped = true,
+            '"' if !escaped => {
+              end = Some(i);
+              break;
+            }
+            _ => escaped = false,
+          }
+        }
+        let Some(end) = end else {
+          return Err(invalid("Unterminated string"));
+        };
+        let value =
+          serde_json::from_str(&filter[start..=end]).map_err(|_| invalid("Invalid string"))?;
+        tokens.push(Token::Str(value));
+      }
+      _ => {
+        let mut end = start + c.len_utf8();
+        while let Some((i, c)) = chars.peek() {
+          if c.is_whitespace() || matches!(c, '(' | ')' | '[' | ']' | '"') {
+            break;
+          }
+          end = i + c.len_utf8();
+          chars.next();
+        }
+        tokens.push(Token::Word(filter[start..end].to_string()));
+      }
+    }
+  }
+
+  Ok(tokens)
+}
+
+struct Parser {
+  tokens: Vec<Token>,
+  pos: usize,
+}
+
+impl Parser {
+  fn next(&mut self) -> Option<Token> {
+    let token = self.tokens.get(self.pos).cloned();
+    self.pos += 1;
+    token
+  }
+
+  fn keyword(&mut self, keyword: &str) -> bool {
+    let found = matches!(self.tokens.get(self.pos), Some(Token::Word(word)) if word.eq_ignore_ascii_case(keyword));
+    if found {
+      self.pos += 1;
+    }
+    found
+  }
+
+  fn expect(&mut self, expected: Token) -> Result<()> {
+    if self.next() != Some(expected) {
+      return Err(invalid("Unbalanced brackets"));
+    }
+    Ok(())
+  }
+
+  fn or(&mut self) -> Result<Filter> {
+    let mut filter = self.and()?;
+    while self.keyword("or") {
+      filter = Filter::Or(Box::new(filter), Box::new(self.and()?));
+    }
+    Ok(filter)
+  }
+
+  fn and(&mut self) -> Result<Filter> {
+    let mut filter = self.not()?;
+    while self.keyword("and") {
+      filter = Filter::And(Box::new(filter), Box::new(self.not()?));
+    }
+    Ok(filter)
+  }
+
+  fn not(&mut self) -> Result<Filter> {
+    if self.keyword("not") {
+      self.expect(Token::Open)?;
+      let filter = self.or()?;
+      self.expect(Token::Close)?;
+      return Ok(Filter::Not(Box::new(filter)));
+    }
+    self.primary()
+  }
+
+  fn primary(&mut self) -> Result<Filter> {
+    let attribute = match self.next() {
+      Some(Token::Open) => {
+        let filter = self.or()?;
+        self.expect(Token::Close)?;
+        return Ok(filter);
+      }
+      Some(Token::Word(attribute)) => attribute,
+      _ => return Err(invalid("Expected an attribute")),
+    };
+
+    if self.tokens.get(self.pos) == Some(&Token::OpenBracket) {
+      self.pos += 1;
+      let filter = self.or()?;
+      self.expect(Token::CloseBracket)?;
+      return Ok(Filter::ValuePath(attribute, Box::new(filter)));
+    }
+
+    let Some(Token::Word(op)) = self.next() else {
+      return Err(invalid("Expected an operator"));
+    };
+    let op = match op.to_ascii_lowercase().as_str() {
+      "pr" => return Ok(Filter::Present(attribute)),
+      "eq" => CompareOp::Eq,
+      "ne" => CompareOp::Ne,
+      "co" => CompareOp::Co,
+      "sw" => CompareOp::Sw,
+      "ew" => CompareOp::Ew,
+      "gt" => CompareOp::Gt,
+      "ge" => CompareOp::Ge,
+      "lt" => CompareOp::Lt,
+      "le" => CompareOp::Le,
+      _ => return Err(invalid("Unknown operator")),
+    };
+
+    let value = match self.next() {
+      Some(Token::Str(value)) => Value::String(value),
+      Some(Token::Word(word)) => match serde_json::from_str(&word) {
+        Ok(value @ (Value::Bool(_) | Value::Null | Value::Number(_))) => value,
+        _ => return Err(invalid("Invalid comparison value")),
+      },
+      _ => return Err(invalid("Expected a comparison value")),
+    };
+
+    Ok(Filter::Compare(attribute, op, value))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn user() -> Value {
+    json!({
+      "userName": "Alice@Example.com",
+      "displayName": "Alice",
+      "active": true,
+      "emails": [
+        {"value": "alice@example.com", "type": "work", "primary": true},
+        {"value": "alice@home.example", "type": "home"},
+      ],
+      "meta": {"resourceType": "User"},
+    })
+  }
+
+  fn matches(filter: &str) -> bool {
+    Filter::parse(filter).unwrap().matches(&user())
+  }
+
+  #[test]
+  fn test_compare_attributes() {
+    assert!(matches(r#"userName eq "alice@example.com""#));
+    assert!(matches(
+      r#"urn:ietf:params:scim:schemas:core:2.0:User:userName Eq "ALICE@example.com""#
+    ));
+    assert!(matches(r#"displayName sw "al" and active eq true"#));
+    assert!(matches(r#"emails.value ew "home.example""#));
+    assert!(matches(
+      r#"emails[type eq "work" and value co "example.com"]"#
+    ));
+    assert!(matches("meta.resourceType pr"));
+    assert!(!matches(r#"userName ne "alice@example.com""#));
+    assert!(!matches("externalId pr"));
+    assert!(!matches(r#"emails[type eq "other"]"#));
+  }
+
+  #[test]
+  fn test_logical_operators() {
+    assert!(matches(r#"userName eq "bob" or displayName eq "Alice""#));
+    assert!(matches(r#"not (active eq false)"#));
+    assert!(!matches(
+      r#"displayName eq "Alice" and (userName eq "bob" or active eq false)"#
+    ));
+    // "and" binds stronger than "or"
+    assert!(matches(
+      r#"userName eq "bob" and active eq true or displayName eq "Alice""#
+    ));
+  }
+
+  #[test]
+  fn test_escaped_strings() {
+    let filter = Filter::parse(r#"displayName eq "a \"b\" c""#).unwrap();
+    assert_eq!(
+      filter,
+      Filter::Compare(
+        "displayName".into(),
+        CompareOp::Eq,
+        Value::String("a \"b\" c".into())
+      )
+    );
+  }
+
+  #[test]
+  fn test_invalid_filters() {
+    for filter in [
+      "",
+      "userName",
+      r#"userName eq"#,
+      r#"userName foo "x""#,
+      r#"userName eq "x"#,
+      r#"(userName eq "x""#,
+      r#"userName eq "x" extra"#,
+      "userName eq unquoted",
+    ] {
+      assert!(Filter::parse(filter).is_err(), "{filter}");
+    }
+  }
+}
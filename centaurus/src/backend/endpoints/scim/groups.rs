@@ -0,0 +1,395 @@
+use std::collections::HashSet;
+
+use aide::axum::{
+  ApiRouter,
+  routing::{ApiMethodRouter, delete_with, get_with, patch_with, post_with, put_with},
+};
+use axum::{
+  Json,
+  extract::{Path, Query},
+};
+use http::StatusCode;
+use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    auth::{jwt_auth::JwtAuth, permission::ScimProvision},
+    endpoints::{
+      audit::{Audit, AuditEvent},
+      scim::{
+        GROUP_SCHEMA, ListQuery, ListResponse, Meta, Reference, Scim, error,
+        patch::{self, PatchRequest},
+      },
+      websocket::state::{UpdateMessage, Updater},
+    },
+  },
+  db::{
+    init::Connection, permission::has_permission, tables::ConnectionExt,
+    tables::group::SimpleUserInfo,
+  },
+  error::Result,
+};
+
+pub fn router<T: UpdateMessage>() -> ApiRouter {
+  ApiRouter::new()
+    .api_route("/Groups", list_groups_route())
+    .api_route("/Groups", create_group_route::<T>())
+    .api_route("/Groups/{id}", get_group_route())
+    .api_route("/Groups/{id}", replace_group_route::<T>())
+    .api_route("/Groups/{id}", patch_group_route::<T>())
+    .api_route("/Groups/{id}", delete_group_route::<T>())
+}
+
+pub fn list_groups_route() -> ApiMethodRouter<()> {
+  get_with(list_groups, |op| op.id("scimListGroups"))
+}
+
+pub fn create_group_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(create_group::<T>, |op| op.id("scimCreateGroup"))
+}
+
+pub fn get_group_route() -> ApiMethodRouter<()> {
+  get_with(get_group, |op| op.id("scimGetGroup"))
+}
+
+pub fn replace_group_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  put_with(replace_group::<T>, |op| op.id("scimReplaceGroup"))
+}
+
+pub fn patch_group_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  patch_with(patch_group::<T>, |op| op.id("scimPatchGroup"))
+}
+
+pub fn delete_group_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  delete_with(delete_group::<T>, |op| op.id("scimDeleteGroup"))
+}
+
+/// Permissions of groups are not part of the resource, they are kept on
+/// updates.
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimGroup {
+  #[serde(default)]
+  pub schemas: Vec<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<Uuid>,
+  pub display_name: String,
+  /// Ids of the member users.
+  #[serde(default)]
+  pub members: Vec<Reference>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub meta: Option<Meta>,
+}
+
+impl ScimGroup {
+  fn new(id: Uuid, name: String, users: Vec<SimpleUserInfo>) -> Self {
+    Self {
+      schemas: vec![GROUP_SCHEMA.to_string()],
+      id: Some(id),
+      display_name: name,
+      members: users
+        .into_iter()
+        .map(|user| Reference {
+          value: user.id.to_string(),
+          display: Some(user.name),
+        })
+        .collect(),
+      meta: Some(Meta {
+        resource_type: "Group".to_string(),
+      }),
+    }
+  }
+
+  fn name(&self) -> Result<String> {
+    let name = self.display_name.trim();
+    if name.is_empty() {
+      return Err(error(
+        StatusCode::BAD_REQUEST,
+        Some("invalidValue"),
+        "Group name cannot be empty",
+      ));
+    }
+    Ok(name.to_string())
+  }
+
+  /// Fails for members that are no users.
+  async fn member_ids(&self, db: &Connection) -> Result<Vec<Uuid>> {
+    if self.members.is_empty() {
+      return Ok(Vec::new());
+    }
+
+    let users: HashSet<_> = db
+      .user()
+      .list_users_simple()
+      .await?
+      .into_iter()
+      .map(|user| user.id)
+      .collect();
+    let mut ids = Vec::new();
+    for member in &self.members {
+      let id = member.value.parse().ok().filter(|id| users.contains(id));
+      let Some(id) = id else {
+        return Err(error(
+          StatusCode::BAD_REQUEST,
+          Some("invalidValue"),
+          format!("Unknown member {}", member.value),
+        ));
+      };
+      if !ids.contains(&id) {
+        ids.push(id);
+      }
+    }
+
+    Ok(ids)
+  }
+}
+
+fn not_found() -> crate::error::ErrorReport {
+  error(StatusCode::NOT_FOUND, None, "Group not found")
+}
+
+async fn load(db: &Connection, id: Uuid) -> Result<ScimGroup> {
+  let Some(group) = db.group().group_info(id).await? else {
+    return Err(not_found());
+  };
+  Ok(ScimGroup::new(group.id, group.name, group.users))
+}
+
+async fn check_name(db: &Connection, id: Option<Uuid>, name: &str) -> Result<()> {
+  if let Some(other) = db.group().find_group_by_name(name).await?
+    && Some(other) != id
+  {
+    return Err(error(
+      StatusCode::CONFLICT,
+      Some("uniqueness"),
+      "A group with this name already exists",
+    ));
+  }
+  Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct GroupPath {
+  id: Uuid,
+}
+
+async fn list_groups(
+  _auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  Query(query): Query<ListQuery>,
+) -> Result<Scim<ListResponse<ScimGroup>>> {
+  let groups = db
+    .group()
+    .list_groups()
+    .await?
+    .into_iter()
+    .map(|group| ScimGroup::new(group.id, group.name, group.users))
+    .collect();
+
+  Ok(Scim(query.apply(groups)?))
+}
+
+async fn get_group(
+  _auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  Path(path): Path<GroupPath>,
+) -> Result<Scim<ScimGroup>> {
+  Ok(Scim(load(&db, path.id).await?))
+}
+
+async fn create_group<T: UpdateMessage>(
+  auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(group): Json<ScimGroup>,
+) -> Result<(StatusCode, Scim<ScimGroup>)> {
+  let name = group.name()?;
+  check_name(&db, None, &name).await?;
+  let members = group.member_ids(&db).await?;
+
+  let txn = db.begin().await?;
+  let id = txn.group().create_group(name).await?;
+  txn.group().add_users_to_group(id, members.clone()).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "group.create")
+        .target("group", id)
+        .after(&txn.group().group_info(id).await?),
+    )
+    .await?;
+  txn.commit().await?;
+
+  updater.broadcast(T::group(id)).await;
+  for user_id in members {
+    updater.send_to(user_id, T::user_permissions()).await;
+  }
+
+  Ok((StatusCode::CREATED, Scim(load(&db, id).await?)))
+}
+
+async fn replace_group<T: UpdateMessage>(
+  auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Path(path): Path<GroupPath>,
+  Json(group): Json<ScimGroup>,
+) -> Result<Scim<ScimGroup>> {
+  update(&db, &updater, &audit, auth.user_id, path.id, group).await?;
+  Ok(Scim(load(&db, path.id).await?))
+}
+
+async fn patch_group<T: UpdateMessage>(
+  auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Path(path): Path<GroupPath>,
+  Json(req): Json<PatchRequest>,
+) -> Result<Scim<ScimGroup>> {
+  let mut value = serde_json::to_value(load(&db, path.id).await?)?;
+  patch::apply(&mut value, req.operations)?;
+  let group = serde_json::from_value(value)
+    .map_err(|e| error(StatusCode::BAD_REQUEST, Some("invalidValue"), e))?;
+
+  update(&db, &updater, &audit, auth.user_id, path.id, group).await?;
+  Ok(Scim(load(&db, path.id).await?))
+}
+
+async fn update<T: UpdateMessage>(
+  db: &Connection,
+  updater: &Updater<T>,
+  audit: &Audit,
+  actor: Uuid,
+  id: Uuid,
+  group: ScimGroup,
+) -> Result<()> {
+  let Some(before) = db.group().group_info(id).await? else {
+    return Err(not_found());
+  };
+
+  let name = group.name()?;
+  check_name(db, Some(id), &name).await?;
+  let members = group.member_ids(db).await?;
+
+  let user_permissions = db.group().get_user_permissions(actor).await?;
+  if before
+    .permissions
+    .iter()
+    .any(|perm| !has_permission(&user_permissions, perm))
+  {
+    return Err(error(
+      StatusCode::FORBIDDEN,
+      None,
+      "Cannot edit a group with permissions you do not have",
+    ));
+  }
+
+  if members.is_empty()
+    && let Some(admin_group) = db.setup().get_admin_group_id().await?
+    && admin_group == id
+  {
+    return Err(error(
+      StatusCode::NOT_ACCEPTABLE,
+      None,
+      "Admin group must have at least one user",
+    ));
+  }
+
+  let old_users: Vec<_> = before.users.iter().map(|user| user.id).collect();
+  let added: Vec<_> = members
+    .iter()
+    .filter(|user| !old_users.contains(user))
+    .copied()
+    .collect();
+  let removed: Vec<_> = old_users
+    .iter()
+    .filter(|user| !members.contains(user))
+    .copied()
+    .collect();
+
+  let txn = db.begin().await?;
+  if name == before.name && removed.is_empty() {
+    txn.group().add_users_to_group(id, added.clone()).await?;
+  } else {
+    txn
+      .group()
+      .edit_group(id, name, before.permissions.clone(), members)
+      .await?;
+  }
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(actor, "group.edit")
+        .target("group", id)
+        .before(&before)
+        .after(&txn.group().group_info(id).await?),
+    )
+    .await?;
+  txn.commit().await?;
+
+  updater.broadcast(T::group(id)).await;
+  for user_id in added.into_iter().chain(removed) {
+    updater.send_to(user_id, T::user_permissions()).await;
+  }
+
+  Ok(())
+}
+
+async fn delete_group<T: UpdateMessage>(
+  auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Path(path): Path<GroupPath>,
+) -> Result<StatusCode> {
+  if let Some(admin_group) = db.setup().get_admin_group_id().await?
+    && admin_group == path.id
+  {
+    return Err(error(
+      StatusCode::BAD_REQUEST,
+      Some("mutability"),
+      "Cannot delete the admin group",
+    ));
+  }
+
+  let Some(before) = db.group().group_info(path.id).await? else {
+    return Err(not_found());
+  };
+  let user_permissions = db.group().get_user_permissions(auth.user_id).await?;
+  if before
+    .permissions
+    .iter()
+    .any(|perm| !has_permission(&user_permissions, perm))
+  {
+    return Err(error(
+      StatusCode::FORBIDDEN,
+      None,
+      "Cannot delete a group with permissions you do not have",
+    ));
+  }
+
+  let txn = db.begin().await?;
+  txn.group().delete_group(path.id).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "group.delete")
+        .target("group", path.id)
+        .before(&before),
+    )
+    .await?;
+  txn.commit().await?;
+
+  updater.broadcast(T::group(path.id)).await;
+  for user in before.users {
+    updater.send_to(user.id, T::user_permissions()).await;
+  }
+
+  Ok(StatusCode::NO_CONTENT)
+}
@@ -0,0 +1,173 @@
+//! SCIM 2.0 provisioning (RFC 7643, RFC 7644) of users and groups, e.g. by an
+//! identity provider. Clients authenticate with an API token holding the
+//! `scim:provision` permission and act with the permissions of its owner.
+
+use std::fmt::Display;
+
+use aide::axum::{
+  ApiRouter,
+  routing::{ApiMethodRouter, get_with},
+};
+use axum::{
+  Json,
+  response::{IntoResponse, Response},
+};
+use http::{StatusCode, header::CONTENT_TYPE};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use serde_json::{Value, json};
+
+use crate::{
+  backend::endpoints::{scim::filter::Filter, websocket::state::UpdateMessage},
+  error::{ErrorBody, ErrorReport, Result},
+};
+
+pub mod filter;
+pub mod groups;
+pub mod patch;
+pub mod users;
+
+pub const USER_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:User";
+pub const GROUP_SCHEMA: &str = "urn:ietf:params:scim:schemas:core:2.0:Group";
+const LIST_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:ListResponse";
+const ERROR_SCHEMA: &str = "urn:ietf:params:scim:api:messages:2.0:Error";
+const SERVICE_PROVIDER_CONFIG_SCHEMA: &str =
+  "urn:ietf:params:scim:schemas:core:2.0:ServiceProviderConfig";
+/// Upper bound for `count` of list requests.
+const MAX_RESULTS: usize = 200;
+
+pub fn router<T: UpdateMessage>() -> ApiRouter {
+  ApiRouter::new()
+    .api_route("/ServiceProviderConfig", service_provider_config_route())
+    .merge(users::router::<T>())
+    .merge(groups::router::<T>())
+}
+
+pub fn service_provider_config_route() -> ApiMethodRouter<()> {
+  get_with(service_provider_config, |op| {
+    op.id("scimServiceProviderConfig")
+  })
+}
+
+/// JSON response with the SCIM media type.
+pub struct Scim<T>(pub T);
+
+impl<T: Serialize> IntoResponse for Scim<T> {
+  fn into_response(self) -> Response {
+    ([(CONTENT_TYPE, "application/scim+json")], Json(self.0)).into_response()
+  }
+}
+
+impl<T> aide::OperationOutput for Scim<T> {
+  type Inner = T;
+}
+
+/// Error in the SCIM format, `scim_type` is one of the error types of RFC 7644,
+/// section 3.12.
+pub fn error(status: StatusCode, scim_type: Option<&str>, detail: impl Display) -> ErrorReport {
+  let mut body = json!({
+    "schemas": [ERROR_SCHEMA],
+    "status": status.as_str(),
+    "detail": detail.to_string(),
+  });
+  if let Some(scim_type) = scim_type {
+    body["scimType"] = json!(scim_type);
+  }
+
+  ErrorReport::new(eyre::Report::new(ErrorBody(body)), status)
+}
+
+/// The attribute of a JSON object, names are case-insensitive.
+pub fn get<'a>(value: &'a Value, name: &str) -> Option<&'a Value> {
+  value
+    .as_object()?
+    .iter()
+    .find(|(key, _)| key.eq_ignore_ascii_case(name))
+    .map(|(_, value)| value)
+}
+
+#[derive(Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ListQuery {
+  filter: Option<String>,
+  /// 1-based index of the first result.
+  start_index: Option<usize>,
+  count: Option<usize>,
+}
+
+#[derive(Serialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ListResponse<T> {
+  schemas: Vec<&'static str>,
+  total_results: usize,
+  start_index: usize,
+  items_per_page: usize,
+  #[serde(rename = "Resources")]
+  resources: Vec<T>,
+}
+
+impl ListQuery {
+  /// Filters and pages the resources.
+  pub fn apply<T: Serialize>(self, resources: Vec<T>) -> Result<ListResponse<T>> {
+    let filter = self.filter.as_deref().map(Filter::parse).transpose()?;
+    let mut matching = Vec::new();
+    for resource in resources {
+      if let Some(filter) = &filter
+        && !filter.matches(&serde_json::to_value(&resource)?)
+      {
+        continue;
+      }
+      matching.push(resource);
+    }
+
+    let total_results = matching.len();
+    let start_index = self.start_index.unwrap_or(1).max(1);
+    let count = self.count.unwrap_or(MAX_RESULTS).min(MAX_RESULTS);
+    let resources: Vec<_> = matching
+      .into_iter()
+      .skip(start_index - 1)
+      .take(count)
+      .collect();
+
+    Ok(ListResponse {
+      schemas: vec![LIST_SCHEMA],
+      total_results,
+      start_index,
+      items_per_page: resources.len(),
+      resources,
+    })
+  }
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Meta {
+  pub resource_type: String,
+}
+
+/// Reference to a user or group, e.g. a group member.
+#[derive(Serialize, Deserialize, Clone, JsonSchema)]
+pub struct Reference {
+  pub value: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub display: Option<String>,
+}
+
+async fn service_provider_config() -> Scim<Value> {
+  Scim(json!({
+    "schemas": [SERVICE_PROVIDER_CONFIG_SCHEMA],
+    "patch": {"supported": true},
+    "bulk": {"supported": false, "maxOperations": 0, "maxPayloadSize": 0},
+    "filter": {"supported": true, "maxResults": MAX_RESULTS},
+    "changePassword": {"supported": false},
+    "sort": {"supported": false},
+    "etag": {"supported": false},
+    "authenticationSchemes": [{
+      "type": "oauthbearertoken",
+      "name": "API token",
+      "description": "API token with the scim:provision permission",
+      "primary": true,
+    }],
+    "meta": {"resourceType": "ServiceProviderConfig"},
+  }))
+}
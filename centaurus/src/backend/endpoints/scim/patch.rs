@@ -0,0 +1,347 @@
+//! PATCH requests (RFC 7644, section 3.5.2). The operations are applied to
+//! the JSON representation of the resource, which is then saved like a PUT.
+
+use http::StatusCode;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use serde_json::{Map, Value};
+
+use crate::{
+  backend::endpoints::scim::{
+    error,
+    filter::{CompareOp, Filter, attribute_name},
+    get,
+  },
+  error::{ErrorReport, Result},
+};
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PatchRequest {
+  #[serde(rename = "Operations")]
+  pub operations: Vec<PatchOperation>,
+}
+
+#[derive(Deserialize, JsonSchema)]
+pub struct PatchOperation {
+  /// `add`, `remove` or `replace`, some clients capitalize it.
+  pub op: String,
+  pub path: Option<String>,
+  pub value: Option<Value>,
+}
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Op {
+  Add,
+  Remove,
+  Replace,
+}
+
+/// `members[value eq "..."]` selects elements of `members`, `name.givenName`
+/// a sub-attribute.
+struct Path {
+  attribute: String,
+  filter: Option<Filter>,
+  sub_attribute: Option<String>,
+}
+
+impl Path {
+  fn parse(path: &str) -> Result<Self> {
+    let (attribute, filter, sub_attribute) = match path.split_once('[') {
+      Some((attribute, rest)) => {
+        let Some((filter, rest)) = rest.rsplit_once(']') else {
+          return Err(invalid_path("Unbalanced brackets"));
+        };
+        let sub_attribute = match rest.strip_prefix('.') {
+          Some(sub_attribute) => Some(sub_attribute.to_string()),
+          None if rest.is_empty() => None,
+          None => return Err(invalid_path("Invalid sub-attribute")),
+        };
+        (attribute, Some(Filter::parse(filter)?), sub_attribute)
+      }
+      None => match attribute_name(path).split_once('.') {
+        Some((attribute, sub_attribute)) => (attribute, None, Some(sub_attribute.to_string())),
+        None => (path, None, None),
+      },
+    };
+
+    let attribute = attribute_name(attribute);
+    if attribute.is_empty() {
+      return Err(invalid_path("Missing attribute"));
+    }
+
+    Ok(Self {
+      attribute: attribute.to_string(),
+      filter,
+      sub_attribute,
+    })
+  }
+}
+
+fn invalid_path(detail: &str) -> ErrorReport {
+  error(StatusCode::BAD_REQUEST, Some("invalidPath"), detail)
+}
+
+fn invalid_value(detail: &str) -> ErrorReport {
+  error(StatusCode::BAD_REQUEST, Some("invalidValue"), detail)
+}
+
+pub fn apply(resource: &mut Value, operations: Vec<PatchOperation>) -> Result<()> {
+  for operation in operations {
+    let op = match operation.op.to_ascii_lowercase().as_str() {
+      "add" => Op::Add,
+      "remove" => Op::Remove,
+      "replace" => Op::Replace,
+      _ => return Err(error(StatusCode::BAD_REQUEST, None, "Unknown operation")),
+    };
+
+    match operation.path {
+      Some(path) => apply_path(resource, op, &Path::parse(&path)?, operation.value)?,
+      None => {
+        // without a path the value holds the attributes to add or replace
+        let (Op::Add | Op::Replace, Some(Value::Object(attributes))) = (op, operation.value) else {
+          return Err(error(
+            StatusCode::BAD_REQUEST,
+            Some("noTarget"),
+            "Operation needs a path or an object value",
+          ));
+        };
+        for (path, value) in attributes {
+          apply_path(resource, op, &Path::parse(&path)?, Some(value))?;
+        }
+      }
+    }
+  }
+
+  Ok(())
+}
+
+fn apply_path(resource: &mut Value, op: Op, path: &Path, value: Option<Value>) -> Result<()> {
+  let Value::Object(object) = resource else {
+    return Err(invalid_path("Resource is not an object"));
+  };
+  if op != Op::Remove && value.is_none() {
+    return Err(invalid_value("Operation needs a value"));
+  }
+  let key = key(object, &path.attribute);
+
+  let Some(filter) = &path.filter else {
+    match &path.sub_attribute {
+      Some(sub_attribute) => {
+        let path = Path {
+          attribute: sub_attribute.clone(),
+          filter: None,
+          sub_attribute: None,
+        };
+        match object.get_mut(&key) {
+          Some(Value::Array(items)) => {
+            for item in items {
+              apply_path(item, op, &path, value.clone())?;
+            }
+          }
+          Some(inner @ Value::Object(_)) => apply_path(inner, op, &path, value)?,
+          _ if op == Op::Remove => {}
+          _ => {
+            let mut inner = Value::Object(Map::new());
+            apply_path(&mut inner, op, &path, value)?;
+            object.insert(key, inner);
+          }
+        }
+      }
+      None => set(object, key, op, value),
+    }
+    return Ok(());
+  };
+
+  if !object.contains_key(&key) {
+    if op == Op::Remove {
+      return Ok(());
+    }
+    object.insert(key.clone(), Value::Array(Vec::new()));
+  }
+  let Some(Value::Array(items)) = object.get_mut(&key) else {
+    return Err(invalid_path("Attribute is not multi-valued"));
+  };
+
+  if op == Op::Remove && path.sub_attribute.is_none() {
+    items.retain(|item| !filter.matches(item));
+    return Ok(());
+  }
+
+  let mut found = false;
+  for item in items.iter_mut().filter(|item| filter.matches(item)) {
+    found = true;
+    match (&path.sub_attribute, value.clone()) {
+      (Some(sub_attribute), value) => {
+        let Value::Object(item) = item else {
+          return Err(invalid_path("Element is not an object"));
+        };
+        let key = self::key(item, sub_attribute);
+        set(item, key, op, value);
+      }
+      (None, Some(Value::Object(attributes))) => {
+        let Value::Object(item) = item else {
+          return Err(invalid_path("Element is not an object"));
+        };
+        for (key, value) in attributes {
+          let key = self::key(item, &key);
+          item.insert(key, value);
+        }
+      }
+      (None, _) => return Err(invalid_value("Elements can only be merged with an object")),
+    }
+  }
+
+  // `emails[type eq "work"].value` on a user without a work email adds one
+  if !found && op != Op::Remove {
+    let (Some(sub_attribute), Filter::Compare(attribute, CompareOp::Eq, expected)) =
+      (&path.sub_attribute, filter)
+    else {
+      return Err(error(
+        StatusCode::BAD_REQUEST,
+        Some("noTarget"),
+        "No element matches the filter",
+      ));
+    };
+    let mut item = Map::new();
+    item.insert(attribute_name(attribute).to_string(), expected.clone());
+    item.insert(sub_attribute.clone(), value.unwrap_or_default());
+    items.push(Value::Object(item));
+  }
+
+  Ok(())
+}
+
+/// The existing key matching the attribute case-insensitively.
+fn key(object: &Map<String, Value>, attribute: &str) -> String {
+  object
+    .keys()
+    .find(|key| key.eq_ignore_ascii_case(attribute))
+    .cloned()
+    .unwrap_or_else(|| attribute.to_string())
+}
+
+fn set(object: &mut Map<String, Value>, key: String, op: Op, value: Option<Value>) {
+  match (op, object.get_mut(&key), value) {
+    // remove with a value removes these elements, e.g. `{"value": "<id>"}` of
+    // members
+    (Op::Remove, Some(Value::Array(items)), Some(value)) => {
+      let removed = match value {
+        Value::Array(removed) => removed,
+        value => vec![value],
+      };
+      items.retain(|item| {
+        !removed.iter().any(|removed| match get(removed, "value") {
+          Some(id) => get(item, "value") == Some(id),
+          None => removed == item,
+        })
+      });
+    }
+    (Op::Remove, _, _) => {
+      object.remove(&key);
+    }
+    (Op::Add, Some(Value::Array(items)), Some(value)) => match value {
+      Value::Array(added) => items.extend(added),
+      value => items.push(value),
+    },
+    (_, _, Some(value)) => {
+      object.insert(key, value);
+    }
+    (_, _, None) => {}
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn patch(resource: &mut Value, operations: Value) -> Result<()> {
+    let request: PatchRequest =
+      serde_json::from_value(json!({ "Operations": operations })).unwrap();
+    apply(resource, request.operations)
+  }
+
+  #[test]
+  fn test_replace_without_path() {
+    let mut user = json!({"userName": "a@example.com", "active": true, "name": {}});
+    patch(
+      &mut user,
+      json!([{"op": "Replace", "value": {"active": "False", "name.givenName": "Alice"}}]),
+    )
+    .unwrap();
+    assert_eq!(
+      user,
+      json!({"userName": "a@example.com", "active": "False", "name": {"givenName": "Alice"}})
+    );
+  }
+
+  #[test]
+  fn test_filtered_path() {
+    let mut user = json!({"emails": [{"type": "home", "value": "a@home.example"}]});
+    patch(
+      &mut user,
+      json!([
+        {"op": "replace", "path": "emails[type eq \"work\"].value", "value": "a@example.com"},
+        {"op": "replace", "path": "emails[type eq \"home\"].value", "value": "a@new.example"},
+      ]),
+    )
+    .unwrap();
+    assert_eq!(
+      user["emails"],
+      json!([
+        {"type": "home", "value": "a@new.example"},
+        {"type": "work", "value": "a@example.com"},
+      ])
+    );
+
+    patch(
+      &mut user,
+      json!([{"op": "remove", "path": "emails[type eq \"home\"]"}]),
+    )
+    .unwrap();
+    assert_eq!(
+      user["emails"],
+      json!([{"type": "work", "value": "a@example.com"}])
+    );
+  }
+
+  #[test]
+  fn test_members() {
+    let mut group = json!({"displayName": "staff", "members": [{"value": "1"}]});
+    patch(
+      &mut group,
+      json!([
+        {"op": "add", "path": "members", "value": [{"value": "2"}, {"value": "3"}]},
+        {"op": "remove", "path": "members[value eq \"1\"]"},
+        {"op": "remove", "path": "members", "value": [{"value": "2"}]},
+        {"op": "replace", "path": "urn:ietf:params:scim:schemas:core:2.0:Group:displayName", "value": "team"},
+      ]),
+    )
+    .unwrap();
+    assert_eq!(
+      group,
+      json!({"displayName": "team", "members": [{"value": "3"}]})
+    );
+
+    patch(&mut group, json!([{"op": "remove", "path": "members"}])).unwrap();
+    assert_eq!(group, json!({"displayName": "team"}));
+  }
+
+  #[test]
+  fn test_invalid_operations() {
+    let mut group = json!({"displayName": "staff"});
+    for operations in [
+      json!([{"op": "move", "path": "displayName"}]),
+      json!([{"op": "remove"}]),
+      json!([{"op": "replace", "path": "displayName"}]),
+      json!([{"op": "replace", "path": "members[value eq \"1\"]", "value": {}}]),
+      json!([{"op": "replace", "path": "displayName[value eq \"1\"]", "value": "x"}]),
+      json!([{"op": "add", "path": "members[value", "value": "x"}]),
+    ] {
+      assert!(
+        patch(&mut group, operations.clone()).is_err(),
+        "{operations}"
+      );
+    }
+  }
+}
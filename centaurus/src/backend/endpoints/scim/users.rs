@@ -0,0 +1,612 @@
+use std::collections::HashMap;
+
+use aide::axum::{
+  ApiRouter,
+  routing::{ApiMethodRouter, delete_with, get_with, patch_with, post_with, put_with},
+};
+use argon2::password_hash::SaltString;
+use axum::{
+  Json,
+  extract::{Path, Query},
+};
+use http::StatusCode;
+use rsa::rand_core::OsRng;
+use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Deserializer, Serialize};
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    auth::{jwt_auth::JwtAuth, permission::ScimProvision},
+    endpoints::{
+      audit::{Audit, AuditEvent},
+      scim::{
+        ListQuery, ListResponse, Meta, Reference, Scim, USER_SCHEMA, error,
+        patch::{self, PatchRequest},
+      },
+      websocket::state::{UpdateMessage, Updater},
+    },
+  },
+  db::{
+    init::Connection, permission::has_permission, tables::ConnectionExt,
+    tables::user::SimpleGroupInfo,
+  },
+  error::Result,
+};
+
+/// Provider of the `oidc_identity` table holding the `externalId` the client
+/// assigned to a user.
+pub const SCIM_PROVIDER: &str = "scim";
+
+pub fn router<T: UpdateMessage>() -> ApiRouter {
+  ApiRouter::new()
+    .api_route("/Users", list_users_route())
+    .api_route("/Users", create_user_route::<T>())
+    .api_route("/Users/{id}", get_user_route())
+    .api_route("/Users/{id}", replace_user_route::<T>())
+    .api_route("/Users/{id}", patch_user_route::<T>())
+    .api_route("/Users/{id}", delete_user_route::<T>())
+}
+
+pub fn list_users_route() -> ApiMethodRouter<()> {
+  get_with(list_users, |op| op.id("scimListUsers"))
+}
+
+pub fn create_user_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(create_user::<T>, |op| op.id("scimCreateUser"))
+}
+
+pub fn get_user_route() -> ApiMethodRouter<()> {
+  get_with(get_user, |op| op.id("scimGetUser"))
+}
+
+pub fn replace_user_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  put_with(replace_user::<T>, |op| op.id("scimReplaceUser"))
+}
+
+pub fn patch_user_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  patch_with(patch_user::<T>, |op| op.id("scimPatchUser"))
+}
+
+pub fn delete_user_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  delete_with(delete_user::<T>, |op| op.id("scimDeleteUser"))
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct ScimUser {
+  #[serde(default)]
+  pub schemas: Vec<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub id: Option<Uuid>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub external_id: Option<String>,
+  /// The email of the user, unless it is no email address. Then the primary
+  /// entry of `emails` is used.
+  pub user_name: String,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub name: Option<Name>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub display_name: Option<String>,
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub emails: Vec<Email>,
+  /// Inactive users cannot log in.
+  #[serde(default = "default_active", deserialize_with = "deserialize_active")]
+  #[schemars(with = "bool")]
+  pub active: bool,
+  /// Read only, memberships are changed through the groups.
+  #[serde(default, skip_serializing_if = "Vec::is_empty")]
+  pub groups: Vec<Reference>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub meta: Option<Meta>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Default, PartialEq, Eq, JsonSchema)]
+#[serde(rename_all = "camelCase")]
+pub struct Name {
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub formatted: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub given_name: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub family_name: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, JsonSchema)]
+pub struct Email {
+  pub value: String,
+  #[serde(default, rename = "type", skip_serializing_if = "Option::is_none")]
+  pub kind: Option<String>,
+  #[serde(default, skip_serializing_if = "Option::is_none")]
+  pub primary: Option<bool>,
+}
+
+fn default_active() -> bool {
+  true
+}
+
+/// Some clients send booleans as strings in PATCH requests, e.g. `"False"`.
+fn deserialize_active<'de, D: Deserializer<'de>>(
+  deserializer: D,
+) -> std::result::Result<bool, D::Error> {
+  #[derive(Deserialize)]
+  #[serde(untagged)]
+  enum Active {
+    Bool(bool),
+    String(String),
+  }
+
+  match Active::deserialize(deserializer)? {
+    Active::Bool(active) => Ok(active),
+    Active::String(active) => active
+      .parse::<bool>()
+      .or_else(|_| active.to_lowercase().parse())
+      .map_err(serde::de::Error::custom),
+  }
+}
+
+impl ScimUser {
+  fn new(
+    id: Uuid,
+    name: String,
+    email: String,
+    active: bool,
+    groups: Vec<SimpleGroupInfo>,
+    external_id: Option<String>,
+  ) -> Self {
+    Self {
+      schemas: vec![USER_SCHEMA.to_string()],
+      id: Some(id),
+      external_id,
+      user_name: email.clone(),
+      name: Some(Name {
+        formatted: Some(name.clone()),
+        ..Default::default()
+      }),
+      display_name: Some(name),
+      emails: vec![Email {
+        value: email,
+        kind: Some("work".to_string()),
+        primary: Some(true),
+      }],
+      active,
+      groups: groups
+        .into_iter()
+        .map(|group| Reference {
+          value: group.uuid.to_string(),
+          display: Some(group.name),
+        })
+        .collect(),
+      meta: Some(Meta {
+        resource_type: "User".to_string(),
+      }),
+    }
+  }
+
+  fn email(&self) -> Result<String> {
+    let email = if self.user_name.contains('@') {
+      Some(self.user_name.as_str())
+    } else {
+      self
+        .emails
+        .iter()
+        .find(|email| email.primary == Some(true))
+        .or(self.emails.first())
+        .map(|email| email.value.as_str())
+    };
+
+    match email.map(str::trim) {
+      Some(email) if email.contains('@') => Ok(email.to_lowercase()),
+      _ => Err(error(
+        StatusCode::BAD_REQUEST,
+        Some("invalidValue"),
+        "userName or emails must hold an email address",
+      )),
+    }
+  }
+
+  /// `displayName`, the formatted or the composed name, the email otherwise.
+  fn display_name(&self, email: &str) -> String {
+    let name = self.name.clone().unwrap_or_default();
+    let composed = [name.given_name, name.family_name]
+      .into_iter()
+      .flatten()
+      .filter(|part| !part.trim().is_empty())
+      .collect::<Vec<_>>()
+      .join(" ");
+
+    [self.display_name.clone(), name.formatted, Some(composed)]
+      .into_iter()
+      .flatten()
+      .map(|name| name.trim().to_string())
+      .find(|name| !name.is_empty())
+      .unwrap_or_else(|| email.to_string())
+  }
+}
+
+fn not_found() -> crate::error::ErrorReport {
+  error(StatusCode::NOT_FOUND, None, "User not found")
+}
+
+async fn external_id(db: &Connection, user: Uuid) -> Result<Option<String>> {
+  Ok(
+    db.oidc_identity()
+      .list_identities(user)
+      .await?
+      .into_iter()
+      .find(|identity| identity.provider == SCIM_PROVIDER)
+      .map(|identity| identity.subject),
+  )
+}
+
+async fn load(db: &Connection, id: Uuid) -> Result<ScimUser> {
+  let Some(user) = db.user().user_info(id).await? else {
+    return Err(not_found());
+  };
+  if user.service_account {
+    return Err(not_found());
+  }
+
+  Ok(ScimUser::new(
+    user.uuid,
+    user.name,
+    user.email,
+    user.active,
+    user.groups,
+    external_id(db, id).await?,
+  ))
+}
+
+/// Fails if another user already holds the email or external id.
+async fn check_unique(db: &Connection, id: Option<Uuid>, user: &ScimUser) -> Result<()> {
+  if let Some(other) = db.user().try_get_user_by_email(&user.email()?).await?
+    && Some(other.id) != id
+  {
+    return Err(error(
+      StatusCode::CONFLICT,
+      Some("uniqueness"),
+      "User with this email already exists",
+    ));
+  }
+
+  if let Some(external_id) = &user.external_id
+    && let Some(other) = db
+      .oidc_identity()
+      .get_identity(SCIM_PROVIDER, external_id)
+      .await?
+    && Some(other.user_id) != id
+  {
+    return Err(error(
+      StatusCode::CONFLICT,
+      Some("uniqueness"),
+      "User with this externalId already exists",
+    ));
+  }
+
+  Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct UserPath {
+  id: Uuid,
+}
+
+async fn list_users(
+  _auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  Query(query): Query<ListQuery>,
+) -> Result<Scim<ListResponse<ScimUser>>> {
+  let mut external_ids: HashMap<_, _> = db
+    .oidc_identity()
+    .list_provider_identities(SCIM_PROVIDER)
+    .await?
+    .into_iter()
+    .map(|identity| (identity.user_id, identity.subject))
+    .collect();
+
+  let users = db
+    .user()
+    .list_users()
+    .await?
+    .into_iter()
+    .map(|user| {
+      let external_id = external_ids.remove(&user.uuid);
+      ScimUser::new(
+        user.uuid,
+        user.name,
+        user.email,
+        user.active,
+        user.groups,
+        external_id,
+      )
+    })
+    .collect();
+
+  Ok(Scim(query.apply(users)?))
+}
+
+async fn get_user(
+  _auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  Path(path): Path<UserPath>,
+) -> Result<Scim<ScimUser>> {
+  Ok(Scim(load(&db, path.id).await?))
+}
+
+async fn create_user<T: UpdateMessage>(
+  auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(user): Json<ScimUser>,
+) -> Result<(StatusCode, Scim<ScimUser>)> {
+  check_unique(&db, None, &user).await?;
+  let email = user.email()?;
+
+  // provisioned users log in through single sign-on
+  let salt = SaltString::generate(OsRng {}).to_string();
+  let txn = db.begin().await?;
+  let id = txn
+    .user()
+    .create_user(
+      user.display_name(&email),
+      email,
+      String::new(),
+      salt,
+      true,
+      None,
+    )
+    .await?;
+  if !user.active {
+    txn.user().set_active(id, false).await?;
+  }
+  if let Some(external_id) = &user.external_id {
+    txn
+      .oidc_identity()
+      .link(id, SCIM_PROVIDER, external_id)
+      .await?;
+  }
+
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.create")
+        .target("user", id)
+        .after(&txn.user().user_info(id).await?),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::user(id)).await;
+
+  Ok((StatusCode::CREATED, Scim(load(&db, id).await?)))
+}
+
+async fn replace_user<T: UpdateMessage>(
+  auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Path(path): Path<UserPath>,
+  Json(user): Json<ScimUser>,
+) -> Result<Scim<ScimUser>> {
+  update(&db, &updater, &audit, auth.user_id, path.id, user).await?;
+  Ok(Scim(load(&db, path.id).await?))
+}
+
+async fn patch_user<T: UpdateMessage>(
+  auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Path(path): Path<UserPath>,
+  Json(req): Json<PatchRequest>,
+) -> Result<Scim<ScimUser>> {
+  let before = load(&db, path.id).await?;
+  let mut value = serde_json::to_value(&before)?;
+  patch::apply(&mut value, req.operations)?;
+  let mut user: ScimUser = serde_json::from_value(value)
+    .map_err(|e| error(StatusCode::BAD_REQUEST, Some("invalidValue"), e))?;
+
+  // the name is also held by displayName, which takes precedence
+  if user.display_name == before.display_name && user.name != before.name {
+    user.display_name = None;
+    if let (Some(name), Some(before)) = (&mut user.name, &before.name)
+      && name.formatted == before.formatted
+    {
+      name.formatted = None;
+    }
+  }
+
+  update(&db, &updater, &audit, auth.user_id, path.id, user).await?;
+  Ok(Scim(load(&db, path.id).await?))
+}
+
+async fn update<T: UpdateMessage>(
+  db: &Connection,
+  updater: &Updater<T>,
+  audit: &Audit,
+  actor: Uuid,
+  id: Uuid,
+  user: ScimUser,
+) -> Result<()> {
+  let Some(before) = db.user().user_info(id).await? else {
+    return Err(not_found());
+  };
+  if before.service_account {
+    return Err(not_found());
+  }
+
+  let self_permissions = db.group().get_user_permissions(actor).await?;
+  if before
+    .permissions
+    .iter()
+    .any(|p| !has_permission(&self_permissions, p))
+  {
+    return Err(error(
+      StatusCode::FORBIDDEN,
+      None,
+      "Cannot edit a user with higher permissions",
+    ));
+  }
+
+  check_unique(db, Some(id), &user).await?;
+  let email = user.email()?;
+  let name = user.display_name(&email);
+
+  let deactivate = !user.active && before.active;
+  if deactivate
+    && let Some(admin_group) = db.setup().get_admin_group_id().await?
+    && db.group().is_last_admin(admin_group, id).await?
+  {
+    return Err(error(
+      StatusCode::CONFLICT,
+      None,
+      "Cannot deactivate the last admin",
+    ));
+  }
+  let current = external_id(db, id).await?;
+
+  let txn = db.begin().await?;
+  if deactivate {
+    txn.session().revoke_user_sessions(id, None).await?;
+    txn.refresh_token().revoke_user_tokens(id).await?;
+  }
+  if user.active != before.active {
+    txn.user().set_active(id, user.active).await?;
+  }
+  if name != before.name {
+    txn.user().update_user_name(id, name).await?;
+  }
+  if email != before.email {
+    txn.user().change_email(id, email).await?;
+  }
+
+  if user.external_id != current {
+    if let Some(current) = current
+      && let Some(identity) = txn
+        .oidc_identity()
+        .get_identity(SCIM_PROVIDER, &current)
+        .await?
+    {
+      txn.oidc_identity().unlink(id, identity.id).await?;
+    }
+    if let Some(external_id) = &user.external_id {
+      txn
+        .oidc_identity()
+        .link(id, SCIM_PROVIDER, external_id)
+        .await?;
+    }
+  }
+
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(actor, "user.edit")
+        .target("user", id)
+        .before(&before)
+        .after(&txn.user().user_info(id).await?),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::user(id)).await;
+
+  Ok(())
+}
+
+async fn delete_user<T: UpdateMessage>(
+  auth: JwtAuth<ScimProvision>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Path(path): Path<UserPath>,
+) -> Result<StatusCode> {
+  let Some(before) = db.user().user_info(path.id).await? else {
+    return Err(not_found());
+  };
+  if before.service_account {
+    return Err(not_found());
+  }
+
+  let Some(admin_group) = db.setup().get_admin_group_id().await? else {
+    return Err(error(
+      StatusCode::INTERNAL_SERVER_ERROR,
+      None,
+      "Admin group is not set up",
+    ));
+  };
+  if db.group().is_last_admin(admin_group, path.id).await? {
+    return Err(error(
+      StatusCode::CONFLICT,
+      None,
+      "Cannot delete the last user from the admin group",
+    ));
+  }
+
+  let self_permissions = db.group().get_user_permissions(auth.user_id).await?;
+  if before
+    .permissions
+    .iter()
+    .any(|p| !has_permission(&self_permissions, p))
+  {
+    return Err(error(
+      StatusCode::FORBIDDEN,
+      None,
+      "Cannot delete a user with higher permissions",
+    ));
+  }
+
+  let txn = db.begin().await?;
+  txn.user().delete_user(path.id).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.delete")
+        .target("user", path.id)
+        .before(&before),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::user(path.id)).await;
+
+  Ok(StatusCode::NO_CONTENT)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use serde_json::json;
+
+  fn user(value: serde_json::Value) -> ScimUser {
+    serde_json::from_value(value).unwrap()
+  }
+
+  #[test]
+  fn test_email_and_name() {
+    let scim = user(json!({
+      "userName": "Alice@Example.com",
+      "name": {"givenName": "Alice", "familyName": "Smith"},
+      "active": "False",
+    }));
+    assert_eq!(scim.email().unwrap(), "alice@example.com");
+    assert_eq!(scim.display_name("alice@example.com"), "Alice Smith");
+    assert!(!scim.active);
+
+    let scim = user(json!({
+      "userName": "alice",
+      "displayName": " Alice ",
+      "emails": [
+        {"value": "alice@home.example", "type": "home"},
+        {"value": "alice@example.com", "type": "work", "primary": true},
+      ],
+    }));
+    assert_eq!(scim.email().unwrap(), "alice@example.com");
+    assert_eq!(scim.display_name("alice@example.com"), "Alice");
+    assert!(scim.active);
+
+    let scim = user(json!({"userName": "alice"}));
+    assert!(scim.email().is_err());
+    let scim = user(json!({"userName": "alice@example.com", "name": {"formatted": ""}}));
+    assert_eq!(scim.display_name("alice@example.com"), "alice@example.com");
+  }
+}
@@ -1,17 +1,28 @@
+use std::collections::HashSet;
+
 use aide::axum::routing::{ApiMethodRouter, get_with, post_with};
 use axum::Json;
 use http::StatusCode;
 use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
 use serde::Serialize;
 
 use crate::backend::BackendRouter;
 use crate::backend::auth::jwt_auth::JwtAuth;
-use crate::backend::auth::oidc::OidcState;
+use crate::backend::auth::oidc::{OidcProviderStatus, OidcState};
 use crate::backend::auth::permission::{SettingsEdit, SettingsView};
-use crate::backend::auth::settings::UserSettings;
+#[cfg(feature = "ldap")]
+use crate::backend::auth::settings::LdapSettings;
+#[cfg(feature = "saml")]
+use crate::backend::auth::settings::SamlSettings;
+use crate::backend::auth::settings::{
+  DEFAULT_PROVIDER, LockoutSettings, PasswordPolicySettings, UserSettings,
+};
+use crate::backend::endpoints::audit::{Audit, AuditEvent};
 use crate::backend::endpoints::websocket::state::{UpdateMessage, Updater};
+use crate::bail;
 use crate::db::init::Connection;
-use crate::db::tables::ConnectionExt;
+use crate::db::tables::{ConnectionExt, password_history::MAX_PASSWORD_HISTORY};
 use crate::error::{ErrorReportStatusExt, Result};
 #[cfg(feature = "mail")]
 use crate::mail::{MailSettings, Mailer};
@@ -20,7 +31,24 @@ use crate::overwrite_with_env_config;
 pub fn router<T: UpdateMessage>() -> BackendRouter {
   let router = BackendRouter::new()
     .api_route("/user", get_user_settings_route())
-    .api_route("/user", save_user_settings_route::<T>());
+    .api_route("/user", save_user_settings_route::<T>())
+    .api_route("/lockout", get_lockout_settings_route())
+    .api_route("/lockout", save_lockout_settings_route::<T>())
+    .api_route("/password_policy", get_password_policy_settings_route())
+    .api_route(
+      "/password_policy",
+      save_password_policy_settings_route::<T>(),
+    );
+
+  #[cfg(feature = "saml")]
+  let router = router
+    .api_route("/saml", get_saml_settings_route())
+    .api_route("/saml", save_saml_settings_route::<T>());
+
+  #[cfg(feature = "ldap")]
+  let router = router
+    .api_route("/ldap", get_ldap_settings_route())
+    .api_route("/ldap", save_ldap_settings_route::<T>());
 
   #[cfg(feature = "mail")]
   {
@@ -43,6 +71,48 @@ pub fn save_user_settings_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
   post_with(save_user_settings::<T>, |op| op.id("saveUserSettings"))
 }
 
+pub fn get_lockout_settings_route() -> ApiMethodRouter<()> {
+  get_with(get_lockout_settings, |op| op.id("getLockoutSettings"))
+}
+
+pub fn save_lockout_settings_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(save_lockout_settings::<T>, |op| {
+    op.id("saveLockoutSettings")
+  })
+}
+
+pub fn get_password_policy_settings_route() -> ApiMethodRouter<()> {
+  get_with(get_password_policy_settings, |op| {
+    op.id("getPasswordPolicySettings")
+  })
+}
+
+pub fn save_password_policy_settings_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(save_password_policy_settings::<T>, |op| {
+    op.id("savePasswordPolicySettings")
+  })
+}
+
+#[cfg(feature = "saml")]
+pub fn get_saml_settings_route() -> ApiMethodRouter<()> {
+  get_with(get_saml_settings, |op| op.id("getSamlSettings"))
+}
+
+#[cfg(feature = "saml")]
+pub fn save_saml_settings_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(save_saml_settings::<T>, |op| op.id("saveSamlSettings"))
+}
+
+#[cfg(feature = "ldap")]
+pub fn get_ldap_settings_route() -> ApiMethodRouter<()> {
+  get_with(get_ldap_settings, |op| op.id("getLdapSettings"))
+}
+
+#[cfg(feature = "ldap")]
+pub fn save_ldap_settings_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(save_ldap_settings::<T>, |op| op.id("saveLdapSettings"))
+}
+
 #[cfg(feature = "mail")]
 pub fn get_mail_settings_route() -> ApiMethodRouter<()> {
   get_with(get_mail_settings, |op| op.id("getMailSettings"))
@@ -59,6 +129,14 @@ pub struct UserSettingsResponse {
   pub from_env: Vec<String>,
 }
 
+#[derive(Serialize, JsonSchema)]
+struct UserSettingsStatusResponse {
+  #[serde(flatten)]
+  response: UserSettingsResponse,
+  /// Connection health of the OIDC providers.
+  oidc_status: Vec<OidcProviderStatus>,
+}
+
 #[macro_export]
 macro_rules! each_field_from_env {
   ($type:tt, $config:ident, $env_config:ident, $($field:ident),*,, $($bool_field:ident),*) => {
@@ -96,8 +174,9 @@ macro_rules! each_field_from_env {
 async fn get_user_settings(
   _auth: JwtAuth<SettingsView>,
   db: Connection,
+  state: OidcState,
   config: Option<UserSettings>,
-) -> Result<Json<UserSettingsResponse>> {
+) -> Result<Json<UserSettingsStatusResponse>> {
   let mut settings = db.settings().get_settings::<UserSettings>().await?;
 
   let mut res = each_field_from_env!(
@@ -107,7 +186,8 @@ async fn get_user_settings(
     oidc_issuer,
     oidc_client_id,
     oidc_client_secret,
-    oidc_scopes,,
+    oidc_scopes,
+    oidc_providers,,
     oidc_enabled,
     oidc_image_sync,
     oidc_group_sync,
@@ -117,8 +197,54 @@ async fn get_user_settings(
   );
 
   res.settings.oidc_client_secret = None;
+  for provider in res.settings.oidc_providers.iter_mut().flatten() {
+    provider.client_secret = None;
+  }
 
-  Ok(Json(res))
+  Ok(Json(UserSettingsStatusResponse {
+    response: res,
+    oidc_status: state.status().await,
+  }))
+}
+
+/// Validate the names of the named providers and keep the stored secret of
+/// those sent without one, as secrets are never returned to the client.
+pub fn prepare_oidc_providers(
+  settings: &mut UserSettings,
+  db_settings: &UserSettings,
+) -> Result<()> {
+  let mut names = HashSet::new();
+  for provider in settings.oidc_providers.iter_mut().flatten() {
+    if provider.name.is_empty()
+      || !provider
+        .name
+        .chars()
+        .all(|c| c.is_ascii_alphanumeric() || c == '-' || c == '_')
+    {
+      bail!(
+        BAD_REQUEST,
+        "OIDC provider names may only contain letters, digits, '-' and '_'"
+      );
+    }
+    if provider.name == DEFAULT_PROVIDER || !names.insert(provider.name.clone()) {
+      bail!(
+        BAD_REQUEST,
+        "OIDC provider name {} is already in use",
+        provider.name
+      );
+    }
+
+    if provider.client_secret.as_ref().is_none_or(|s| s.is_empty()) {
+      provider.client_secret = db_settings
+        .oidc_providers
+        .iter()
+        .flatten()
+        .find(|p| p.name == provider.name)
+        .and_then(|p| p.client_secret.clone());
+    }
+  }
+
+  Ok(())
 }
 
 #[cfg(feature = "mail")]
@@ -156,22 +282,24 @@ async fn get_mail_settings(
 }
 
 async fn save_user_settings<T: UpdateMessage>(
-  _auth: JwtAuth<SettingsEdit>,
+  auth: JwtAuth<SettingsEdit>,
   db: Connection,
   state: OidcState,
   updater: Updater<T>,
+  audit: Audit,
   config: Option<UserSettings>,
   Json(mut settings): Json<UserSettings>,
 ) -> Result<()> {
+  let db_settings = db.settings().get_settings::<UserSettings>().await?;
   if let Some(secret) = &settings.oidc_client_secret
     && secret.is_empty()
   {
     settings.oidc_client_secret = None;
   }
   if settings.oidc_client_secret.is_none() {
-    let db_settings = db.settings().get_settings::<UserSettings>().await?;
-    settings.oidc_client_secret = db_settings.oidc_client_secret;
+    settings.oidc_client_secret = db_settings.oidc_client_secret.clone();
   }
+  prepare_oidc_providers(&mut settings, &db_settings)?;
 
   let settings_to_db = settings.clone();
 
@@ -181,7 +309,8 @@ async fn save_user_settings<T: UpdateMessage>(
     oidc_issuer,
     oidc_client_id,
     oidc_client_secret,
-    oidc_scopes,,
+    oidc_scopes,
+    oidc_providers,,
     oidc_enabled,
     oidc_image_sync,
     oidc_group_sync,
@@ -190,16 +319,26 @@ async fn save_user_settings<T: UpdateMessage>(
     sso_instant_redirect
   );
 
-  if let Some(oidc_settings) = &settings.oidc_settings() {
-    state.try_init(oidc_settings).await.status_context(
+  state
+    .configure(&settings.oidc_providers())
+    .await
+    .status_context(
       StatusCode::NOT_ACCEPTABLE,
       "Failed to initialize OIDC state",
     )?;
-  } else {
-    state.deactivate().await;
-  }
 
-  db.settings().save_settings(&settings_to_db).await?;
+  let txn = db.begin().await?;
+  txn.settings().save_settings(&settings_to_db).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "settings.user")
+        .target("settings", "user")
+        .before(&db_settings)
+        .after(&settings_to_db),
+    )
+    .await?;
+  txn.commit().await?;
   updater.broadcast(T::settings()).await;
 
   Ok(())
@@ -207,21 +346,22 @@ async fn save_user_settings<T: UpdateMessage>(
 
 #[cfg(feature = "mail")]
 async fn save_mail_settings<T: UpdateMessage>(
-  _auth: JwtAuth<SettingsEdit>,
+  auth: JwtAuth<SettingsEdit>,
   db: Connection,
   state: Mailer,
   updater: Updater<T>,
+  audit: Audit,
   config: Option<MailSettings>,
   Json(mut settings): Json<MailSettings>,
 ) -> Result<()> {
+  let db_settings = db.settings().get_settings::<MailSettings>().await?;
   if let Some(password) = &settings.smtp_password
     && password.is_empty()
   {
     settings.smtp_password = None;
   }
   if settings.smtp_password.is_none() {
-    let db_settings = db.settings().get_settings::<MailSettings>().await?;
-    settings.smtp_password = db_settings.smtp_password;
+    settings.smtp_password = db_settings.smtp_password.clone();
   }
 
   let settings_to_db = settings.clone();
@@ -245,7 +385,193 @@ async fn save_mail_settings<T: UpdateMessage>(
     state.deactivate().await;
   }
 
-  db.settings().save_settings(&settings_to_db).await?;
+  let txn = db.begin().await?;
+  txn.settings().save_settings(&settings_to_db).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "settings.mail")
+        .target("settings", "mail")
+        .before(&db_settings)
+        .after(&settings_to_db),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::settings()).await;
+
+  Ok(())
+}
+
+async fn get_lockout_settings(
+  _auth: JwtAuth<SettingsView>,
+  db: Connection,
+) -> Result<Json<LockoutSettings>> {
+  Ok(Json(db.settings().get_settings::<LockoutSettings>().await?))
+}
+
+async fn save_lockout_settings<T: UpdateMessage>(
+  auth: JwtAuth<SettingsEdit>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(settings): Json<LockoutSettings>,
+) -> Result<()> {
+  if settings.lockout_threshold == Some(0) {
+    bail!(BAD_REQUEST, "Lockout threshold must be at least 1");
+  }
+  if settings.lockout_duration.is_some_and(|d| d < 0)
+    || settings.lockout_delay.is_some_and(|d| d < 0)
+  {
+    bail!(BAD_REQUEST, "Lockout durations cannot be negative");
+  }
+
+  let db_settings = db.settings().get_settings::<LockoutSettings>().await?;
+  let txn = db.begin().await?;
+  txn.settings().save_settings(&settings).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "settings.lockout")
+        .target("settings", "lockout")
+        .before(&db_settings)
+        .after(&settings),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::settings()).await;
+
+  Ok(())
+}
+
+async fn get_password_policy_settings(
+  _auth: JwtAuth<SettingsView>,
+  db: Connection,
+) -> Result<Json<PasswordPolicySettings>> {
+  Ok(Json(
+    db.settings()
+      .get_settings::<PasswordPolicySettings>()
+      .await?,
+  ))
+}
+
+async fn save_password_policy_settings<T: UpdateMessage>(
+  auth: JwtAuth<SettingsEdit>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(settings): Json<PasswordPolicySettings>,
+) -> Result<()> {
+  if settings
+    .password_history
+    .is_some_and(|history| u64::from(history) > MAX_PASSWORD_HISTORY + 1)
+  {
+    bail!(
+      BAD_REQUEST,
+      "At most {} previous passwords can be checked",
+      MAX_PASSWORD_HISTORY + 1
+    );
+  }
+
+  let db_settings = db
+    .settings()
+    .get_settings::<PasswordPolicySettings>()
+    .await?;
+  let txn = db.begin().await?;
+  txn.settings().save_settings(&settings).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "settings.password_policy")
+        .target("settings", "password_policy")
+        .before(&db_settings)
+        .after(&settings),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::settings()).await;
+
+  Ok(())
+}
+
+#[cfg(feature = "saml")]
+async fn get_saml_settings(
+  _auth: JwtAuth<SettingsView>,
+  db: Connection,
+) -> Result<Json<SamlSettings>> {
+  Ok(Json(db.settings().get_settings::<SamlSettings>().await?))
+}
+
+#[cfg(feature = "saml")]
+async fn save_saml_settings<T: UpdateMessage>(
+  auth: JwtAuth<SettingsEdit>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(settings): Json<SamlSettings>,
+) -> Result<()> {
+  if let Some(certificate) = &settings.saml_idp_certificate {
+    crate::backend::auth::saml::idp_key(certificate)?;
+  }
+
+  let db_settings = db.settings().get_settings::<SamlSettings>().await?;
+  let txn = db.begin().await?;
+  txn.settings().save_settings(&settings).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "settings.saml")
+        .target("settings", "saml")
+        .before(&db_settings)
+        .after(&settings),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::settings()).await;
+
+  Ok(())
+}
+
+#[cfg(feature = "ldap")]
+async fn get_ldap_settings(
+  _auth: JwtAuth<SettingsView>,
+  db: Connection,
+) -> Result<Json<LdapSettings>> {
+  let mut settings = db.settings().get_settings::<LdapSettings>().await?;
+  settings.ldap_bind_password = None;
+
+  Ok(Json(settings))
+}
+
+#[cfg(feature = "ldap")]
+async fn save_ldap_settings<T: UpdateMessage>(
+  auth: JwtAuth<SettingsEdit>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(mut settings): Json<LdapSettings>,
+) -> Result<()> {
+  let db_settings = db.settings().get_settings::<LdapSettings>().await?;
+  if let Some(password) = &settings.ldap_bind_password
+    && password.is_empty()
+  {
+    settings.ldap_bind_password = None;
+  }
+  if settings.ldap_bind_password.is_none() {
+    settings.ldap_bind_password = db_settings.ldap_bind_password.clone();
+  }
+
+  let txn = db.begin().await?;
+  txn.settings().save_settings(&settings).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "settings.ldap")
+        .target("settings", "ldap")
+        .before(&db_settings)
+        .after(&settings),
+    )
+    .await?;
+  txn.commit().await?;
   updater.broadcast(T::settings()).await;
 
   Ok(())
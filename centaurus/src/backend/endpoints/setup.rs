@@ -6,6 +6,7 @@ use axum_extra::extract::CookieJar;
 use http::StatusCode;
 use rsa::rand_core::OsRng;
 use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
 use serde::{Deserialize, Serialize};
 use tracing::info;
 use url::Url;
@@ -13,10 +14,13 @@ use uuid::Uuid;
 
 use crate::backend::auth::jwt_state::JwtState;
 use crate::backend::auth::oidc::OidcState;
+use crate::backend::auth::password_policy::check_password;
 use crate::backend::auth::pw_state::PasswordState;
+use crate::backend::auth::session::ClientInfo;
 use crate::backend::auth::settings::UserSettings;
 use crate::backend::config::SiteConfig;
-use crate::backend::endpoints::settings::UserSettingsResponse;
+use crate::backend::endpoints::audit::{Audit, AuditEvent};
+use crate::backend::endpoints::settings::{UserSettingsResponse, prepare_oidc_providers};
 use crate::db::init::Connection;
 use crate::db::tables::ConnectionExt;
 use crate::error::{ErrorReportStatusExt, Result};
@@ -112,7 +116,9 @@ async fn complete_setup(
   db: Connection,
   jwt: JwtState,
   state: PasswordState,
+  audit: Audit,
   mut cookies: CookieJar,
+  client: ClientInfo,
   Json(payload): Json<SetupPayload>,
 ) -> Result<(CookieJar, Json<SetupResponse>)> {
   if db.setup().is_setup().await? {
@@ -134,10 +140,13 @@ async fn complete_setup(
     );
   };
 
+  let password = state.decrypt_password(&payload.admin_password)?;
+  check_password(&db, &state, None, &password).await?;
   let salt = SaltString::generate(OsRng {}).to_string();
-  let hash = state.pw_hash(&salt, &payload.admin_password)?;
+  let hash = state.pw_hash_raw(&password)?;
 
-  let admin = db
+  let txn = db.begin().await?;
+  let admin = txn
     .user()
     .create_user(
       payload.admin_username,
@@ -148,15 +157,24 @@ async fn complete_setup(
       None,
     )
     .await?;
-  db.group()
+  txn
+    .group()
     .add_user_to_groups(admin, vec![admin_group_id])
     .await?;
 
-  db.setup().mark_completed().await?;
+  txn.setup().mark_completed().await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(admin, "setup.complete")
+        .target("user", admin)
+        .after(&txn.user().user_info(admin).await?),
+    )
+    .await?;
+  txn.commit().await?;
   info!("Setup completed, created admin user with ID {}", admin);
 
-  let cookie = jwt.create_token(admin)?;
-  cookies = cookies.add(cookie);
+  cookies = jwt.create_login(&db, cookies, admin, &client).await?;
   info!("Created post setup login token for admin user");
 
   Ok((cookies, Json(SetupResponse { user: admin })))
@@ -215,7 +233,8 @@ async fn get_oidc_settings(
     oidc_issuer,
     oidc_client_id,
     oidc_client_secret,
-    oidc_scopes,,
+    oidc_scopes,
+    oidc_providers,,
     oidc_enabled,
     oidc_image_sync,
     oidc_group_sync,
@@ -234,6 +253,7 @@ async fn get_oidc_settings(
 async fn init_oidc(
   db: Connection,
   state: OidcState,
+  audit: Audit,
   config: Option<UserSettings>,
   Json(mut settings): Json<UserSettings>,
 ) -> Result<()> {
@@ -241,6 +261,8 @@ async fn init_oidc(
     bail!(FORBIDDEN, "Setup has already been completed");
   }
 
+  let db_settings = db.settings().get_settings::<UserSettings>().await?;
+  prepare_oidc_providers(&mut settings, &db_settings)?;
   let mut settings_to_db = settings.clone();
 
   overwrite_with_env_config!(
@@ -249,7 +271,8 @@ async fn init_oidc(
     oidc_issuer,
     oidc_client_id,
     oidc_client_secret,
-    oidc_scopes,,
+    oidc_scopes,
+    oidc_providers,,
     oidc_enabled,
     oidc_image_sync,
     oidc_group_sync,
@@ -264,16 +287,26 @@ async fn init_oidc(
   settings_to_db.oidc_enabled = Some(true);
   settings_to_db.sso_create_user = Some(true);
 
-  if let Some(oidc_settings) = &settings.oidc_settings() {
-    state.try_init(oidc_settings).await.status_context(
+  state
+    .configure(&settings.oidc_providers())
+    .await
+    .status_context(
       StatusCode::NOT_ACCEPTABLE,
       "Failed to initialize OIDC state",
     )?;
-  } else {
-    state.deactivate().await;
-  }
 
-  db.settings().save_settings(&settings_to_db).await?;
+  let txn = db.begin().await?;
+  txn.settings().save_settings(&settings_to_db).await?;
+  // nobody is logged in before the setup is completed
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(None, "setup.oidc")
+        .target("settings", "user")
+        .after(&settings_to_db),
+    )
+    .await?;
+  txn.commit().await?;
 
   Ok(())
 }
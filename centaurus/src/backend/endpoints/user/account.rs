@@ -15,9 +15,24 @@ use serde::Deserialize;
 use crate::error::ErrorReportStatusExt;
 use crate::{
   backend::{
-    auth::{jwt_auth::JwtAuth, pw_state::PasswordState},
+    auth::{
+      jwt_auth::JwtAuth,
+      password_policy::check_password,
+      pw_state::{PasswordState, Verified},
+    },
     endpoints::{
-      user::email::{confirm_email_change_route, start_email_change_route},
+      user::{
+        api_token::{create_api_token_route, delete_api_token_route, list_api_tokens_route},
+        email::{confirm_email_change_route, start_email_change_route},
+        identity::{list_identities_route, unlink_identity_route},
+        pake::{disable_pake_route, enable_pake_route, pake_status_route},
+        passkey::{delete_passkey_route, list_passkeys_route, rename_passkey_route},
+        session::{list_sessions_route, revoke_other_sessions_route, revoke_session_route},
+        totp::{
+          confirm_totp_route, disable_totp_route, regenerate_recovery_codes_route,
+          start_totp_route, totp_status_route,
+        },
+      },
       websocket::state::{UpdateMessage, Updater},
     },
     middleware::rate_limiter::RateLimiter,
@@ -31,9 +46,28 @@ pub fn router<T: UpdateMessage>(rate_limiter: &mut RateLimiter) -> ApiRouter {
   let router = ApiRouter::new()
     .api_route("/password", update_password_route())
     .api_route("/email_change_start", start_email_change_route())
-    .layer(rate_limiter.create_limiter())
+    .api_route("/totp", disable_totp_route())
+    .api_route("/totp/confirm", confirm_totp_route())
+    .api_route("/totp/recovery_codes", regenerate_recovery_codes_route())
+    .api_route("/pake", enable_pake_route())
+    .layer(rate_limiter.create_limiter("account"))
     .api_route("/update", update_account_route::<T>())
-    .api_route("/email_change_confirm", confirm_email_change_route::<T>());
+    .api_route("/email_change_confirm", confirm_email_change_route::<T>())
+    .api_route("/sessions", list_sessions_route())
+    .api_route("/sessions", revoke_session_route())
+    .api_route("/sessions/revoke_others", revoke_other_sessions_route())
+    .api_route("/totp", totp_status_route())
+    .api_route("/totp", start_totp_route())
+    .api_route("/pake", pake_status_route())
+    .api_route("/pake", disable_pake_route())
+    .api_route("/identities", list_identities_route())
+    .api_route("/identities", unlink_identity_route())
+    .api_route("/passkeys", list_passkeys_route())
+    .api_route("/passkeys", rename_passkey_route())
+    .api_route("/passkeys", delete_passkey_route())
+    .api_route("/tokens", list_api_tokens_route())
+    .api_route("/tokens", create_api_token_route())
+    .api_route("/tokens", delete_api_token_route());
 
   #[cfg(feature = "avatar")]
   {
@@ -138,12 +172,13 @@ async fn update_password(
     bail!(NOT_ACCEPTABLE, "OIDC users cannot change their password");
   }
 
-  let old_hash = state.pw_hash(&user.salt, &data.old_password)?;
-  if old_hash != user.password {
+  if state.pw_verify(&user.salt, &user.password, &data.old_password)? == Verified::Invalid {
     bail!(FORBIDDEN, "Old password is incorrect");
   }
 
-  let new_hash = state.pw_hash(&user.salt, &data.new_password)?;
+  let new_password = state.decrypt_password(&data.new_password)?;
+  check_password(&db, &state, Some(&user), &new_password).await?;
+  let new_hash = state.pw_hash_raw(&new_password)?;
   db.user()
     .update_user_password(auth.user_id, new_hash)
     .await?;
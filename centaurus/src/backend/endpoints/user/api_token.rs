@@ -0,0 +1,116 @@
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with};
+use axum::Json;
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+  backend::auth::{
+    api_token::{ACCOUNT_SCOPE, generate_api_token},
+    jwt_auth::JwtAuth,
+    token::hash_token,
+  },
+  bail,
+  db::{
+    init::Connection, permission::has_permission, tables::ConnectionExt,
+    tables::api_token::ApiTokenInfo,
+  },
+  error::Result,
+};
+
+pub fn list_api_tokens_route() -> ApiMethodRouter<()> {
+  get_with(list_api_tokens, |op| op.id("listApiTokens"))
+}
+
+pub fn create_api_token_route() -> ApiMethodRouter<()> {
+  post_with(create_api_token, |op| op.id("createApiToken"))
+}
+
+pub fn delete_api_token_route() -> ApiMethodRouter<()> {
+  delete_with(delete_api_token, |op| op.id("deleteApiToken"))
+}
+
+async fn list_api_tokens(auth: JwtAuth, db: Connection) -> Result<Json<Vec<ApiTokenInfo>>> {
+  Ok(Json(db.api_token().list_user_tokens(auth.user_id).await?))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct CreateApiTokenRequest {
+  name: String,
+  /// Must be a subset of the permissions the user currently has, apart from
+  /// `account:self` which allows managing the own account.
+  permissions: Vec<String>,
+  exp: Option<DateTime<Utc>>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct CreateApiTokenResponse {
+  uuid: Uuid,
+  /// Only returned once, the server only keeps a hash.
+  token: String,
+}
+
+async fn create_api_token(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<CreateApiTokenRequest>,
+) -> Result<Json<CreateApiTokenResponse>> {
+  if auth.api_token.is_some() {
+    bail!(FORBIDDEN, "API tokens cannot create other API tokens");
+  }
+  if req.name.trim().is_empty() {
+    bail!(BAD_REQUEST, "Name cannot be empty");
+  }
+  if req.exp.is_some_and(|exp| exp <= Utc::now()) {
+    bail!(BAD_REQUEST, "Expiry must be in the future");
+  }
+
+  let user_permissions = db.group().get_user_permissions(auth.user_id).await?;
+  if let Some(missing) = req
+    .permissions
+    .iter()
+    .find(|p| *p != ACCOUNT_SCOPE && !has_permission(&user_permissions, p))
+  {
+    bail!(FORBIDDEN, "Missing permission {missing}");
+  }
+
+  let mut permissions = req.permissions;
+  permissions.sort();
+  permissions.dedup();
+
+  let token = generate_api_token();
+  let uuid = db
+    .api_token()
+    .create_api_token(
+      auth.user_id,
+      req.name.trim().to_string(),
+      hash_token(&token),
+      permissions,
+      req.exp.map(|exp| exp.naive_utc()),
+    )
+    .await?;
+
+  Ok(Json(CreateApiTokenResponse { uuid, token }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeleteApiTokenRequest {
+  uuid: Uuid,
+}
+
+async fn delete_api_token(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<DeleteApiTokenRequest>,
+) -> Result<()> {
+  if !db
+    .api_token()
+    .delete_api_token(auth.user_id, req.uuid)
+    .await?
+  {
+    bail!(NOT_FOUND, "API token not found");
+  }
+
+  Ok(())
+}
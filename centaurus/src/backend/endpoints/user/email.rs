@@ -1,15 +1,14 @@
-use std::{sync::Arc, time::Instant};
+use std::time::Duration;
 
 use aide::{
   OperationIo,
   axum::routing::{ApiMethodRouter, post_with},
 };
 use axum::{Extension, Json, extract::FromRequestParts};
-use dashmap::DashMap;
 use rand::{RngExt, distr::Uniform};
 use schemars::JsonSchema;
-use serde::Deserialize;
-use tokio::spawn;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::{
@@ -17,12 +16,14 @@ use crate::{
     auth::{jwt_auth::JwtAuth, permission::UserEdit},
     config::SiteConfig,
     endpoints::{
+      audit::{Audit, AuditEvent},
       mail::template::confirm_code,
       websocket::state::{UpdateMessage, Updater},
     },
+    store::Store,
   },
   bail,
-  db::{init::Connection, tables::ConnectionExt},
+  db::{init::Connection, permission::has_permission, tables::ConnectionExt},
   error::Result,
   mail::Mailer,
 };
@@ -58,6 +59,7 @@ async fn change_user_email<T: UpdateMessage>(
   auth: JwtAuth<UserEdit>,
   db: Connection,
   updater: Updater<T>,
+  audit: Audit,
   Json(req): Json<ChangeUserEmail>,
 ) -> Result<()> {
   if req.new_email.is_empty() {
@@ -69,7 +71,7 @@ async fn change_user_email<T: UpdateMessage>(
 
   if target_permissions
     .iter()
-    .any(|p| !self_permissions.contains(p))
+    .any(|p| !has_permission(&self_permissions, p))
   {
     bail!(
       FORBIDDEN,
@@ -86,42 +88,45 @@ async fn change_user_email<T: UpdateMessage>(
     bail!(CONFLICT, "Email is already in use");
   }
 
-  db.user().change_email(req.uuid, req.new_email).await?;
+  let txn = db.begin().await?;
+  txn
+    .user()
+    .change_email(req.uuid, req.new_email.clone())
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.change_email")
+        .target("user", req.uuid)
+        .before(&serde_json::json!({ "email": user.email }))
+        .after(&serde_json::json!({ "email": req.new_email })),
+    )
+    .await?;
+  txn.commit().await?;
   updater.broadcast(T::user(req.uuid)).await;
 
   Ok(())
 }
 
+const NAMESPACE: &str = "email_change";
+const CHANGE_TTL: Duration = Duration::from_secs(600);
+
+#[derive(Serialize, Deserialize)]
 struct ChangeInfo {
   new_email: String,
   new_code: String,
   old_code: String,
-  created: Instant,
 }
 
 #[derive(Clone, FromRequestParts, OperationIo)]
 #[from_request(via(Extension))]
 pub struct EmailChangeState {
-  changes: Arc<DashMap<Uuid, ChangeInfo>>,
+  store: Store,
 }
 
 impl EmailChangeState {
-  pub fn init() -> Self {
-    let changes: Arc<DashMap<Uuid, ChangeInfo>> = Arc::new(DashMap::new());
-
-    spawn({
-      let changes = Arc::clone(&changes);
-
-      async move {
-        loop {
-          let now = Instant::now();
-          changes.retain(|_, data| now.duration_since(data.created).as_secs() < 600);
-          tokio::time::sleep(tokio::time::Duration::from_secs(60)).await;
-        }
-      }
-    });
-
-    Self { changes }
+  pub fn init(store: Store) -> Self {
+    Self { store }
   }
 }
 
@@ -146,7 +151,6 @@ async fn start_email_change(
     new_email: req.new_email.clone(),
     new_code: gen_code(),
     old_code: gen_code(),
-    created: Instant::now(),
   };
 
   let user = db.user().get_user_by_id(auth.user_id).await?;
@@ -172,7 +176,10 @@ async fn start_email_change(
     )
     .await?;
 
-  state.changes.insert(auth.user_id, change);
+  state
+    .store
+    .set_json(NAMESPACE, &auth.user_id.to_string(), &change, CHANGE_TTL)
+    .await?;
 
   Ok(())
 }
@@ -190,7 +197,8 @@ async fn confirm_email_change<T: UpdateMessage>(
   updater: Updater<T>,
   Json(req): Json<EmailChangeConfirm>,
 ) -> Result<()> {
-  let Some(change) = state.changes.get(&auth.user_id) else {
+  let key = auth.user_id.to_string();
+  let Some(change) = state.store.get_json::<ChangeInfo>(NAMESPACE, &key).await? else {
     bail!(NOT_FOUND, "No email change request found");
   };
 
@@ -203,11 +211,9 @@ async fn confirm_email_change<T: UpdateMessage>(
   }
 
   db.user()
-    .change_email(auth.user_id, change.new_email.clone())
+    .change_email(auth.user_id, change.new_email)
     .await?;
-
-  drop(change);
-  state.changes.remove(&auth.user_id);
+  state.store.remove(NAMESPACE, &key).await?;
 
   updater.broadcast(T::user(auth.user_id)).await;
 
@@ -0,0 +1,85 @@
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with};
+use axum::Json;
+use chrono::NaiveDateTime;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  backend::auth::{jwt_auth::JwtAuth, settings::DEFAULT_PROVIDER},
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::Result,
+};
+
+pub fn list_identities_route() -> ApiMethodRouter<()> {
+  get_with(list_identities, |op| op.id("listIdentities"))
+}
+
+pub fn unlink_identity_route() -> ApiMethodRouter<()> {
+  delete_with(unlink_identity, |op| op.id("unlinkIdentity"))
+}
+
+#[derive(Serialize, JsonSchema)]
+struct IdentityInfo {
+  provider: String,
+  /// Not known for the default provider.
+  created: Option<NaiveDateTime>,
+}
+
+async fn list_identities(auth: JwtAuth, db: Connection) -> Result<Json<Vec<IdentityInfo>>> {
+  let user = db.user().get_user_by_id(auth.user_id).await?;
+
+  let mut identities = Vec::new();
+  if user.oidc_subject.is_some() {
+    identities.push(IdentityInfo {
+      provider: DEFAULT_PROVIDER.to_string(),
+      created: None,
+    });
+  }
+  identities.extend(
+    db.oidc_identity()
+      .list_identities(user.id)
+      .await?
+      .into_iter()
+      .map(|identity| IdentityInfo {
+        provider: identity.provider,
+        created: Some(identity.created),
+      }),
+  );
+
+  Ok(Json(identities))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct UnlinkIdentityRequest {
+  provider: String,
+}
+
+async fn unlink_identity(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<UnlinkIdentityRequest>,
+) -> Result<()> {
+  let user = db.user().get_user_by_id(auth.user_id).await?;
+  let identities = db.oidc_identity().list_identities(user.id).await?;
+
+  let remaining = identities.len() + usize::from(user.oidc_subject.is_some());
+  // oidc users have no password to fall back to
+  if user.oidc_user && remaining <= 1 {
+    bail!(CONFLICT, "Cannot unlink the last login method");
+  }
+
+  if req.provider == DEFAULT_PROVIDER {
+    if user.oidc_subject.is_none() {
+      bail!(NOT_FOUND, "Identity not found");
+    }
+    return db.user().clear_oidc_subject(user.id).await;
+  }
+
+  let Some(identity) = identities.iter().find(|i| i.provider == req.provider) else {
+    bail!(NOT_FOUND, "Identity not found");
+  };
+  db.oidc_identity().unlink(user.id, identity.id).await?;
+
+  Ok(())
+}
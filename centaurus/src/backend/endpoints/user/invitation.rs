@@ -0,0 +1,404 @@
+use aide::OperationIo;
+use aide::axum::ApiRouter;
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with};
+use argon2::password_hash::SaltString;
+use axum::{Extension, Json, extract::FromRequestParts};
+use chrono::{DateTime, Utc};
+use rsa::rand_core::OsRng;
+use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::backend::auth::jwt_auth::JwtAuth;
+use crate::backend::auth::password_policy::check_password;
+use crate::backend::auth::permission::{UserEdit, UserView};
+use crate::backend::auth::pw_state::PasswordState;
+use crate::backend::auth::token::{generate_token, hash_token};
+use crate::backend::config::SiteConfig;
+use crate::backend::endpoints::audit::{Audit, AuditEvent};
+use crate::backend::endpoints::user::template;
+use crate::backend::endpoints::websocket::state::{UpdateMessage, Updater};
+use crate::backend::middleware::rate_limiter::RateLimiter;
+use crate::bail;
+use crate::db::entities::invitation;
+use crate::db::init::Connection;
+use crate::db::permission::has_permission;
+use crate::db::tables::ConnectionExt;
+use crate::db::tables::invitation::InvitationInfo;
+use crate::error::Result;
+use crate::mail::Mailer;
+
+const TOKEN_LENGTH: usize = 64;
+
+/// Invitation links carry a random token, only its hash is stored. Sending an
+/// invitation again replaces the token.
+#[derive(FromRequestParts, Clone, OperationIo)]
+#[from_request(via(Extension))]
+pub struct InvitationState {
+  expiration: i64,
+}
+
+impl InvitationState {
+  /// Links are valid for `expiration` seconds.
+  pub fn init(expiration: i64) -> Self {
+    Self { expiration }
+  }
+
+  fn expires(&self) -> DateTime<Utc> {
+    Utc::now() + chrono::Duration::seconds(self.expiration)
+  }
+
+  /// Open invitation the link was issued for. Expired ones resolve to `None`.
+  pub async fn resolve(&self, db: &Connection, token: &str) -> Result<Option<invitation::Model>> {
+    db.invitation()
+      .get_invitation_by_token(&hash_token(token))
+      .await
+  }
+}
+
+/// Routes used by the invited user, the token is the only credential.
+pub fn router<T: UpdateMessage>(rate_limiter: &mut RateLimiter) -> ApiRouter {
+  ApiRouter::new()
+    .api_route("/info", invitation_info_route())
+    .api_route("/accept", accept_invitation_route::<T>())
+    .layer(rate_limiter.create_limiter("invitation"))
+}
+
+pub fn list_invitations_route() -> ApiMethodRouter<()> {
+  get_with(list_invitations, |op| op.id("listInvitations"))
+}
+
+pub fn create_invitation_route() -> ApiMethodRouter<()> {
+  post_with(create_invitation, |op| op.id("createInvitation"))
+}
+
+pub fn resend_invitation_route() -> ApiMethodRouter<()> {
+  post_with(resend_invitation, |op| op.id("resendInvitation"))
+}
+
+pub fn revoke_invitation_route() -> ApiMethodRouter<()> {
+  delete_with(revoke_invitation, |op| op.id("revokeInvitation"))
+}
+
+pub fn invitation_info_route() -> ApiMethodRouter<()> {
+  post_with(invitation_info, |op| op.id("invitationInfo"))
+}
+
+pub fn accept_invitation_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(accept_invitation_with_password::<T>, |op| {
+    op.id("acceptInvitation")
+  })
+}
+
+async fn list_invitations(
+  _auth: JwtAuth<UserView>,
+  db: Connection,
+) -> Result<Json<Vec<InvitationInfo>>> {
+  Ok(Json(db.invitation().list_invitations().await?))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct CreateInvitation {
+  name: String,
+  email: String,
+  groups: Vec<Uuid>,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct InvitationResponse {
+  uuid: Uuid,
+  /// Only returned if the mail service is not active, the link then has to be
+  /// handed out by the admin.
+  link: Option<String>,
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn create_invitation(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  audit: Audit,
+  mailer: Mailer,
+  state: InvitationState,
+  config: SiteConfig,
+  Json(req): Json<CreateInvitation>,
+) -> Result<Json<InvitationResponse>> {
+  let name = req.name.trim().to_string();
+  let email = req.email.trim().to_string();
+  if name.is_empty() {
+    bail!(BAD_REQUEST, "Name cannot be empty");
+  }
+  if email.is_empty() {
+    bail!(BAD_REQUEST, "Email cannot be empty");
+  }
+  if db.user().try_get_user_by_email(&email).await?.is_some() {
+    bail!(CONFLICT, "User with this email already exists");
+  }
+  if db
+    .invitation()
+    .try_get_invitation_by_email(&email)
+    .await?
+    .is_some()
+  {
+    bail!(CONFLICT, "This email has already been invited");
+  }
+
+  let groups = db.group().list_groups_simple().await?;
+  if req
+    .groups
+    .iter()
+    .any(|id| !groups.iter().any(|group| group.uuid == *id))
+  {
+    bail!(BAD_REQUEST, "Unknown group");
+  }
+  check_group_permissions(&db, auth.user_id, req.groups.clone()).await?;
+
+  let token = generate_token(TOKEN_LENGTH);
+  let txn = db.begin().await?;
+  let uuid = txn
+    .invitation()
+    .create_invitation(
+      name.clone(),
+      email.clone(),
+      hash_token(&token),
+      req.groups,
+      state.expires(),
+    )
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "invitation.create")
+        .target("invitation", uuid)
+        .after(&txn.invitation().invitation_info(uuid).await?),
+    )
+    .await?;
+  txn.commit().await?;
+  let link = send_invitation(&mailer, &config, name, email, &token).await?;
+
+  Ok(Json(InvitationResponse { uuid, link }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct InvitationRequest {
+  uuid: Uuid,
+}
+
+/// Sends a new link, links sent before stop working. Expired invitations can be
+/// sent again as well.
+async fn resend_invitation(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  audit: Audit,
+  mailer: Mailer,
+  state: InvitationState,
+  config: SiteConfig,
+  Json(req): Json<InvitationRequest>,
+) -> Result<Json<InvitationResponse>> {
+  let Some(invitation) = db.invitation().get_invitation(req.uuid).await? else {
+    bail!(NOT_FOUND, "Invitation not found");
+  };
+  let groups = db.invitation().get_invitation_groups(invitation.id).await?;
+  check_group_permissions(&db, auth.user_id, groups).await?;
+
+  let token = generate_token(TOKEN_LENGTH);
+  let txn = db.begin().await?;
+  txn
+    .invitation()
+    .renew_token(invitation.id, hash_token(&token), state.expires())
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "invitation.resend").target("invitation", req.uuid),
+    )
+    .await?;
+  txn.commit().await?;
+  let link = send_invitation(&mailer, &config, invitation.name, invitation.email, &token).await?;
+
+  Ok(Json(InvitationResponse {
+    uuid: req.uuid,
+    link,
+  }))
+}
+
+async fn revoke_invitation(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<InvitationRequest>,
+) -> Result<()> {
+  let Some(before) = db.invitation().invitation_info(req.uuid).await? else {
+    bail!(NOT_FOUND, "Invitation not found");
+  };
+  let groups = db.invitation().get_invitation_groups(req.uuid).await?;
+  check_group_permissions(&db, auth.user_id, groups).await?;
+  let txn = db.begin().await?;
+  txn.invitation().delete_invitation(req.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "invitation.revoke")
+        .target("invitation", req.uuid)
+        .before(&before),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(())
+}
+
+/// Invitations to groups with permissions the editor does not have can't be
+/// managed by them, accepting one would grant these permissions.
+async fn check_group_permissions(db: &Connection, editor: Uuid, groups: Vec<Uuid>) -> Result<()> {
+  let self_permissions = db.group().get_user_permissions(editor).await?;
+  let target_permissions = db.group().get_groups_permissions(groups).await?;
+  if target_permissions
+    .iter()
+    .any(|p| !has_permission(&self_permissions, p))
+  {
+    bail!(
+      FORBIDDEN,
+      "Cannot assign permissions that the editor does not have"
+    );
+  }
+
+  Ok(())
+}
+
+/// Mails the link if the mail service is active, otherwise it is returned.
+async fn send_invitation(
+  mailer: &Mailer,
+  config: &SiteConfig,
+  name: String,
+  email: String,
+  token: &str,
+) -> Result<Option<String>> {
+  let mut link = config.site_url.clone();
+  if let Ok(segments) = &mut link.path_segments_mut() {
+    segments.pop_if_empty();
+    segments.push("invite");
+  }
+  link.query_pairs_mut().append_pair("token", token);
+
+  if !mailer.is_active().await {
+    return Ok(Some(link.to_string()));
+  }
+
+  mailer
+    .send_mail(
+      name,
+      email,
+      "You have been invited".to_string(),
+      template::invitation(link.as_str(), config.site_url.as_str()),
+    )
+    .await?;
+
+  Ok(None)
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct InvitationToken {
+  token: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct InvitationDetails {
+  name: String,
+  email: String,
+}
+
+/// Shown on the page of the link before the user picks a password or a
+/// provider.
+async fn invitation_info(
+  db: Connection,
+  state: InvitationState,
+  Json(req): Json<InvitationToken>,
+) -> Result<Json<InvitationDetails>> {
+  let Some(invitation) = state.resolve(&db, &req.token).await? else {
+    bail!(NOT_FOUND, "Invitation not found or expired");
+  };
+
+  Ok(Json(InvitationDetails {
+    name: invitation.name,
+    email: invitation.email,
+  }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct AcceptInvitation {
+  token: String,
+  password: String,
+}
+
+async fn accept_invitation_with_password<T: UpdateMessage>(
+  db: Connection,
+  state: InvitationState,
+  pw: PasswordState,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(req): Json<AcceptInvitation>,
+) -> Result<()> {
+  let Some(invitation) = state.resolve(&db, &req.token).await? else {
+    bail!(NOT_FOUND, "Invitation not found or expired");
+  };
+
+  let password = pw.decrypt_password(&req.password)?;
+  check_password(&db, &pw, None, &password).await?;
+  let password_hash = pw.pw_hash_raw(&password)?;
+
+  accept_invitation(&db, &audit, &updater, invitation, password_hash, None).await?;
+
+  Ok(())
+}
+
+/// Creates the invited user with the groups of the invitation and deletes the
+/// invitation. Users without a password hash log in through OIDC.
+pub(crate) async fn accept_invitation<T: UpdateMessage>(
+  db: &Connection,
+  audit: &Audit,
+  updater: &Updater<T>,
+  invitation: invitation::Model,
+  password_hash: String,
+  oidc_subject: Option<String>,
+) -> Result<Uuid> {
+  if db
+    .user()
+    .try_get_user_by_email(&invitation.email)
+    .await?
+    .is_some()
+  {
+    bail!(CONFLICT, "User with this email already exists");
+  }
+
+  let groups = db.invitation().get_invitation_groups(invitation.id).await?;
+  let oidc_user = password_hash.is_empty();
+  let txn = db.begin().await?;
+  // the unique email makes sure the invitation is only accepted once
+  let user_id = txn
+    .user()
+    .create_user(
+      invitation.name,
+      invitation.email,
+      password_hash,
+      SaltString::generate(OsRng {}).to_string(),
+      oidc_user,
+      oidc_subject,
+    )
+    .await?;
+  txn.group().add_user_to_groups(user_id, groups).await?;
+  txn.invitation().delete_invitation(invitation.id).await?;
+
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(user_id, "invitation.accept")
+        .target("user", user_id)
+        .after(&txn.user().user_info(user_id).await?),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::user(user_id)).await;
+
+  Ok(user_id)
+}
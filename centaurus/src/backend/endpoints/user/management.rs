@@ -7,18 +7,31 @@ use http::StatusCode;
 use rand::RngExt;
 use rsa::rand_core::OsRng;
 use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
 use serde::{Deserialize, Serialize};
 use uuid::Uuid;
 
 use crate::backend::auth::jwt_auth::JwtAuth;
+use crate::backend::auth::password_policy::check_password;
 use crate::backend::auth::permission::{UserEdit, UserView};
 use crate::backend::auth::pw_state::PasswordState;
 use crate::backend::config::SiteConfig;
+use crate::backend::endpoints::audit::{Audit, AuditEvent};
 use crate::backend::endpoints::user::email::change_email_route;
+use crate::backend::endpoints::user::invitation::{
+  create_invitation_route, list_invitations_route, resend_invitation_route, revoke_invitation_route,
+};
+use crate::backend::endpoints::user::service_account::{
+  create_service_account_route, delete_service_account_route, edit_service_account_route,
+  list_service_accounts_route, rotate_client_secret_route,
+};
+use crate::backend::endpoints::user::session::revoke_user_sessions_route;
 use crate::backend::endpoints::user::template;
+use crate::backend::endpoints::user::totp::reset_user_totp_route;
 use crate::backend::endpoints::websocket::state::{UpdateMessage, Updater};
 use crate::bail;
 use crate::db::init::Connection;
+use crate::db::permission::has_permission;
 use crate::db::tables::ConnectionExt;
 use crate::db::tables::user::{DetailUserInfo, SimpleGroupInfo, UserListInfo};
 use crate::error::{ErrorReportStatusExt, Result};
@@ -43,6 +56,18 @@ pub fn router<T: UpdateMessage>() -> ApiRouter {
     .api_route("/password", reset_user_password_route())
     .api_route("/email", change_email_route::<T>())
     .api_route("/convert-oidc", convert_oidc_user_route::<T>())
+    .api_route("/sessions", revoke_user_sessions_route())
+    .api_route("/totp", reset_user_totp_route())
+    .api_route("/unlock", unlock_user_route::<T>())
+    .api_route("/service_accounts", list_service_accounts_route())
+    .api_route("/service_accounts", create_service_account_route::<T>())
+    .api_route("/service_accounts", edit_service_account_route::<T>())
+    .api_route("/service_accounts", delete_service_account_route::<T>())
+    .api_route("/service_accounts/secret", rotate_client_secret_route())
+    .api_route("/invitations", list_invitations_route())
+    .api_route("/invitations", create_invitation_route())
+    .api_route("/invitations", revoke_invitation_route())
+    .api_route("/invitations/resend", resend_invitation_route())
 }
 
 pub fn list_users_route() -> ApiMethodRouter<()> {
@@ -86,6 +111,10 @@ pub fn convert_oidc_user_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
   put_with(convert_oidc_user::<T>, |op| op.id("convertOidcUser"))
 }
 
+pub fn unlock_user_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(unlock_user::<T>, |op| op.id("unlockUser"))
+}
+
 async fn list_users(_auth: JwtAuth<UserView>, db: Connection) -> Result<Json<Vec<UserListInfo>>> {
   let users = db.user().list_users().await?;
   Ok(Json(users))
@@ -130,10 +159,12 @@ struct CreateUserResponse {
   uuid: Uuid,
 }
 
+#[allow(clippy::too_many_arguments)]
 async fn create_user<T: UpdateMessage>(
-  _auth: JwtAuth<UserEdit>,
+  auth: JwtAuth<UserEdit>,
   db: Connection,
   updater: Updater<T>,
+  audit: Audit,
   mailer: Mailer,
   state: PasswordState,
   config: SiteConfig,
@@ -162,7 +193,9 @@ async fn create_user<T: UpdateMessage>(
   } else if let Some(pw) = req.password {
     let bytes = BASE64_STANDARD.decode(pw).status(StatusCode::BAD_REQUEST)?;
     let pw_bytes = state.decrypt(&bytes).status(StatusCode::BAD_REQUEST)?;
-    String::from_utf8_lossy(&pw_bytes).to_string()
+    let password = String::from_utf8_lossy(&pw_bytes).to_string();
+    check_password(&db, &state, None, &password).await?;
+    password
   } else {
     bail!(
       BAD_REQUEST,
@@ -171,9 +204,10 @@ async fn create_user<T: UpdateMessage>(
   };
 
   let salt = SaltString::generate(OsRng {}).to_string();
-  let password_hash = state.pw_hash_raw(&salt, &password)?;
+  let password_hash = state.pw_hash_raw(&password)?;
 
-  let user_id = db
+  let txn = db.begin().await?;
+  let user_id = txn
     .user()
     .create_user(
       req.name.clone(),
@@ -184,6 +218,15 @@ async fn create_user<T: UpdateMessage>(
       None,
     )
     .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.create")
+        .target("user", user_id)
+        .after(&txn.user().user_info(user_id).await?),
+    )
+    .await?;
+  txn.commit().await?;
   if mailer.is_active().await {
     let subject = "Your new account";
     mailer
@@ -209,6 +252,7 @@ async fn delete_user<T: UpdateMessage>(
   auth: JwtAuth<UserEdit>,
   db: Connection,
   updater: Updater<T>,
+  audit: Audit,
   Json(data): Json<DeleteUserRequest>,
 ) -> Result<()> {
   let Some(admin_group) = db.setup().get_admin_group_id().await? else {
@@ -228,7 +272,18 @@ async fn delete_user<T: UpdateMessage>(
     );
   }
 
-  db.user().delete_user(data.uuid).await?;
+  let before = db.user().user_info(data.uuid).await?;
+  let txn = db.begin().await?;
+  txn.user().delete_user(data.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.delete")
+        .target("user", data.uuid)
+        .before(&before),
+    )
+    .await?;
+  txn.commit().await?;
   updater.broadcast(T::user(data.uuid)).await;
 
   Ok(())
@@ -253,6 +308,7 @@ async fn edit_user<T: UpdateMessage>(
   auth: JwtAuth<UserEdit>,
   db: Connection,
   updater: Updater<T>,
+  audit: Audit,
   Json(req): Json<UserEditReq>,
 ) -> Result<()> {
   if req.name.trim().is_empty() {
@@ -268,10 +324,10 @@ async fn edit_user<T: UpdateMessage>(
 
   if target_permissions
     .iter()
-    .any(|p| !self_permissions.contains(p))
+    .any(|p| !has_permission(&self_permissions, p))
     || current_user_permissions
       .iter()
-      .any(|p| !self_permissions.contains(p))
+      .any(|p| !has_permission(&self_permissions, p))
   {
     bail!(
       FORBIDDEN,
@@ -287,11 +343,22 @@ async fn edit_user<T: UpdateMessage>(
     bail!(CONFLICT, "Cannot remove the last user from the admin group");
   }
 
-  let Some(_user) = db.user().user_info(req.uuid).await? else {
+  let Some(before) = db.user().user_info(req.uuid).await? else {
     bail!(NOT_FOUND, "User not found");
   };
 
-  db.user().edit_user(req.uuid, req.name, req.groups).await?;
+  let txn = db.begin().await?;
+  txn.user().edit_user(req.uuid, req.name, req.groups).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.edit")
+        .target("user", req.uuid)
+        .before(&before)
+        .after(&txn.user().user_info(req.uuid).await?),
+    )
+    .await?;
+  txn.commit().await?;
   updater.broadcast(T::user(req.uuid)).await;
 
   Ok(())
@@ -305,12 +372,21 @@ struct UserAvatarResetRequest {
 
 #[cfg(feature = "avatar")]
 async fn reset_user_avatar<T: UpdateMessage>(
-  _auth: JwtAuth<UserEdit>,
+  auth: JwtAuth<UserEdit>,
   db: Connection,
   updater: Updater<T>,
+  audit: Audit,
   Json(req): Json<UserAvatarResetRequest>,
 ) -> Result<()> {
-  db.user().reset_avatar(req.uuid).await?;
+  let txn = db.begin().await?;
+  txn.user().reset_avatar(req.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.reset_avatar").target("user", req.uuid),
+    )
+    .await?;
+  txn.commit().await?;
   updater.broadcast(T::user(req.uuid)).await;
 
   Ok(())
@@ -326,6 +402,7 @@ async fn reset_user_password(
   auth: JwtAuth<UserEdit>,
   db: Connection,
   state: PasswordState,
+  audit: Audit,
   Json(req): Json<ResetUserPassword>,
 ) -> Result<()> {
   let self_permissions = db.group().get_user_permissions(auth.user_id).await?;
@@ -333,7 +410,7 @@ async fn reset_user_password(
 
   if target_permissions
     .iter()
-    .any(|p| !self_permissions.contains(p))
+    .any(|p| !has_permission(&self_permissions, p))
   {
     bail!(
       FORBIDDEN,
@@ -347,8 +424,22 @@ async fn reset_user_password(
     bail!(BAD_REQUEST, "Cannot reset password for an OIDC user");
   }
 
-  let hash = state.pw_hash(&user.salt, &req.new_password)?;
-  db.user().update_user_password(req.uuid, hash).await?;
+  if user.service_account {
+    bail!(BAD_REQUEST, "Service accounts have no password");
+  }
+
+  let new_password = state.decrypt_password(&req.new_password)?;
+  check_password(&db, &state, Some(&user), &new_password).await?;
+  let hash = state.pw_hash_raw(&new_password)?;
+  let txn = db.begin().await?;
+  txn.user().update_user_password(req.uuid, hash).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.reset_password").target("user", req.uuid),
+    )
+    .await?;
+  txn.commit().await?;
 
   Ok(())
 }
@@ -358,6 +449,7 @@ async fn convert_oidc_user<T: UpdateMessage>(
   db: Connection,
   state: PasswordState,
   updater: Updater<T>,
+  audit: Audit,
   Json(req): Json<ResetUserPassword>,
 ) -> Result<()> {
   let self_permissions = db.group().get_user_permissions(auth.user_id).await?;
@@ -365,7 +457,7 @@ async fn convert_oidc_user<T: UpdateMessage>(
 
   if target_permissions
     .iter()
-    .any(|p| !self_permissions.contains(p))
+    .any(|p| !has_permission(&self_permissions, p))
   {
     bail!(FORBIDDEN, "Cannot convert user with higher permissions");
   }
@@ -376,11 +468,67 @@ async fn convert_oidc_user<T: UpdateMessage>(
     bail!(BAD_REQUEST, "Cannot convert a non-OIDC user");
   }
 
-  let hash = state.pw_hash(&user.salt, &req.new_password)?;
-  db.user().update_user_password(req.uuid, hash).await?;
-  db.user().to_local_user(req.uuid).await?;
+  let new_password = state.decrypt_password(&req.new_password)?;
+  check_password(&db, &state, Some(&user), &new_password).await?;
+  let hash = state.pw_hash_raw(&new_password)?;
+  let txn = db.begin().await?;
+  txn.user().update_user_password(req.uuid, hash).await?;
+  txn.user().to_local_user(req.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.convert_oidc").target("user", req.uuid),
+    )
+    .await?;
+  txn.commit().await?;
 
   updater.broadcast(T::user(req.uuid)).await;
 
   Ok(())
 }
+
+#[derive(Deserialize, JsonSchema)]
+struct UnlockUserRequest {
+  uuid: Uuid,
+}
+
+/// Lift a lockout after too many failed logins and forget the failures.
+async fn unlock_user<T: UpdateMessage>(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(req): Json<UnlockUserRequest>,
+) -> Result<()> {
+  if db.user().user_info(req.uuid).await?.is_none() {
+    bail!(NOT_FOUND, "User not found");
+  }
+
+  let self_permissions = db.group().get_user_permissions(auth.user_id).await?;
+  let target_permissions = db.group().get_user_permissions(req.uuid).await?;
+
+  if target_permissions
+    .iter()
+    .any(|p| !has_permission(&self_permissions, p))
+  {
+    bail!(FORBIDDEN, "Cannot unlock a user with higher permissions");
+  }
+
+  let txn = db.begin().await?;
+  let unlocked = txn.login_attempt().reset(req.uuid).await?;
+  if unlocked {
+    audit
+      .record(
+        &txn,
+        AuditEvent::new(auth.user_id, "user.unlock").target("user", req.uuid),
+      )
+      .await?;
+  }
+  txn.commit().await?;
+
+  if unlocked {
+    updater.broadcast(T::user(req.uuid)).await;
+  }
+
+  Ok(())
+}
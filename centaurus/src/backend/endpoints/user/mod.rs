@@ -1,23 +1,33 @@
 use crate::backend::{
   endpoints::{user::email::EmailChangeState, websocket::state::UpdateMessage},
   middleware::rate_limiter::RateLimiter,
+  store::Store,
 };
 use aide::axum::ApiRouter;
 use axum::Extension;
 
 pub mod account;
+pub mod api_token;
 pub mod email;
+pub mod identity;
 pub mod info;
+pub mod invitation;
 pub mod management;
+pub mod pake;
+pub mod passkey;
+pub mod service_account;
+pub mod session;
 pub mod template;
+pub mod totp;
 
 pub fn router<T: UpdateMessage>(rate_limiter: &mut RateLimiter) -> ApiRouter {
   ApiRouter::new()
     .nest("/account", account::router::<T>(rate_limiter))
     .nest("/info", info::router())
+    .nest("/invitation", invitation::router::<T>(rate_limiter))
     .nest("/management", management::router::<T>())
 }
 
-pub fn state(router: ApiRouter) -> ApiRouter {
-  router.layer(Extension(EmailChangeState::init()))
+pub fn state(router: ApiRouter, store: &Store) -> ApiRouter {
+  router.layer(Extension(EmailChangeState::init(store.clone())))
 }
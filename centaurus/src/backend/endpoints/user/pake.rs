@@ -0,0 +1,84 @@
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with};
+use axum::Json;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+  backend::auth::{
+    jwt_auth::JwtAuth,
+    pake::validate_verifier,
+    pw_state::{PasswordState, Verified},
+  },
+  bail,
+  db::{init::Connection, tables::ConnectionExt},
+  error::Result,
+};
+
+pub fn pake_status_route() -> ApiMethodRouter<()> {
+  get_with(pake_status, |op| op.id("pakeStatus"))
+}
+
+pub fn enable_pake_route() -> ApiMethodRouter<()> {
+  post_with(enable_pake, |op| op.id("enablePake"))
+}
+
+pub fn disable_pake_route() -> ApiMethodRouter<()> {
+  delete_with(disable_pake, |op| op.id("disablePake"))
+}
+
+#[derive(Serialize, JsonSchema)]
+struct PakeStatus {
+  enabled: bool,
+}
+
+async fn pake_status(auth: JwtAuth, db: Connection) -> Result<Json<PakeStatus>> {
+  let enabled = db
+    .pake_verifier()
+    .get_verifier(auth.user_id)
+    .await?
+    .is_some();
+
+  Ok(Json(PakeStatus { enabled }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct EnablePake {
+  old_password: String,
+  /// Base64 salt of at least 16 bytes.
+  salt: String,
+  /// Verifier `v = g^x`, computed by the client from the password and salt.
+  verifier: String,
+}
+
+/// Store the verifier for PAKE logins, the client derives it so the password
+/// is not sent. The verifier can't be checked against the password policy,
+/// the current password is required instead. A password change removes the
+/// verifier again.
+async fn enable_pake(
+  auth: JwtAuth,
+  db: Connection,
+  state: PasswordState,
+  Json(req): Json<EnablePake>,
+) -> Result<()> {
+  let user = db.user().get_user_by_id(auth.user_id).await?;
+  if user.oidc_user || user.service_account {
+    bail!(
+      BAD_REQUEST,
+      "PAKE login is only available for local accounts"
+    );
+  }
+
+  if state.pw_verify(&user.salt, &user.password, &req.old_password)? == Verified::Invalid {
+    bail!(FORBIDDEN, "Old password is incorrect");
+  }
+
+  validate_verifier(&req.salt, &req.verifier)?;
+
+  db.pake_verifier()
+    .set_verifier(user.id, req.salt, req.verifier)
+    .await
+}
+
+async fn disable_pake(auth: JwtAuth, db: Connection) -> Result<()> {
+  db.pake_verifier().remove_verifier(auth.user_id).await
+}
@@ -0,0 +1,71 @@
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, put_with};
+use axum::Json;
+use schemars::JsonSchema;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+  backend::auth::jwt_auth::JwtAuth,
+  bail,
+  db::{init::Connection, tables::ConnectionExt, tables::passkey::PasskeyInfo},
+  error::Result,
+};
+
+pub fn list_passkeys_route() -> ApiMethodRouter<()> {
+  get_with(list_passkeys, |op| op.id("listPasskeys"))
+}
+
+pub fn rename_passkey_route() -> ApiMethodRouter<()> {
+  put_with(rename_passkey, |op| op.id("renamePasskey"))
+}
+
+pub fn delete_passkey_route() -> ApiMethodRouter<()> {
+  delete_with(delete_passkey, |op| op.id("deletePasskey"))
+}
+
+async fn list_passkeys(auth: JwtAuth, db: Connection) -> Result<Json<Vec<PasskeyInfo>>> {
+  Ok(Json(db.passkey().list_user_passkeys(auth.user_id).await?))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct RenamePasskeyRequest {
+  uuid: Uuid,
+  name: String,
+}
+
+async fn rename_passkey(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<RenamePasskeyRequest>,
+) -> Result<()> {
+  if req.name.trim().is_empty() {
+    bail!(BAD_REQUEST, "Name cannot be empty");
+  }
+
+  if !db
+    .passkey()
+    .rename_passkey(auth.user_id, req.uuid, req.name.trim().to_string())
+    .await?
+  {
+    bail!(NOT_FOUND, "Passkey not found");
+  }
+
+  Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct DeletePasskeyRequest {
+  uuid: Uuid,
+}
+
+async fn delete_passkey(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<DeletePasskeyRequest>,
+) -> Result<()> {
+  if !db.passkey().delete_passkey(auth.user_id, req.uuid).await? {
+    bail!(NOT_FOUND, "Passkey not found");
+  }
+
+  Ok(())
+}
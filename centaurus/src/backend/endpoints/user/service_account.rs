@@ -0,0 +1,250 @@
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with, put_with};
+use axum::Json;
+use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    auth::{
+      jwt_auth::JwtAuth,
+      permission::{UserEdit, UserView},
+      token::{generate_token, hash_token},
+    },
+    endpoints::{
+      audit::{Audit, AuditEvent},
+      websocket::state::{UpdateMessage, Updater},
+    },
+  },
+  bail,
+  db::{
+    init::Connection,
+    permission::has_permission,
+    tables::{ConnectionExt, service_account::ServiceAccountInfo},
+  },
+  error::Result,
+};
+
+pub fn list_service_accounts_route() -> ApiMethodRouter<()> {
+  get_with(list_service_accounts, |op| op.id("listServiceAccounts"))
+}
+
+pub fn create_service_account_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  post_with(create_service_account::<T>, |op| {
+    op.id("createServiceAccount")
+  })
+}
+
+pub fn edit_service_account_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  put_with(edit_service_account::<T>, |op| op.id("editServiceAccount"))
+}
+
+pub fn delete_service_account_route<T: UpdateMessage>() -> ApiMethodRouter<()> {
+  delete_with(delete_service_account::<T>, |op| {
+    op.id("deleteServiceAccount")
+  })
+}
+
+pub fn rotate_client_secret_route() -> ApiMethodRouter<()> {
+  post_with(rotate_client_secret, |op| op.id("rotateClientSecret"))
+}
+
+async fn list_service_accounts(
+  _auth: JwtAuth<UserView>,
+  db: Connection,
+) -> Result<Json<Vec<ServiceAccountInfo>>> {
+  Ok(Json(db.service_account().list_service_accounts().await?))
+}
+
+/// The editor must hold every permission the groups grant.
+async fn check_groups(db: &Connection, editor: Uuid, groups: &[Uuid]) -> Result<()> {
+  let self_permissions = db.group().get_user_permissions(editor).await?;
+  let target_permissions = db.group().get_groups_permissions(groups.to_vec()).await?;
+
+  if target_permissions
+    .iter()
+    .any(|p| !has_permission(&self_permissions, p))
+  {
+    bail!(
+      FORBIDDEN,
+      "Cannot assign permissions that the editor does not have"
+    );
+  }
+
+  Ok(())
+}
+
+/// The editor must hold every permission the service account has.
+async fn check_account(db: &Connection, editor: Uuid, account: Uuid) -> Result<()> {
+  let groups: Vec<Uuid> = db
+    .user()
+    .get_user_groups(account)
+    .await?
+    .into_iter()
+    .map(|g| g.uuid)
+    .collect();
+  check_groups(db, editor, &groups).await
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct CreateServiceAccount {
+  name: String,
+  groups: Vec<Uuid>,
+}
+
+#[derive(Serialize, Debug, JsonSchema)]
+struct ClientCredentials {
+  client_id: Uuid,
+  /// Only returned once, the server only keeps a hash.
+  client_secret: String,
+}
+
+async fn create_service_account<T: UpdateMessage>(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(req): Json<CreateServiceAccount>,
+) -> Result<Json<ClientCredentials>> {
+  if req.name.trim().is_empty() {
+    bail!(BAD_REQUEST, "Name cannot be empty");
+  }
+  check_groups(&db, auth.user_id, &req.groups).await?;
+
+  let client_secret = generate_token(48);
+  let txn = db.begin().await?;
+  let client_id = txn
+    .service_account()
+    .create_service_account(
+      req.name.trim().to_string(),
+      req.groups,
+      hash_token(&client_secret),
+    )
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "service_account.create")
+        .target("user", client_id)
+        .after(&txn.user().user_info(client_id).await?),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::user(client_id)).await;
+
+  Ok(Json(ClientCredentials {
+    client_id,
+    client_secret,
+  }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct EditServiceAccount {
+  uuid: Uuid,
+  name: String,
+  groups: Vec<Uuid>,
+}
+
+async fn edit_service_account<T: UpdateMessage>(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(req): Json<EditServiceAccount>,
+) -> Result<()> {
+  if req.name.trim().is_empty() {
+    bail!(BAD_REQUEST, "Name cannot be empty");
+  }
+  if !db.service_account().is_service_account(req.uuid).await? {
+    bail!(NOT_FOUND, "Service account not found");
+  }
+
+  check_account(&db, auth.user_id, req.uuid).await?;
+  check_groups(&db, auth.user_id, &req.groups).await?;
+
+  let before = db.user().user_info(req.uuid).await?;
+  let txn = db.begin().await?;
+  txn
+    .user()
+    .edit_user(req.uuid, req.name.trim().to_string(), req.groups)
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "service_account.edit")
+        .target("user", req.uuid)
+        .before(&before)
+        .after(&txn.user().user_info(req.uuid).await?),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::user(req.uuid)).await;
+
+  Ok(())
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ServiceAccountRequest {
+  uuid: Uuid,
+}
+
+async fn delete_service_account<T: UpdateMessage>(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  updater: Updater<T>,
+  audit: Audit,
+  Json(req): Json<ServiceAccountRequest>,
+) -> Result<()> {
+  if !db.service_account().is_service_account(req.uuid).await? {
+    bail!(NOT_FOUND, "Service account not found");
+  }
+  check_account(&db, auth.user_id, req.uuid).await?;
+
+  let before = db.user().user_info(req.uuid).await?;
+  let txn = db.begin().await?;
+  txn.user().delete_user(req.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "service_account.delete")
+        .target("user", req.uuid)
+        .before(&before),
+    )
+    .await?;
+  txn.commit().await?;
+  updater.broadcast(T::user(req.uuid)).await;
+
+  Ok(())
+}
+
+async fn rotate_client_secret(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<ServiceAccountRequest>,
+) -> Result<Json<ClientCredentials>> {
+  if !db.service_account().is_service_account(req.uuid).await? {
+    bail!(NOT_FOUND, "Service account not found");
+  }
+  check_account(&db, auth.user_id, req.uuid).await?;
+
+  let client_secret = generate_token(48);
+  let txn = db.begin().await?;
+  txn
+    .service_account()
+    .set_client_secret(req.uuid, hash_token(&client_secret))
+    .await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "service_account.rotate_secret").target("user", req.uuid),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(Json(ClientCredentials {
+    client_id: req.uuid,
+    client_secret,
+  }))
+}
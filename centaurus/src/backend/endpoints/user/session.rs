@@ -0,0 +1,107 @@
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with};
+use axum::Json;
+use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
+use serde::Deserialize;
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    auth::{jwt_auth::JwtAuth, permission::UserEdit},
+    endpoints::audit::{Audit, AuditEvent},
+  },
+  bail,
+  db::{
+    init::Connection, permission::has_permission, tables::ConnectionExt,
+    tables::session::SessionListInfo,
+  },
+  error::Result,
+};
+
+pub fn list_sessions_route() -> ApiMethodRouter<()> {
+  get_with(list_sessions, |op| op.id("listSessions"))
+}
+
+pub fn revoke_session_route() -> ApiMethodRouter<()> {
+  delete_with(revoke_session, |op| op.id("revokeSession"))
+}
+
+pub fn revoke_other_sessions_route() -> ApiMethodRouter<()> {
+  post_with(revoke_other_sessions, |op| op.id("revokeOtherSessions"))
+}
+
+pub fn revoke_user_sessions_route() -> ApiMethodRouter<()> {
+  delete_with(revoke_user_sessions, |op| op.id("revokeUserSessions"))
+}
+
+async fn list_sessions(auth: JwtAuth, db: Connection) -> Result<Json<Vec<SessionListInfo>>> {
+  let sessions = db
+    .session()
+    .list_user_sessions(auth.user_id, auth.sid)
+    .await?;
+  Ok(Json(sessions))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct RevokeSessionRequest {
+  uuid: Uuid,
+}
+
+async fn revoke_session(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<RevokeSessionRequest>,
+) -> Result<()> {
+  let Some(session) = db.session().get_session(req.uuid).await? else {
+    bail!(NOT_FOUND, "Session not found");
+  };
+
+  if session.user_id != auth.user_id {
+    bail!(NOT_FOUND, "Session not found");
+  }
+
+  db.session().revoke_session(session.id).await
+}
+
+async fn revoke_other_sessions(auth: JwtAuth, db: Connection) -> Result<()> {
+  db.session()
+    .revoke_user_sessions(auth.user_id, auth.sid)
+    .await
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct RevokeUserSessionsRequest {
+  uuid: Uuid,
+}
+
+async fn revoke_user_sessions(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<RevokeUserSessionsRequest>,
+) -> Result<()> {
+  let self_permissions = db.group().get_user_permissions(auth.user_id).await?;
+  let target_permissions = db.group().get_user_permissions(req.uuid).await?;
+
+  if target_permissions
+    .iter()
+    .any(|p| !has_permission(&self_permissions, p))
+  {
+    bail!(
+      FORBIDDEN,
+      "Cannot revoke sessions of a user with higher permissions"
+    );
+  }
+
+  let txn = db.begin().await?;
+  txn.session().revoke_user_sessions(req.uuid, None).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.revoke_sessions").target("user", req.uuid),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(())
+}
@@ -25,6 +25,39 @@ pub fn init_password(link: &str, password: &str) -> String {
   )
 }
 
+pub fn invitation(invite_link: &str, link: &str) -> String {
+  format!(
+    r#"
+  <!DOCTYPE html>
+  <html lang="en">
+    <head>
+      <meta charset="UTF-8">
+      <meta name="viewport" content="width=device-width, initial-scale=1.0">
+      <title>Invitation</title>
+    </head>
+    <body>
+      <div style="display: flex; flex-direction: column;">
+        <header style="padding: 1rem; display: flex; flex-direction: column; align-items: center; justify-content: center;">
+          <h2 style="margin: 0;">Invitation</h2>
+          <p style="margin: 0;">You have been invited to create an account. Click on the link below to get started</p>
+        </header>
+        <div style="display: flex; align-items: center; justify-content: center;">
+          <a href="{invite_link}">Accept Invitation</a>
+        </div>
+        <div style="display: flex; align-items: center; justify-content: center; flex-direction: column;">
+          <p>Or copy and paste the link below into your browser:</p>
+          <p>{invite_link}</p>
+        </div>
+        <footer style="display: flex; align-items: center; justify-content: center;">
+          <p>Mail send from <a href="{link}">{link}</a></p>
+        </footer>
+      </div>
+    </body>
+  </html>
+  "#
+  )
+}
+
 #[cfg(test)]
 mod tests {
   use super::*;
@@ -36,4 +69,11 @@ mod tests {
     assert!(html.contains(r#"href="https://app""#));
     assert!(html.contains("Initialize Password"));
   }
+
+  #[test]
+  fn test_invitation_embeds_links() {
+    let html = invitation("https://app/invite?token=abc", "https://app");
+    assert!(html.contains(r#"href="https://app/invite?token=abc""#));
+    assert!(html.contains(r#"href="https://app""#));
+  }
 }
@@ -0,0 +1,197 @@
+use aide::axum::routing::{ApiMethodRouter, delete_with, get_with, post_with};
+use axum::Json;
+use schemars::JsonSchema;
+use sea_orm::TransactionTrait;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+  backend::{
+    auth::{
+      jwt_auth::JwtAuth,
+      jwt_state::JwtState,
+      permission::UserEdit,
+      totp::{
+        check_second_factor, generate_recovery_codes, generate_secret, hash_recovery_code,
+        otpauth_uri, verify_code,
+      },
+    },
+    endpoints::audit::{Audit, AuditEvent},
+  },
+  bail,
+  db::{init::Connection, permission::has_permission, tables::ConnectionExt},
+  error::Result,
+};
+
+pub fn totp_status_route() -> ApiMethodRouter<()> {
+  get_with(totp_status, |op| op.id("totpStatus"))
+}
+
+pub fn start_totp_route() -> ApiMethodRouter<()> {
+  post_with(start_totp, |op| op.id("startTotp"))
+}
+
+pub fn confirm_totp_route() -> ApiMethodRouter<()> {
+  post_with(confirm_totp, |op| op.id("confirmTotp"))
+}
+
+pub fn disable_totp_route() -> ApiMethodRouter<()> {
+  delete_with(disable_totp, |op| op.id("disableTotp"))
+}
+
+pub fn regenerate_recovery_codes_route() -> ApiMethodRouter<()> {
+  post_with(regenerate_recovery_codes, |op| {
+    op.id("regenerateRecoveryCodes")
+  })
+}
+
+pub fn reset_user_totp_route() -> ApiMethodRouter<()> {
+  delete_with(reset_user_totp, |op| op.id("resetUserTotp"))
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TotpStatus {
+  enabled: bool,
+  recovery_codes: u64,
+}
+
+async fn totp_status(auth: JwtAuth, db: Connection) -> Result<Json<TotpStatus>> {
+  let enabled = db.totp().is_enabled(auth.user_id).await?;
+  let recovery_codes = db.totp().remaining_recovery_codes(auth.user_id).await?;
+
+  Ok(Json(TotpStatus {
+    enabled,
+    recovery_codes,
+  }))
+}
+
+#[derive(Serialize, JsonSchema)]
+struct TotpSetup {
+  secret: String,
+  uri: String,
+}
+
+async fn start_totp(auth: JwtAuth, db: Connection, jwt: JwtState) -> Result<Json<TotpSetup>> {
+  let user = db.user().get_user_by_id(auth.user_id).await?;
+  if user.oidc_user {
+    bail!(
+      BAD_REQUEST,
+      "Two-factor authentication is only available for local accounts"
+    );
+  }
+  if db.totp().is_enabled(user.id).await? {
+    bail!(CONFLICT, "Two-factor authentication is already enabled");
+  }
+
+  let secret = generate_secret();
+  db.totp()
+    .set_pending_secret(user.id, secret.clone())
+    .await?;
+  let uri = otpauth_uri(&jwt.iss, &user.email, &secret);
+
+  Ok(Json(TotpSetup { secret, uri }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct TotpCode {
+  code: String,
+}
+
+#[derive(Serialize, JsonSchema)]
+struct RecoveryCodes {
+  recovery_codes: Vec<String>,
+}
+
+async fn confirm_totp(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<TotpCode>,
+) -> Result<Json<RecoveryCodes>> {
+  let Some(totp) = db.totp().get_totp(auth.user_id).await? else {
+    bail!(NOT_FOUND, "No pending two-factor setup");
+  };
+  if totp.enabled {
+    bail!(CONFLICT, "Two-factor authentication is already enabled");
+  }
+
+  let now = chrono::Utc::now().timestamp();
+  let Some(step) = verify_code(&totp.secret, req.code.trim(), now)? else {
+    bail!(BAD_REQUEST, "Invalid code");
+  };
+
+  let recovery_codes = generate_recovery_codes();
+  let hashes = recovery_codes
+    .iter()
+    .map(|c| hash_recovery_code(c))
+    .collect();
+  db.totp().enable_totp(auth.user_id, hashes).await?;
+  db.totp().use_step(auth.user_id, step).await?;
+
+  Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+async fn disable_totp(auth: JwtAuth, db: Connection, Json(req): Json<TotpCode>) -> Result<()> {
+  if !check_second_factor(&db, auth.user_id, &req.code).await? {
+    bail!(UNAUTHORIZED, "Invalid code");
+  }
+
+  db.totp().disable_totp(auth.user_id).await
+}
+
+async fn regenerate_recovery_codes(
+  auth: JwtAuth,
+  db: Connection,
+  Json(req): Json<TotpCode>,
+) -> Result<Json<RecoveryCodes>> {
+  if !check_second_factor(&db, auth.user_id, &req.code).await? {
+    bail!(UNAUTHORIZED, "Invalid code");
+  }
+
+  let recovery_codes = generate_recovery_codes();
+  let hashes = recovery_codes
+    .iter()
+    .map(|c| hash_recovery_code(c))
+    .collect();
+  db.totp()
+    .replace_recovery_codes(auth.user_id, hashes)
+    .await?;
+
+  Ok(Json(RecoveryCodes { recovery_codes }))
+}
+
+#[derive(Deserialize, JsonSchema)]
+struct ResetUserTotpRequest {
+  uuid: Uuid,
+}
+
+async fn reset_user_totp(
+  auth: JwtAuth<UserEdit>,
+  db: Connection,
+  audit: Audit,
+  Json(req): Json<ResetUserTotpRequest>,
+) -> Result<()> {
+  let self_permissions = db.group().get_user_permissions(auth.user_id).await?;
+  let target_permissions = db.group().get_user_permissions(req.uuid).await?;
+
+  if target_permissions
+    .iter()
+    .any(|p| !has_permission(&self_permissions, p))
+  {
+    bail!(
+      FORBIDDEN,
+      "Cannot reset TOTP for a user with higher permissions"
+    );
+  }
+
+  let txn = db.begin().await?;
+  txn.totp().disable_totp(req.uuid).await?;
+  audit
+    .record(
+      &txn,
+      AuditEvent::new(auth.user_id, "user.reset_totp").target("user", req.uuid),
+    )
+    .await?;
+  txn.commit().await?;
+
+  Ok(())
+}
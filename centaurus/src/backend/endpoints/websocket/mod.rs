@@ -3,6 +3,7 @@ use axum::Extension;
 use crate::backend::{
   BackendRouter,
   endpoints::websocket::state::{UpdateMessage, UpdateState},
+  store::Store,
 };
 
 pub mod state;
@@ -12,8 +13,10 @@ pub fn router<T: UpdateMessage>() -> BackendRouter {
   BackendRouter::new().merge(updater::router::<T>())
 }
 
-pub async fn state<T: UpdateMessage>(router: BackendRouter) -> BackendRouter {
-  let (state, updater) = UpdateState::<T>::init().await;
+pub async fn state<T: UpdateMessage>(router: BackendRouter, store: &Store) -> BackendRouter {
+  let (state, updater) = UpdateState::<T>::init(store)
+    .await
+    .expect("Failed to subscribe to update messages");
 
   router.layer(Extension(state)).layer(Extension(updater))
 }
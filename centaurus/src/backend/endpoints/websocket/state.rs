@@ -5,15 +5,22 @@ use axum::{
   extract::{FromRequestParts, rejection::ExtensionRejection},
 };
 use dashmap::DashMap;
-use serde::{Serialize, de::DeserializeOwned};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
 use tokio::{
   spawn,
-  sync::mpsc::{self, Receiver, Sender},
+  sync::{
+    broadcast::error::RecvError,
+    mpsc::{self, Receiver, Sender},
+  },
   task::JoinHandle,
 };
-use tracing::debug;
+use tracing::{debug, warn};
 use uuid::Uuid;
 
+use crate::backend::store::Store;
+
+const UPDATE_CHANNEL: &str = "update";
+
 pub trait UpdateMessage: Serialize + DeserializeOwned + Clone + Debug + Send + 'static {
   fn settings() -> Self;
   fn group(uuid: Uuid) -> Self;
@@ -61,21 +68,56 @@ impl<T: UpdateMessage, R: Sync> FromRequestParts<R> for Updater<T> {
   }
 }
 
-pub struct UpdateTrigger<T: DeserializeOwned + Serialize + Clone + Debug + Send + 'static> {
+/// Published through the [`Store`] so every replica delivers the message to
+/// its own sessions.
+#[derive(Serialize, Deserialize)]
+pub struct UpdateTrigger<T> {
   target: Option<Uuid>,
   message: T,
 }
 
 impl<T: UpdateMessage> UpdateState<T> {
-  pub async fn init() -> (Self, Updater<T>) {
+  pub async fn init(store: &Store) -> crate::error::Result<(Self, Updater<T>)> {
     let sessions: Arc<DashMap<Uuid, DashMap<Uuid, Sender<T>>>> = Arc::new(DashMap::default());
-    let (sender, mut receiver) = mpsc::channel(100);
+    let (sender, mut receiver) = mpsc::channel::<UpdateTrigger<T>>(100);
     let updater: Updater<T> = Updater(sender);
 
+    spawn({
+      let store = store.clone();
+      async move {
+        while let Some(trigger) = receiver.recv().await {
+          let published = match serde_json::to_string(&trigger) {
+            Ok(trigger) => store.publish(UPDATE_CHANNEL, trigger).await,
+            Err(e) => Err(e.into()),
+          };
+          if let Err(e) = published {
+            warn!("Failed to publish update message: {:?}", e);
+          }
+        }
+      }
+    });
+
+    let mut updates = store.subscribe(UPDATE_CHANNEL).await?;
     let update_proxy = spawn({
       let sessions = sessions.clone();
       async move {
-        while let Some(message) = receiver.recv().await {
+        loop {
+          let message = match updates.recv().await {
+            Ok(message) => message,
+            Err(RecvError::Lagged(skipped)) => {
+              warn!("Skipped {} update messages", skipped);
+              continue;
+            }
+            Err(RecvError::Closed) => break,
+          };
+          let message: UpdateTrigger<T> = match serde_json::from_str(&message) {
+            Ok(message) => message,
+            Err(e) => {
+              warn!("Received invalid update message: {:?}", e);
+              continue;
+            }
+          };
+
           if let Some(target) = message.target {
             debug!(
               "Sending update message to {}: {:?}",
@@ -103,7 +145,7 @@ impl<T: UpdateMessage> UpdateState<T> {
       update_proxy: Arc::new(update_proxy),
     };
 
-    (state, updater)
+    Ok((state, updater))
   }
 
   pub async fn create_session(&self, user: Uuid) -> (Uuid, Receiver<T>) {
@@ -171,7 +213,7 @@ mod tests {
 
   #[tokio::test]
   async fn test_send_to_targets_single_user() {
-    let (state, updater) = UpdateState::<Msg>::init().await;
+    let (state, updater) = UpdateState::<Msg>::init(&Store::memory()).await.unwrap();
     let user = Uuid::now_v7();
     let other = Uuid::now_v7();
 
@@ -188,7 +230,7 @@ mod tests {
 
   #[tokio::test]
   async fn test_broadcast_reaches_all_sessions() {
-    let (state, updater) = UpdateState::<Msg>::init().await;
+    let (state, updater) = UpdateState::<Msg>::init(&Store::memory()).await.unwrap();
     let user = Uuid::now_v7();
     let (_a, mut rx_a) = state.create_session(user).await;
     let (_b, mut rx_b) = state.create_session(user).await;
@@ -201,7 +243,7 @@ mod tests {
 
   #[tokio::test]
   async fn test_remove_session_stops_delivery() {
-    let (state, updater) = UpdateState::<Msg>::init().await;
+    let (state, updater) = UpdateState::<Msg>::init(&Store::memory()).await.unwrap();
     let user = Uuid::now_v7();
     let (id, mut rx) = state.create_session(user).await;
 
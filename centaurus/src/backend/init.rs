@@ -12,6 +12,8 @@ pub async fn listener_setup(port: u16) -> TcpListener {
     .expect("Failed to bind to address")
 }
 
+/// Serves the app with the peer address as `ConnectInfo<SocketAddr>`, the
+/// client ip of sessions and rate limits is taken from it.
 pub async fn run_app<S>(listener: TcpListener, app: S)
 where
   S: Service<Request, Response = Response, Error = Infallible>
@@ -21,12 +23,16 @@ where
     + ServiceExt<Request>,
   S::Future: Send,
 {
-  serve(listener, app.into_make_service())
-    .with_graceful_shutdown(shutdown_signal())
-    .await
-    .expect("Failed to start server");
+  serve(
+    listener,
+    app.into_make_service_with_connect_info::<SocketAddr>(),
+  )
+  .with_graceful_shutdown(shutdown_signal())
+  .await
+  .expect("Failed to start server");
 }
 
+/// Same as [`run_app`], which provides the connect info as well.
 pub async fn run_app_connect_info<S>(listener: TcpListener, app: S)
 where
   S: Service<Request, Response = Response, Error = Infallible>
@@ -36,13 +42,7 @@ where
     + ServiceExt<Request>,
   S::Future: Send,
 {
-  serve(
-    listener,
-    app.into_make_service_with_connect_info::<SocketAddr>(),
-  )
-  .with_graceful_shutdown(shutdown_signal())
-  .await
-  .expect("Failed to start server");
+  run_app(listener, app).await
 }
 
 pub async fn shutdown_signal() {
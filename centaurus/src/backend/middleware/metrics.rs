@@ -86,6 +86,11 @@ fn describe_metrics(prefix: &str) {
     Unit::Count,
     "Total number of failed HTTP requests"
   );
+  describe_counter!(
+    format!("{}_http_requests_rate_limited", prefix),
+    Unit::Count,
+    "Total number of HTTP requests rejected by a rate limit policy"
+  );
   describe_histogram!(
     format!("{}_http_request_body_size", prefix),
     Unit::Bytes,
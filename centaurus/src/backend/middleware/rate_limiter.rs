@@ -1,25 +1,64 @@
 use std::{
+  convert::Infallible,
   net::IpAddr,
+  num::NonZeroU32,
+  pin::Pin,
   sync::Arc,
+  task::{Context, Poll},
   thread::{sleep, spawn},
-  time::Duration,
+  time::{Duration, SystemTime, UNIX_EPOCH},
 };
 
+use axum::{extract::Request, response::Response};
 use dashmap::DashMap;
-use governor::{clock::QuantaClock, middleware::StateInformationMiddleware, state::InMemoryState};
-use tower_governor::{
-  GovernorLayer,
-  governor::{GovernorConfig, GovernorConfigBuilder},
-  key_extractor::SmartIpKeyExtractor,
+use governor::{
+  Quota,
+  clock::{Clock, QuantaClock},
+  middleware::StateInformationMiddleware,
+  state::InMemoryState,
 };
+use http::{HeaderMap, HeaderValue, request::Parts};
+use ipnet::IpNet;
+use tower::{Layer, Service};
+use tower_governor::GovernorError;
+#[cfg(feature = "endpoints")]
+use uuid::Uuid;
+
+use crate::backend::{
+  config::{RateLimitConfig, RateLimitKey, RateLimitPolicy},
+  request::client_ip::client_ip,
+  store::Store,
+};
+
+/// Policy used when no name was configured for the requested one.
+pub const DEFAULT_POLICY: &str = "default";
+const STORE_NAMESPACE: &str = "rate_limit";
+
+#[derive(Debug, Clone, PartialEq, Eq, Hash)]
+enum LimitKey {
+  Ip(IpAddr),
+  #[cfg(feature = "endpoints")]
+  User(Uuid),
+  #[cfg(feature = "endpoints")]
+  ApiToken(Uuid),
+}
+
+impl LimitKey {
+  fn store_key(&self) -> String {
+    match self {
+      LimitKey::Ip(ip) => format!("ip:{}", ip),
+      #[cfg(feature = "endpoints")]
+      LimitKey::User(id) => format!("user:{}", id),
+      #[cfg(feature = "endpoints")]
+      LimitKey::ApiToken(id) => format!("api_token:{}", id),
+    }
+  }
+}
 
-pub type RateLimiterLayer =
-  GovernorLayer<SmartIpKeyExtractor, StateInformationMiddleware, axum::body::Body>;
-pub type Governor = GovernorConfig<SmartIpKeyExtractor, StateInformationMiddleware>;
 type Limiter = Arc<
   governor::RateLimiter<
-    IpAddr,
-    DashMap<IpAddr, InMemoryState>,
+    LimitKey,
+    DashMap<LimitKey, InMemoryState>,
     QuantaClock,
     StateInformationMiddleware,
   >,
@@ -27,22 +66,64 @@ type Limiter = Arc<
 
 #[derive(Default)]
 pub struct RateLimiter {
+  config: RateLimitConfig,
+  metrics_prefix: Option<String>,
   cleaner: Vec<Limiter>,
 }
 
 impl RateLimiter {
-  pub fn create_limiter(&mut self) -> RateLimiterLayer {
-    let conf = GovernorConfigBuilder::default()
-      .key_extractor(SmartIpKeyExtractor)
-      .per_second(10)
-      .burst_size(20)
-      .use_headers()
-      .finish()
-      .unwrap();
+  pub fn new(config: RateLimitConfig) -> Self {
+    Self {
+      config,
+      ..Default::default()
+    }
+  }
+
+  /// Count rejected requests in `<prefix>_http_requests_rate_limited`.
+  pub fn with_metrics(mut self, prefix: String) -> Self {
+    self.metrics_prefix = Some(prefix);
+    self
+  }
+
+  fn policy(&self, name: &str) -> RateLimitPolicy {
+    self
+      .config
+      .rate_limit_policies
+      .get(name)
+      .or_else(|| self.config.rate_limit_policies.get(DEFAULT_POLICY))
+      .cloned()
+      .unwrap_or_default()
+  }
 
-    self.cleaner.push(conf.limiter().clone());
+  /// Layer that limits requests with the policy `name` from the config.
+  /// Every call creates its own quota, even for the same policy.
+  ///
+  /// If a shared [`Store`] is added as extension, requests are counted in
+  /// fixed windows of `period * burst` in the store instead, so all replicas
+  /// enforce one quota.
+  pub fn create_limiter(&mut self, name: &str) -> RateLimiterLayer {
+    let policy = self.policy(name);
+    let burst_size = NonZeroU32::new(policy.burst_size).unwrap_or(NonZeroU32::MIN);
+    let quota = Quota::with_period(Duration::from_millis(policy.period_ms.max(1)))
+      .expect("period is not zero")
+      .allow_burst(burst_size);
+    let limiter = Arc::new(governor::RateLimiter::dashmap(quota).with_middleware());
 
-    GovernorLayer::new(conf)
+    let id = self.cleaner.len();
+    self.cleaner.push(limiter.clone());
+
+    RateLimiterLayer {
+      state: Arc::new(PolicyState {
+        name: name.to_string(),
+        id,
+        period_ms: policy.period_ms.max(1),
+        burst_size: burst_size.get(),
+        key: policy.key,
+        allowlist: self.config.rate_limit_allowlist.clone(),
+        metrics_prefix: self.metrics_prefix.clone(),
+        limiter,
+      }),
+    }
   }
 
   pub fn init(self) {
@@ -57,3 +138,478 @@ impl RateLimiter {
     });
   }
 }
+
+struct PolicyState {
+  name: String,
+  /// Separates limiters of the same policy in the shared store.
+  id: usize,
+  period_ms: u64,
+  burst_size: u32,
+  #[cfg_attr(not(feature = "endpoints"), allow(dead_code))]
+  key: RateLimitKey,
+  allowlist: Vec<IpNet>,
+  #[cfg_attr(not(feature = "metrics"), allow(dead_code))]
+  metrics_prefix: Option<String>,
+  limiter: Limiter,
+}
+
+impl PolicyState {
+  /// `Ok(None)` for allowlisted clients. Forwarding headers only count if
+  /// they were set by a trusted proxy, see [`client_ip`].
+  async fn key(&self, parts: &mut Parts) -> Result<Option<LimitKey>, GovernorError> {
+    let ip = client_ip(parts).ok_or(GovernorError::UnableToExtractKey)?;
+    if self.allowlist.iter().any(|net| net.contains(&ip)) {
+      return Ok(None);
+    }
+
+    #[cfg(feature = "endpoints")]
+    if self.key != RateLimitKey::Ip
+      && let Some(key) = auth_key(parts, self.key).await
+    {
+      return Ok(Some(key));
+    }
+
+    Ok(Some(LimitKey::Ip(ip)))
+  }
+
+  /// Remaining requests in the current window, or the seconds until the
+  /// next one.
+  async fn check_shared(
+    &self,
+    store: &Store,
+    key: &LimitKey,
+  ) -> crate::error::Result<Result<u32, u64>> {
+    let window_ms = self.period_ms.saturating_mul(self.burst_size.into());
+    let now_ms = SystemTime::now()
+      .duration_since(UNIX_EPOCH)
+      .unwrap_or_default()
+      .as_millis() as u64;
+    let window = now_ms / window_ms;
+    let left_ms = window_ms - now_ms % window_ms;
+
+    let count = store
+      .increment(
+        STORE_NAMESPACE,
+        &format!("{}#{}:{}:{}", self.name, self.id, key.store_key(), window),
+        Duration::from_millis(left_ms),
+      )
+      .await?;
+
+    Ok(match u32::try_from(count) {
+      Ok(count) if count <= self.burst_size => Ok(self.burst_size - count),
+      _ => Err(left_ms.div_ceil(1000)),
+    })
+  }
+
+  fn rejected(&self, key: &LimitKey) {
+    #[cfg(feature = "metrics")]
+    if let Some(prefix) = &self.metrics_prefix {
+      let kind = match key {
+        LimitKey::Ip(_) => "ip",
+        #[cfg(feature = "endpoints")]
+        LimitKey::User(_) => "user",
+        #[cfg(feature = "endpoints")]
+        LimitKey::ApiToken(_) => "api_token",
+      };
+      ::metrics::counter!(
+        format!("{}_http_requests_rate_limited", prefix),
+        "policy" => self.name.clone(),
+        "key" => kind,
+      )
+      .increment(1);
+    }
+
+    tracing::debug!("Rate limit policy {} rejected {:?}", self.name, key);
+  }
+}
+
+/// Key by the authenticated user or API token. Only the signature of a JWT is
+/// checked here, revocation is left to the handler.
+#[cfg(feature = "endpoints")]
+async fn auth_key(parts: &mut Parts, key: RateLimitKey) -> Option<LimitKey> {
+  use crate::{
+    backend::auth::{
+      api_token::is_api_token,
+      jwt::jwt_from_request,
+      jwt_state::{JWT_COOKIE_NAME, JwtState},
+      token::hash_token,
+    },
+    db::{init::Connection, tables::ConnectionExt},
+  };
+
+  let token = jwt_from_request(parts, JWT_COOKIE_NAME).await.ok()?;
+  if is_api_token(&token) {
+    let db = parts.extensions.get::<Connection>()?;
+    let (stored, _) = db
+      .api_token()
+      .get_api_token(&hash_token(&token))
+      .await
+      .ok()??;
+
+    return Some(match key {
+      RateLimitKey::ApiToken => LimitKey::ApiToken(stored.id),
+      _ => LimitKey::User(stored.user_id),
+    });
+  }
+
+  let claims = parts
+    .extensions
+    .get::<JwtState>()?
+    .validate_token(&token)
+    .await
+    .ok()?;
+  Some(LimitKey::User(claims.sub))
+}
+
+#[derive(Clone)]
+pub struct RateLimiterLayer {
+  state: Arc<PolicyState>,
+}
+
+impl<S> Layer<S> for RateLimiterLayer {
+  type Service = RateLimiterService<S>;
+
+  fn layer(&self, inner: S) -> Self::Service {
+    RateLimiterService {
+      inner,
+      state: self.state.clone(),
+    }
+  }
+}
+
+#[derive(Clone)]
+pub struct RateLimiterService<S> {
+  inner: S,
+  state: Arc<PolicyState>,
+}
+
+impl<S> Service<Request> for RateLimiterService<S>
+where
+  S: Service<Request, Response = Response, Error = Infallible> + Clone + Send + 'static,
+  S::Future: Send + 'static,
+{
+  type Response = Response;
+  type Error = Infallible;
+  type Future = Pin<Box<dyn Future<Output = Result<Response, Infallible>> + Send>>;
+
+  fn poll_ready(&mut self, cx: &mut Context<'_>) -> Poll<Result<(), Self::Error>> {
+    self.inner.poll_ready(cx)
+  }
+
+  fn call(&mut self, req: Request) -> Self::Future {
+    let state = self.state.clone();
+    // the clone is not ready, keep the one that was polled
+    let clone = self.inner.clone();
+    let mut inner = std::mem::replace(&mut self.inner, clone);
+
+    Box::pin(async move {
+      let (mut parts, body) = req.into_parts();
+      let key = match state.key(&mut parts).await {
+        Ok(key) => key,
+        Err(e) => return Ok(e.into()),
+      };
+      let req = Request::from_parts(parts, body);
+
+      let Some(key) = key else {
+        let mut res = inner.call(req).await?;
+        res
+          .headers_mut()
+          .insert("x-ratelimit-whitelisted", HeaderValue::from_static("true"));
+        return Ok(res);
+      };
+
+      let shared = req
+        .extensions()
+        .get::<Store>()
+        .filter(|store| store.is_shared())
+        .cloned();
+      if let Some(store) = shared {
+        match state.check_shared(&store, &key).await {
+          Ok(Ok(remaining)) => {
+            let mut res = inner.call(req).await?;
+            limit_headers(res.headers_mut(), state.burst_size, remaining);
+            return Ok(res);
+          }
+          Ok(Err(wait_time)) => {
+            state.rejected(&key);
+            return Ok(too_many_requests(state.burst_size, wait_time));
+          }
+          Err(e) => {
+            tracing::warn!(
+              "Failed to check shared rate limit, using local limit: {:?}",
+              e
+            );
+          }
+        }
+      }
+
+      match state.limiter.check_key(&key) {
+        Ok(snapshot) => {
+          let mut res = inner.call(req).await?;
+          limit_headers(
+            res.headers_mut(),
+            snapshot.quota().burst_size().get(),
+            snapshot.remaining_burst_capacity(),
+          );
+          Ok(res)
+        }
+        Err(negative) => {
+          state.rejected(&key);
+
+          let wait_time = negative
+            .wait_time_from(state.limiter.clock().now())
+            .as_secs();
+          Ok(too_many_requests(
+            negative.quota().burst_size().get(),
+            wait_time,
+          ))
+        }
+      }
+    })
+  }
+}
+
+fn limit_headers(headers: &mut HeaderMap, limit: u32, remaining: u32) {
+  headers.insert("x-ratelimit-limit", limit.into());
+  headers.insert("x-ratelimit-remaining", remaining.into());
+}
+
+fn too_many_requests(limit: u32, wait_time: u64) -> Response {
+  let mut headers = HeaderMap::new();
+  headers.insert("x-ratelimit-after", wait_time.into());
+  headers.insert("retry-after", wait_time.into());
+  limit_headers(&mut headers, limit, 0);
+
+  GovernorError::TooManyRequests {
+    wait_time,
+    headers: Some(headers),
+  }
+  .into()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use std::collections::BTreeMap;
+
+  use std::net::SocketAddr;
+
+  use axum::{Router, body::Body, extract::ConnectInfo, routing::get};
+  use http::StatusCode;
+  use tower::ServiceExt;
+
+  fn app(rate_limiter: &mut RateLimiter, policy: &str) -> Router {
+    Router::new()
+      .route("/", get(async || "ok"))
+      .layer(rate_limiter.create_limiter(policy))
+  }
+
+  fn peer(ip: &str) -> ConnectInfo<SocketAddr> {
+    ConnectInfo(SocketAddr::new(ip.parse().unwrap(), 1234))
+  }
+
+  async fn status(app: &Router, ip: &str) -> StatusCode {
+    let req = Request::builder()
+      .uri("/")
+      .extension(peer(ip))
+      .body(Body::empty())
+      .unwrap();
+    app.clone().oneshot(req).await.unwrap().status()
+  }
+
+  fn config() -> RateLimitConfig {
+    RateLimitConfig {
+      rate_limit_policies: BTreeMap::from([(
+        "strict".to_string(),
+        RateLimitPolicy {
+          period_ms: 60_000,
+          burst_size: 1,
+          key: RateLimitKey::Ip,
+        },
+      )]),
+      rate_limit_allowlist: vec!["10.0.0.0/8".parse().unwrap()],
+    }
+  }
+
+  #[tokio::test]
+  async fn test_policy_limits_per_ip() {
+    let mut rate_limiter = RateLimiter::new(config());
+    let app = app(&mut rate_limiter, "strict");
+
+    assert_eq!(status(&app, "192.168.0.1").await, StatusCode::OK);
+    assert_eq!(
+      status(&app, "192.168.0.1").await,
+      StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(status(&app, "192.168.0.2").await, StatusCode::OK);
+  }
+
+  #[tokio::test]
+  async fn test_policy_limits_apps_served_with_run_app() {
+    use crate::backend::init::{listener_setup, run_app};
+    use tokio::{
+      io::{AsyncReadExt, AsyncWriteExt},
+      net::TcpStream,
+    };
+
+    let mut rate_limiter = RateLimiter::new(config());
+    let listener = listener_setup(0).await;
+    let port = listener.local_addr().unwrap().port();
+    let server = tokio::spawn(run_app(listener, app(&mut rate_limiter, "strict")));
+
+    let get = async || {
+      let mut stream = TcpStream::connect(("127.0.0.1", port)).await.unwrap();
+      stream
+        .write_all(b"GET / HTTP/1.1\r\nhost: localhost\r\nconnection: close\r\n\r\n")
+        .await
+        .unwrap();
+      let mut response = String::new();
+      stream.read_to_string(&mut response).await.unwrap();
+      response.lines().next().unwrap().to_string()
+    };
+    assert_eq!(get().await, "HTTP/1.1 200 OK");
+    assert_eq!(get().await, "HTTP/1.1 429 Too Many Requests");
+
+    server.abort();
+  }
+
+  #[tokio::test]
+  async fn test_allowlist_is_never_limited() {
+    let mut rate_limiter = RateLimiter::new(config());
+    let app = app(&mut rate_limiter, "strict");
+
+    for _ in 0..3 {
+      assert_eq!(status(&app, "10.1.2.3").await, StatusCode::OK);
+    }
+  }
+
+  #[tokio::test]
+  async fn test_allowlist_ignores_forged_headers() {
+    use crate::backend::request::client_ip::TrustedProxies;
+    use axum::Extension;
+
+    let mut rate_limiter = RateLimiter::new(config());
+    let app = app(&mut rate_limiter, "strict");
+    let send = |app: Router, peer_ip: &str| {
+      let req = Request::builder()
+        .uri("/")
+        .extension(peer(peer_ip))
+        .header("x-forwarded-for", "10.1.2.3")
+        .body(Body::empty())
+        .unwrap();
+      app.oneshot(req)
+    };
+
+    // the header of an untrusted peer is ignored
+    assert_eq!(
+      send(app.clone(), "192.168.0.1").await.unwrap().status(),
+      StatusCode::OK
+    );
+    assert_eq!(
+      send(app.clone(), "192.168.0.1").await.unwrap().status(),
+      StatusCode::TOO_MANY_REQUESTS
+    );
+
+    // a trusted proxy forwards the allowlisted client
+    let proxied = app.layer(Extension(TrustedProxies::new(vec![
+      "192.168.0.0/16".parse().unwrap(),
+    ])));
+    for _ in 0..3 {
+      assert_eq!(
+        send(proxied.clone(), "192.168.0.1").await.unwrap().status(),
+        StatusCode::OK
+      );
+    }
+  }
+
+  #[cfg(feature = "endpoints")]
+  #[tokio::test]
+  async fn test_user_policy_keys_by_jwt_subject() {
+    use crate::backend::auth::{jwt_state::JwtState, settings::AuthConfig};
+    use crate::db::{config::DBConfig, init::connect_db, migrations::Migrator};
+    use axum::Extension;
+    use sea_orm_migration::MigratorTrait;
+
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let jwt = JwtState::init(&AuthConfig::default(), &conn).await;
+    let alice = jwt.create_raw_token(Uuid::now_v7()).unwrap();
+    let bob = jwt.create_raw_token(Uuid::now_v7()).unwrap();
+
+    let mut config = config();
+    config.rate_limit_policies.insert(
+      "user".into(),
+      RateLimitPolicy {
+        period_ms: 60_000,
+        burst_size: 1,
+        key: RateLimitKey::User,
+      },
+    );
+    let mut rate_limiter = RateLimiter::new(config);
+    let app = app(&mut rate_limiter, "user").layer(Extension(jwt));
+
+    let send = |token: &str| {
+      let req = Request::builder()
+        .uri("/")
+        .extension(peer("192.168.0.1"))
+        .header("authorization", format!("Bearer {token}"))
+        .body(Body::empty())
+        .unwrap();
+      app.clone().oneshot(req)
+    };
+
+    // same ip, but every user has their own quota
+    assert_eq!(send(&alice).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(send(&bob).await.unwrap().status(), StatusCode::OK);
+    assert_eq!(
+      send(&alice).await.unwrap().status(),
+      StatusCode::TOO_MANY_REQUESTS
+    );
+  }
+
+  #[cfg(feature = "db")]
+  #[tokio::test]
+  async fn test_shared_store_limits_across_replicas() {
+    use crate::{
+      backend::store::database::DatabaseStore,
+      db::{config::DBConfig, init::connect_db, migrations::Migrator},
+    };
+    use axum::Extension;
+    use sea_orm_migration::MigratorTrait;
+
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let store = Store::new(DatabaseStore::new(conn));
+
+    let mut first = RateLimiter::new(config());
+    let first = app(&mut first, "strict").layer(Extension(store.clone()));
+    let mut second = RateLimiter::new(config());
+    let second = app(&mut second, "strict").layer(Extension(store));
+
+    assert_eq!(status(&first, "192.168.0.1").await, StatusCode::OK);
+    assert_eq!(
+      status(&second, "192.168.0.1").await,
+      StatusCode::TOO_MANY_REQUESTS
+    );
+    assert_eq!(status(&second, "192.168.0.2").await, StatusCode::OK);
+  }
+
+  #[test]
+  fn test_unknown_policy_falls_back_to_default() {
+    let mut config = config();
+    let rate_limiter = RateLimiter::new(config.clone());
+    assert_eq!(rate_limiter.policy("other"), RateLimitPolicy::default());
+
+    let default = RateLimitPolicy {
+      period_ms: 1,
+      burst_size: 100,
+      key: RateLimitKey::User,
+    };
+    config
+      .rate_limit_policies
+      .insert(DEFAULT_POLICY.into(), default.clone());
+    let rate_limiter = RateLimiter::new(config);
+    assert_eq!(rate_limiter.policy("other"), default);
+    assert_eq!(rate_limiter.policy("strict").burst_size, 1);
+  }
+}
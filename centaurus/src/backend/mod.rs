@@ -7,6 +7,7 @@ pub mod middleware;
 pub mod request;
 pub mod rewrite;
 pub mod router;
+pub mod store;
 
 #[cfg(not(feature = "openapi"))]
 pub type BackendRouter = axum::Router;
@@ -0,0 +1,133 @@
+use std::{
+  net::{IpAddr, SocketAddr},
+  sync::Arc,
+};
+
+use axum::extract::ConnectInfo;
+use http::{HeaderMap, request::Parts};
+use ipnet::IpNet;
+
+/// Reverse proxies whose forwarding headers are believed, see
+/// [`BaseConfig::trusted_proxies`](crate::backend::config::BaseConfig::trusted_proxies).
+/// Without this extension no proxy is trusted.
+#[derive(Clone, Debug, Default)]
+pub struct TrustedProxies(Arc<Vec<IpNet>>);
+
+impl TrustedProxies {
+  pub fn new(proxies: Vec<IpNet>) -> Self {
+    Self(Arc::new(proxies))
+  }
+
+  pub fn contains(&self, ip: &IpAddr) -> bool {
+    self.0.iter().any(|net| net.contains(ip))
+  }
+}
+
+/// Address of the client. This is the peer address, unless the peer is a
+/// trusted proxy. Then it is the last address of `X-Forwarded-For` that is
+/// not a trusted proxy itself, or `X-Real-IP` if that header is missing.
+pub fn client_ip(parts: &Parts) -> Option<IpAddr> {
+  let peer = parts.extensions.get::<ConnectInfo<SocketAddr>>()?.0.ip();
+  let Some(proxies) = parts.extensions.get::<TrustedProxies>() else {
+    return Some(peer);
+  };
+  if !proxies.contains(&peer) {
+    return Some(peer);
+  }
+
+  Some(forwarded_ip(&parts.headers, proxies).unwrap_or(peer))
+}
+
+fn forwarded_ip(headers: &HeaderMap, proxies: &TrustedProxies) -> Option<IpAddr> {
+  let forwarded: Vec<IpAddr> = headers
+    .get_all("x-forwarded-for")
+    .iter()
+    .filter_map(|v| v.to_str().ok())
+    .flat_map(|v| v.split(','))
+    .filter_map(|ip| ip.trim().parse().ok())
+    .collect();
+  if let Some(first) = forwarded.first() {
+    // every proxy appends the address it got the request from, so only the
+    // entries after the last untrusted one can be relied on
+    return Some(
+      forwarded
+        .iter()
+        .rev()
+        .find(|ip| !proxies.contains(ip))
+        .unwrap_or(first)
+        .to_owned(),
+    );
+  }
+
+  headers
+    .get("x-real-ip")
+    .and_then(|v| v.to_str().ok())
+    .and_then(|v| v.trim().parse().ok())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use axum::body::Body;
+
+  fn parts(peer: [u8; 4], proxies: Option<&str>, headers: &[(&str, &str)]) -> Parts {
+    let mut builder =
+      http::Request::builder().extension(ConnectInfo(SocketAddr::from((peer, 1234))));
+    if let Some(proxies) = proxies {
+      builder = builder.extension(TrustedProxies::new(vec![proxies.parse().unwrap()]));
+    }
+    for (name, value) in headers {
+      builder = builder.header(*name, *value);
+    }
+    builder.body(Body::empty()).unwrap().into_parts().0
+  }
+
+  fn ip(ip: &str) -> Option<IpAddr> {
+    Some(ip.parse().unwrap())
+  }
+
+  #[test]
+  fn test_headers_of_untrusted_peers_are_ignored() {
+    let headers = [("x-forwarded-for", "10.0.0.1"), ("x-real-ip", "10.0.0.2")];
+    assert_eq!(
+      client_ip(&parts([1, 2, 3, 4], None, &headers)),
+      ip("1.2.3.4")
+    );
+    assert_eq!(
+      client_ip(&parts([1, 2, 3, 4], Some("10.0.0.0/8"), &headers)),
+      ip("1.2.3.4")
+    );
+  }
+
+  #[test]
+  fn test_trusted_proxy_forwards_client() {
+    let proxies = Some("10.0.0.0/8");
+    // the client prepended a forged address
+    let headers = [("x-forwarded-for", "6.6.6.6, 1.2.3.4, 10.0.0.2")];
+    assert_eq!(
+      client_ip(&parts([10, 0, 0, 1], proxies, &headers)),
+      ip("1.2.3.4")
+    );
+
+    let headers = [("x-real-ip", "1.2.3.4")];
+    assert_eq!(
+      client_ip(&parts([10, 0, 0, 1], proxies, &headers)),
+      ip("1.2.3.4")
+    );
+
+    assert_eq!(
+      client_ip(&parts([10, 0, 0, 1], proxies, &[])),
+      ip("10.0.0.1")
+    );
+  }
+
+  #[test]
+  fn test_no_peer_address() {
+    let (parts, _) = http::Request::builder()
+      .header("x-real-ip", "1.2.3.4")
+      .body(Body::empty())
+      .unwrap()
+      .into_parts();
+    assert_eq!(client_ip(&parts), None);
+  }
+}
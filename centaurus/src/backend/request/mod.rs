@@ -1,3 +1,4 @@
+pub mod client_ip;
 pub mod extract;
 pub mod header;
 pub mod redirect;
@@ -13,6 +13,11 @@ impl IntoResponse for Redirect {
   }
 }
 
+#[cfg(feature = "openapi")]
+impl aide::OperationOutput for Redirect {
+  type Inner = ();
+}
+
 impl Redirect {
   pub fn found(uri: String) -> Self {
     Self::with_status_code(StatusCode::FOUND, &uri)
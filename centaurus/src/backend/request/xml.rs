@@ -50,6 +50,11 @@ impl<T: Serialize> IntoResponse for Xml<T> {
   }
 }
 
+#[cfg(feature = "openapi")]
+impl<T> aide::OperationOutput for Xml<T> {
+  type Inner = T;
+}
+
 impl<T, S: Send + Sync> FromRequest<S> for Xml<T>
 where
   T: for<'de> Deserialize<'de>,
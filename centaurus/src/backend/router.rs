@@ -8,6 +8,7 @@ use tower::ServiceBuilder;
 use crate::backend::middleware::metrics::init_metrics;
 use crate::backend::{
   BackendRouter, config::Config, endpoints::health, middleware::rate_limiter::RateLimiter,
+  request::client_ip::TrustedProxies,
 };
 
 pub async fn build_router<R, S, C, F>(router: R, state: S, config: C) -> Router
@@ -22,7 +23,14 @@ where
   #[cfg(feature = "metrics")]
   let handle = init_metrics(metrics_config.metrics_name.clone());
 
-  let mut rate_limiter = RateLimiter::default();
+  let rate_limiter = RateLimiter::new(config.rate_limit().cloned().unwrap_or_default());
+  #[cfg(feature = "metrics")]
+  let rate_limiter = if metrics_config.metrics_enabled {
+    rate_limiter.with_metrics(metrics_config.metrics_name.clone())
+  } else {
+    rate_limiter
+  };
+  let mut rate_limiter = rate_limiter;
   let api_router = router(&mut rate_limiter);
   rate_limiter.init();
 
@@ -50,6 +58,18 @@ where
     }
   }
 
+  #[cfg(feature = "endpoints")]
+  {
+    use crate::backend::auth::jwks::well_known_router;
+    router = router.merge(well_known_router());
+  }
+
+  #[cfg(feature = "oidc_provider")]
+  {
+    use crate::backend::auth::provider::well_known_router;
+    router = router.merge(well_known_router());
+  }
+
   router = router.nest("/api", sub_router).layer(
     ServiceBuilder::new()
       .layer(super::middleware::cors::cors(config.base()).expect("Faield to build CORS layer")),
@@ -121,6 +141,9 @@ where
     }
   }
 
+  router = router.layer(Extension(TrustedProxies::new(
+    config.base().trusted_proxies.clone(),
+  )));
   router = state(router, config.clone()).await.layer(Extension(config));
 
   #[cfg(feature = "openapi")]
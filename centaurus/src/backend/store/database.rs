@@ -0,0 +1,197 @@
+use std::{
+  collections::BTreeMap,
+  time::{Duration, Instant},
+};
+
+use chrono::{NaiveDateTime, Utc};
+use tokio::{
+  spawn,
+  sync::broadcast::{self, Receiver},
+  time::sleep,
+};
+use tracing::warn;
+
+use crate::{
+  backend::store::EphemeralStore,
+  db::{init::Connection, tables::ConnectionExt},
+  error::Result,
+};
+
+const CHANNEL_CAPACITY: usize = 100;
+const POLL_INTERVAL: Duration = Duration::from_millis(500);
+/// How long an event may take to commit after one with a higher id was seen.
+/// Ids are taken when the event is inserted, so on Postgres they can become
+/// visible out of order.
+const COMMIT_GRACE: Duration = Duration::from_secs(10);
+/// Seconds to keep events. Subscribers poll every [`POLL_INTERVAL`], older
+/// events are not needed.
+const EVENT_RETENTION: i64 = 60;
+
+/// Keeps the state in the `ephemeral_entry` and `ephemeral_event` tables so
+/// every replica using the same database sees it.
+pub struct DatabaseStore {
+  db: Connection,
+}
+
+impl DatabaseStore {
+  pub fn new(db: Connection) -> Self {
+    Self { db }
+  }
+}
+
+fn expires(ttl: Duration) -> NaiveDateTime {
+  chrono::Duration::from_std(ttl)
+    .ok()
+    .and_then(|ttl| Utc::now().naive_utc().checked_add_signed(ttl))
+    .unwrap_or(NaiveDateTime::MAX)
+}
+
+#[async_trait::async_trait]
+impl EphemeralStore for DatabaseStore {
+  async fn set(&self, namespace: &str, key: &str, value: String, ttl: Duration) -> Result<()> {
+    self
+      .db
+      .ephemeral()
+      .set(namespace, key, value, expires(ttl))
+      .await
+  }
+
+  async fn get(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+    self.db.ephemeral().get(namespace, key).await
+  }
+
+  async fn take(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+    self.db.ephemeral().take(namespace, key).await
+  }
+
+  async fn remove(&self, namespace: &str, key: &str) -> Result<()> {
+    self.db.ephemeral().remove(namespace, key).await
+  }
+
+  async fn increment(&self, namespace: &str, key: &str, ttl: Duration) -> Result<i64> {
+    self
+      .db
+      .ephemeral()
+      .increment(namespace, key, expires(ttl))
+      .await
+  }
+
+  async fn publish(&self, channel: &str, message: String) -> Result<()> {
+    self.db.ephemeral().publish(channel, message).await?;
+    Ok(())
+  }
+
+  async fn subscribe(&self, channel: &str) -> Result<Receiver<String>> {
+    let (sender, receiver) = broadcast::channel(CHANNEL_CAPACITY);
+    let mut last = self.db.ephemeral().last_event_id().await?;
+    let db = self.db.clone();
+    let channel = channel.to_string();
+
+    spawn(async move {
+      // events after `last` that were already delivered, a gap before one of
+      // them may still be filled until its grace period is over
+      let mut seen = BTreeMap::new();
+      while sender.receiver_count() > 0 {
+        match db.ephemeral().events_after(&channel, last).await {
+          Ok(events) => {
+            for event in events {
+              if seen.insert(event.id, Instant::now()).is_none() {
+                let _ = sender.send(event.payload);
+              }
+            }
+          }
+          Err(e) => warn!("Failed to poll events of {}: {:?}", channel, e),
+        }
+
+        if let Some(settled) = seen
+          .iter()
+          .rev()
+          .find(|(_, seen)| seen.elapsed() > COMMIT_GRACE)
+          .map(|(id, _)| *id)
+        {
+          last = settled;
+          seen = seen.split_off(&(settled + 1));
+        }
+        sleep(POLL_INTERVAL).await;
+      }
+    });
+
+    Ok(receiver)
+  }
+
+  async fn purge_expired(&self) -> Result<()> {
+    let events_before = Utc::now().naive_utc() - chrono::Duration::seconds(EVENT_RETENTION);
+    self.db.ephemeral().remove_expired(events_before).await?;
+    Ok(())
+  }
+
+  fn is_shared(&self) -> bool {
+    true
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::connect_db;
+  use crate::db::migrations::Migrator;
+  use sea_orm_migration::MigratorTrait;
+
+  #[tokio::test]
+  async fn test_replicas_share_state() {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let a = DatabaseStore::new(conn.clone());
+    let b = DatabaseStore::new(conn);
+
+    a.set("ns", "k", "v".into(), Duration::from_secs(60))
+      .await
+      .unwrap();
+    assert_eq!(b.take("ns", "k").await.unwrap().as_deref(), Some("v"));
+    assert!(a.take("ns", "k").await.unwrap().is_none());
+
+    let mut rx = b.subscribe("ws").await.unwrap();
+    a.publish("ws", "hello".into()).await.unwrap();
+    let msg = tokio::time::timeout(Duration::from_secs(5), rx.recv())
+      .await
+      .unwrap()
+      .unwrap();
+    assert_eq!(msg, "hello");
+  }
+
+  #[tokio::test]
+  async fn test_subscribe_receives_events_committed_out_of_order() {
+    use crate::db::entities::ephemeral_event;
+    use sea_orm::{ActiveModelTrait, ActiveValue::Set};
+
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let store = DatabaseStore::new(conn.clone());
+    let mut rx = store.subscribe("ws").await.unwrap();
+    let recv = async |rx: &mut Receiver<String>| {
+      tokio::time::timeout(Duration::from_secs(5), rx.recv())
+        .await
+        .unwrap()
+        .unwrap()
+    };
+
+    // the event with the higher id commits first
+    let base = conn.ephemeral().last_event_id().await.unwrap();
+    for (id, payload) in [(base + 2, "second"), (base + 1, "first")] {
+      ephemeral_event::ActiveModel {
+        id: Set(id),
+        channel: Set("ws".into()),
+        payload: Set(payload.into()),
+        created: Set(Utc::now().naive_utc()),
+      }
+      .insert(&*conn)
+      .await
+      .unwrap();
+      assert_eq!(recv(&mut rx).await, payload);
+    }
+
+    store.publish("ws", "third".into()).await.unwrap();
+    assert_eq!(recv(&mut rx).await, "third");
+  }
+}
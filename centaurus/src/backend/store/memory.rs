@@ -0,0 +1,161 @@
+use std::time::{Duration, Instant};
+
+use dashmap::DashMap;
+use tokio::sync::broadcast::{self, Receiver, Sender};
+
+use crate::{backend::store::EphemeralStore, error::Result};
+
+const CHANNEL_CAPACITY: usize = 100;
+
+struct Entry {
+  value: String,
+  counter: i64,
+  expires: Instant,
+}
+
+/// Keeps everything in this process.
+#[derive(Default)]
+pub struct MemoryStore {
+  entries: DashMap<(String, String), Entry>,
+  channels: DashMap<String, Sender<String>>,
+}
+
+fn id(namespace: &str, key: &str) -> (String, String) {
+  (namespace.to_string(), key.to_string())
+}
+
+#[async_trait::async_trait]
+impl EphemeralStore for MemoryStore {
+  async fn set(&self, namespace: &str, key: &str, value: String, ttl: Duration) -> Result<()> {
+    self.entries.insert(
+      id(namespace, key),
+      Entry {
+        value,
+        counter: 0,
+        expires: Instant::now() + ttl,
+      },
+    );
+    Ok(())
+  }
+
+  async fn get(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+    let now = Instant::now();
+    Ok(
+      self
+        .entries
+        .get(&id(namespace, key))
+        .filter(|entry| entry.expires > now)
+        .map(|entry| entry.value.clone()),
+    )
+  }
+
+  async fn take(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+    let now = Instant::now();
+    Ok(
+      self
+        .entries
+        .remove(&id(namespace, key))
+        .filter(|(_, entry)| entry.expires > now)
+        .map(|(_, entry)| entry.value),
+    )
+  }
+
+  async fn remove(&self, namespace: &str, key: &str) -> Result<()> {
+    self.entries.remove(&id(namespace, key));
+    Ok(())
+  }
+
+  async fn increment(&self, namespace: &str, key: &str, ttl: Duration) -> Result<i64> {
+    let now = Instant::now();
+    let mut entry = self.entries.entry(id(namespace, key)).or_insert(Entry {
+      value: String::new(),
+      counter: 0,
+      expires: now + ttl,
+    });
+    if entry.expires <= now {
+      entry.counter = 0;
+      entry.expires = now + ttl;
+    }
+    entry.counter += 1;
+
+    Ok(entry.counter)
+  }
+
+  async fn publish(&self, channel: &str, message: String) -> Result<()> {
+    if let Some(sender) = self.channels.get(channel) {
+      // no receivers is not an error
+      let _ = sender.send(message);
+    }
+    Ok(())
+  }
+
+  async fn subscribe(&self, channel: &str) -> Result<Receiver<String>> {
+    Ok(
+      self
+        .channels
+        .entry(channel.to_string())
+        .or_insert_with(|| broadcast::channel(CHANNEL_CAPACITY).0)
+        .subscribe(),
+    )
+  }
+
+  async fn purge_expired(&self) -> Result<()> {
+    let now = Instant::now();
+    self.entries.retain(|_, entry| entry.expires > now);
+    self
+      .channels
+      .retain(|_, sender| sender.receiver_count() > 0);
+    Ok(())
+  }
+
+  fn is_shared(&self) -> bool {
+    false
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[tokio::test]
+  async fn test_entries_expire() {
+    let store = MemoryStore::default();
+    store
+      .set("ns", "a", "1".into(), Duration::from_secs(60))
+      .await
+      .unwrap();
+    store
+      .set("ns", "b", "2".into(), Duration::ZERO)
+      .await
+      .unwrap();
+
+    assert_eq!(store.get("ns", "a").await.unwrap().as_deref(), Some("1"));
+    assert!(store.get("ns", "b").await.unwrap().is_none());
+    assert_eq!(store.take("ns", "a").await.unwrap().as_deref(), Some("1"));
+    assert!(store.take("ns", "a").await.unwrap().is_none());
+
+    store.purge_expired().await.unwrap();
+    assert!(store.entries.is_empty());
+  }
+
+  #[tokio::test]
+  async fn test_increment_restarts_after_ttl() {
+    let store = MemoryStore::default();
+    let ttl = Duration::from_secs(60);
+    assert_eq!(store.increment("rl", "k", ttl).await.unwrap(), 1);
+    assert_eq!(store.increment("rl", "k", ttl).await.unwrap(), 2);
+    assert_eq!(store.increment("rl", "x", Duration::ZERO).await.unwrap(), 1);
+    assert_eq!(store.increment("rl", "x", ttl).await.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_publish_reaches_subscribers() {
+    let store = MemoryStore::default();
+    // publishing without subscribers is fine
+    store.publish("ws", "lost".into()).await.unwrap();
+
+    let mut rx = store.subscribe("ws").await.unwrap();
+    store.publish("ws", "hello".into()).await.unwrap();
+    assert_eq!(rx.recv().await.unwrap(), "hello");
+  }
+}
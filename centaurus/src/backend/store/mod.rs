@@ -0,0 +1,162 @@
+//! Short lived state like OIDC nonces, reset tokens and update messages. With
+//! the [`database`] store every replica of a service sees the same state.
+
+use std::{
+  fmt::{self, Debug},
+  ops::Deref,
+  sync::{Arc, Weak},
+  time::Duration,
+};
+
+use axum::{Extension, extract::FromRequestParts};
+use serde::{Deserialize, Serialize, de::DeserializeOwned};
+use tokio::{runtime::Handle, sync::broadcast::Receiver, time::sleep};
+use tracing::warn;
+
+use crate::error::Result;
+#[cfg(feature = "db")]
+use crate::{backend::config::BaseConfig, db::init::Connection};
+
+#[cfg(feature = "db")]
+pub mod database;
+pub mod memory;
+
+const PURGE_INTERVAL: Duration = Duration::from_secs(60);
+
+#[async_trait::async_trait]
+pub trait EphemeralStore: Send + Sync + 'static {
+  /// Store `value` for `ttl`, replacing an existing value.
+  async fn set(&self, namespace: &str, key: &str, value: String, ttl: Duration) -> Result<()>;
+  async fn get(&self, namespace: &str, key: &str) -> Result<Option<String>>;
+  /// Remove the value and return it. Of concurrent callers only one gets it,
+  /// which makes it suitable for single use tokens.
+  async fn take(&self, namespace: &str, key: &str) -> Result<Option<String>>;
+  async fn remove(&self, namespace: &str, key: &str) -> Result<()>;
+  /// Increment a counter and return the new value. A missing counter starts
+  /// at 1 and expires after `ttl`.
+  async fn increment(&self, namespace: &str, key: &str, ttl: Duration) -> Result<i64>;
+  /// Deliver `message` to every subscriber of `channel`, on all replicas.
+  async fn publish(&self, channel: &str, message: String) -> Result<()>;
+  /// Messages published after subscribing.
+  async fn subscribe(&self, channel: &str) -> Result<Receiver<String>>;
+  async fn purge_expired(&self) -> Result<()>;
+  /// Whether the state is shared with other replicas.
+  fn is_shared(&self) -> bool;
+}
+
+/// Which [`EphemeralStore`] [`Store::from_config`] creates.
+#[derive(
+  Deserialize, Serialize, Clone, Copy, Debug, PartialEq, Eq, PartialOrd, Ord, Hash, Default,
+)]
+#[serde(rename_all = "snake_case")]
+pub enum StoreBackend {
+  /// Only visible to this process, fine for a single replica.
+  #[default]
+  Memory,
+  /// Shared through the database, required for multiple replicas.
+  Database,
+}
+
+#[derive(Clone, FromRequestParts)]
+#[cfg_attr(feature = "openapi", derive(aide::OperationIo))]
+#[from_request(via(Extension))]
+pub struct Store(Arc<dyn EphemeralStore>);
+
+impl Store {
+  /// Expired entries are purged in the background as long as the store is
+  /// alive, if a tokio runtime is available.
+  pub fn new(store: impl EphemeralStore) -> Self {
+    let store: Arc<dyn EphemeralStore> = Arc::new(store);
+
+    if let Ok(handle) = Handle::try_current() {
+      let weak: Weak<dyn EphemeralStore> = Arc::downgrade(&store);
+      handle.spawn(async move {
+        loop {
+          sleep(PURGE_INTERVAL).await;
+          let Some(store) = weak.upgrade() else {
+            break;
+          };
+          if let Err(e) = store.purge_expired().await {
+            warn!("Failed to purge expired ephemeral state: {:?}", e);
+          }
+        }
+      });
+    }
+
+    Self(store)
+  }
+
+  pub fn memory() -> Self {
+    Self::new(memory::MemoryStore::default())
+  }
+
+  #[cfg(feature = "db")]
+  pub fn from_config(config: &BaseConfig, db: &Connection) -> Self {
+    match config.ephemeral_store {
+      StoreBackend::Memory => Self::memory(),
+      StoreBackend::Database => Self::new(database::DatabaseStore::new(db.clone())),
+    }
+  }
+
+  pub async fn set_json<T: Serialize>(
+    &self,
+    namespace: &str,
+    key: &str,
+    value: &T,
+    ttl: Duration,
+  ) -> Result<()> {
+    self
+      .set(namespace, key, serde_json::to_string(value)?, ttl)
+      .await
+  }
+
+  pub async fn get_json<T: DeserializeOwned>(
+    &self,
+    namespace: &str,
+    key: &str,
+  ) -> Result<Option<T>> {
+    Ok(
+      self
+        .get(namespace, key)
+        .await?
+        .map(|value| serde_json::from_str(&value))
+        .transpose()?,
+    )
+  }
+
+  pub async fn take_json<T: DeserializeOwned>(
+    &self,
+    namespace: &str,
+    key: &str,
+  ) -> Result<Option<T>> {
+    Ok(
+      self
+        .take(namespace, key)
+        .await?
+        .map(|value| serde_json::from_str(&value))
+        .transpose()?,
+    )
+  }
+}
+
+impl Default for Store {
+  fn default() -> Self {
+    Self::memory()
+  }
+}
+
+impl Debug for Store {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    f.debug_struct("Store")
+      .field("shared", &self.is_shared())
+      .finish()
+  }
+}
+
+impl Deref for Store {
+  type Target = dyn EphemeralStore;
+
+  fn deref(&self) -> &Self::Target {
+    self.0.as_ref()
+  }
+}
@@ -0,0 +1,34 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "acl")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub id: Uuid,
+  pub resource_type: String,
+  pub resource_id: String,
+  pub permission: String,
+  pub user_id: Option<Uuid>,
+  pub group_id: Option<Uuid>,
+  pub created: DateTime,
+  #[sea_orm(
+    belongs_to,
+    from = "user_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub user: HasOne<super::user::Entity>,
+  #[sea_orm(
+    belongs_to,
+    from = "group_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub group: HasOne<super::group::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,29 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "api_token")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub name: String,
+  #[sea_orm(unique)]
+  pub token_hash: String,
+  pub created: DateTime,
+  pub last_used: Option<DateTime>,
+  pub exp: Option<DateTime>,
+  #[sea_orm(has_many)]
+  pub permissions: HasMany<super::api_token_permission::Entity>,
+  #[sea_orm(
+    belongs_to,
+    from = "user_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub user: BelongsTo<super::user::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,22 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "api_token_permission")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub token_id: Uuid,
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub permission: String,
+  #[sea_orm(
+    belongs_to,
+    from = "token_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub token: BelongsTo<super::api_token::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,22 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "audit_log")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub id: Uuid,
+  pub actor: Option<Uuid>,
+  pub action: String,
+  pub target_type: Option<String>,
+  pub target_id: Option<String>,
+  #[sea_orm(column_type = "Text", nullable)]
+  pub before: Option<String>,
+  #[sea_orm(column_type = "Text", nullable)]
+  pub after: Option<String>,
+  pub ip: Option<String>,
+  pub created: DateTime,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,23 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "client_secret")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub user_id: Uuid,
+  pub secret_hash: String,
+  pub created: DateTime,
+  pub last_used: Option<DateTime>,
+  #[sea_orm(
+    belongs_to,
+    from = "user_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub user: BelongsTo<super::user::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,18 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "ephemeral_entry")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub namespace: String,
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub key: String,
+  #[sea_orm(column_type = "Text")]
+  pub value: String,
+  pub counter: i64,
+  pub expires: DateTime,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,16 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "ephemeral_event")]
+pub struct Model {
+  #[sea_orm(primary_key)]
+  pub id: i32,
+  pub channel: String,
+  #[sea_orm(column_type = "Text")]
+  pub payload: String,
+  pub created: DateTime,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
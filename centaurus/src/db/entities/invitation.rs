@@ -0,0 +1,20 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Open invitation, becomes a user once the link is used.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "invitation")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub id: Uuid,
+  pub name: String,
+  #[sea_orm(unique)]
+  pub email: String,
+  #[sea_orm(unique)]
+  pub token_hash: String,
+  pub created: DateTime,
+  pub exp: DateTime,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
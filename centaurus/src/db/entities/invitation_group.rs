@@ -0,0 +1,31 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Group the invited user is added to on acceptance.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "invitation_group")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub invitation_id: Uuid,
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub group_id: Uuid,
+  #[sea_orm(
+    belongs_to,
+    from = "invitation_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub invitation: BelongsTo<super::invitation::Entity>,
+  #[sea_orm(
+    belongs_to,
+    from = "group_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub group: BelongsTo<super::group::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
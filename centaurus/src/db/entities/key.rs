@@ -9,6 +9,7 @@ pub struct Model {
   pub id: Uuid,
   pub name: String,
   pub private_key: String,
+  pub created: Option<DateTime>,
 }
 
 impl ActiveModelBehavior for ActiveModel {}
@@ -1,10 +1,31 @@
+pub mod acl;
+pub mod api_token;
+pub mod api_token_permission;
+pub mod audit_log;
+pub mod client_secret;
+pub mod ephemeral_entry;
+pub mod ephemeral_event;
 pub mod group;
 pub mod group_permission;
 pub mod group_user;
 pub mod invalid_jwt;
+pub mod invitation;
+pub mod invitation_group;
 pub mod key;
+pub mod login_attempt;
+pub mod oidc_client;
+pub mod oidc_consent;
+pub mod oidc_identity;
+pub mod pake_verifier;
+pub mod passkey;
+pub mod password_history;
+pub mod password_reset;
+pub mod recovery_code;
+pub mod refresh_token;
+pub mod session;
 pub mod settings;
 pub mod setup;
+pub mod totp;
 pub mod user;
 #[cfg(feature = "avatar")]
 pub mod user_avatar;
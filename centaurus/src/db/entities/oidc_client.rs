@@ -0,0 +1,22 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Downstream application logging in against the built-in OIDC provider.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "oidc_client")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub id: Uuid,
+  pub name: String,
+  /// `None` for public clients, which authenticate with PKCE only.
+  pub secret_hash: Option<String>,
+  /// Space separated, redirect uris have to match exactly.
+  #[sea_orm(column_type = "Text")]
+  pub redirect_uris: String,
+  /// First party clients are authorized without asking the user.
+  pub skip_consent: bool,
+  pub created: DateTime,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
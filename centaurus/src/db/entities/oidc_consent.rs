@@ -0,0 +1,34 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+/// Scopes a user allowed an OIDC client to access.
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "oidc_consent")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub user_id: Uuid,
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub client_id: Uuid,
+  /// Space separated.
+  pub scopes: String,
+  pub created: DateTime,
+  #[sea_orm(
+    belongs_to,
+    from = "user_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub user: BelongsTo<super::user::Entity>,
+  #[sea_orm(
+    belongs_to,
+    from = "client_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub client: BelongsTo<super::oidc_client::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
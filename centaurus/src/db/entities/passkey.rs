@@ -0,0 +1,31 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "passkey")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub id: Uuid,
+  pub user_id: Uuid,
+  #[sea_orm(unique)]
+  pub credential_id: String,
+  pub name: String,
+  /// Serialized `webauthn_rs_core::proto::Credential`.
+  #[sea_orm(column_type = "Text")]
+  pub credential: String,
+  pub created: DateTime,
+  pub last_used: Option<DateTime>,
+  /// Password logins of the user have to be completed with a passkey.
+  pub second_factor: bool,
+  #[sea_orm(
+    belongs_to,
+    from = "user_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub user: BelongsTo<super::user::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
@@ -0,0 +1,30 @@
+use sea_orm::entity::prelude::*;
+use serde::{Deserialize, Serialize};
+
+#[sea_orm::model]
+#[derive(Clone, Debug, PartialEq, Eq, DeriveEntityModel, Serialize, Deserialize)]
+#[sea_orm(table_name = "session")]
+pub struct Model {
+  #[sea_orm(primary_key, auto_increment = false)]
+  pub id: Uuid,
+  pub user_id: Uuid,
+  pub device: Option<String>,
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+  pub created: DateTime,
+  pub last_seen: DateTime,
+  /// Provider the session was started with, for logouts started by it.
+  pub oidc_provider: Option<String>,
+  /// Session id at the provider, the `sid` claim of the id token.
+  pub oidc_sid: Option<String>,
+  #[sea_orm(
+    belongs_to,
+    from = "user_id",
+    to = "id",
+    on_update = "Cascade",
+    on_delete = "Cascade"
+  )]
+  pub user: BelongsTo<super::user::Entity>,
+}
+
+impl ActiveModelBehavior for ActiveModel {}
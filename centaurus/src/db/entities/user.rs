@@ -15,6 +15,9 @@ pub struct Model {
   pub oidc_user: bool,
   #[sea_orm(unique)]
   pub oidc_subject: Option<String>,
+  pub service_account: bool,
+  /// Deactivated users cannot log in.
+  pub active: bool,
   #[cfg(feature = "avatar")]
   #[sea_orm(has_one)]
   pub user_avatar: HasOne<super::user_avatar::Entity>,
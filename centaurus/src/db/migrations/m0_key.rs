@@ -33,4 +33,5 @@ pub enum Key {
   Name,
   #[allow(clippy::enum_variant_names)]
   PrivateKey,
+  Created,
 }
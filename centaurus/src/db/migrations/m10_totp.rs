@@ -0,0 +1,98 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const RECOVERY_CODE_USER_INDEX_NAME: &str = "recovery_code.user_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Totp::Table)
+          .if_not_exists()
+          .col(pk_uuid(Totp::UserId))
+          .col(string(Totp::Secret))
+          .col(boolean(Totp::Enabled))
+          .col(big_integer_null(Totp::LastStep))
+          .col(date_time(Totp::Created))
+          .foreign_key(
+            ForeignKey::create()
+              .from(Totp::Table, Totp::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(RecoveryCode::Table)
+          .if_not_exists()
+          .col(pk_uuid(RecoveryCode::Id))
+          .col(uuid(RecoveryCode::UserId))
+          .col(string(RecoveryCode::CodeHash))
+          .col(boolean(RecoveryCode::Used))
+          .foreign_key(
+            ForeignKey::create()
+              .from(RecoveryCode::Table, RecoveryCode::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(RECOVERY_CODE_USER_INDEX_NAME)
+          .table(RecoveryCode::Table)
+          .col(RecoveryCode::UserId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop().name(RECOVERY_CODE_USER_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(RecoveryCode::Table).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(Totp::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum Totp {
+  Table,
+  UserId,
+  Secret,
+  Enabled,
+  LastStep,
+  Created,
+}
+
+#[derive(DeriveIden)]
+pub enum RecoveryCode {
+  Table,
+  Id,
+  UserId,
+  CodeHash,
+  Used,
+}
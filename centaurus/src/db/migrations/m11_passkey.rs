@@ -0,0 +1,88 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const PASSKEY_CREDENTIAL_INDEX_NAME: &str = "passkey.credential_id";
+const PASSKEY_USER_INDEX_NAME: &str = "passkey.user_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Passkey::Table)
+          .if_not_exists()
+          .col(pk_uuid(Passkey::Id))
+          .col(uuid(Passkey::UserId))
+          .col(string(Passkey::CredentialId))
+          .col(string(Passkey::Name))
+          .col(text(Passkey::Credential))
+          .col(date_time(Passkey::Created))
+          .col(date_time_null(Passkey::LastUsed))
+          .col(boolean(Passkey::SecondFactor).default(false))
+          .foreign_key(
+            ForeignKey::create()
+              .from(Passkey::Table, Passkey::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(PASSKEY_CREDENTIAL_INDEX_NAME)
+          .table(Passkey::Table)
+          .col(Passkey::CredentialId)
+          .unique()
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(PASSKEY_USER_INDEX_NAME)
+          .table(Passkey::Table)
+          .col(Passkey::UserId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop().name(PASSKEY_USER_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_index(Index::drop().name(PASSKEY_CREDENTIAL_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(Passkey::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum Passkey {
+  Table,
+  Id,
+  UserId,
+  CredentialId,
+  Name,
+  Credential,
+  Created,
+  LastUsed,
+  SecondFactor,
+}
@@ -0,0 +1,121 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const API_TOKEN_HASH_INDEX_NAME: &str = "api_token.token_hash";
+const API_TOKEN_USER_INDEX_NAME: &str = "api_token.user_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(ApiToken::Table)
+          .if_not_exists()
+          .col(pk_uuid(ApiToken::Id))
+          .col(uuid(ApiToken::UserId))
+          .col(string(ApiToken::Name))
+          .col(string(ApiToken::TokenHash))
+          .col(date_time(ApiToken::Created))
+          .col(date_time_null(ApiToken::LastUsed))
+          .col(date_time_null(ApiToken::Exp))
+          .foreign_key(
+            ForeignKey::create()
+              .from(ApiToken::Table, ApiToken::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(ApiTokenPermission::Table)
+          .if_not_exists()
+          .primary_key(
+            Index::create()
+              .table(ApiTokenPermission::Table)
+              .col(ApiTokenPermission::TokenId)
+              .col(ApiTokenPermission::Permission),
+          )
+          .col(uuid(ApiTokenPermission::TokenId))
+          .col(string(ApiTokenPermission::Permission))
+          .foreign_key(
+            ForeignKey::create()
+              .from(ApiTokenPermission::Table, ApiTokenPermission::TokenId)
+              .to(ApiToken::Table, ApiToken::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(API_TOKEN_HASH_INDEX_NAME)
+          .table(ApiToken::Table)
+          .col(ApiToken::TokenHash)
+          .unique()
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(API_TOKEN_USER_INDEX_NAME)
+          .table(ApiToken::Table)
+          .col(ApiToken::UserId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop().name(API_TOKEN_USER_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_index(Index::drop().name(API_TOKEN_HASH_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(ApiTokenPermission::Table).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(ApiToken::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum ApiToken {
+  Table,
+  Id,
+  UserId,
+  Name,
+  TokenHash,
+  Created,
+  LastUsed,
+  Exp,
+}
+
+#[derive(DeriveIden)]
+pub enum ApiTokenPermission {
+  Table,
+  TokenId,
+  Permission,
+}
@@ -0,0 +1,64 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .alter_table(
+        Table::alter()
+          .table(User::Table)
+          .add_column(boolean(User::ServiceAccount).default(false))
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(ClientSecret::Table)
+          .if_not_exists()
+          .col(pk_uuid(ClientSecret::UserId))
+          .col(string(ClientSecret::SecretHash))
+          .col(date_time(ClientSecret::Created))
+          .col(date_time_null(ClientSecret::LastUsed))
+          .foreign_key(
+            ForeignKey::create()
+              .from(ClientSecret::Table, ClientSecret::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(ClientSecret::Table).to_owned())
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(User::Table)
+          .drop_column(User::ServiceAccount)
+          .to_owned(),
+      )
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum ClientSecret {
+  Table,
+  UserId,
+  SecretHash,
+  Created,
+  LastUsed,
+}
@@ -0,0 +1,90 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::{m3_user::User, m4_groups::Group};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const ACL_RESOURCE_INDEX_NAME: &str = "acl.resource";
+const ACL_SUBJECT_CHECK_NAME: &str = "acl_subject";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Acl::Table)
+          .if_not_exists()
+          .col(pk_uuid(Acl::Id))
+          .col(string(Acl::ResourceType))
+          .col(string(Acl::ResourceId))
+          .col(string(Acl::Permission))
+          .col(uuid_null(Acl::UserId))
+          .col(uuid_null(Acl::GroupId))
+          .col(date_time(Acl::Created))
+          // a grant applies to exactly one user or group
+          .check((
+            ACL_SUBJECT_CHECK_NAME,
+            Expr::col(Acl::UserId)
+              .is_not_null()
+              .and(Expr::col(Acl::GroupId).is_null())
+              .or(
+                Expr::col(Acl::UserId)
+                  .is_null()
+                  .and(Expr::col(Acl::GroupId).is_not_null()),
+              ),
+          ))
+          .foreign_key(
+            ForeignKey::create()
+              .from(Acl::Table, Acl::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .foreign_key(
+            ForeignKey::create()
+              .from(Acl::Table, Acl::GroupId)
+              .to(Group::Table, Group::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(ACL_RESOURCE_INDEX_NAME)
+          .table(Acl::Table)
+          .col(Acl::ResourceType)
+          .col(Acl::ResourceId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop().name(ACL_RESOURCE_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(Acl::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum Acl {
+  Table,
+  Id,
+  ResourceType,
+  ResourceId,
+  Permission,
+  UserId,
+  GroupId,
+  Created,
+}
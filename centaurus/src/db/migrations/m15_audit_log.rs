@@ -0,0 +1,97 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const AUDIT_LOG_CREATED_INDEX_NAME: &str = "audit_log.created";
+const AUDIT_LOG_ACTOR_INDEX_NAME: &str = "audit_log.actor";
+const AUDIT_LOG_TARGET_INDEX_NAME: &str = "audit_log.target";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    // no foreign keys, entries have to outlive the users and objects they
+    // refer to
+    manager
+      .create_table(
+        Table::create()
+          .table(AuditLog::Table)
+          .if_not_exists()
+          .col(pk_uuid(AuditLog::Id))
+          .col(uuid_null(AuditLog::Actor))
+          .col(string(AuditLog::Action))
+          .col(string_null(AuditLog::TargetType))
+          .col(string_null(AuditLog::TargetId))
+          .col(text_null(AuditLog::Before))
+          .col(text_null(AuditLog::After))
+          .col(string_null(AuditLog::Ip))
+          .col(date_time(AuditLog::Created))
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(AUDIT_LOG_CREATED_INDEX_NAME)
+          .table(AuditLog::Table)
+          .col(AuditLog::Created)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(AUDIT_LOG_ACTOR_INDEX_NAME)
+          .table(AuditLog::Table)
+          .col(AuditLog::Actor)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(AUDIT_LOG_TARGET_INDEX_NAME)
+          .table(AuditLog::Table)
+          .col(AuditLog::TargetType)
+          .col(AuditLog::TargetId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    for name in [
+      AUDIT_LOG_TARGET_INDEX_NAME,
+      AUDIT_LOG_ACTOR_INDEX_NAME,
+      AUDIT_LOG_CREATED_INDEX_NAME,
+    ] {
+      manager
+        .drop_index(Index::drop().name(name).to_owned())
+        .await?;
+    }
+
+    manager
+      .drop_table(Table::drop().table(AuditLog::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum AuditLog {
+  Table,
+  Id,
+  Actor,
+  Action,
+  TargetType,
+  TargetId,
+  Before,
+  After,
+  Ip,
+  Created,
+}
@@ -0,0 +1,46 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(LoginAttempt::Table)
+          .if_not_exists()
+          .col(pk_uuid(LoginAttempt::UserId))
+          .col(integer(LoginAttempt::Failed))
+          .col(date_time(LoginAttempt::LastFailed))
+          .col(date_time_null(LoginAttempt::LockedUntil))
+          .foreign_key(
+            ForeignKey::create()
+              .from(LoginAttempt::Table, LoginAttempt::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(LoginAttempt::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum LoginAttempt {
+  Table,
+  UserId,
+  Failed,
+  LastFailed,
+  LockedUntil,
+}
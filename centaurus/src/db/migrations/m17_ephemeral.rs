@@ -0,0 +1,113 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const EPHEMERAL_ENTRY_EXPIRES_INDEX_NAME: &str = "ephemeral_entry.expires";
+const EPHEMERAL_EVENT_CHANNEL_INDEX_NAME: &str = "ephemeral_event.channel";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(EphemeralEntry::Table)
+          .if_not_exists()
+          .col(string(EphemeralEntry::Namespace))
+          .col(string(EphemeralEntry::Key))
+          .col(text(EphemeralEntry::Value))
+          .col(big_integer(EphemeralEntry::Counter))
+          .col(date_time(EphemeralEntry::Expires))
+          .primary_key(
+            Index::create()
+              .table(EphemeralEntry::Table)
+              .col(EphemeralEntry::Namespace)
+              .col(EphemeralEntry::Key),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(EPHEMERAL_ENTRY_EXPIRES_INDEX_NAME)
+          .table(EphemeralEntry::Table)
+          .col(EphemeralEntry::Expires)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(EphemeralEvent::Table)
+          .if_not_exists()
+          .col(pk_auto(EphemeralEvent::Id))
+          .col(string(EphemeralEvent::Channel))
+          .col(text(EphemeralEvent::Payload))
+          .col(date_time(EphemeralEvent::Created))
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(EPHEMERAL_EVENT_CHANNEL_INDEX_NAME)
+          .table(EphemeralEvent::Table)
+          .col(EphemeralEvent::Channel)
+          .col(EphemeralEvent::Id)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(
+        Index::drop()
+          .name(EPHEMERAL_EVENT_CHANNEL_INDEX_NAME)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(EphemeralEvent::Table).to_owned())
+      .await?;
+
+    manager
+      .drop_index(
+        Index::drop()
+          .name(EPHEMERAL_ENTRY_EXPIRES_INDEX_NAME)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(EphemeralEntry::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum EphemeralEntry {
+  Table,
+  Namespace,
+  Key,
+  Value,
+  Counter,
+  Expires,
+}
+
+#[derive(DeriveIden)]
+pub enum EphemeralEvent {
+  Table,
+  Id,
+  Channel,
+  Payload,
+  Created,
+}
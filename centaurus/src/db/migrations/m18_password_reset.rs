@@ -0,0 +1,90 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const PASSWORD_RESET_HASH_INDEX_NAME: &str = "password_reset.token_hash";
+const PASSWORD_RESET_USER_INDEX_NAME: &str = "password_reset.user_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(PasswordReset::Table)
+          .if_not_exists()
+          .col(pk_uuid(PasswordReset::Id))
+          .col(uuid(PasswordReset::UserId))
+          .col(string(PasswordReset::TokenHash))
+          .col(date_time(PasswordReset::Created))
+          .col(date_time(PasswordReset::Exp))
+          .foreign_key(
+            ForeignKey::create()
+              .from(PasswordReset::Table, PasswordReset::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(PASSWORD_RESET_HASH_INDEX_NAME)
+          .table(PasswordReset::Table)
+          .col(PasswordReset::TokenHash)
+          .unique()
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(PASSWORD_RESET_USER_INDEX_NAME)
+          .table(PasswordReset::Table)
+          .col(PasswordReset::UserId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(
+        Index::drop()
+          .name(PASSWORD_RESET_USER_INDEX_NAME)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .drop_index(
+        Index::drop()
+          .name(PASSWORD_RESET_HASH_INDEX_NAME)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(PasswordReset::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum PasswordReset {
+  Table,
+  Id,
+  UserId,
+  TokenHash,
+  Created,
+  Exp,
+}
@@ -0,0 +1,67 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const PASSWORD_HISTORY_USER_INDEX_NAME: &str = "password_history.user_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(PasswordHistory::Table)
+          .if_not_exists()
+          .col(pk_uuid(PasswordHistory::Id))
+          .col(uuid(PasswordHistory::UserId))
+          .col(string(PasswordHistory::Hash))
+          .col(date_time(PasswordHistory::Created))
+          .foreign_key(
+            ForeignKey::create()
+              .from(PasswordHistory::Table, PasswordHistory::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(PASSWORD_HISTORY_USER_INDEX_NAME)
+          .table(PasswordHistory::Table)
+          .col(PasswordHistory::UserId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(
+        Index::drop()
+          .name(PASSWORD_HISTORY_USER_INDEX_NAME)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(PasswordHistory::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum PasswordHistory {
+  Table,
+  Id,
+  UserId,
+  Hash,
+  Created,
+}
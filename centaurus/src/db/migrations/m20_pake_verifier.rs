@@ -0,0 +1,46 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(PakeVerifier::Table)
+          .if_not_exists()
+          .col(pk_uuid(PakeVerifier::UserId))
+          .col(string(PakeVerifier::Salt))
+          .col(text(PakeVerifier::Verifier))
+          .col(date_time(PakeVerifier::Created))
+          .foreign_key(
+            ForeignKey::create()
+              .from(PakeVerifier::Table, PakeVerifier::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(PakeVerifier::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum PakeVerifier {
+  Table,
+  UserId,
+  Salt,
+  Verifier,
+  Created,
+}
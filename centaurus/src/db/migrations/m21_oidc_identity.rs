@@ -0,0 +1,87 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const OIDC_IDENTITY_SUBJECT_INDEX_NAME: &str = "oidc_identity.provider_subject";
+const OIDC_IDENTITY_USER_INDEX_NAME: &str = "oidc_identity.user_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(OidcIdentity::Table)
+          .if_not_exists()
+          .col(pk_uuid(OidcIdentity::Id))
+          .col(uuid(OidcIdentity::UserId))
+          .col(string(OidcIdentity::Provider))
+          .col(string(OidcIdentity::Subject))
+          .col(date_time(OidcIdentity::Created))
+          .foreign_key(
+            ForeignKey::create()
+              .from(OidcIdentity::Table, OidcIdentity::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(OIDC_IDENTITY_SUBJECT_INDEX_NAME)
+          .table(OidcIdentity::Table)
+          .col(OidcIdentity::Provider)
+          .col(OidcIdentity::Subject)
+          .unique()
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(OIDC_IDENTITY_USER_INDEX_NAME)
+          .table(OidcIdentity::Table)
+          .col(OidcIdentity::UserId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop().name(OIDC_IDENTITY_USER_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_index(
+        Index::drop()
+          .name(OIDC_IDENTITY_SUBJECT_INDEX_NAME)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(OidcIdentity::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum OidcIdentity {
+  Table,
+  Id,
+  UserId,
+  Provider,
+  Subject,
+  Created,
+}
@@ -0,0 +1,68 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m9_session::Session;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const SESSION_OIDC_SID_INDEX_NAME: &str = "session.oidc_provider_sid";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    // sqlite can only add one column per statement
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Session::Table)
+          .add_column(string_null(Session::OidcProvider))
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Session::Table)
+          .add_column(string_null(Session::OidcSid))
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(SESSION_OIDC_SID_INDEX_NAME)
+          .table(Session::Table)
+          .col(Session::OidcProvider)
+          .col(Session::OidcSid)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop().name(SESSION_OIDC_SID_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Session::Table)
+          .drop_column(Session::OidcSid)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .alter_table(
+        Table::alter()
+          .table(Session::Table)
+          .drop_column(Session::OidcProvider)
+          .to_owned(),
+      )
+      .await
+  }
+}
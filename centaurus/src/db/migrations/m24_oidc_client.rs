@@ -0,0 +1,89 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(OidcClient::Table)
+          .if_not_exists()
+          .col(pk_uuid(OidcClient::Id))
+          .col(string(OidcClient::Name))
+          .col(string_null(OidcClient::SecretHash))
+          .col(text(OidcClient::RedirectUris))
+          .col(boolean(OidcClient::SkipConsent).default(false))
+          .col(date_time(OidcClient::Created))
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(OidcConsent::Table)
+          .if_not_exists()
+          .primary_key(
+            Index::create()
+              .table(OidcConsent::Table)
+              .col(OidcConsent::UserId)
+              .col(OidcConsent::ClientId),
+          )
+          .col(uuid(OidcConsent::UserId))
+          .col(uuid(OidcConsent::ClientId))
+          .col(string(OidcConsent::Scopes))
+          .col(date_time(OidcConsent::Created))
+          .foreign_key(
+            ForeignKey::create()
+              .from(OidcConsent::Table, OidcConsent::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .foreign_key(
+            ForeignKey::create()
+              .from(OidcConsent::Table, OidcConsent::ClientId)
+              .to(OidcClient::Table, OidcClient::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(OidcConsent::Table).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(OidcClient::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum OidcClient {
+  Table,
+  Id,
+  Name,
+  SecretHash,
+  RedirectUris,
+  SkipConsent,
+  Created,
+}
+
+#[derive(DeriveIden)]
+enum OidcConsent {
+  Table,
+  UserId,
+  ClientId,
+  Scopes,
+  Created,
+}
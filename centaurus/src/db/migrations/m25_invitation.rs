@@ -0,0 +1,120 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m4_groups::Group;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const INVITATION_EMAIL_INDEX_NAME: &str = "invitation.email";
+const INVITATION_HASH_INDEX_NAME: &str = "invitation.token_hash";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Invitation::Table)
+          .if_not_exists()
+          .col(pk_uuid(Invitation::Id))
+          .col(string(Invitation::Name))
+          .col(string(Invitation::Email))
+          .col(string(Invitation::TokenHash))
+          .col(date_time(Invitation::Created))
+          .col(date_time(Invitation::Exp))
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(INVITATION_EMAIL_INDEX_NAME)
+          .table(Invitation::Table)
+          .col(Invitation::Email)
+          .unique()
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(INVITATION_HASH_INDEX_NAME)
+          .table(Invitation::Table)
+          .col(Invitation::TokenHash)
+          .unique()
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_table(
+        Table::create()
+          .table(InvitationGroup::Table)
+          .if_not_exists()
+          .primary_key(
+            Index::create()
+              .table(InvitationGroup::Table)
+              .col(InvitationGroup::InvitationId)
+              .col(InvitationGroup::GroupId),
+          )
+          .col(uuid(InvitationGroup::InvitationId))
+          .col(uuid(InvitationGroup::GroupId))
+          .foreign_key(
+            ForeignKey::create()
+              .from(InvitationGroup::Table, InvitationGroup::InvitationId)
+              .to(Invitation::Table, Invitation::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .foreign_key(
+            ForeignKey::create()
+              .from(InvitationGroup::Table, InvitationGroup::GroupId)
+              .to(Group::Table, Group::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_table(Table::drop().table(InvitationGroup::Table).to_owned())
+      .await?;
+
+    manager
+      .drop_index(Index::drop().name(INVITATION_HASH_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_index(Index::drop().name(INVITATION_EMAIL_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(Invitation::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum Invitation {
+  Table,
+  Id,
+  Name,
+  Email,
+  TokenHash,
+  Created,
+  Exp,
+}
+
+#[derive(DeriveIden)]
+enum InvitationGroup {
+  Table,
+  InvitationId,
+  GroupId,
+}
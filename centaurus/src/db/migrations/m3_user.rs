@@ -81,6 +81,8 @@ pub enum User {
   Salt,
   OidcUser,
   OidcSubject,
+  ServiceAccount,
+  Active,
 }
 
 #[cfg(feature = "avatar")]
@@ -0,0 +1,90 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const REFRESH_TOKEN_HASH_INDEX_NAME: &str = "refresh_token.token_hash";
+const REFRESH_TOKEN_FAMILY_INDEX_NAME: &str = "refresh_token.family";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(RefreshToken::Table)
+          .if_not_exists()
+          .col(pk_uuid(RefreshToken::Id))
+          .col(uuid(RefreshToken::Family))
+          .col(uuid(RefreshToken::UserId))
+          .col(string(RefreshToken::TokenHash))
+          .col(boolean(RefreshToken::Used))
+          .col(date_time(RefreshToken::Created))
+          .col(date_time(RefreshToken::Exp))
+          .foreign_key(
+            ForeignKey::create()
+              .from(RefreshToken::Table, RefreshToken::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(REFRESH_TOKEN_HASH_INDEX_NAME)
+          .table(RefreshToken::Table)
+          .col(RefreshToken::TokenHash)
+          .unique()
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(REFRESH_TOKEN_FAMILY_INDEX_NAME)
+          .table(RefreshToken::Table)
+          .col(RefreshToken::Family)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(
+        Index::drop()
+          .name(REFRESH_TOKEN_FAMILY_INDEX_NAME)
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .drop_index(Index::drop().name(REFRESH_TOKEN_HASH_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(RefreshToken::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum RefreshToken {
+  Table,
+  Id,
+  Family,
+  UserId,
+  TokenHash,
+  Used,
+  Created,
+  Exp,
+}
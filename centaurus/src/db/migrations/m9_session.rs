@@ -0,0 +1,71 @@
+use sea_orm_migration::{prelude::*, schema::*};
+
+use crate::db::migrations::m3_user::User;
+
+#[derive(DeriveMigrationName)]
+pub struct Migration;
+
+const SESSION_USER_ID_INDEX_NAME: &str = "session.user_id";
+
+#[async_trait::async_trait]
+impl MigrationTrait for Migration {
+  async fn up(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .create_table(
+        Table::create()
+          .table(Session::Table)
+          .if_not_exists()
+          .col(pk_uuid(Session::Id))
+          .col(uuid(Session::UserId))
+          .col(string_null(Session::Device))
+          .col(string_null(Session::Ip))
+          .col(string_null(Session::UserAgent))
+          .col(date_time(Session::Created))
+          .col(date_time(Session::LastSeen))
+          .foreign_key(
+            ForeignKey::create()
+              .from(Session::Table, Session::UserId)
+              .to(User::Table, User::Id)
+              .on_delete(ForeignKeyAction::Cascade)
+              .on_update(ForeignKeyAction::Cascade),
+          )
+          .to_owned(),
+      )
+      .await?;
+
+    manager
+      .create_index(
+        Index::create()
+          .if_not_exists()
+          .name(SESSION_USER_ID_INDEX_NAME)
+          .table(Session::Table)
+          .col(Session::UserId)
+          .to_owned(),
+      )
+      .await
+  }
+
+  async fn down(&self, manager: &SchemaManager) -> Result<(), DbErr> {
+    manager
+      .drop_index(Index::drop().name(SESSION_USER_ID_INDEX_NAME).to_owned())
+      .await?;
+
+    manager
+      .drop_table(Table::drop().table(Session::Table).to_owned())
+      .await
+  }
+}
+
+#[derive(DeriveIden)]
+pub enum Session {
+  Table,
+  Id,
+  UserId,
+  Device,
+  Ip,
+  UserAgent,
+  Created,
+  LastSeen,
+  OidcProvider,
+  OidcSid,
+}
@@ -1,12 +1,31 @@
 use sea_orm_migration::prelude::*;
 
 pub mod m0_key;
+pub mod m10_totp;
+pub mod m11_passkey;
+pub mod m12_api_token;
+pub mod m13_service_account;
+pub mod m14_acl;
+pub mod m15_audit_log;
+pub mod m16_login_attempt;
+pub mod m17_ephemeral;
+pub mod m18_password_reset;
+pub mod m19_password_history;
 pub mod m1_invalid_jwt;
+pub mod m20_pake_verifier;
+pub mod m21_oidc_identity;
+pub mod m22_session_oidc;
+pub mod m23_user_active;
+pub mod m24_oidc_client;
+pub mod m25_invitation;
 pub mod m2_settings;
 pub mod m3_user;
 pub mod m4_groups;
 pub mod m5_setup;
 pub mod m6_user_oidc_subject;
+pub mod m7_key_created;
+pub mod m8_refresh_token;
+pub mod m9_session;
 
 pub struct Migrator;
 
@@ -21,6 +40,25 @@ impl MigratorTrait for Migrator {
       Box::new(m4_groups::Migration),
       Box::new(m5_setup::Migration),
       Box::new(m6_user_oidc_subject::Migration),
+      Box::new(m7_key_created::Migration),
+      Box::new(m8_refresh_token::Migration),
+      Box::new(m9_session::Migration),
+      Box::new(m10_totp::Migration),
+      Box::new(m11_passkey::Migration),
+      Box::new(m12_api_token::Migration),
+      Box::new(m13_service_account::Migration),
+      Box::new(m14_acl::Migration),
+      Box::new(m15_audit_log::Migration),
+      Box::new(m16_login_attempt::Migration),
+      Box::new(m17_ephemeral::Migration),
+      Box::new(m18_password_reset::Migration),
+      Box::new(m19_password_history::Migration),
+      Box::new(m20_pake_verifier::Migration),
+      Box::new(m21_oidc_identity::Migration),
+      Box::new(m22_session_oidc::Migration),
+      Box::new(m23_user_active::Migration),
+      Box::new(m24_oidc_client::Migration),
+      Box::new(m25_invitation::Migration),
     ]
   }
 }
@@ -2,5 +2,6 @@ pub mod config;
 pub mod entities;
 pub mod init;
 pub mod migrations;
+pub mod permission;
 pub mod settings;
 pub mod tables;
@@ -0,0 +1,143 @@
+//! Matching of granted permissions against required ones.
+//!
+//! A granted permission either names a permission directly, ends in `:*` to
+//! cover everything with that prefix (`user:*` covers `user:view`) or is `*`
+//! which covers every permission. Permissions can also imply others, e.g.
+//! `settings:edit` implies `settings:view`. Implications are registered once
+//! per process, see `backend::auth::permission::register_permission`.
+
+use std::{
+  collections::{BTreeSet, HashMap},
+  sync::{LazyLock, RwLock},
+};
+
+static IMPLICATIONS: LazyLock<RwLock<HashMap<String, Vec<String>>>> = LazyLock::new(|| {
+  #[allow(unused_mut)]
+  let mut map = HashMap::new();
+  #[cfg(feature = "endpoints")]
+  for (name, implies) in crate::backend::auth::permission::builtin_permissions() {
+    map.insert(
+      name.to_string(),
+      implies.into_iter().map(str::to_string).collect(),
+    );
+  }
+  RwLock::new(map)
+});
+
+/// Make the permission known and declare which permissions it implies.
+/// Registering the same permission again replaces its implications.
+pub fn register_permission(name: &str, implies: &[&str]) {
+  IMPLICATIONS
+    .write()
+    .expect("permission registry poisoned")
+    .insert(
+      name.to_string(),
+      implies.iter().map(|p| p.to_string()).collect(),
+    );
+}
+
+/// Whether `granted` covers `required`. Also works if `required` is a
+/// wildcard itself, `*` covers `user:*` but `user:*` does not cover `*`.
+pub fn permission_matches(granted: &str, required: &str) -> bool {
+  if granted == "*" || granted == required {
+    return true;
+  }
+
+  match granted.strip_suffix('*') {
+    Some(prefix) if prefix.ends_with(':') => required.starts_with(prefix),
+    _ => false,
+  }
+}
+
+/// The granted permissions together with every known permission they cover
+/// through wildcards or implications, sorted and without duplicates.
+pub fn effective_permissions<I: IntoIterator<Item = String>>(granted: I) -> Vec<String> {
+  let implications = IMPLICATIONS.read().expect("permission registry poisoned");
+  let granted: Vec<String> = granted.into_iter().collect();
+
+  let mut effective: BTreeSet<String> = granted.iter().cloned().collect();
+  effective.extend(
+    implications
+      .keys()
+      .filter(|known| granted.iter().any(|g| permission_matches(g, known)))
+      .cloned(),
+  );
+
+  let mut pending: Vec<String> = effective.iter().cloned().collect();
+  while let Some(permission) = pending.pop() {
+    for implied in implications.get(&permission).into_iter().flatten() {
+      if effective.insert(implied.clone()) {
+        pending.push(implied.clone());
+      }
+    }
+  }
+
+  effective.into_iter().collect()
+}
+
+/// Whether the granted permissions cover `required`, including wildcards and
+/// implications.
+pub fn has_permission(granted: &[String], required: &str) -> bool {
+  effective_permissions(granted.iter().cloned())
+    .iter()
+    .any(|p| permission_matches(p, required))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn strings(perms: &[&str]) -> Vec<String> {
+    perms.iter().map(|p| p.to_string()).collect()
+  }
+
+  #[test]
+  fn test_wildcards() {
+    assert!(permission_matches("user:view", "user:view"));
+    assert!(permission_matches("user:*", "user:view"));
+    assert!(permission_matches("user:*", "user:*"));
+    assert!(permission_matches("*", "group:edit"));
+    assert!(permission_matches("*", "user:*"));
+    assert!(!permission_matches("user:*", "group:view"));
+    assert!(!permission_matches("user:*", "*"));
+    assert!(!permission_matches("user*", "user:view"));
+    assert!(!permission_matches("user:view", "user:edit"));
+  }
+
+  #[test]
+  fn test_implications_are_transitive() {
+    register_permission("test_impl:admin", &["test_impl:edit"]);
+    register_permission("test_impl:edit", &["test_impl:view"]);
+    register_permission("test_impl:view", &[]);
+
+    let effective = effective_permissions(strings(&["test_impl:admin"]));
+    assert_eq!(
+      effective,
+      strings(&["test_impl:admin", "test_impl:edit", "test_impl:view"])
+    );
+    assert!(has_permission(
+      &strings(&["test_impl:edit"]),
+      "test_impl:view"
+    ));
+    assert!(!has_permission(
+      &strings(&["test_impl:view"]),
+      "test_impl:edit"
+    ));
+  }
+
+  #[test]
+  fn test_wildcards_expand_to_known_permissions() {
+    register_permission("test_wild:view", &[]);
+    register_permission("test_wild:edit", &[]);
+
+    let effective = effective_permissions(strings(&["test_wild:*"]));
+    assert!(effective.contains(&"test_wild:*".to_string()));
+    assert!(effective.contains(&"test_wild:view".to_string()));
+    assert!(effective.contains(&"test_wild:edit".to_string()));
+    // Unknown permissions are still covered by the wildcard.
+    assert!(has_permission(
+      &strings(&["test_wild:*"]),
+      "test_wild:other"
+    ));
+  }
+}
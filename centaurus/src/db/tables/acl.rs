@@ -0,0 +1,336 @@
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{ActiveValue::Set, Condition, QueryOrder, prelude::*};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+  db::{
+    entities::{acl, group_user},
+    permission::has_permission,
+  },
+  error::Result,
+};
+
+pub struct AclTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+/// Who a grant applies to.
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq, Eq)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+#[serde(tag = "type", content = "uuid", rename_all = "lowercase")]
+pub enum AclSubject {
+  User(Uuid),
+  Group(Uuid),
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct AclInfo {
+  pub uuid: Uuid,
+  pub resource_type: String,
+  pub resource_id: String,
+  pub permission: String,
+  pub subject: AclSubject,
+  pub created: NaiveDateTime,
+}
+
+impl TryFrom<acl::Model> for AclInfo {
+  type Error = DbErr;
+
+  fn try_from(model: acl::Model) -> std::result::Result<Self, DbErr> {
+    let subject = match (model.user_id, model.group_id) {
+      (Some(user), None) => AclSubject::User(user),
+      (None, Some(group)) => AclSubject::Group(group),
+      _ => {
+        return Err(DbErr::Type(format!(
+          "acl entry {} needs exactly one subject",
+          model.id
+        )));
+      }
+    };
+
+    Ok(Self {
+      uuid: model.id,
+      resource_type: model.resource_type,
+      resource_id: model.resource_id,
+      permission: model.permission,
+      subject,
+      created: model.created,
+    })
+  }
+}
+
+impl<'db, C: ConnectionTrait> AclTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  fn subject_condition(subject: AclSubject) -> Condition {
+    match subject {
+      AclSubject::User(user) => Condition::all().add(acl::Column::UserId.eq(user)),
+      AclSubject::Group(group) => Condition::all().add(acl::Column::GroupId.eq(group)),
+    }
+  }
+
+  /// Grant the permission on a single resource. Granting an existing entry
+  /// again returns the id of the existing one.
+  #[instrument(skip(self))]
+  pub async fn grant(
+    &self,
+    resource_type: &str,
+    resource_id: &str,
+    permission: &str,
+    subject: AclSubject,
+  ) -> Result<Uuid> {
+    if let Some(existing) = acl::Entity::find()
+      .filter(acl::Column::ResourceType.eq(resource_type))
+      .filter(acl::Column::ResourceId.eq(resource_id))
+      .filter(acl::Column::Permission.eq(permission))
+      .filter(Self::subject_condition(subject))
+      .one(self.db)
+      .await?
+    {
+      return Ok(existing.id);
+    }
+
+    let (user_id, group_id) = match subject {
+      AclSubject::User(user) => (Some(user), None),
+      AclSubject::Group(group) => (None, Some(group)),
+    };
+    let id = Uuid::now_v7();
+    let model = acl::ActiveModel {
+      id: Set(id),
+      resource_type: Set(resource_type.to_string()),
+      resource_id: Set(resource_id.to_string()),
+      permission: Set(permission.to_string()),
+      user_id: Set(user_id),
+      group_id: Set(group_id),
+      created: Set(Utc::now().naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(id)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_grant(&self, id: Uuid) -> Result<Option<AclInfo>> {
+    Ok(
+      acl::Entity::find_by_id(id)
+        .one(self.db)
+        .await?
+        .map(AclInfo::try_from)
+        .transpose()?,
+    )
+  }
+
+  /// Returns false if there is no grant with this id.
+  #[instrument(skip(self))]
+  pub async fn revoke(&self, id: Uuid) -> Result<bool> {
+    let res = acl::Entity::delete_by_id(id).exec(self.db).await?;
+    Ok(res.rows_affected == 1)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_resource_grants(
+    &self,
+    resource_type: &str,
+    resource_id: &str,
+  ) -> Result<Vec<AclInfo>> {
+    Ok(
+      acl::Entity::find()
+        .filter(acl::Column::ResourceType.eq(resource_type))
+        .filter(acl::Column::ResourceId.eq(resource_id))
+        .order_by_asc(acl::Column::Created)
+        .all(self.db)
+        .await?
+        .into_iter()
+        .map(AclInfo::try_from)
+        .collect::<std::result::Result<_, _>>()?,
+    )
+  }
+
+  /// Permissions the user holds on the resource, either directly or through
+  /// one of their groups.
+  #[instrument(skip(self))]
+  pub async fn get_user_resource_permissions(
+    &self,
+    user_id: Uuid,
+    resource_type: &str,
+    resource_id: &str,
+  ) -> Result<Vec<String>> {
+    let groups: Vec<Uuid> = group_user::Entity::find()
+      .filter(group_user::Column::UserId.eq(user_id))
+      .all(self.db)
+      .await?
+      .into_iter()
+      .map(|gu| gu.group_id)
+      .collect();
+
+    Ok(
+      acl::Entity::find()
+        .filter(acl::Column::ResourceType.eq(resource_type))
+        .filter(acl::Column::ResourceId.eq(resource_id))
+        .filter(
+          Condition::any()
+            .add(acl::Column::UserId.eq(user_id))
+            .add(acl::Column::GroupId.is_in(groups)),
+        )
+        .all(self.db)
+        .await?
+        .into_iter()
+        .map(|entry| entry.permission)
+        .collect(),
+    )
+  }
+
+  /// Whether a grant on the resource covers the permission, wildcards and
+  /// implications apply like for group permissions.
+  #[instrument(skip(self))]
+  pub async fn user_has_access(
+    &self,
+    user_id: Uuid,
+    resource_type: &str,
+    resource_id: &str,
+    permission: &str,
+  ) -> Result<bool> {
+    let granted = self
+      .get_user_resource_permissions(user_id, resource_type, resource_id)
+      .await?;
+    Ok(has_permission(&granted, permission))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_user_grant_is_scoped_to_resource() {
+    let (conn, user) = setup().await;
+    let table = conn.acl();
+
+    let id = table
+      .grant("project", "42", "project:edit", AclSubject::User(user))
+      .await
+      .unwrap();
+    let again = table
+      .grant("project", "42", "project:edit", AclSubject::User(user))
+      .await
+      .unwrap();
+    assert_eq!(id, again);
+    assert_eq!(
+      table
+        .list_resource_grants("project", "42")
+        .await
+        .unwrap()
+        .len(),
+      1
+    );
+
+    assert!(
+      table
+        .user_has_access(user, "project", "42", "project:edit")
+        .await
+        .unwrap()
+    );
+    assert!(
+      !table
+        .user_has_access(user, "project", "43", "project:edit")
+        .await
+        .unwrap()
+    );
+    assert!(
+      !table
+        .user_has_access(user, "project", "42", "project:delete")
+        .await
+        .unwrap()
+    );
+
+    assert!(table.revoke(id).await.unwrap());
+    assert!(!table.revoke(id).await.unwrap());
+    assert!(
+      !table
+        .user_has_access(user, "project", "42", "project:edit")
+        .await
+        .unwrap()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_grant_needs_exactly_one_subject() {
+    let (conn, user) = setup().await;
+    let group = conn.group().create_group("team".into()).await.unwrap();
+
+    for (user_id, group_id) in [(None, None), (Some(user), Some(group))] {
+      let model = acl::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        resource_type: Set("project".into()),
+        resource_id: Set("42".into()),
+        permission: Set("project:edit".into()),
+        user_id: Set(user_id),
+        group_id: Set(group_id),
+        created: Set(Utc::now().naive_utc()),
+      };
+      assert!(model.insert(&*conn).await.is_err());
+    }
+  }
+
+  #[tokio::test]
+  async fn test_group_grant_applies_to_members() {
+    let (conn, user) = setup().await;
+    let group = conn.group().create_group("team".into()).await.unwrap();
+    conn
+      .group()
+      .add_users_to_group(group, vec![user])
+      .await
+      .unwrap();
+
+    conn
+      .acl()
+      .grant("project", "42", "project:*", AclSubject::Group(group))
+      .await
+      .unwrap();
+    assert!(
+      conn
+        .acl()
+        .user_has_access(user, "project", "42", "project:view")
+        .await
+        .unwrap()
+    );
+
+    // Deleting the group removes its grants.
+    conn.group().delete_group(group).await.unwrap();
+    assert!(
+      conn
+        .acl()
+        .list_resource_grants("project", "42")
+        .await
+        .unwrap()
+        .is_empty()
+    );
+  }
+}
@@ -0,0 +1,235 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use sea_orm::{ActiveValue::Set, IntoActiveModel, QueryOrder, prelude::*, sea_query::Expr};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+  db::entities::{api_token, api_token_permission},
+  error::Result,
+};
+
+/// `last_used` is only written when it is older than this.
+const TOUCH_INTERVAL: i64 = 60;
+
+pub struct ApiTokenTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct ApiTokenInfo {
+  pub uuid: Uuid,
+  pub name: String,
+  pub permissions: Vec<String>,
+  pub created: NaiveDateTime,
+  pub last_used: Option<NaiveDateTime>,
+  pub exp: Option<NaiveDateTime>,
+}
+
+impl<'db, C: ConnectionTrait> ApiTokenTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self, token_hash))]
+  pub async fn create_api_token(
+    &self,
+    user_id: Uuid,
+    name: String,
+    token_hash: String,
+    permissions: Vec<String>,
+    exp: Option<NaiveDateTime>,
+  ) -> Result<Uuid> {
+    let id = Uuid::now_v7();
+    let model = api_token::ActiveModel {
+      id: Set(id),
+      user_id: Set(user_id),
+      name: Set(name),
+      token_hash: Set(token_hash),
+      created: Set(Utc::now().naive_utc()),
+      last_used: Set(None),
+      exp: Set(exp),
+    };
+    model.insert(self.db).await?;
+
+    let permissions: Vec<_> = permissions
+      .into_iter()
+      .map(|permission| {
+        api_token_permission::Model {
+          token_id: id,
+          permission,
+        }
+        .into_active_model()
+      })
+      .collect();
+    if !permissions.is_empty() {
+      api_token_permission::Entity::insert_many(permissions)
+        .exec(self.db)
+        .await?;
+    }
+
+    Ok(id)
+  }
+
+  /// Token with the given hash and its permissions.
+  #[instrument(skip(self, token_hash))]
+  pub async fn get_api_token(
+    &self,
+    token_hash: &str,
+  ) -> Result<Option<(api_token::Model, Vec<String>)>> {
+    let Some(token) = api_token::Entity::find()
+      .filter(api_token::Column::TokenHash.eq(token_hash))
+      .one(self.db)
+      .await?
+    else {
+      return Ok(None);
+    };
+
+    let permissions = self.token_permissions(token.id).await?;
+    Ok(Some((token, permissions)))
+  }
+
+  async fn token_permissions(&self, token_id: Uuid) -> Result<Vec<String>> {
+    Ok(
+      api_token_permission::Entity::find()
+        .filter(api_token_permission::Column::TokenId.eq(token_id))
+        .all(self.db)
+        .await?
+        .into_iter()
+        .map(|p| p.permission)
+        .collect(),
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_user_tokens(&self, user_id: Uuid) -> Result<Vec<ApiTokenInfo>> {
+    let tokens = api_token::Entity::find()
+      .filter(api_token::Column::UserId.eq(user_id))
+      .order_by_asc(api_token::Column::Created)
+      .all(self.db)
+      .await?;
+    let permissions = tokens
+      .load_many(api_token_permission::Entity, self.db)
+      .await?;
+
+    Ok(
+      tokens
+        .into_iter()
+        .zip(permissions)
+        .map(|(t, p)| ApiTokenInfo {
+          uuid: t.id,
+          name: t.name,
+          permissions: p.into_iter().map(|p| p.permission).collect(),
+          created: t.created,
+          last_used: t.last_used,
+          exp: t.exp,
+        })
+        .collect(),
+    )
+  }
+
+  #[instrument(skip(self, token))]
+  pub async fn touch_token(&self, token: &api_token::Model) -> Result<()> {
+    let now = Utc::now().naive_utc();
+    if token
+      .last_used
+      .is_some_and(|last| now - last < Duration::seconds(TOUCH_INTERVAL))
+    {
+      return Ok(());
+    }
+
+    api_token::Entity::update_many()
+      .col_expr(api_token::Column::LastUsed, Expr::value(now))
+      .filter(api_token::Column::Id.eq(token.id))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  /// Returns false if the user has no token with this id.
+  #[instrument(skip(self))]
+  pub async fn delete_api_token(&self, user_id: Uuid, id: Uuid) -> Result<bool> {
+    let res = api_token::Entity::delete_many()
+      .filter(api_token::Column::Id.eq(id))
+      .filter(api_token::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_api_token_lifecycle() {
+    let (conn, user) = setup().await;
+    let table = conn.api_token();
+
+    let id = table
+      .create_api_token(
+        user,
+        "ci".into(),
+        "hash".into(),
+        vec!["user:view".into(), "group:view".into()],
+        None,
+      )
+      .await
+      .unwrap();
+
+    let (token, mut permissions) = table.get_api_token("hash").await.unwrap().unwrap();
+    permissions.sort();
+    assert_eq!(token.id, id);
+    assert_eq!(permissions, vec!["group:view", "user:view"]);
+    assert!(table.get_api_token("other").await.unwrap().is_none());
+
+    table.touch_token(&token).await.unwrap();
+    let list = table.list_user_tokens(user).await.unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].permissions.len(), 2);
+    assert!(list[0].last_used.is_some());
+
+    assert!(!table.delete_api_token(Uuid::now_v7(), id).await.unwrap());
+    assert!(table.delete_api_token(user, id).await.unwrap());
+    assert!(table.get_api_token("hash").await.unwrap().is_none());
+  }
+
+  #[tokio::test]
+  async fn test_api_token_without_permissions() {
+    let (conn, user) = setup().await;
+    let table = conn.api_token();
+
+    table
+      .create_api_token(user, "read".into(), "hash".into(), Vec::new(), None)
+      .await
+      .unwrap();
+    let (_, permissions) = table.get_api_token("hash").await.unwrap().unwrap();
+    assert!(permissions.is_empty());
+  }
+}
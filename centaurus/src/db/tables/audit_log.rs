@@ -0,0 +1,285 @@
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{ActiveValue::Set, QueryOrder, QuerySelect, prelude::*};
+use serde::{Deserialize, Serialize};
+use serde_json::Value;
+use tracing::instrument;
+
+use crate::{db::entities::audit_log, error::Result};
+
+/// Upper bound for `per_page` in [`AuditLogTable::query`].
+pub const MAX_PAGE_SIZE: u64 = 200;
+
+/// Append only, entries are never changed after they were written and only
+/// removed by [`AuditLogTable::remove_older_than`].
+pub struct AuditLogTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+#[derive(Debug, Clone, Default)]
+pub struct NewAuditEntry {
+  /// `None` for changes that were not made by a user.
+  pub actor: Option<Uuid>,
+  pub action: String,
+  pub target_type: Option<String>,
+  pub target_id: Option<String>,
+  /// State of the target before the change.
+  pub before: Option<Value>,
+  /// State of the target after the change.
+  pub after: Option<Value>,
+  pub ip: Option<String>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct AuditEntry {
+  pub uuid: Uuid,
+  pub actor: Option<Uuid>,
+  pub action: String,
+  pub target_type: Option<String>,
+  pub target_id: Option<String>,
+  pub before: Option<Value>,
+  pub after: Option<Value>,
+  pub ip: Option<String>,
+  pub created: NaiveDateTime,
+}
+
+impl AuditEntry {
+  fn from_model(model: audit_log::Model) -> Result<Self> {
+    let parse = |value: Option<String>| -> Result<Option<Value>> {
+      Ok(value.as_deref().map(serde_json::from_str).transpose()?)
+    };
+
+    Ok(Self {
+      uuid: model.id,
+      actor: model.actor,
+      action: model.action,
+      target_type: model.target_type,
+      target_id: model.target_id,
+      before: parse(model.before)?,
+      after: parse(model.after)?,
+      ip: model.ip,
+      created: model.created,
+    })
+  }
+}
+
+/// All set fields have to match. `action` matches exactly, or every action
+/// with the given prefix if it ends with `*`, e.g. `group.*`.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct AuditFilter {
+  pub actor: Option<Uuid>,
+  pub action: Option<String>,
+  pub target_type: Option<String>,
+  pub target_id: Option<String>,
+  /// Only entries created at or after this time.
+  pub from: Option<NaiveDateTime>,
+  /// Only entries created before this time.
+  pub to: Option<NaiveDateTime>,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct AuditPage {
+  pub entries: Vec<AuditEntry>,
+  pub total: u64,
+}
+
+impl<'db, C: ConnectionTrait> AuditLogTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self, entry), fields(action = %entry.action))]
+  pub async fn record(&self, entry: NewAuditEntry) -> Result<Uuid> {
+    let id = Uuid::now_v7();
+    let model = audit_log::ActiveModel {
+      id: Set(id),
+      actor: Set(entry.actor),
+      action: Set(entry.action),
+      target_type: Set(entry.target_type),
+      target_id: Set(entry.target_id),
+      before: Set(entry.before.map(|v| v.to_string())),
+      after: Set(entry.after.map(|v| v.to_string())),
+      ip: Set(entry.ip),
+      created: Set(Utc::now().naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(id)
+  }
+
+  /// Matching entries, newest first. `page` is zero based and `per_page` is
+  /// capped at [`MAX_PAGE_SIZE`].
+  #[instrument(skip(self))]
+  pub async fn query(&self, filter: &AuditFilter, page: u64, per_page: u64) -> Result<AuditPage> {
+    let mut query = audit_log::Entity::find();
+    if let Some(actor) = filter.actor {
+      query = query.filter(audit_log::Column::Actor.eq(actor));
+    }
+    if let Some(action) = &filter.action {
+      query = match action.strip_suffix('*') {
+        Some(prefix) => query.filter(audit_log::Column::Action.starts_with(prefix)),
+        None => query.filter(audit_log::Column::Action.eq(action)),
+      };
+    }
+    if let Some(target_type) = &filter.target_type {
+      query = query.filter(audit_log::Column::TargetType.eq(target_type));
+    }
+    if let Some(target_id) = &filter.target_id {
+      query = query.filter(audit_log::Column::TargetId.eq(target_id));
+    }
+    if let Some(from) = filter.from {
+      query = query.filter(audit_log::Column::Created.gte(from));
+    }
+    if let Some(to) = filter.to {
+      query = query.filter(audit_log::Column::Created.lt(to));
+    }
+
+    let total = query.clone().count(self.db).await?;
+    let per_page = per_page.clamp(1, MAX_PAGE_SIZE);
+    let entries = query
+      .order_by_desc(audit_log::Column::Created)
+      .order_by_desc(audit_log::Column::Id)
+      .offset(page.saturating_mul(per_page))
+      .limit(per_page)
+      .all(self.db)
+      .await?
+      .into_iter()
+      .map(AuditEntry::from_model)
+      .collect::<Result<_>>()?;
+
+    Ok(AuditPage { entries, total })
+  }
+
+  /// Returns the number of removed entries.
+  #[instrument(skip(self))]
+  pub async fn remove_older_than(&self, before: NaiveDateTime) -> Result<u64> {
+    let res = audit_log::Entity::delete_many()
+      .filter(audit_log::Column::Created.lt(before))
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use chrono::Duration;
+  use sea_orm_migration::MigratorTrait;
+  use serde_json::json;
+
+  async fn setup() -> Connection {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    conn
+  }
+
+  fn entry(actor: Uuid, action: &str, target: &str) -> NewAuditEntry {
+    NewAuditEntry {
+      actor: Some(actor),
+      action: action.into(),
+      target_type: Some("group".into()),
+      target_id: Some(target.into()),
+      ..Default::default()
+    }
+  }
+
+  #[tokio::test]
+  async fn test_record_and_query() {
+    let conn = setup().await;
+    let table = conn.audit_log();
+    let actor = Uuid::now_v7();
+
+    table
+      .record(NewAuditEntry {
+        before: Some(json!({ "name": "old" })),
+        after: Some(json!({ "name": "new" })),
+        ip: Some("10.0.0.1".into()),
+        ..entry(actor, "group.edit", "a")
+      })
+      .await
+      .unwrap();
+    table
+      .record(entry(actor, "group.delete", "b"))
+      .await
+      .unwrap();
+    table
+      .record(entry(Uuid::now_v7(), "user.create", "c"))
+      .await
+      .unwrap();
+
+    let all = table.query(&AuditFilter::default(), 0, 50).await.unwrap();
+    assert_eq!(all.total, 3);
+    // newest first
+    assert_eq!(all.entries[0].action, "user.create");
+    let edit = &all.entries[2];
+    assert_eq!(edit.before, Some(json!({ "name": "old" })));
+    assert_eq!(edit.after, Some(json!({ "name": "new" })));
+    assert_eq!(edit.ip.as_deref(), Some("10.0.0.1"));
+
+    let filter = AuditFilter {
+      actor: Some(actor),
+      ..Default::default()
+    };
+    assert_eq!(table.query(&filter, 0, 50).await.unwrap().total, 2);
+
+    let filter = AuditFilter {
+      action: Some("group.*".into()),
+      target_id: Some("b".into()),
+      ..Default::default()
+    };
+    let page = table.query(&filter, 0, 50).await.unwrap();
+    assert_eq!(page.total, 1);
+    assert_eq!(page.entries[0].action, "group.delete");
+  }
+
+  #[tokio::test]
+  async fn test_query_pagination() {
+    let conn = setup().await;
+    let table = conn.audit_log();
+    let actor = Uuid::now_v7();
+    for i in 0..5 {
+      table
+        .record(entry(actor, "group.create", &i.to_string()))
+        .await
+        .unwrap();
+    }
+
+    let filter = AuditFilter::default();
+    let first = table.query(&filter, 0, 2).await.unwrap();
+    let last = table.query(&filter, 2, 2).await.unwrap();
+    assert_eq!(first.total, 5);
+    assert_eq!(first.entries.len(), 2);
+    assert_eq!(first.entries[0].target_id.as_deref(), Some("4"));
+    assert_eq!(last.entries.len(), 1);
+    assert_eq!(last.entries[0].target_id.as_deref(), Some("0"));
+  }
+
+  #[tokio::test]
+  async fn test_remove_older_than() {
+    let conn = setup().await;
+    let table = conn.audit_log();
+    table
+      .record(entry(Uuid::now_v7(), "group.create", "a"))
+      .await
+      .unwrap();
+
+    let past = Utc::now().naive_utc() - Duration::days(1);
+    assert_eq!(table.remove_older_than(past).await.unwrap(), 0);
+    let filter = AuditFilter {
+      to: Some(past),
+      ..Default::default()
+    };
+    assert_eq!(table.query(&filter, 0, 10).await.unwrap().total, 0);
+
+    let future = Utc::now().naive_utc() + Duration::seconds(1);
+    assert_eq!(table.remove_older_than(future).await.unwrap(), 1);
+  }
+}
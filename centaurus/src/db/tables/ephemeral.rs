@@ -0,0 +1,276 @@
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{
+  ActiveValue::Set,
+  QueryOrder, QuerySelect,
+  prelude::*,
+  sea_query::{Expr, ExprTrait, OnConflict},
+};
+use tracing::instrument;
+
+use crate::{
+  db::entities::{ephemeral_entry, ephemeral_event},
+  error::Result,
+};
+
+/// Short lived state shared between replicas, see
+/// [`DatabaseStore`](crate::backend::store::database::DatabaseStore).
+pub struct EphemeralTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+impl<'db, C: ConnectionTrait> EphemeralTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self, value))]
+  pub async fn set(
+    &self,
+    namespace: &str,
+    key: &str,
+    value: String,
+    expires: NaiveDateTime,
+  ) -> Result<()> {
+    let model = ephemeral_entry::ActiveModel {
+      namespace: Set(namespace.to_string()),
+      key: Set(key.to_string()),
+      value: Set(value),
+      counter: Set(0),
+      expires: Set(expires),
+    };
+    ephemeral_entry::Entity::insert(model)
+      .on_conflict(
+        OnConflict::columns([
+          ephemeral_entry::Column::Namespace,
+          ephemeral_entry::Column::Key,
+        ])
+        .update_columns([
+          ephemeral_entry::Column::Value,
+          ephemeral_entry::Column::Counter,
+          ephemeral_entry::Column::Expires,
+        ])
+        .to_owned(),
+      )
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+    Ok(
+      ephemeral_entry::Entity::find_by_id((namespace.to_string(), key.to_string()))
+        .filter(ephemeral_entry::Column::Expires.gt(Utc::now().naive_utc()))
+        .one(self.db)
+        .await?
+        .map(|e| e.value),
+    )
+  }
+
+  /// Remove the entry and return its value. Of concurrent callers only one
+  /// gets the value.
+  #[instrument(skip(self))]
+  pub async fn take(&self, namespace: &str, key: &str) -> Result<Option<String>> {
+    let Some(value) = self.get(namespace, key).await? else {
+      return Ok(None);
+    };
+
+    let res = ephemeral_entry::Entity::delete_by_id((namespace.to_string(), key.to_string()))
+      .filter(ephemeral_entry::Column::Expires.gt(Utc::now().naive_utc()))
+      .exec(self.db)
+      .await?;
+
+    Ok((res.rows_affected == 1).then_some(value))
+  }
+
+  #[instrument(skip(self))]
+  pub async fn remove(&self, namespace: &str, key: &str) -> Result<()> {
+    ephemeral_entry::Entity::delete_by_id((namespace.to_string(), key.to_string()))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  /// Increment the counter of the entry and return the new value. Missing or
+  /// expired entries start over at 1 and expire at `expires`.
+  #[instrument(skip(self))]
+  pub async fn increment(&self, namespace: &str, key: &str, expires: NaiveDateTime) -> Result<i64> {
+    ephemeral_entry::Entity::delete_many()
+      .filter(ephemeral_entry::Column::Namespace.eq(namespace))
+      .filter(ephemeral_entry::Column::Key.eq(key))
+      .filter(ephemeral_entry::Column::Expires.lte(Utc::now().naive_utc()))
+      .exec(self.db)
+      .await?;
+
+    let model = ephemeral_entry::ActiveModel {
+      namespace: Set(namespace.to_string()),
+      key: Set(key.to_string()),
+      value: Set(String::new()),
+      counter: Set(1),
+      expires: Set(expires),
+    };
+    ephemeral_entry::Entity::insert(model)
+      .on_conflict(
+        OnConflict::columns([
+          ephemeral_entry::Column::Namespace,
+          ephemeral_entry::Column::Key,
+        ])
+        .value(
+          ephemeral_entry::Column::Counter,
+          Expr::col((ephemeral_entry::Entity, ephemeral_entry::Column::Counter)).add(1),
+        )
+        .to_owned(),
+      )
+      .exec(self.db)
+      .await?;
+
+    Ok(
+      ephemeral_entry::Entity::find_by_id((namespace.to_string(), key.to_string()))
+        .one(self.db)
+        .await?
+        .map(|e| e.counter)
+        .unwrap_or(1),
+    )
+  }
+
+  /// Returns the id of the new event.
+  #[instrument(skip(self, payload))]
+  pub async fn publish(&self, channel: &str, payload: String) -> Result<i32> {
+    let model = ephemeral_event::ActiveModel {
+      channel: Set(channel.to_string()),
+      payload: Set(payload),
+      created: Set(Utc::now().naive_utc()),
+      ..Default::default()
+    };
+
+    Ok(model.insert(self.db).await?.id)
+  }
+
+  /// Events of the channel with an id greater than `after`, oldest first.
+  #[instrument(skip(self))]
+  pub async fn events_after(
+    &self,
+    channel: &str,
+    after: i32,
+  ) -> Result<Vec<ephemeral_event::Model>> {
+    Ok(
+      ephemeral_event::Entity::find()
+        .filter(ephemeral_event::Column::Channel.eq(channel))
+        .filter(ephemeral_event::Column::Id.gt(after))
+        .order_by_asc(ephemeral_event::Column::Id)
+        .all(self.db)
+        .await?,
+    )
+  }
+
+  /// Id of the newest event, 0 if there is none.
+  #[instrument(skip(self))]
+  pub async fn last_event_id(&self) -> Result<i32> {
+    let id: Option<Option<i32>> = ephemeral_event::Entity::find()
+      .select_only()
+      .column_as(ephemeral_event::Column::Id.max(), "id")
+      .into_tuple()
+      .one(self.db)
+      .await?;
+
+    Ok(id.flatten().unwrap_or(0))
+  }
+
+  /// Remove expired entries and events created before `events_before`.
+  /// Returns the number of removed rows.
+  #[instrument(skip(self))]
+  pub async fn remove_expired(&self, events_before: NaiveDateTime) -> Result<u64> {
+    let entries = ephemeral_entry::Entity::delete_many()
+      .filter(ephemeral_entry::Column::Expires.lte(Utc::now().naive_utc()))
+      .exec(self.db)
+      .await?;
+    let events = ephemeral_event::Entity::delete_many()
+      .filter(ephemeral_event::Column::Created.lt(events_before))
+      .exec(self.db)
+      .await?;
+
+    Ok(entries.rows_affected + events.rows_affected)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use chrono::Duration;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> Connection {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    conn
+  }
+
+  fn in_minutes(minutes: i64) -> NaiveDateTime {
+    Utc::now().naive_utc() + Duration::minutes(minutes)
+  }
+
+  #[tokio::test]
+  async fn test_set_get_take() {
+    let conn = setup().await;
+    let table = conn.ephemeral();
+
+    table
+      .set("ns", "a", "1".into(), in_minutes(5))
+      .await
+      .unwrap();
+    table
+      .set("ns", "a", "2".into(), in_minutes(5))
+      .await
+      .unwrap();
+    assert_eq!(table.get("ns", "a").await.unwrap().as_deref(), Some("2"));
+    assert!(table.get("other", "a").await.unwrap().is_none());
+
+    assert_eq!(table.take("ns", "a").await.unwrap().as_deref(), Some("2"));
+    assert!(table.take("ns", "a").await.unwrap().is_none());
+
+    table
+      .set("ns", "b", "1".into(), in_minutes(-1))
+      .await
+      .unwrap();
+    assert!(table.get("ns", "b").await.unwrap().is_none());
+    assert_eq!(table.remove_expired(in_minutes(-1)).await.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_increment() {
+    let conn = setup().await;
+    let table = conn.ephemeral();
+
+    assert_eq!(table.increment("rl", "k", in_minutes(1)).await.unwrap(), 1);
+    assert_eq!(table.increment("rl", "k", in_minutes(1)).await.unwrap(), 2);
+
+    // an expired counter starts over
+    table
+      .set("rl", "k", String::new(), in_minutes(-1))
+      .await
+      .unwrap();
+    assert_eq!(table.increment("rl", "k", in_minutes(1)).await.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_events() {
+    let conn = setup().await;
+    let table = conn.ephemeral();
+    assert_eq!(table.last_event_id().await.unwrap(), 0);
+
+    let first = table.publish("ws", "a".into()).await.unwrap();
+    table.publish("other", "b".into()).await.unwrap();
+    table.publish("ws", "c".into()).await.unwrap();
+
+    let events = table.events_after("ws", first).await.unwrap();
+    assert_eq!(events.len(), 1);
+    assert_eq!(events[0].payload, "c");
+    assert_eq!(table.last_event_id().await.unwrap(), first + 2);
+  }
+}
@@ -4,13 +4,14 @@ use serde::{Deserialize, Serialize};
 use crate::{
   db::{
     entities::{group, group_permission, group_user, user},
+    permission::{effective_permissions, has_permission},
     tables::user::SimpleGroupInfo,
   },
   error::Result,
 };
 
-pub struct GroupTable<'db> {
-  db: &'db DatabaseConnection,
+pub struct GroupTable<'db, C = DatabaseConnection> {
+  db: &'db C,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -38,8 +39,8 @@ pub struct SimpleUserInfo {
   pub name: String,
 }
 
-impl<'db> GroupTable<'db> {
-  pub fn new(db: &'db DatabaseConnection) -> Self {
+impl<'db, C: ConnectionTrait> GroupTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
     Self { db }
   }
 
@@ -159,19 +160,22 @@ impl<'db> GroupTable<'db> {
     Ok(())
   }
 
+  /// Whether the user holds the permission, directly, through a wildcard or
+  /// through a permission implying it.
   pub async fn user_hash_permissions(&self, user_id: Uuid, permission: &str) -> Result<bool> {
-    let res = group_user::Entity::find()
-      .join(JoinType::InnerJoin, group_user::Relation::Group.def())
-      .join(JoinType::InnerJoin, group::Relation::GroupPermission.def())
-      .filter(group_user::Column::UserId.eq(user_id))
-      .filter(group_permission::Column::Permission.eq(permission))
-      .all(self.db)
-      .await?;
-
-    Ok(!res.is_empty())
+    let granted = self.get_granted_permissions(user_id).await?;
+    Ok(has_permission(&granted, permission))
   }
 
+  /// The effective permissions of the user, see `effective_permissions`.
   pub async fn get_user_permissions(&self, user_id: Uuid) -> Result<Vec<String>> {
+    Ok(effective_permissions(
+      self.get_granted_permissions(user_id).await?,
+    ))
+  }
+
+  /// Permissions exactly as they are assigned to the groups of the user.
+  pub async fn get_granted_permissions(&self, user_id: Uuid) -> Result<Vec<String>> {
     let group_permissions = group_permission::Entity::find()
       .join(JoinType::InnerJoin, group_permission::Relation::Group.def())
       .join_rev(JoinType::InnerJoin, group_user::Relation::Group.def())
@@ -381,7 +385,7 @@ mod tests {
     conn
   }
 
-  async fn make_user(conn: &Connection, name: &str) -> Uuid {
+  async fn make_user(conn: &DatabaseConnection, name: &str) -> Uuid {
     UserTable::new(conn)
       .create_user(
         name.into(),
@@ -399,7 +403,7 @@ mod tests {
   async fn test_group_table() {
     let conn = setup().await;
 
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let id = table.create_group("admin".into()).await.unwrap();
     table
       .add_permissions_to_group(id, vec!["read".into(), "write".into()])
@@ -414,7 +418,7 @@ mod tests {
   #[tokio::test]
   async fn test_add_permissions_empty_is_noop() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let id = table.create_group("g".into()).await.unwrap();
 
     table.add_permissions_to_group(id, vec![]).await.unwrap();
@@ -431,7 +435,7 @@ mod tests {
   #[tokio::test]
   async fn test_group_users_membership() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let group = table.create_group("team".into()).await.unwrap();
     let user_a = make_user(&conn, "a").await;
     let user_b = make_user(&conn, "b").await;
@@ -455,7 +459,7 @@ mod tests {
   #[tokio::test]
   async fn test_permission_invariants() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let group = table.create_group("admins".into()).await.unwrap();
     let user = make_user(&conn, "u").await;
     table.add_users_to_group(group, vec![user]).await.unwrap();
@@ -497,7 +501,7 @@ mod tests {
   #[tokio::test]
   async fn test_find_and_ids() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let id = table.create_group("findme".into()).await.unwrap();
 
     assert_eq!(table.find_group_by_name("findme").await.unwrap(), Some(id));
@@ -515,7 +519,7 @@ mod tests {
   #[tokio::test]
   async fn test_list_and_info() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let group = table.create_group("g1".into()).await.unwrap();
     let user = make_user(&conn, "m").await;
     table.add_users_to_group(group, vec![user]).await.unwrap();
@@ -545,7 +549,7 @@ mod tests {
   #[tokio::test]
   async fn test_edit_group_replaces_state() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let group = table.create_group("old".into()).await.unwrap();
     let user_a = make_user(&conn, "ea").await;
     let user_b = make_user(&conn, "eb").await;
@@ -578,7 +582,7 @@ mod tests {
   #[tokio::test]
   async fn test_delete_group() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let group = table.create_group("doomed".into()).await.unwrap();
 
     let deleted = table.delete_group(group).await.unwrap();
@@ -592,7 +596,7 @@ mod tests {
   #[tokio::test]
   async fn test_is_last_admin() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     let admin = table.create_group("admin".into()).await.unwrap();
     let user_a = make_user(&conn, "la").await;
     let user_b = make_user(&conn, "lb").await;
@@ -611,7 +615,7 @@ mod tests {
   #[tokio::test]
   async fn test_edit_missing_group_errors() {
     let conn = setup().await;
-    let table = GroupTable::new(&conn);
+    let table = GroupTable::new(&*conn);
     assert!(
       table
         .edit_group(Uuid::now_v7(), "x".into(), vec![], vec![])
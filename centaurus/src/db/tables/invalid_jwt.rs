@@ -9,12 +9,12 @@ use tracing::instrument;
 
 use crate::{db::entities::invalid_jwt, error::Result};
 
-pub struct InvalidJwtTable<'db> {
-  db: &'db DatabaseConnection,
+pub struct InvalidJwtTable<'db, C = DatabaseConnection> {
+  db: &'db C,
 }
 
-impl<'db> InvalidJwtTable<'db> {
-  pub fn new(db: &'db DatabaseConnection) -> Self {
+impl<'db, C: ConnectionTrait> InvalidJwtTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
     Self { db }
   }
 
@@ -78,7 +78,7 @@ mod tests {
     let conn = connect_db(&db_config, "sqlite::memory:").await;
     Migrator::up(&*conn, None).await.unwrap();
 
-    let table = InvalidJwtTable::new(&conn);
+    let table = InvalidJwtTable::new(&*conn);
     let count = Arc::new(AtomicI32::new(0));
     let token = "test_token".to_string();
     let exp = Utc::now() + Duration::seconds(3600);
@@ -99,7 +99,7 @@ mod tests {
     let conn = connect_db(&db_config, "sqlite::memory:").await;
     Migrator::up(&*conn, None).await.unwrap();
 
-    let table = InvalidJwtTable::new(&conn);
+    let table = InvalidJwtTable::new(&*conn);
     let count = Arc::new(AtomicI32::new(0));
 
     let expired = Utc::now() - Duration::seconds(3600);
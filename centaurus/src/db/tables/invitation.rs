@@ -0,0 +1,295 @@
+use chrono::{DateTime, NaiveDateTime, Utc};
+use sea_orm::{ActiveValue::Set, IntoActiveModel, QueryOrder, prelude::*};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+  db::{
+    entities::{group, invitation, invitation_group},
+    tables::user::SimpleGroupInfo,
+  },
+  error::Result,
+};
+
+pub struct InvitationTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct InvitationInfo {
+  pub uuid: Uuid,
+  pub name: String,
+  pub email: String,
+  pub groups: Vec<SimpleGroupInfo>,
+  pub created: NaiveDateTime,
+  pub exp: NaiveDateTime,
+  /// Expired invitations can be sent again.
+  pub expired: bool,
+}
+
+impl<'db, C: ConnectionTrait> InvitationTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self, token_hash))]
+  pub async fn create_invitation(
+    &self,
+    name: String,
+    email: String,
+    token_hash: String,
+    groups: Vec<Uuid>,
+    exp: DateTime<Utc>,
+  ) -> Result<Uuid> {
+    let id = Uuid::now_v7();
+    let model = invitation::ActiveModel {
+      id: Set(id),
+      name: Set(name),
+      email: Set(email),
+      token_hash: Set(token_hash),
+      created: Set(Utc::now().naive_utc()),
+      exp: Set(exp.naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    let groups: Vec<_> = groups
+      .into_iter()
+      .map(|group_id| {
+        invitation_group::Model {
+          invitation_id: id,
+          group_id,
+        }
+        .into_active_model()
+      })
+      .collect();
+    if !groups.is_empty() {
+      invitation_group::Entity::insert_many(groups)
+        .exec(self.db)
+        .await?;
+    }
+
+    Ok(id)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_invitation(&self, id: Uuid) -> Result<Option<invitation::Model>> {
+    Ok(invitation::Entity::find_by_id(id).one(self.db).await?)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn try_get_invitation_by_email(
+    &self,
+    email: &str,
+  ) -> Result<Option<invitation::Model>> {
+    Ok(
+      invitation::Entity::find()
+        .filter(invitation::Column::Email.eq(email.to_string()))
+        .one(self.db)
+        .await?,
+    )
+  }
+
+  /// Expired invitations resolve to `None`.
+  #[instrument(skip(self, token_hash))]
+  pub async fn get_invitation_by_token(
+    &self,
+    token_hash: &str,
+  ) -> Result<Option<invitation::Model>> {
+    Ok(
+      invitation::Entity::find()
+        .filter(invitation::Column::TokenHash.eq(token_hash))
+        .filter(invitation::Column::Exp.gt(Utc::now().naive_utc()))
+        .one(self.db)
+        .await?,
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_invitation_groups(&self, id: Uuid) -> Result<Vec<Uuid>> {
+    Ok(
+      invitation_group::Entity::find()
+        .filter(invitation_group::Column::InvitationId.eq(id))
+        .all(self.db)
+        .await?
+        .into_iter()
+        .map(|group| group.group_id)
+        .collect(),
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn invitation_info(&self, id: Uuid) -> Result<Option<InvitationInfo>> {
+    let Some(invitation) = self.get_invitation(id).await? else {
+      return Ok(None);
+    };
+    Ok(Some(self.to_info(invitation).await?))
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_invitations(&self) -> Result<Vec<InvitationInfo>> {
+    let invitations = invitation::Entity::find()
+      .order_by_asc(invitation::Column::Email)
+      .all(self.db)
+      .await?;
+
+    let mut infos = Vec::with_capacity(invitations.len());
+    for invitation in invitations {
+      infos.push(self.to_info(invitation).await?);
+    }
+
+    Ok(infos)
+  }
+
+  async fn to_info(&self, invitation: invitation::Model) -> Result<InvitationInfo> {
+    let groups = invitation_group::Entity::find()
+      .filter(invitation_group::Column::InvitationId.eq(invitation.id))
+      .find_also_related(group::Entity)
+      .all(self.db)
+      .await?
+      .into_iter()
+      .filter_map(|(_, group)| {
+        let group = group?;
+        Some(SimpleGroupInfo {
+          uuid: group.id,
+          name: group.name,
+        })
+      })
+      .collect();
+
+    Ok(InvitationInfo {
+      uuid: invitation.id,
+      name: invitation.name,
+      email: invitation.email,
+      groups,
+      created: invitation.created,
+      expired: invitation.exp <= Utc::now().naive_utc(),
+      exp: invitation.exp,
+    })
+  }
+
+  /// Replaces the token, so links sent earlier stop working. Returns false if
+  /// the invitation does not exist.
+  #[instrument(skip(self, token_hash))]
+  pub async fn renew_token(
+    &self,
+    id: Uuid,
+    token_hash: String,
+    exp: DateTime<Utc>,
+  ) -> Result<bool> {
+    let Some(invitation) = self.get_invitation(id).await? else {
+      return Ok(false);
+    };
+
+    let mut invitation = invitation.into_active_model();
+    invitation.token_hash = Set(token_hash);
+    invitation.exp = Set(exp.naive_utc());
+    invitation.update(self.db).await?;
+
+    Ok(true)
+  }
+
+  /// Returns false if the invitation does not exist, e.g. because it was
+  /// already accepted.
+  #[instrument(skip(self))]
+  pub async fn delete_invitation(&self, id: Uuid) -> Result<bool> {
+    let res = invitation::Entity::delete_by_id(id).exec(self.db).await?;
+    Ok(res.rows_affected == 1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn db() -> Connection {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    conn
+  }
+
+  #[tokio::test]
+  async fn test_invitation_lifecycle() {
+    let conn = db().await;
+    let group = conn.group().create_group("staff".into()).await.unwrap();
+    let table = conn.invitation();
+    let exp = Utc::now() + chrono::Duration::hours(1);
+
+    let id = table
+      .create_invitation(
+        "Invitee".into(),
+        "invitee@example.com".into(),
+        "hash".into(),
+        vec![group],
+        exp,
+      )
+      .await
+      .unwrap();
+    // One open invitation per email.
+    assert!(
+      table
+        .create_invitation(
+          "Other".into(),
+          "invitee@example.com".into(),
+          "other".into(),
+          vec![],
+          exp,
+        )
+        .await
+        .is_err()
+    );
+
+    let info = table.invitation_info(id).await.unwrap().unwrap();
+    assert_eq!(info.groups.len(), 1);
+    assert_eq!(info.groups[0].name, "staff");
+    assert!(!info.expired);
+    assert_eq!(table.get_invitation_groups(id).await.unwrap(), vec![group]);
+    assert_eq!(
+      table
+        .get_invitation_by_token("hash")
+        .await
+        .unwrap()
+        .map(|i| i.id),
+      Some(id)
+    );
+
+    // A renewed token replaces the old one.
+    assert!(table.renew_token(id, "new".into(), exp).await.unwrap());
+    assert!(
+      table
+        .get_invitation_by_token("hash")
+        .await
+        .unwrap()
+        .is_none()
+    );
+    assert!(
+      table
+        .get_invitation_by_token("new")
+        .await
+        .unwrap()
+        .is_some()
+    );
+
+    // Expired invitations are listed but their token no longer resolves.
+    let past = Utc::now() - chrono::Duration::hours(1);
+    table.renew_token(id, "new".into(), past).await.unwrap();
+    assert!(
+      table
+        .get_invitation_by_token("new")
+        .await
+        .unwrap()
+        .is_none()
+    );
+    assert!(table.list_invitations().await.unwrap()[0].expired);
+
+    assert!(table.delete_invitation(id).await.unwrap());
+    assert!(!table.delete_invitation(id).await.unwrap());
+    assert!(!table.renew_token(id, "x".into(), exp).await.unwrap());
+    assert!(table.list_invitations().await.unwrap().is_empty());
+  }
+}
@@ -1,14 +1,15 @@
+use chrono::Utc;
 use eyre::ContextCompat;
-use sea_orm::{ActiveValue::Set, prelude::*};
+use sea_orm::{ActiveValue::Set, Order, QueryOrder, prelude::*, sea_query::NullOrdering};
 
 use crate::{db::entities::key, error::Result};
 
-pub struct KeyTable<'db> {
-  db: &'db DatabaseConnection,
+pub struct KeyTable<'db, C = DatabaseConnection> {
+  db: &'db C,
 }
 
-impl<'db> KeyTable<'db> {
-  pub fn new(db: &'db DatabaseConnection) -> Self {
+impl<'db, C: ConnectionTrait> KeyTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
     Self { db }
   }
 
@@ -26,12 +27,32 @@ impl<'db> KeyTable<'db> {
       name: Set(name),
       private_key: Set(key),
       id: Set(id),
+      created: Set(Some(Utc::now().naive_utc())),
     };
 
     model.insert(self.db).await?;
 
     Ok(())
   }
+
+  /// All keys with the given name, oldest first. Keys created before the
+  /// `created` column existed sort before every other key.
+  pub async fn list_keys_by_name(&self, name: String) -> Result<Vec<key::Model>> {
+    Ok(
+      key::Entity::find()
+        .filter(key::Column::Name.eq(name))
+        // Postgres sorts NULL last by default
+        .order_by_with_nulls(key::Column::Created, Order::Asc, NullOrdering::First)
+        .order_by_asc(key::Column::Id)
+        .all(self.db)
+        .await?,
+    )
+  }
+
+  pub async fn delete_key(&self, id: Uuid) -> Result<()> {
+    key::Entity::delete_by_id(id).exec(self.db).await?;
+    Ok(())
+  }
 }
 
 #[cfg(test)]
@@ -48,7 +69,7 @@ mod tests {
     let conn = connect_db(&db_config, "sqlite::memory:").await;
     Migrator::up(&*conn, None).await.unwrap();
 
-    let table = KeyTable::new(&conn);
+    let table = KeyTable::new(&*conn);
     let id = Uuid::now_v7();
     table
       .create_key("test".into(), "private".into(), id)
@@ -58,5 +79,52 @@ mod tests {
     let key = table.get_key_by_name("test".into()).await.unwrap();
     assert_eq!(key.id, id);
     assert_eq!(key.private_key, "private");
+    assert!(key.created.is_some());
+  }
+
+  #[tokio::test]
+  async fn test_list_and_delete_keys_by_name() {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+
+    let table = KeyTable::new(&*conn);
+    let first = Uuid::now_v7();
+    let second = Uuid::now_v7();
+    table
+      .create_key("rotating".into(), "a".into(), first)
+      .await
+      .unwrap();
+    table
+      .create_key("rotating".into(), "b".into(), second)
+      .await
+      .unwrap();
+    table
+      .create_key("other".into(), "c".into(), Uuid::now_v7())
+      .await
+      .unwrap();
+
+    let keys = table.list_keys_by_name("rotating".into()).await.unwrap();
+    let ids: Vec<Uuid> = keys.iter().map(|k| k.id).collect();
+    assert_eq!(ids, vec![first, second]);
+
+    table.delete_key(first).await.unwrap();
+    let keys = table.list_keys_by_name("rotating".into()).await.unwrap();
+    assert_eq!(keys.len(), 1);
+    assert_eq!(keys[0].id, second);
+
+    // a key from before the `created` column counts as the oldest
+    let legacy = Uuid::now_v7();
+    key::ActiveModel {
+      name: Set("rotating".into()),
+      private_key: Set("legacy".into()),
+      id: Set(legacy),
+      created: Set(None),
+    }
+    .insert(&*conn)
+    .await
+    .unwrap();
+    let keys = table.list_keys_by_name("rotating".into()).await.unwrap();
+    let ids: Vec<Uuid> = keys.iter().map(|k| k.id).collect();
+    assert_eq!(ids, vec![legacy, second]);
   }
 }
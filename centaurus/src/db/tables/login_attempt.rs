@@ -0,0 +1,182 @@
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{
+  ActiveValue::Set,
+  prelude::*,
+  sea_query::{Expr, ExprTrait, OnConflict},
+};
+use tracing::instrument;
+
+use crate::{db::entities::login_attempt, error::Result};
+
+/// Failed logins per account, used for the progressive delay and lockout.
+pub struct LoginAttemptTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+impl<'db, C: ConnectionTrait> LoginAttemptTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get(&self, user_id: Uuid) -> Result<Option<login_attempt::Model>> {
+    Ok(
+      login_attempt::Entity::find_by_id(user_id)
+        .one(self.db)
+        .await?,
+    )
+  }
+
+  /// Count a failed login and return the number of failures. Failures before
+  /// `window_start` are forgotten.
+  #[instrument(skip(self))]
+  pub async fn record_failure(&self, user_id: Uuid, window_start: NaiveDateTime) -> Result<i32> {
+    let now = Utc::now().naive_utc();
+    let res = login_attempt::Entity::update_many()
+      .col_expr(
+        login_attempt::Column::Failed,
+        Expr::col(login_attempt::Column::Failed).add(1),
+      )
+      .col_expr(login_attempt::Column::LastFailed, Expr::value(now))
+      .filter(login_attempt::Column::UserId.eq(user_id))
+      .filter(login_attempt::Column::LastFailed.gte(window_start))
+      .exec(self.db)
+      .await?;
+
+    if res.rows_affected == 0 {
+      let model = login_attempt::ActiveModel {
+        user_id: Set(user_id),
+        failed: Set(1),
+        last_failed: Set(now),
+        locked_until: Set(None),
+      };
+      login_attempt::Entity::insert(model)
+        .on_conflict(
+          OnConflict::column(login_attempt::Column::UserId)
+            .update_columns([
+              login_attempt::Column::Failed,
+              login_attempt::Column::LastFailed,
+              login_attempt::Column::LockedUntil,
+            ])
+            .to_owned(),
+        )
+        .exec(self.db)
+        .await?;
+    }
+
+    Ok(self.get(user_id).await?.map(|a| a.failed).unwrap_or(1))
+  }
+
+  #[instrument(skip(self))]
+  pub async fn lock(&self, user_id: Uuid, until: NaiveDateTime) -> Result<()> {
+    login_attempt::Entity::update_many()
+      .col_expr(login_attempt::Column::LockedUntil, Expr::value(until))
+      .filter(login_attempt::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  /// End of the current lockout, `None` if the account is not locked.
+  #[instrument(skip(self))]
+  pub async fn locked_until(&self, user_id: Uuid) -> Result<Option<NaiveDateTime>> {
+    let now = Utc::now().naive_utc();
+    Ok(
+      self
+        .get(user_id)
+        .await?
+        .and_then(|a| a.locked_until)
+        .filter(|until| *until > now),
+    )
+  }
+
+  /// Forget all failures and lift a lockout. Returns false if there was
+  /// nothing to reset.
+  #[instrument(skip(self))]
+  pub async fn reset(&self, user_id: Uuid) -> Result<bool> {
+    let res = login_attempt::Entity::delete_by_id(user_id)
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use chrono::Duration;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_failures_are_counted_within_window() {
+    let (conn, user) = setup().await;
+    let table = conn.login_attempt();
+    let window = Utc::now().naive_utc() - Duration::minutes(15);
+
+    assert_eq!(table.record_failure(user, window).await.unwrap(), 1);
+    assert_eq!(table.record_failure(user, window).await.unwrap(), 2);
+
+    // everything before the window is forgotten
+    let later = Utc::now().naive_utc() + Duration::seconds(1);
+    assert_eq!(table.record_failure(user, later).await.unwrap(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_lock_and_reset() {
+    let (conn, user) = setup().await;
+    let table = conn.login_attempt();
+    assert!(table.locked_until(user).await.unwrap().is_none());
+
+    table
+      .record_failure(user, Utc::now().naive_utc())
+      .await
+      .unwrap();
+    let until = Utc::now().naive_utc() + Duration::minutes(5);
+    table.lock(user, until).await.unwrap();
+    assert_eq!(table.locked_until(user).await.unwrap(), Some(until));
+
+    assert!(table.reset(user).await.unwrap());
+    assert!(!table.reset(user).await.unwrap());
+    assert!(table.locked_until(user).await.unwrap().is_none());
+  }
+
+  #[tokio::test]
+  async fn test_expired_lock_is_ignored() {
+    let (conn, user) = setup().await;
+    let table = conn.login_attempt();
+    table
+      .record_failure(user, Utc::now().naive_utc())
+      .await
+      .unwrap();
+    table
+      .lock(user, Utc::now().naive_utc() - Duration::seconds(1))
+      .await
+      .unwrap();
+
+    assert!(table.locked_until(user).await.unwrap().is_none());
+  }
+}
@@ -1,49 +1,148 @@
+use sea_orm::{ConnectionTrait, DatabaseConnection, DatabaseTransaction};
+
 use crate::db::{
   init::Connection,
   tables::{
-    group::GroupTable, invalid_jwt::InvalidJwtTable, key::KeyTable, settings::SettingsTable,
-    user::UserTable,
+    acl::AclTable, api_token::ApiTokenTable, audit_log::AuditLogTable, ephemeral::EphemeralTable,
+    group::GroupTable, invalid_jwt::InvalidJwtTable, invitation::InvitationTable, key::KeyTable,
+    login_attempt::LoginAttemptTable, oidc_client::OidcClientTable,
+    oidc_identity::OidcIdentityTable, pake_verifier::PakeVerifierTable, passkey::PasskeyTable,
+    password_history::PasswordHistoryTable, password_reset::PasswordResetTable,
+    refresh_token::RefreshTokenTable, service_account::ServiceAccountTable, session::SessionTable,
+    settings::SettingsTable, totp::TotpTable, user::UserTable,
   },
 };
 
+pub mod acl;
+pub mod api_token;
+pub mod audit_log;
+pub mod ephemeral;
 pub mod group;
 pub mod invalid_jwt;
+pub mod invitation;
 pub mod key;
+pub mod login_attempt;
+pub mod oidc_client;
+pub mod oidc_identity;
+pub mod pake_verifier;
+pub mod passkey;
+pub mod password_history;
+pub mod password_reset;
+pub mod refresh_token;
+pub mod service_account;
+pub mod session;
 pub mod settings;
 pub mod setup;
+pub mod totp;
 pub mod user;
 
+/// Access to the tables on a connection or inside a transaction.
 pub trait ConnectionExt {
-  fn key(&self) -> KeyTable<'_>;
-  fn invalid_jwt(&self) -> InvalidJwtTable<'_>;
-  fn settings(&self) -> SettingsTable<'_>;
-  fn user(&self) -> user::UserTable<'_>;
-  fn group(&self) -> group::GroupTable<'_>;
-  fn setup(&self) -> setup::SetupTable<'_>;
-}
+  type Conn: ConnectionTrait;
 
-impl ConnectionExt for Connection {
-  fn key(&self) -> KeyTable<'_> {
-    KeyTable::new(self)
+  fn conn(&self) -> &Self::Conn;
+
+  fn key(&self) -> KeyTable<'_, Self::Conn> {
+    KeyTable::new(self.conn())
+  }
+
+  fn invalid_jwt(&self) -> InvalidJwtTable<'_, Self::Conn> {
+    InvalidJwtTable::new(self.conn())
+  }
+
+  fn settings(&self) -> SettingsTable<'_, Self::Conn> {
+    SettingsTable::new(self.conn())
+  }
+
+  fn user(&self) -> UserTable<'_, Self::Conn> {
+    UserTable::new(self.conn())
+  }
+
+  fn group(&self) -> GroupTable<'_, Self::Conn> {
+    GroupTable::new(self.conn())
   }
 
-  fn invalid_jwt(&self) -> InvalidJwtTable<'_> {
-    InvalidJwtTable::new(self)
+  fn setup(&self) -> setup::SetupTable<'_, Self::Conn> {
+    setup::SetupTable::new(self.conn())
   }
 
-  fn settings(&self) -> SettingsTable<'_> {
-    SettingsTable::new(self)
+  fn refresh_token(&self) -> RefreshTokenTable<'_, Self::Conn> {
+    RefreshTokenTable::new(self.conn())
   }
 
-  fn user(&self) -> user::UserTable<'_> {
-    UserTable::new(self)
+  fn session(&self) -> SessionTable<'_, Self::Conn> {
+    SessionTable::new(self.conn())
   }
 
-  fn group(&self) -> group::GroupTable<'_> {
-    GroupTable::new(self)
+  fn totp(&self) -> TotpTable<'_, Self::Conn> {
+    TotpTable::new(self.conn())
   }
 
-  fn setup(&self) -> setup::SetupTable<'_> {
-    setup::SetupTable::new(self)
+  fn passkey(&self) -> PasskeyTable<'_, Self::Conn> {
+    PasskeyTable::new(self.conn())
+  }
+
+  fn api_token(&self) -> ApiTokenTable<'_, Self::Conn> {
+    ApiTokenTable::new(self.conn())
+  }
+
+  fn service_account(&self) -> ServiceAccountTable<'_, Self::Conn> {
+    ServiceAccountTable::new(self.conn())
+  }
+
+  fn acl(&self) -> AclTable<'_, Self::Conn> {
+    AclTable::new(self.conn())
+  }
+
+  fn audit_log(&self) -> AuditLogTable<'_, Self::Conn> {
+    AuditLogTable::new(self.conn())
+  }
+
+  fn login_attempt(&self) -> LoginAttemptTable<'_, Self::Conn> {
+    LoginAttemptTable::new(self.conn())
+  }
+
+  fn ephemeral(&self) -> EphemeralTable<'_, Self::Conn> {
+    EphemeralTable::new(self.conn())
+  }
+
+  fn password_reset(&self) -> PasswordResetTable<'_, Self::Conn> {
+    PasswordResetTable::new(self.conn())
+  }
+
+  fn password_history(&self) -> PasswordHistoryTable<'_, Self::Conn> {
+    PasswordHistoryTable::new(self.conn())
+  }
+
+  fn pake_verifier(&self) -> PakeVerifierTable<'_, Self::Conn> {
+    PakeVerifierTable::new(self.conn())
+  }
+
+  fn oidc_identity(&self) -> OidcIdentityTable<'_, Self::Conn> {
+    OidcIdentityTable::new(self.conn())
+  }
+
+  fn oidc_client(&self) -> OidcClientTable<'_, Self::Conn> {
+    OidcClientTable::new(self.conn())
+  }
+
+  fn invitation(&self) -> InvitationTable<'_, Self::Conn> {
+    InvitationTable::new(self.conn())
+  }
+}
+
+impl ConnectionExt for Connection {
+  type Conn = DatabaseConnection;
+
+  fn conn(&self) -> &DatabaseConnection {
+    self
+  }
+}
+
+impl ConnectionExt for DatabaseTransaction {
+  type Conn = DatabaseTransaction;
+
+  fn conn(&self) -> &DatabaseTransaction {
+    self
   }
 }
@@ -0,0 +1,333 @@
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{ActiveValue::Set, IntoActiveModel, QueryOrder, prelude::*};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+  db::entities::{oidc_client, oidc_consent},
+  error::Result,
+};
+
+pub struct OidcClientTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct OidcClientInfo {
+  pub uuid: Uuid,
+  pub name: String,
+  pub redirect_uris: Vec<String>,
+  /// Public clients have no secret and authenticate with PKCE only.
+  pub public: bool,
+  pub skip_consent: bool,
+  pub created: NaiveDateTime,
+}
+
+impl From<oidc_client::Model> for OidcClientInfo {
+  fn from(client: oidc_client::Model) -> Self {
+    Self {
+      uuid: client.id,
+      redirect_uris: client.redirect_uris(),
+      name: client.name,
+      public: client.secret_hash.is_none(),
+      skip_consent: client.skip_consent,
+      created: client.created,
+    }
+  }
+}
+
+#[derive(Serialize, Deserialize, Debug)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct ConsentInfo {
+  pub client: Uuid,
+  pub client_name: String,
+  pub scopes: Vec<String>,
+  pub created: NaiveDateTime,
+}
+
+impl oidc_client::Model {
+  pub fn redirect_uris(&self) -> Vec<String> {
+    self
+      .redirect_uris
+      .split_whitespace()
+      .map(str::to_string)
+      .collect()
+  }
+}
+
+impl<'db, C: ConnectionTrait> OidcClientTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self, secret_hash))]
+  pub async fn create_client(
+    &self,
+    name: String,
+    secret_hash: Option<String>,
+    redirect_uris: Vec<String>,
+    skip_consent: bool,
+  ) -> Result<Uuid> {
+    let id = Uuid::now_v7();
+    let model = oidc_client::ActiveModel {
+      id: Set(id),
+      name: Set(name),
+      secret_hash: Set(secret_hash),
+      redirect_uris: Set(redirect_uris.join(" ")),
+      skip_consent: Set(skip_consent),
+      created: Set(Utc::now().naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(id)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_client(&self, id: Uuid) -> Result<Option<oidc_client::Model>> {
+    Ok(oidc_client::Entity::find_by_id(id).one(self.db).await?)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_clients(&self) -> Result<Vec<OidcClientInfo>> {
+    Ok(
+      oidc_client::Entity::find()
+        .order_by_asc(oidc_client::Column::Name)
+        .all(self.db)
+        .await?
+        .into_iter()
+        .map(OidcClientInfo::from)
+        .collect(),
+    )
+  }
+
+  /// Returns false if the client does not exist.
+  #[instrument(skip(self))]
+  pub async fn edit_client(
+    &self,
+    id: Uuid,
+    name: String,
+    redirect_uris: Vec<String>,
+    skip_consent: bool,
+  ) -> Result<bool> {
+    let Some(client) = self.get_client(id).await? else {
+      return Ok(false);
+    };
+
+    let mut client = client.into_active_model();
+    client.name = Set(name);
+    client.redirect_uris = Set(redirect_uris.join(" "));
+    client.skip_consent = Set(skip_consent);
+    client.update(self.db).await?;
+
+    Ok(true)
+  }
+
+  /// Replaces the current secret, so the old one stops working immediately.
+  #[instrument(skip(self, secret_hash))]
+  pub async fn set_secret(&self, id: Uuid, secret_hash: String) -> Result<()> {
+    oidc_client::ActiveModel {
+      id: Set(id),
+      secret_hash: Set(Some(secret_hash)),
+      ..Default::default()
+    }
+    .update(self.db)
+    .await?;
+
+    Ok(())
+  }
+
+  /// Consents given for the client are deleted as well.
+  #[instrument(skip(self))]
+  pub async fn delete_client(&self, id: Uuid) -> Result<bool> {
+    let res = oidc_client::Entity::delete_by_id(id).exec(self.db).await?;
+    Ok(res.rows_affected == 1)
+  }
+
+  /// Scopes the user allowed the client to access, empty without consent.
+  #[instrument(skip(self))]
+  pub async fn get_consent(&self, user_id: Uuid, client_id: Uuid) -> Result<Vec<String>> {
+    Ok(
+      oidc_consent::Entity::find_by_id((user_id, client_id))
+        .one(self.db)
+        .await?
+        .map(|consent| {
+          consent
+            .scopes
+            .split_whitespace()
+            .map(str::to_string)
+            .collect()
+        })
+        .unwrap_or_default(),
+    )
+  }
+
+  /// Replaces the scopes the user allowed the client to access.
+  #[instrument(skip(self))]
+  pub async fn grant_consent(
+    &self,
+    user_id: Uuid,
+    client_id: Uuid,
+    scopes: Vec<String>,
+  ) -> Result<()> {
+    oidc_consent::Entity::delete_by_id((user_id, client_id))
+      .exec(self.db)
+      .await?;
+
+    let model = oidc_consent::ActiveModel {
+      user_id: Set(user_id),
+      client_id: Set(client_id),
+      scopes: Set(scopes.join(" ")),
+      created: Set(Utc::now().naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_consents(&self, user_id: Uuid) -> Result<Vec<ConsentInfo>> {
+    let consents = oidc_consent::Entity::find()
+      .filter(oidc_consent::Column::UserId.eq(user_id))
+      .find_also_related(oidc_client::Entity)
+      .all(self.db)
+      .await?;
+
+    Ok(
+      consents
+        .into_iter()
+        .filter_map(|(consent, client)| {
+          Some(ConsentInfo {
+            client: consent.client_id,
+            client_name: client?.name,
+            scopes: consent
+              .scopes
+              .split_whitespace()
+              .map(str::to_string)
+              .collect(),
+            created: consent.created,
+          })
+        })
+        .collect(),
+    )
+  }
+
+  /// Returns false if the user never consented to the client.
+  #[instrument(skip(self))]
+  pub async fn revoke_consent(&self, user_id: Uuid, client_id: Uuid) -> Result<bool> {
+    let res = oidc_consent::Entity::delete_by_id((user_id, client_id))
+      .exec(self.db)
+      .await?;
+    Ok(res.rows_affected == 1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_clients() {
+    let (conn, _) = setup().await;
+    let table = conn.oidc_client();
+    let id = table
+      .create_client(
+        "wiki".into(),
+        None,
+        vec![
+          "https://wiki.example/cb".into(),
+          "http://localhost/cb".into(),
+        ],
+        false,
+      )
+      .await
+      .unwrap();
+
+    let clients = table.list_clients().await.unwrap();
+    assert_eq!(clients.len(), 1);
+    assert!(clients[0].public);
+    assert_eq!(clients[0].redirect_uris.len(), 2);
+
+    assert!(
+      table
+        .edit_client(
+          id,
+          "docs".into(),
+          vec!["https://docs.example/cb".into()],
+          true
+        )
+        .await
+        .unwrap()
+    );
+    table.set_secret(id, "hash".into()).await.unwrap();
+    let client = table.get_client(id).await.unwrap().unwrap();
+    assert_eq!(client.name, "docs");
+    assert_eq!(client.redirect_uris(), vec!["https://docs.example/cb"]);
+    assert_eq!(client.secret_hash.as_deref(), Some("hash"));
+    assert!(client.skip_consent);
+
+    assert!(table.delete_client(id).await.unwrap());
+    assert!(!table.delete_client(id).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_consents() {
+    let (conn, user) = setup().await;
+    let table = conn.oidc_client();
+    let client = table
+      .create_client(
+        "wiki".into(),
+        None,
+        vec!["https://wiki.example/cb".into()],
+        false,
+      )
+      .await
+      .unwrap();
+
+    assert!(table.get_consent(user, client).await.unwrap().is_empty());
+    table
+      .grant_consent(user, client, vec!["openid".into()])
+      .await
+      .unwrap();
+    table
+      .grant_consent(user, client, vec!["openid".into(), "email".into()])
+      .await
+      .unwrap();
+    assert_eq!(
+      table.get_consent(user, client).await.unwrap(),
+      vec!["openid", "email"]
+    );
+
+    let consents = table.list_consents(user).await.unwrap();
+    assert_eq!(consents.len(), 1);
+    assert_eq!(consents[0].client_name, "wiki");
+
+    // Deleting the client removes its consents.
+    table.delete_client(client).await.unwrap();
+    assert!(table.list_consents(user).await.unwrap().is_empty());
+    assert!(!table.revoke_consent(user, client).await.unwrap());
+  }
+}
@@ -0,0 +1,207 @@
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, prelude::*};
+use tracing::instrument;
+
+use crate::{
+  db::{
+    entities::{oidc_identity, user},
+    tables::user::UserTable,
+  },
+  error::Result,
+};
+
+pub struct OidcIdentityTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+impl<'db, C: ConnectionTrait> OidcIdentityTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_identities(&self, user_id: Uuid) -> Result<Vec<oidc_identity::Model>> {
+    Ok(
+      oidc_identity::Entity::find()
+        .filter(oidc_identity::Column::UserId.eq(user_id))
+        .all(self.db)
+        .await?,
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_provider_identities(
+    &self,
+    provider: &str,
+  ) -> Result<Vec<oidc_identity::Model>> {
+    Ok(
+      oidc_identity::Entity::find()
+        .filter(oidc_identity::Column::Provider.eq(provider))
+        .all(self.db)
+        .await?,
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_identity(
+    &self,
+    provider: &str,
+    subject: &str,
+  ) -> Result<Option<oidc_identity::Model>> {
+    Ok(
+      oidc_identity::Entity::find()
+        .filter(oidc_identity::Column::Provider.eq(provider))
+        .filter(oidc_identity::Column::Subject.eq(subject))
+        .one(self.db)
+        .await?,
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn has_identity(&self, user_id: Uuid, provider: &str) -> Result<bool> {
+    Ok(
+      oidc_identity::Entity::find()
+        .filter(oidc_identity::Column::UserId.eq(user_id))
+        .filter(oidc_identity::Column::Provider.eq(provider))
+        .one(self.db)
+        .await?
+        .is_some(),
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn link(&self, user_id: Uuid, provider: &str, subject: &str) -> Result<()> {
+    let model = oidc_identity::ActiveModel {
+      id: Set(Uuid::now_v7()),
+      user_id: Set(user_id),
+      provider: Set(provider.to_string()),
+      subject: Set(subject.to_string()),
+      created: Set(Utc::now().naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(())
+  }
+
+  /// Returns false if the identity does not exist or belongs to another user.
+  #[instrument(skip(self))]
+  pub async fn unlink(&self, user_id: Uuid, id: Uuid) -> Result<bool> {
+    let res = oidc_identity::Entity::delete_many()
+      .filter(oidc_identity::Column::Id.eq(id))
+      .filter(oidc_identity::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+
+  /// The user holding the subject at the provider. Without one the user with
+  /// the same email is linked if `link_by_email` is set and has no other
+  /// subject at this provider yet.
+  #[instrument(skip(self))]
+  pub async fn resolve_user(
+    &self,
+    provider: &str,
+    subject: &str,
+    email: &str,
+    link_by_email: bool,
+  ) -> Result<Option<user::Model>> {
+    let users = UserTable::new(self.db);
+    if let Some(identity) = self.get_identity(provider, subject).await? {
+      return Ok(Some(users.get_user_by_id(identity.user_id).await?));
+    }
+    if !link_by_email {
+      return Ok(None);
+    }
+
+    let Some(user) = users.try_get_user_by_email(email).await? else {
+      return Ok(None);
+    };
+    if self.has_identity(user.id, provider).await? {
+      return Ok(None);
+    }
+
+    self.link(user.id, provider, subject).await?;
+    Ok(Some(user))
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_user_holds_subjects_of_several_providers() {
+    let (conn, user) = setup().await;
+    let table = conn.oidc_identity();
+
+    table.link(user, "corp", "corp-1").await.unwrap();
+    table.link(user, "github", "gh-1").await.unwrap();
+    assert_eq!(table.list_identities(user).await.unwrap().len(), 2);
+    // A subject can only belong to one user.
+    assert!(table.link(user, "corp", "corp-1").await.is_err());
+
+    let resolved = table
+      .resolve_user("github", "gh-1", "other@example.com", false)
+      .await
+      .unwrap();
+    assert_eq!(resolved.map(|u| u.id), Some(user));
+
+    let identity = table.get_identity("github", "gh-1").await.unwrap().unwrap();
+    assert!(!table.unlink(Uuid::new_v4(), identity.id).await.unwrap());
+    assert!(table.unlink(user, identity.id).await.unwrap());
+    assert_eq!(table.list_identities(user).await.unwrap().len(), 1);
+  }
+
+  #[tokio::test]
+  async fn test_resolve_user_by_email_only_if_allowed() {
+    let (conn, user) = setup().await;
+    let table = conn.oidc_identity();
+
+    assert!(
+      table
+        .resolve_user("github", "gh-1", "user@example.com", false)
+        .await
+        .unwrap()
+        .is_none()
+    );
+    let resolved = table
+      .resolve_user("github", "gh-1", "user@example.com", true)
+      .await
+      .unwrap();
+    assert_eq!(resolved.map(|u| u.id), Some(user));
+    assert!(table.has_identity(user, "github").await.unwrap());
+
+    // Another subject of the same provider does not take over the account.
+    assert!(
+      table
+        .resolve_user("github", "gh-2", "user@example.com", true)
+        .await
+        .unwrap()
+        .is_none()
+    );
+  }
+}
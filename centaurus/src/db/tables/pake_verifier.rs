@@ -0,0 +1,49 @@
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, prelude::*};
+use tracing::instrument;
+
+use crate::{db::entities::pake_verifier, error::Result};
+
+pub struct PakeVerifierTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+impl<'db, C: ConnectionTrait> PakeVerifierTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_verifier(&self, user_id: Uuid) -> Result<Option<pake_verifier::Model>> {
+    Ok(
+      pake_verifier::Entity::find_by_id(user_id)
+        .one(self.db)
+        .await?,
+    )
+  }
+
+  /// Replaces an existing verifier of the user.
+  #[instrument(skip(self, salt, verifier))]
+  pub async fn set_verifier(&self, user_id: Uuid, salt: String, verifier: String) -> Result<()> {
+    self.remove_verifier(user_id).await?;
+
+    let model = pake_verifier::ActiveModel {
+      user_id: Set(user_id),
+      salt: Set(salt),
+      verifier: Set(verifier),
+      created: Set(Utc::now().naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self))]
+  pub async fn remove_verifier(&self, user_id: Uuid) -> Result<()> {
+    pake_verifier::Entity::delete_by_id(user_id)
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+}
@@ -0,0 +1,272 @@
+use chrono::{NaiveDateTime, Utc};
+use sea_orm::{ActiveValue::Set, QueryOrder, prelude::*, sea_query::Expr};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{db::entities::passkey, error::Result};
+
+pub struct PasskeyTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct PasskeyInfo {
+  pub uuid: Uuid,
+  pub name: String,
+  pub created: NaiveDateTime,
+  pub last_used: Option<NaiveDateTime>,
+  pub second_factor: bool,
+}
+
+impl<'db, C: ConnectionTrait> PasskeyTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self, credential))]
+  pub async fn create_passkey(
+    &self,
+    user_id: Uuid,
+    name: String,
+    credential_id: String,
+    credential: String,
+    second_factor: bool,
+  ) -> Result<Uuid> {
+    let id = Uuid::now_v7();
+    let model = passkey::ActiveModel {
+      id: Set(id),
+      user_id: Set(user_id),
+      credential_id: Set(credential_id),
+      name: Set(name),
+      credential: Set(credential),
+      created: Set(Utc::now().naive_utc()),
+      last_used: Set(None),
+      second_factor: Set(second_factor),
+    };
+    model.insert(self.db).await?;
+
+    Ok(id)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_user_passkeys(&self, user_id: Uuid) -> Result<Vec<passkey::Model>> {
+    Ok(
+      passkey::Entity::find()
+        .filter(passkey::Column::UserId.eq(user_id))
+        .order_by_asc(passkey::Column::Created)
+        .all(self.db)
+        .await?,
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_user_passkeys(&self, user_id: Uuid) -> Result<Vec<PasskeyInfo>> {
+    Ok(
+      self
+        .get_user_passkeys(user_id)
+        .await?
+        .into_iter()
+        .map(|p| PasskeyInfo {
+          uuid: p.id,
+          name: p.name,
+          created: p.created,
+          last_used: p.last_used,
+          second_factor: p.second_factor,
+        })
+        .collect(),
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn has_passkeys(&self, user_id: Uuid) -> Result<bool> {
+    Ok(
+      passkey::Entity::find()
+        .filter(passkey::Column::UserId.eq(user_id))
+        .count(self.db)
+        .await?
+        > 0,
+    )
+  }
+
+  /// Whether one of the passkeys was registered as second factor.
+  #[instrument(skip(self))]
+  pub async fn has_second_factor(&self, user_id: Uuid) -> Result<bool> {
+    Ok(
+      passkey::Entity::find()
+        .filter(passkey::Column::UserId.eq(user_id))
+        .filter(passkey::Column::SecondFactor.eq(true))
+        .count(self.db)
+        .await?
+        > 0,
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_by_credential_id(&self, credential_id: &str) -> Result<Option<passkey::Model>> {
+    Ok(
+      passkey::Entity::find()
+        .filter(passkey::Column::CredentialId.eq(credential_id))
+        .one(self.db)
+        .await?,
+    )
+  }
+
+  /// Store the credential after a successful authentication, which may have
+  /// changed its signature counter.
+  #[instrument(skip(self, credential))]
+  pub async fn mark_used(&self, id: Uuid, credential: String) -> Result<()> {
+    passkey::Entity::update_many()
+      .col_expr(passkey::Column::Credential, Expr::value(credential))
+      .col_expr(
+        passkey::Column::LastUsed,
+        Expr::value(Utc::now().naive_utc()),
+      )
+      .filter(passkey::Column::Id.eq(id))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  /// Returns false if the user has no passkey with this id.
+  #[instrument(skip(self))]
+  pub async fn rename_passkey(&self, user_id: Uuid, id: Uuid, name: String) -> Result<bool> {
+    let res = passkey::Entity::update_many()
+      .col_expr(passkey::Column::Name, Expr::value(name))
+      .filter(passkey::Column::Id.eq(id))
+      .filter(passkey::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+
+  /// Returns false if the user has no passkey with this id.
+  #[instrument(skip(self))]
+  pub async fn delete_passkey(&self, user_id: Uuid, id: Uuid) -> Result<bool> {
+    let res = passkey::Entity::delete_many()
+      .filter(passkey::Column::Id.eq(id))
+      .filter(passkey::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_passkey_lifecycle() {
+    let (conn, user) = setup().await;
+    let table = conn.passkey();
+    assert!(!table.has_passkeys(user).await.unwrap());
+
+    let id = table
+      .create_passkey(user, "Laptop".into(), "cred".into(), "{}".into(), false)
+      .await
+      .unwrap();
+    assert!(table.has_passkeys(user).await.unwrap());
+    assert_eq!(
+      table
+        .get_by_credential_id("cred")
+        .await
+        .unwrap()
+        .unwrap()
+        .id,
+      id
+    );
+    assert!(table.get_by_credential_id("other").await.unwrap().is_none());
+
+    table.mark_used(id, "{\"a\":1}".into()).await.unwrap();
+    let stored = table.get_by_credential_id("cred").await.unwrap().unwrap();
+    assert_eq!(stored.credential, "{\"a\":1}");
+    assert!(stored.last_used.is_some());
+
+    assert!(
+      table
+        .rename_passkey(user, id, "Phone".into())
+        .await
+        .unwrap()
+    );
+    let list = table.list_user_passkeys(user).await.unwrap();
+    assert_eq!(list.len(), 1);
+    assert_eq!(list[0].name, "Phone");
+
+    assert!(table.delete_passkey(user, id).await.unwrap());
+    assert!(!table.has_passkeys(user).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_passkey_changes_are_scoped_to_owner() {
+    let (conn, user) = setup().await;
+    let other = conn
+      .user()
+      .create_user(
+        "other".into(),
+        "other@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    let table = conn.passkey();
+    let id = table
+      .create_passkey(user, "Laptop".into(), "cred".into(), "{}".into(), false)
+      .await
+      .unwrap();
+
+    assert!(
+      !table
+        .rename_passkey(other, id, "Mine".into())
+        .await
+        .unwrap()
+    );
+    assert!(!table.delete_passkey(other, id).await.unwrap());
+    assert!(table.has_passkeys(user).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_credential_id_is_unique() {
+    let (conn, user) = setup().await;
+    let table = conn.passkey();
+    table
+      .create_passkey(user, "a".into(), "cred".into(), "{}".into(), false)
+      .await
+      .unwrap();
+    assert!(
+      table
+        .create_passkey(user, "b".into(), "cred".into(), "{}".into(), false)
+        .await
+        .is_err()
+    );
+  }
+}
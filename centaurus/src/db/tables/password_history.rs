@@ -0,0 +1,115 @@
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, QueryOrder, QuerySelect, prelude::*};
+use tracing::instrument;
+
+use crate::{db::entities::password_history, error::Result};
+
+/// Previous hashes kept per user, the upper bound for the reuse check.
+pub const MAX_PASSWORD_HISTORY: u64 = 24;
+
+pub struct PasswordHistoryTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+impl<'db, C: ConnectionTrait> PasswordHistoryTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  /// Remember a replaced hash and forget the ones beyond
+  /// [`MAX_PASSWORD_HISTORY`].
+  #[instrument(skip(self, hash))]
+  pub async fn add(&self, user_id: Uuid, hash: String) -> Result<()> {
+    let model = password_history::ActiveModel {
+      id: Set(Uuid::now_v7()),
+      user_id: Set(user_id),
+      hash: Set(hash),
+      created: Set(Utc::now().naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    let ids: Vec<Uuid> = password_history::Entity::find()
+      .select_only()
+      .column(password_history::Column::Id)
+      .filter(password_history::Column::UserId.eq(user_id))
+      .order_by_desc(password_history::Column::Id)
+      .into_tuple()
+      .all(self.db)
+      .await?;
+    let outdated: Vec<Uuid> = ids
+      .into_iter()
+      .skip(MAX_PASSWORD_HISTORY as usize)
+      .collect();
+    if !outdated.is_empty() {
+      password_history::Entity::delete_many()
+        .filter(password_history::Column::Id.is_in(outdated))
+        .exec(self.db)
+        .await?;
+    }
+
+    Ok(())
+  }
+
+  /// The `limit` most recently replaced hashes, newest first.
+  #[instrument(skip(self))]
+  pub async fn recent(&self, user_id: Uuid, limit: u64) -> Result<Vec<String>> {
+    Ok(
+      password_history::Entity::find()
+        .select_only()
+        .column(password_history::Column::Hash)
+        .filter(password_history::Column::UserId.eq(user_id))
+        .order_by_desc(password_history::Column::Id)
+        .limit(limit)
+        .into_tuple()
+        .all(self.db)
+        .await?,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::connect_db;
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  #[tokio::test]
+  async fn test_history_keeps_newest_hashes() {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "0".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+
+    // Every password change moves the old hash into the history.
+    for i in 1..=MAX_PASSWORD_HISTORY + 2 {
+      conn
+        .user()
+        .update_user_password(user, i.to_string())
+        .await
+        .unwrap();
+    }
+
+    let table = conn.password_history();
+    let newest = MAX_PASSWORD_HISTORY + 1;
+    assert_eq!(
+      table.recent(user, 2).await.unwrap(),
+      vec![newest.to_string(), (newest - 1).to_string()]
+    );
+    let all = table.recent(user, 100).await.unwrap();
+    assert_eq!(all.len() as u64, MAX_PASSWORD_HISTORY);
+    assert!(!all.contains(&"0".to_string()));
+  }
+}
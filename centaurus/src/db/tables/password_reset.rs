@@ -0,0 +1,153 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{ActiveValue::Set, prelude::*};
+use tracing::instrument;
+
+use crate::{db::entities::password_reset, error::Result};
+
+pub struct PasswordResetTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+impl<'db, C: ConnectionTrait> PasswordResetTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self, token_hash))]
+  pub async fn create_token(
+    &self,
+    user_id: Uuid,
+    token_hash: String,
+    exp: DateTime<Utc>,
+  ) -> Result<()> {
+    let model = password_reset::ActiveModel {
+      id: Set(Uuid::now_v7()),
+      user_id: Set(user_id),
+      token_hash: Set(token_hash),
+      created: Set(Utc::now().naive_utc()),
+      exp: Set(exp.naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(())
+  }
+
+  /// The user of the token, without using it up. Expired tokens resolve to
+  /// `None`.
+  #[instrument(skip(self, token_hash))]
+  pub async fn get_token_user(&self, token_hash: &str) -> Result<Option<Uuid>> {
+    Ok(
+      password_reset::Entity::find()
+        .filter(password_reset::Column::TokenHash.eq(token_hash))
+        .filter(password_reset::Column::Exp.gt(Utc::now().naive_utc()))
+        .one(self.db)
+        .await?
+        .map(|token| token.user_id),
+    )
+  }
+
+  /// Delete the token and return its user. Expired tokens and tokens that were
+  /// already used resolve to `None`.
+  #[instrument(skip(self, token_hash))]
+  pub async fn consume_token(&self, token_hash: &str) -> Result<Option<Uuid>> {
+    let Some(token) = password_reset::Entity::find()
+      .filter(password_reset::Column::TokenHash.eq(token_hash))
+      .filter(password_reset::Column::Exp.gt(Utc::now().naive_utc()))
+      .one(self.db)
+      .await?
+    else {
+      return Ok(None);
+    };
+
+    let res = password_reset::Entity::delete_by_id(token.id)
+      .exec(self.db)
+      .await?;
+
+    Ok((res.rows_affected == 1).then_some(token.user_id))
+  }
+
+  #[instrument(skip(self))]
+  pub async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<()> {
+    password_reset::Entity::delete_many()
+      .filter(password_reset::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self))]
+  pub async fn remove_expired(&self) -> Result<()> {
+    password_reset::Entity::delete_many()
+      .filter(password_reset::Column::Exp.lt(Utc::now().naive_utc()))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use chrono::Duration;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_consume_token_only_once() {
+    let (conn, user) = setup().await;
+    let table = conn.password_reset();
+    let exp = Utc::now() + Duration::seconds(3600);
+
+    table.create_token(user, "hash".into(), exp).await.unwrap();
+    assert_eq!(table.consume_token("hash").await.unwrap(), Some(user));
+    assert_eq!(table.consume_token("hash").await.unwrap(), None);
+    assert_eq!(table.consume_token("other").await.unwrap(), None);
+  }
+
+  #[tokio::test]
+  async fn test_expired_and_revoked_tokens_are_rejected() {
+    let (conn, user) = setup().await;
+    let table = conn.password_reset();
+
+    table
+      .create_token(user, "expired".into(), Utc::now() - Duration::seconds(1))
+      .await
+      .unwrap();
+    table
+      .create_token(user, "valid".into(), Utc::now() + Duration::seconds(3600))
+      .await
+      .unwrap();
+    assert_eq!(table.consume_token("expired").await.unwrap(), None);
+
+    // Changing the password revokes every outstanding token of the user.
+    conn
+      .user()
+      .update_user_password(user, "new".into())
+      .await
+      .unwrap();
+    assert_eq!(table.consume_token("valid").await.unwrap(), None);
+  }
+}
@@ -0,0 +1,197 @@
+use chrono::{DateTime, Utc};
+use sea_orm::{ActiveValue::Set, prelude::*, sea_query::Expr};
+use tracing::instrument;
+
+use crate::{db::entities::refresh_token, error::Result};
+
+pub struct RefreshTokenTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+impl<'db, C: ConnectionTrait> RefreshTokenTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self, token_hash))]
+  pub async fn create_refresh_token(
+    &self,
+    user_id: Uuid,
+    family: Uuid,
+    token_hash: String,
+    exp: DateTime<Utc>,
+  ) -> Result<()> {
+    let model = refresh_token::ActiveModel {
+      id: Set(Uuid::now_v7()),
+      family: Set(family),
+      user_id: Set(user_id),
+      token_hash: Set(token_hash),
+      used: Set(false),
+      created: Set(Utc::now().naive_utc()),
+      exp: Set(exp.naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self, token_hash))]
+  pub async fn get_refresh_token(&self, token_hash: &str) -> Result<Option<refresh_token::Model>> {
+    Ok(
+      refresh_token::Entity::find()
+        .filter(refresh_token::Column::TokenHash.eq(token_hash))
+        .one(self.db)
+        .await?,
+    )
+  }
+
+  /// Mark the token as used. Returns false if it was already used, which means
+  /// the token has been replayed.
+  #[instrument(skip(self))]
+  pub async fn consume_refresh_token(&self, id: Uuid) -> Result<bool> {
+    let res = refresh_token::Entity::update_many()
+      .col_expr(refresh_token::Column::Used, Expr::value(true))
+      .filter(refresh_token::Column::Id.eq(id))
+      .filter(refresh_token::Column::Used.eq(false))
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn revoke_family(&self, family: Uuid) -> Result<()> {
+    refresh_token::Entity::delete_many()
+      .filter(refresh_token::Column::Family.eq(family))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self))]
+  pub async fn revoke_user_tokens(&self, user_id: Uuid) -> Result<()> {
+    refresh_token::Entity::delete_many()
+      .filter(refresh_token::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self))]
+  pub async fn remove_expired(&self) -> Result<()> {
+    refresh_token::Entity::delete_many()
+      .filter(refresh_token::Column::Exp.lt(Utc::now().naive_utc()))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use chrono::Duration;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_consume_refresh_token_only_once() {
+    let (conn, user) = setup().await;
+    let table = conn.refresh_token();
+    let exp = Utc::now() + Duration::seconds(3600);
+
+    table
+      .create_refresh_token(user, Uuid::now_v7(), "hash".into(), exp)
+      .await
+      .unwrap();
+    let token = table.get_refresh_token("hash").await.unwrap().unwrap();
+    assert_eq!(token.user_id, user);
+    assert!(!token.used);
+
+    assert!(table.consume_refresh_token(token.id).await.unwrap());
+    // A second consume means the token was replayed.
+    assert!(!table.consume_refresh_token(token.id).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_revoke_family_leaves_other_families() {
+    let (conn, user) = setup().await;
+    let table = conn.refresh_token();
+    let exp = Utc::now() + Duration::seconds(3600);
+    let family = Uuid::now_v7();
+
+    table
+      .create_refresh_token(user, family, "a".into(), exp)
+      .await
+      .unwrap();
+    table
+      .create_refresh_token(user, family, "b".into(), exp)
+      .await
+      .unwrap();
+    table
+      .create_refresh_token(user, Uuid::now_v7(), "c".into(), exp)
+      .await
+      .unwrap();
+
+    table.revoke_family(family).await.unwrap();
+    assert!(table.get_refresh_token("a").await.unwrap().is_none());
+    assert!(table.get_refresh_token("b").await.unwrap().is_none());
+    assert!(table.get_refresh_token("c").await.unwrap().is_some());
+
+    table.revoke_user_tokens(user).await.unwrap();
+    assert!(table.get_refresh_token("c").await.unwrap().is_none());
+  }
+
+  #[tokio::test]
+  async fn test_remove_expired_only_removes_past() {
+    let (conn, user) = setup().await;
+    let table = conn.refresh_token();
+
+    table
+      .create_refresh_token(
+        user,
+        Uuid::now_v7(),
+        "expired".into(),
+        Utc::now() - Duration::seconds(3600),
+      )
+      .await
+      .unwrap();
+    table
+      .create_refresh_token(
+        user,
+        Uuid::now_v7(),
+        "valid".into(),
+        Utc::now() + Duration::seconds(3600),
+      )
+      .await
+      .unwrap();
+
+    table.remove_expired().await.unwrap();
+    assert!(table.get_refresh_token("expired").await.unwrap().is_none());
+    assert!(table.get_refresh_token("valid").await.unwrap().is_some());
+  }
+}
@@ -0,0 +1,231 @@
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, IntoActiveModel, QueryOrder, prelude::*, sea_query::Expr};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+  db::{
+    entities::{client_secret, group, group_user, user},
+    tables::{group::GroupTable, session::SessionTable, user::SimpleGroupInfo},
+  },
+  error::Result,
+};
+
+pub struct ServiceAccountTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct ServiceAccountInfo {
+  pub uuid: Uuid,
+  pub name: String,
+  pub groups: Vec<SimpleGroupInfo>,
+}
+
+impl<'db, C: ConnectionTrait> ServiceAccountTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  /// Service accounts have no usable email or password, the email column is
+  /// filled with a reserved address because it is unique and not null.
+  #[instrument(skip(self, secret_hash))]
+  pub async fn create_service_account(
+    &self,
+    name: String,
+    groups: Vec<Uuid>,
+    secret_hash: String,
+  ) -> Result<Uuid> {
+    let id = Uuid::now_v7();
+    user::Model {
+      id,
+      name,
+      email: format!("{id}@service.invalid"),
+      password: String::new(),
+      salt: String::new(),
+      oidc_user: false,
+      oidc_subject: None,
+      service_account: true,
+      active: true,
+    }
+    .into_active_model()
+    .insert(self.db)
+    .await?;
+
+    if !groups.is_empty() {
+      GroupTable::new(self.db)
+        .add_user_to_groups(id, groups)
+        .await?;
+    }
+    self.set_client_secret(id, secret_hash).await?;
+
+    Ok(id)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn is_service_account(&self, id: Uuid) -> Result<bool> {
+    Ok(
+      user::Entity::find_by_id(id)
+        .one(self.db)
+        .await?
+        .is_some_and(|u| u.service_account),
+    )
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_service_accounts(&self) -> Result<Vec<ServiceAccountInfo>> {
+    let accounts = user::Entity::find()
+      .filter(user::Column::ServiceAccount.eq(true))
+      .order_by_asc(user::Column::Name)
+      .all(self.db)
+      .await?;
+    let groups = accounts
+      .load_many_to_many(group::Entity, group_user::Entity, self.db)
+      .await?;
+
+    Ok(
+      accounts
+        .into_iter()
+        .zip(groups)
+        .map(|(account, groups)| ServiceAccountInfo {
+          uuid: account.id,
+          name: account.name,
+          groups: groups
+            .into_iter()
+            .map(|group| SimpleGroupInfo {
+              uuid: group.id,
+              name: group.name,
+            })
+            .collect(),
+        })
+        .collect(),
+    )
+  }
+
+  /// Replaces the current secret, so the old one and the tokens issued for it
+  /// stop working immediately.
+  #[instrument(skip(self, secret_hash))]
+  pub async fn set_client_secret(&self, id: Uuid, secret_hash: String) -> Result<()> {
+    client_secret::Entity::delete_by_id(id)
+      .exec(self.db)
+      .await?;
+    SessionTable::new(self.db)
+      .revoke_user_sessions(id, None)
+      .await?;
+
+    let model = client_secret::ActiveModel {
+      user_id: Set(id),
+      secret_hash: Set(secret_hash),
+      created: Set(Utc::now().naive_utc()),
+      last_used: Set(None),
+    };
+    model.insert(self.db).await?;
+
+    Ok(())
+  }
+
+  /// Returns true and records the use if the secret belongs to the account.
+  #[instrument(skip(self, secret_hash))]
+  pub async fn use_client_secret(&self, id: Uuid, secret_hash: &str) -> Result<bool> {
+    let res = client_secret::Entity::update_many()
+      .col_expr(
+        client_secret::Column::LastUsed,
+        Expr::value(Utc::now().naive_utc()),
+      )
+      .filter(client_secret::Column::UserId.eq(id))
+      .filter(client_secret::Column::SecretHash.eq(secret_hash))
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> Connection {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    conn
+  }
+
+  #[tokio::test]
+  async fn test_service_accounts_are_listed_separately() {
+    let conn = setup().await;
+    let group = conn.group().create_group("bots".into()).await.unwrap();
+    conn
+      .group()
+      .add_permissions_to_group(group, vec!["user:view".into()])
+      .await
+      .unwrap();
+    conn
+      .user()
+      .create_user(
+        "human".into(),
+        "human@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    let bot = conn
+      .service_account()
+      .create_service_account("bot".into(), vec![group], "hash".into())
+      .await
+      .unwrap();
+
+    let users = conn.user().list_users().await.unwrap();
+    assert_eq!(users.len(), 1);
+    assert_eq!(users[0].name, "human");
+
+    let accounts = conn
+      .service_account()
+      .list_service_accounts()
+      .await
+      .unwrap();
+    assert_eq!(accounts.len(), 1);
+    assert_eq!(accounts[0].uuid, bot);
+    assert_eq!(accounts[0].groups[0].uuid, group);
+
+    assert!(
+      conn
+        .service_account()
+        .is_service_account(bot)
+        .await
+        .unwrap()
+    );
+    assert!(
+      conn
+        .group()
+        .user_hash_permissions(bot, "user:view")
+        .await
+        .unwrap()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_client_secret_rotation() {
+    let conn = setup().await;
+    let table = conn.service_account();
+    let bot = table
+      .create_service_account("bot".into(), Vec::new(), "first".into())
+      .await
+      .unwrap();
+
+    assert!(table.use_client_secret(bot, "first").await.unwrap());
+    assert!(!table.use_client_secret(bot, "other").await.unwrap());
+
+    table.set_client_secret(bot, "second".into()).await.unwrap();
+    assert!(!table.use_client_secret(bot, "first").await.unwrap());
+    assert!(table.use_client_secret(bot, "second").await.unwrap());
+  }
+}
@@ -0,0 +1,367 @@
+use chrono::{Duration, NaiveDateTime, Utc};
+use sea_orm::{ActiveValue::Set, QueryOrder, prelude::*, sea_query::Expr};
+use serde::{Deserialize, Serialize};
+use tracing::instrument;
+
+use crate::{
+  db::entities::{refresh_token, session},
+  error::Result,
+};
+
+/// `last_seen` is only written when it is older than this, so validating a token
+/// does not cause a write on every request.
+const TOUCH_INTERVAL: i64 = 60;
+
+pub struct SessionTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+#[derive(Serialize, Deserialize)]
+#[cfg_attr(feature = "openapi", derive(schemars::JsonSchema))]
+pub struct SessionListInfo {
+  pub uuid: Uuid,
+  pub device: Option<String>,
+  pub ip: Option<String>,
+  pub user_agent: Option<String>,
+  pub created: NaiveDateTime,
+  pub last_seen: NaiveDateTime,
+  pub current: bool,
+}
+
+impl<'db, C: ConnectionTrait> SessionTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self))]
+  pub async fn create_session(
+    &self,
+    user_id: Uuid,
+    device: Option<String>,
+    ip: Option<String>,
+    user_agent: Option<String>,
+  ) -> Result<Uuid> {
+    let id = Uuid::now_v7();
+    let now = Utc::now().naive_utc();
+
+    let model = session::ActiveModel {
+      id: Set(id),
+      user_id: Set(user_id),
+      device: Set(device),
+      ip: Set(ip),
+      user_agent: Set(user_agent),
+      created: Set(now),
+      last_seen: Set(now),
+      oidc_provider: Set(None),
+      oidc_sid: Set(None),
+    };
+    model.insert(self.db).await?;
+
+    Ok(id)
+  }
+
+  /// Remember the OIDC provider the session was started with.
+  #[instrument(skip(self))]
+  pub async fn set_oidc_session(
+    &self,
+    id: Uuid,
+    provider: String,
+    oidc_sid: Option<String>,
+  ) -> Result<()> {
+    session::Entity::update_many()
+      .col_expr(session::Column::OidcProvider, Expr::value(provider))
+      .col_expr(session::Column::OidcSid, Expr::value(oidc_sid))
+      .filter(session::Column::Id.eq(id))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_session(&self, id: Uuid) -> Result<Option<session::Model>> {
+    Ok(session::Entity::find_by_id(id).one(self.db).await?)
+  }
+
+  /// Returns false if the session does not exist anymore, i.e. it was revoked.
+  #[instrument(skip(self))]
+  pub async fn touch_session(&self, id: Uuid) -> Result<bool> {
+    let Some(session) = self.get_session(id).await? else {
+      return Ok(false);
+    };
+
+    let now = Utc::now().naive_utc();
+    if now - session.last_seen > Duration::seconds(TOUCH_INTERVAL) {
+      session::Entity::update_many()
+        .col_expr(session::Column::LastSeen, Expr::value(now))
+        .filter(session::Column::Id.eq(id))
+        .exec(self.db)
+        .await?;
+    }
+
+    Ok(true)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn list_user_sessions(
+    &self,
+    user_id: Uuid,
+    current: Option<Uuid>,
+  ) -> Result<Vec<SessionListInfo>> {
+    let sessions = session::Entity::find()
+      .filter(session::Column::UserId.eq(user_id))
+      .order_by_desc(session::Column::LastSeen)
+      .all(self.db)
+      .await?;
+
+    Ok(
+      sessions
+        .into_iter()
+        .map(|s| SessionListInfo {
+          uuid: s.id,
+          current: Some(s.id) == current,
+          device: s.device,
+          ip: s.ip,
+          user_agent: s.user_agent,
+          created: s.created,
+          last_seen: s.last_seen,
+        })
+        .collect(),
+    )
+  }
+
+  /// Delete the session and its refresh tokens, which share the session id as
+  /// their family.
+  #[instrument(skip(self))]
+  pub async fn revoke_session(&self, id: Uuid) -> Result<()> {
+    refresh_token::Entity::delete_many()
+      .filter(refresh_token::Column::Family.eq(id))
+      .exec(self.db)
+      .await?;
+    session::Entity::delete_by_id(id).exec(self.db).await?;
+
+    Ok(())
+  }
+
+  /// Revoke every session of the user, optionally keeping one (the caller's own).
+  #[instrument(skip(self))]
+  pub async fn revoke_user_sessions(&self, user_id: Uuid, except: Option<Uuid>) -> Result<()> {
+    let mut refresh =
+      refresh_token::Entity::delete_many().filter(refresh_token::Column::UserId.eq(user_id));
+    let mut sessions = session::Entity::delete_many().filter(session::Column::UserId.eq(user_id));
+
+    if let Some(except) = except {
+      refresh = refresh.filter(refresh_token::Column::Family.ne(except));
+      sessions = sessions.filter(session::Column::Id.ne(except));
+    }
+
+    refresh.exec(self.db).await?;
+    sessions.exec(self.db).await?;
+
+    Ok(())
+  }
+
+  /// Revoke the sessions started with the provider, of the user and/or with
+  /// the session `oidc_sid` at the provider. Returns the number of revoked
+  /// sessions.
+  #[instrument(skip(self))]
+  pub async fn revoke_oidc_sessions(
+    &self,
+    provider: &str,
+    user_id: Option<Uuid>,
+    oidc_sid: Option<&str>,
+  ) -> Result<usize> {
+    if user_id.is_none() && oidc_sid.is_none() {
+      return Ok(0);
+    }
+
+    let mut query = session::Entity::find().filter(session::Column::OidcProvider.eq(provider));
+    if let Some(user_id) = user_id {
+      query = query.filter(session::Column::UserId.eq(user_id));
+    }
+    if let Some(oidc_sid) = oidc_sid {
+      query = query.filter(session::Column::OidcSid.eq(oidc_sid));
+    }
+    let sessions = query.all(self.db).await?;
+
+    for session in &sessions {
+      self.revoke_session(session.id).await?;
+    }
+
+    Ok(sessions.len())
+  }
+
+  #[instrument(skip(self))]
+  pub async fn remove_inactive(&self, before: NaiveDateTime) -> Result<()> {
+    session::Entity::delete_many()
+      .filter(session::Column::LastSeen.lt(before))
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_session_lifecycle() {
+    let (conn, user) = setup().await;
+    let table = conn.session();
+
+    let id = table
+      .create_session(
+        user,
+        Some("Firefox on Linux".into()),
+        Some("127.0.0.1".into()),
+        None,
+      )
+      .await
+      .unwrap();
+    assert!(table.touch_session(id).await.unwrap());
+
+    let sessions = table.list_user_sessions(user, Some(id)).await.unwrap();
+    assert_eq!(sessions.len(), 1);
+    assert!(sessions[0].current);
+    assert_eq!(sessions[0].device.as_deref(), Some("Firefox on Linux"));
+
+    table.revoke_session(id).await.unwrap();
+    assert!(!table.touch_session(id).await.unwrap());
+    assert!(
+      table
+        .list_user_sessions(user, None)
+        .await
+        .unwrap()
+        .is_empty()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_revoke_session_removes_refresh_family() {
+    let (conn, user) = setup().await;
+    let id = conn
+      .session()
+      .create_session(user, None, None, None)
+      .await
+      .unwrap();
+    conn
+      .refresh_token()
+      .create_refresh_token(user, id, "hash".into(), Utc::now() + Duration::hours(1))
+      .await
+      .unwrap();
+
+    conn.session().revoke_session(id).await.unwrap();
+    assert!(
+      conn
+        .refresh_token()
+        .get_refresh_token("hash")
+        .await
+        .unwrap()
+        .is_none()
+    );
+  }
+
+  #[tokio::test]
+  async fn test_revoke_user_sessions_keeps_exception() {
+    let (conn, user) = setup().await;
+    let table = conn.session();
+    let keep = table.create_session(user, None, None, None).await.unwrap();
+    let other = table.create_session(user, None, None, None).await.unwrap();
+
+    table.revoke_user_sessions(user, Some(keep)).await.unwrap();
+    assert!(table.get_session(keep).await.unwrap().is_some());
+    assert!(table.get_session(other).await.unwrap().is_none());
+
+    table.revoke_user_sessions(user, None).await.unwrap();
+    assert!(table.get_session(keep).await.unwrap().is_none());
+  }
+
+  #[tokio::test]
+  async fn test_revoke_oidc_sessions_of_provider() {
+    let (conn, user) = setup().await;
+    let table = conn.session();
+    let corp = table.create_session(user, None, None, None).await.unwrap();
+    let other = table.create_session(user, None, None, None).await.unwrap();
+    let local = table.create_session(user, None, None, None).await.unwrap();
+    table
+      .set_oidc_session(corp, "corp".into(), Some("idp-sid".into()))
+      .await
+      .unwrap();
+    table
+      .set_oidc_session(other, "other".into(), Some("idp-sid".into()))
+      .await
+      .unwrap();
+
+    // Without user or sid nothing is revoked.
+    assert_eq!(
+      table
+        .revoke_oidc_sessions("corp", None, None)
+        .await
+        .unwrap(),
+      0
+    );
+    assert_eq!(
+      table
+        .revoke_oidc_sessions("corp", None, Some("idp-sid"))
+        .await
+        .unwrap(),
+      1
+    );
+    assert!(table.get_session(corp).await.unwrap().is_none());
+    assert!(table.get_session(other).await.unwrap().is_some());
+
+    // Sessions not started with the provider are kept.
+    assert_eq!(
+      table
+        .revoke_oidc_sessions("other", Some(user), None)
+        .await
+        .unwrap(),
+      1
+    );
+    assert!(table.get_session(local).await.unwrap().is_some());
+  }
+
+  #[tokio::test]
+  async fn test_remove_inactive() {
+    let (conn, user) = setup().await;
+    let table = conn.session();
+    let id = table.create_session(user, None, None, None).await.unwrap();
+
+    table
+      .remove_inactive(Utc::now().naive_utc() - Duration::hours(1))
+      .await
+      .unwrap();
+    assert!(table.get_session(id).await.unwrap().is_some());
+
+    table
+      .remove_inactive(Utc::now().naive_utc() + Duration::hours(1))
+      .await
+      .unwrap();
+    assert!(table.get_session(id).await.unwrap().is_none());
+  }
+}
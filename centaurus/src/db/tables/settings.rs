@@ -5,12 +5,12 @@ use crate::{
   error::Result,
 };
 
-pub struct SettingsTable<'db> {
-  db: &'db DatabaseConnection,
+pub struct SettingsTable<'db, C = DatabaseConnection> {
+  db: &'db C,
 }
 
-impl<'db> SettingsTable<'db> {
-  pub fn new(db: &'db DatabaseConnection) -> Self {
+impl<'db, C: ConnectionTrait> SettingsTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
     Self { db }
   }
 
@@ -73,7 +73,7 @@ mod tests {
     let conn = connect_db(&db_config, "sqlite::memory:").await;
     Migrator::up(&*conn, None).await.unwrap();
 
-    let table = SettingsTable::new(&conn);
+    let table = SettingsTable::new(&*conn);
     let s = TestSettings { val: "test".into() };
     table.save_settings(&s).await.unwrap();
 
@@ -87,7 +87,7 @@ mod tests {
     let conn = connect_db(&db_config, "sqlite::memory:").await;
     Migrator::up(&*conn, None).await.unwrap();
 
-    let table = SettingsTable::new(&conn);
+    let table = SettingsTable::new(&*conn);
     // Reading settings that were never saved yields the type's default.
     let s: TestSettings = table.get_settings().await.unwrap();
     assert_eq!(s, TestSettings::default());
@@ -99,7 +99,7 @@ mod tests {
     let conn = connect_db(&db_config, "sqlite::memory:").await;
     Migrator::up(&*conn, None).await.unwrap();
 
-    let table = SettingsTable::new(&conn);
+    let table = SettingsTable::new(&*conn);
     table
       .save_settings(&TestSettings {
         val: "first".into(),
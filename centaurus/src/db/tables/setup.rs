@@ -4,12 +4,12 @@ use crate::{db::entities::setup, error::Result};
 
 const SETUP_ID: i32 = 1;
 
-pub struct SetupTable<'db> {
-  db: &'db DatabaseConnection,
+pub struct SetupTable<'db, C = DatabaseConnection> {
+  db: &'db C,
 }
 
-impl<'db> SetupTable<'db> {
-  pub fn new(db: &'db DatabaseConnection) -> Self {
+impl<'db, C: ConnectionTrait> SetupTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
     Self { db }
   }
 
@@ -78,7 +78,7 @@ mod tests {
   #[tokio::test]
   async fn test_fresh_setup_state() {
     let conn = setup().await;
-    let table = SetupTable::new(&conn);
+    let table = SetupTable::new(&*conn);
 
     // A fresh database is not set up and has no admin group.
     assert!(!table.is_setup().await.unwrap());
@@ -88,7 +88,7 @@ mod tests {
   #[tokio::test]
   async fn test_mark_completed() {
     let conn = setup().await;
-    let table = SetupTable::new(&conn);
+    let table = SetupTable::new(&*conn);
 
     table.mark_completed().await.unwrap();
     assert!(table.is_setup().await.unwrap());
@@ -101,7 +101,7 @@ mod tests {
   #[tokio::test]
   async fn test_admin_group_persistence() {
     let conn = setup().await;
-    let table = SetupTable::new(&conn);
+    let table = SetupTable::new(&*conn);
     let group = Uuid::now_v7();
 
     table.set_admin_group_created(group).await.unwrap();
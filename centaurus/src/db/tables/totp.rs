@@ -0,0 +1,247 @@
+use chrono::Utc;
+use sea_orm::{ActiveValue::Set, Condition, prelude::*, sea_query::Expr};
+use tracing::instrument;
+
+use crate::{
+  db::entities::{recovery_code, totp},
+  error::Result,
+};
+
+pub struct TotpTable<'db, C = DatabaseConnection> {
+  db: &'db C,
+}
+
+impl<'db, C: ConnectionTrait> TotpTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
+    Self { db }
+  }
+
+  #[instrument(skip(self))]
+  pub async fn get_totp(&self, user_id: Uuid) -> Result<Option<totp::Model>> {
+    Ok(totp::Entity::find_by_id(user_id).one(self.db).await?)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn is_enabled(&self, user_id: Uuid) -> Result<bool> {
+    Ok(
+      self
+        .get_totp(user_id)
+        .await?
+        .map(|totp| totp.enabled)
+        .unwrap_or(false),
+    )
+  }
+
+  /// Store a new secret that is not enabled until it has been confirmed with a
+  /// code. Replaces any earlier unconfirmed secret.
+  #[instrument(skip(self, secret))]
+  pub async fn set_pending_secret(&self, user_id: Uuid, secret: String) -> Result<()> {
+    totp::Entity::delete_by_id(user_id).exec(self.db).await?;
+
+    let model = totp::ActiveModel {
+      user_id: Set(user_id),
+      secret: Set(secret),
+      enabled: Set(false),
+      last_step: Set(None),
+      created: Set(Utc::now().naive_utc()),
+    };
+    model.insert(self.db).await?;
+
+    Ok(())
+  }
+
+  #[instrument(skip(self, code_hashes))]
+  pub async fn enable_totp(&self, user_id: Uuid, code_hashes: Vec<String>) -> Result<()> {
+    totp::Entity::update_many()
+      .col_expr(totp::Column::Enabled, Expr::value(true))
+      .filter(totp::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    self.replace_recovery_codes(user_id, code_hashes).await
+  }
+
+  /// Remove the secret and all recovery codes of the user.
+  #[instrument(skip(self))]
+  pub async fn disable_totp(&self, user_id: Uuid) -> Result<()> {
+    recovery_code::Entity::delete_many()
+      .filter(recovery_code::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+    totp::Entity::delete_by_id(user_id).exec(self.db).await?;
+
+    Ok(())
+  }
+
+  /// Record the time step of an accepted code. Returns false if the step (or a
+  /// later one) was already used, so every code can only be used once.
+  #[instrument(skip(self))]
+  pub async fn use_step(&self, user_id: Uuid, step: i64) -> Result<bool> {
+    let res = totp::Entity::update_many()
+      .col_expr(totp::Column::LastStep, Expr::value(step))
+      .filter(totp::Column::UserId.eq(user_id))
+      .filter(
+        Condition::any()
+          .add(totp::Column::LastStep.is_null())
+          .add(totp::Column::LastStep.lt(step)),
+      )
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+
+  #[instrument(skip(self, code_hashes))]
+  pub async fn replace_recovery_codes(
+    &self,
+    user_id: Uuid,
+    code_hashes: Vec<String>,
+  ) -> Result<()> {
+    recovery_code::Entity::delete_many()
+      .filter(recovery_code::Column::UserId.eq(user_id))
+      .exec(self.db)
+      .await?;
+
+    if code_hashes.is_empty() {
+      return Ok(());
+    }
+
+    let models = code_hashes
+      .into_iter()
+      .map(|code_hash| recovery_code::ActiveModel {
+        id: Set(Uuid::now_v7()),
+        user_id: Set(user_id),
+        code_hash: Set(code_hash),
+        used: Set(false),
+      });
+    recovery_code::Entity::insert_many(models)
+      .exec(self.db)
+      .await?;
+
+    Ok(())
+  }
+
+  /// Mark the matching recovery code as used. Returns false if there is no
+  /// unused code with this hash.
+  #[instrument(skip(self, code_hash))]
+  pub async fn use_recovery_code(&self, user_id: Uuid, code_hash: &str) -> Result<bool> {
+    let res = recovery_code::Entity::update_many()
+      .col_expr(recovery_code::Column::Used, Expr::value(true))
+      .filter(recovery_code::Column::UserId.eq(user_id))
+      .filter(recovery_code::Column::CodeHash.eq(code_hash))
+      .filter(recovery_code::Column::Used.eq(false))
+      .exec(self.db)
+      .await?;
+
+    Ok(res.rows_affected == 1)
+  }
+
+  #[instrument(skip(self))]
+  pub async fn remaining_recovery_codes(&self, user_id: Uuid) -> Result<u64> {
+    Ok(
+      recovery_code::Entity::find()
+        .filter(recovery_code::Column::UserId.eq(user_id))
+        .filter(recovery_code::Column::Used.eq(false))
+        .count(self.db)
+        .await?,
+    )
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::db::config::DBConfig;
+  use crate::db::init::{Connection, connect_db};
+  use crate::db::migrations::Migrator;
+  use crate::db::tables::ConnectionExt;
+  use sea_orm_migration::MigratorTrait;
+
+  async fn setup() -> (Connection, Uuid) {
+    let conn = connect_db(&DBConfig::default(), "sqlite::memory:").await;
+    Migrator::up(&*conn, None).await.unwrap();
+    let user = conn
+      .user()
+      .create_user(
+        "user".into(),
+        "user@example.com".into(),
+        "hash".into(),
+        "salt".into(),
+        false,
+        None,
+      )
+      .await
+      .unwrap();
+    (conn, user)
+  }
+
+  #[tokio::test]
+  async fn test_totp_enable_and_disable() {
+    let (conn, user) = setup().await;
+    let table = conn.totp();
+
+    assert!(table.get_totp(user).await.unwrap().is_none());
+    table
+      .set_pending_secret(user, "first".into())
+      .await
+      .unwrap();
+    table
+      .set_pending_secret(user, "second".into())
+      .await
+      .unwrap();
+    assert!(!table.is_enabled(user).await.unwrap());
+    assert_eq!(
+      table.get_totp(user).await.unwrap().unwrap().secret,
+      "second"
+    );
+
+    table
+      .enable_totp(user, vec!["a".into(), "b".into()])
+      .await
+      .unwrap();
+    assert!(table.is_enabled(user).await.unwrap());
+    assert_eq!(table.remaining_recovery_codes(user).await.unwrap(), 2);
+
+    table.disable_totp(user).await.unwrap();
+    assert!(table.get_totp(user).await.unwrap().is_none());
+    assert_eq!(table.remaining_recovery_codes(user).await.unwrap(), 0);
+  }
+
+  #[tokio::test]
+  async fn test_use_step_rejects_replay() {
+    let (conn, user) = setup().await;
+    let table = conn.totp();
+    table
+      .set_pending_secret(user, "secret".into())
+      .await
+      .unwrap();
+
+    assert!(table.use_step(user, 10).await.unwrap());
+    assert!(!table.use_step(user, 10).await.unwrap());
+    assert!(!table.use_step(user, 9).await.unwrap());
+    assert!(table.use_step(user, 11).await.unwrap());
+  }
+
+  #[tokio::test]
+  async fn test_recovery_codes_are_single_use() {
+    let (conn, user) = setup().await;
+    let table = conn.totp();
+    table
+      .set_pending_secret(user, "secret".into())
+      .await
+      .unwrap();
+    table.enable_totp(user, vec!["a".into()]).await.unwrap();
+
+    assert!(!table.use_recovery_code(user, "b").await.unwrap());
+    assert!(table.use_recovery_code(user, "a").await.unwrap());
+    assert!(!table.use_recovery_code(user, "a").await.unwrap());
+    assert_eq!(table.remaining_recovery_codes(user).await.unwrap(), 0);
+
+    table
+      .replace_recovery_codes(user, vec!["c".into()])
+      .await
+      .unwrap();
+    assert!(!table.use_recovery_code(user, "a").await.unwrap());
+    assert!(table.use_recovery_code(user, "c").await.unwrap());
+  }
+}
@@ -1,3 +1,4 @@
+use chrono::NaiveDateTime;
 use eyre::ContextCompat;
 use sea_orm::{Set, prelude::*};
 use serde::{Deserialize, Serialize};
@@ -5,13 +6,19 @@ use serde::{Deserialize, Serialize};
 use crate::{
   db::{
     entities::{group, group_user, user},
-    tables::group::{GroupTable, SimpleUserInfo},
+    tables::{
+      group::{GroupTable, SimpleUserInfo},
+      login_attempt::LoginAttemptTable,
+      pake_verifier::PakeVerifierTable,
+      password_history::PasswordHistoryTable,
+      password_reset::PasswordResetTable,
+    },
   },
   error::Result,
 };
 
-pub struct UserTable<'db> {
-  db: &'db DatabaseConnection,
+pub struct UserTable<'db, C = DatabaseConnection> {
+  db: &'db C,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -21,6 +28,7 @@ pub struct UserListInfo {
   pub name: String,
   pub email: String,
   pub groups: Vec<SimpleGroupInfo>,
+  pub active: bool,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -32,6 +40,10 @@ pub struct DetailUserInfo {
   pub groups: Vec<SimpleGroupInfo>,
   pub permissions: Vec<String>,
   pub oidc_user: bool,
+  pub service_account: bool,
+  pub active: bool,
+  /// Set while the account is locked after too many failed logins.
+  pub locked_until: Option<NaiveDateTime>,
 }
 
 #[derive(Serialize, Deserialize)]
@@ -41,8 +53,8 @@ pub struct SimpleGroupInfo {
   pub name: String,
 }
 
-impl<'db> UserTable<'db> {
-  pub fn new(db: &'db DatabaseConnection) -> Self {
+impl<'db, C: ConnectionTrait> UserTable<'db, C> {
+  pub fn new(db: &'db C) -> Self {
     Self { db }
   }
 
@@ -93,6 +105,8 @@ impl<'db> UserTable<'db> {
       salt,
       oidc_user,
       oidc_subject,
+      service_account: false,
+      active: true,
     }
     .into_active_model();
 
@@ -138,6 +152,15 @@ impl<'db> UserTable<'db> {
     Ok(user.update(self.db).await?)
   }
 
+  pub async fn clear_oidc_subject(&self, id: Uuid) -> Result<()> {
+    let mut user: user::ActiveModel = self.get_user_by_id(id).await?.into();
+
+    user.oidc_subject = Set(None);
+    user.update(self.db).await?;
+
+    Ok(())
+  }
+
   pub async fn resolve_oidc_user(&self, subject: &str, email: &str) -> Result<Option<user::Model>> {
     if let Some(user) = self.try_get_user_by_oidc_subject(subject).await? {
       return Ok(Some(user));
@@ -174,11 +197,34 @@ impl<'db> UserTable<'db> {
     )
   }
 
+  /// Also keeps the old hash for the reuse check and revokes all outstanding
+  /// password reset tokens of the user.
   pub async fn update_user_password(&self, id: Uuid, new_password: String) -> Result<()> {
-    let mut user: user::ActiveModel = self.get_user_by_id(id).await?.into();
+    let old = self.get_user_by_id(id).await?;
+    PasswordHistoryTable::new(self.db)
+      .add(id, old.password.clone())
+      .await?;
+    let mut user: user::ActiveModel = old.into();
 
     user.password = Set(new_password);
 
+    user.update(self.db).await?;
+    PasswordResetTable::new(self.db)
+      .revoke_user_tokens(id)
+      .await?;
+    // The verifier was derived from the old password
+    PakeVerifierTable::new(self.db).remove_verifier(id).await?;
+
+    Ok(())
+  }
+
+  /// Replace the hash of an unchanged password, e.g. after the hash parameters
+  /// changed.
+  pub async fn rehash_password(&self, id: Uuid, hash: String) -> Result<()> {
+    let mut user: user::ActiveModel = self.get_user_by_id(id).await?.into();
+
+    user.password = Set(hash);
+
     user.update(self.db).await?;
 
     Ok(())
@@ -194,6 +240,16 @@ impl<'db> UserTable<'db> {
     Ok(())
   }
 
+  pub async fn set_active(&self, id: Uuid, active: bool) -> Result<()> {
+    let mut user: user::ActiveModel = self.get_user_by_id(id).await?.into();
+
+    user.active = Set(active);
+
+    user.update(self.db).await?;
+
+    Ok(())
+  }
+
   pub async fn update_user_name(&self, id: Uuid, new_name: String) -> Result<()> {
     let mut user: user::ActiveModel = self.get_user_by_id(id).await?.into();
 
@@ -246,6 +302,9 @@ impl<'db> UserTable<'db> {
     let permissions = GroupTable::new(self.db)
       .get_user_permissions(user_id)
       .await?;
+    let locked_until = LoginAttemptTable::new(self.db)
+      .locked_until(user_id)
+      .await?;
 
     Ok(Some(DetailUserInfo {
       uuid: user.id,
@@ -254,6 +313,9 @@ impl<'db> UserTable<'db> {
       groups,
       permissions,
       oidc_user: user.oidc_user,
+      service_account: user.service_account,
+      active: user.active,
+      locked_until,
     }))
   }
 
@@ -331,8 +393,13 @@ impl<'db> UserTable<'db> {
     Ok(avatar.map(|a| a.data))
   }
 
+  /// Human users only, service accounts are listed by
+  /// `ServiceAccountTable::list_service_accounts`.
   pub async fn list_users(&self) -> Result<Vec<UserListInfo>> {
-    let users = user::Entity::find().all(self.db).await?;
+    let users = user::Entity::find()
+      .filter(user::Column::ServiceAccount.eq(false))
+      .all(self.db)
+      .await?;
     let group_user = users
       .load_many_to_many(group::Entity, group_user::Entity, self.db)
       .await?;
@@ -351,6 +418,7 @@ impl<'db> UserTable<'db> {
             name: group.name,
           })
           .collect(),
+        active: user.active,
       })
       .collect();
 
@@ -455,7 +523,7 @@ mod tests {
   async fn test_user_table() {
     let conn = setup().await;
 
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = make_user(&table, "test").await;
 
     let user = table.get_user_by_id(id).await.unwrap();
@@ -469,7 +537,7 @@ mod tests {
   #[tokio::test]
   async fn test_lookup_by_email() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = make_user(&table, "lookup").await;
 
     let user = table.get_user_by_email("lookup@example.com").await.unwrap();
@@ -494,7 +562,7 @@ mod tests {
   #[tokio::test]
   async fn test_missing_user_errors() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     assert!(table.get_user_by_id(Uuid::now_v7()).await.is_err());
     // user_info resolves to None rather than erroring for an unknown id.
     assert!(table.user_info(Uuid::now_v7()).await.unwrap().is_none());
@@ -503,7 +571,7 @@ mod tests {
   #[tokio::test]
   async fn test_update_fields() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "orig".into(),
@@ -533,7 +601,7 @@ mod tests {
   #[tokio::test]
   async fn test_change_email_lowercases() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = make_user(&table, "case").await;
 
     // change_email normalizes to lowercase regardless of input casing.
@@ -548,8 +616,8 @@ mod tests {
   #[tokio::test]
   async fn test_groups_and_info() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
-    let group_table = GroupTable::new(&conn);
+    let table = UserTable::new(&*conn);
+    let group_table = GroupTable::new(&*conn);
     let id = make_user(&table, "member").await;
     let group = group_table.create_group("grp".into()).await.unwrap();
     group_table
@@ -578,8 +646,8 @@ mod tests {
   #[tokio::test]
   async fn test_edit_user_replaces_groups() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
-    let group_table = GroupTable::new(&conn);
+    let table = UserTable::new(&*conn);
+    let group_table = GroupTable::new(&*conn);
     let id = make_user(&table, "edit").await;
     let g1 = group_table.create_group("g1".into()).await.unwrap();
     let g2 = group_table.create_group("g2".into()).await.unwrap();
@@ -608,7 +676,7 @@ mod tests {
   #[tokio::test]
   async fn test_list_users() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     make_user(&table, "u1").await;
     make_user(&table, "u2").await;
 
@@ -620,7 +688,7 @@ mod tests {
   #[tokio::test]
   async fn test_resolve_oidc_user_by_subject() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "subj".into(),
@@ -644,7 +712,7 @@ mod tests {
   #[tokio::test]
   async fn test_resolve_oidc_user_email_backfill() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "backfill".into(),
@@ -669,7 +737,7 @@ mod tests {
   #[tokio::test]
   async fn test_resolve_oidc_user_conflicting_subject() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     table
       .create_user(
         "conflict".into(),
@@ -694,7 +762,7 @@ mod tests {
   #[tokio::test]
   async fn test_resolve_oidc_user_no_match() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     table
       .create_user(
         "other".into(),
@@ -719,7 +787,7 @@ mod tests {
   #[tokio::test]
   async fn test_resolve_oidc_user_subject_precedence_over_email() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let subject_id = table
       .create_user(
         "by-subject".into(),
@@ -759,7 +827,7 @@ mod tests {
   #[tokio::test]
   async fn test_try_get_user_by_oidc_subject() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "subj".into(),
@@ -793,7 +861,7 @@ mod tests {
   #[tokio::test]
   async fn test_set_oidc_subject() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "user".into(),
@@ -820,7 +888,7 @@ mod tests {
   #[tokio::test]
   async fn test_create_user_duplicate_oidc_subject_fails() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     table
       .create_user(
         "first".into(),
@@ -851,7 +919,7 @@ mod tests {
   #[tokio::test]
   async fn test_create_user_allows_multiple_null_subjects() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     table
       .create_user(
         "first".into(),
@@ -881,7 +949,7 @@ mod tests {
   #[tokio::test]
   async fn test_sync_from_oidc_updates_name_and_email() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "old".into(),
@@ -909,7 +977,7 @@ mod tests {
   #[tokio::test]
   async fn test_sync_from_oidc_skips_conflicting_email() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "user".into(),
@@ -947,7 +1015,7 @@ mod tests {
   #[tokio::test]
   async fn test_sync_from_oidc_noop_when_unchanged() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "same".into(),
@@ -971,7 +1039,7 @@ mod tests {
   #[tokio::test]
   async fn test_sync_from_oidc_updates_name_only() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "old".into(),
@@ -999,7 +1067,7 @@ mod tests {
   #[tokio::test]
   async fn test_sync_from_oidc_updates_email_only() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "keep".into(),
@@ -1027,7 +1095,7 @@ mod tests {
   #[tokio::test]
   async fn test_sync_from_oidc_lowercases_email() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "user".into(),
@@ -1054,7 +1122,7 @@ mod tests {
   #[tokio::test]
   async fn test_sync_from_oidc_noop_when_email_differs_only_in_case() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "same".into(),
@@ -1081,7 +1149,7 @@ mod tests {
   #[tokio::test]
   async fn test_sync_from_oidc_updates_name_but_skips_conflicting_email() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = table
       .create_user(
         "old".into(),
@@ -1120,7 +1188,7 @@ mod tests {
   #[tokio::test]
   async fn test_delete_user() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = make_user(&table, "del").await;
 
     table.delete_user(id).await.unwrap();
@@ -1133,7 +1201,7 @@ mod tests {
   #[tokio::test]
   async fn test_avatar_roundtrip() {
     let conn = setup().await;
-    let table = UserTable::new(&conn);
+    let table = UserTable::new(&*conn);
     let id = make_user(&table, "avatar").await;
 
     table.update_user_avatar(id, vec![1, 2, 3]).await.unwrap();
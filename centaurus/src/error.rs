@@ -167,6 +167,8 @@ impl_from_error!(
   argon2::password_hash::Error,
   StatusCode::INTERNAL_SERVER_ERROR
 );
+#[cfg(feature = "argon2")]
+impl_from_error!(argon2::Error, StatusCode::INTERNAL_SERVER_ERROR);
 #[cfg(feature = "jsonwebtoken")]
 impl_from_error!(jsonwebtoken::errors::Error, StatusCode::BAD_REQUEST);
 #[cfg(feature = "db")]
@@ -194,6 +196,8 @@ impl_from_error!(MultipartError, StatusCode::BAD_REQUEST);
 impl_from_error!(reqwest::Error, StatusCode::INTERNAL_SERVER_ERROR);
 #[cfg(feature = "mail")]
 impl_from_error!(lettre::error::Error, StatusCode::INTERNAL_SERVER_ERROR);
+#[cfg(feature = "ldap")]
+impl_from_error!(ldap3::LdapError, StatusCode::INTERNAL_SERVER_ERROR);
 #[cfg(feature = "k8s")]
 impl_from_error!(kube::Error, StatusCode::INTERNAL_SERVER_ERROR);
 #[cfg(feature = "k8s")]
@@ -295,6 +299,22 @@ impl<T, E: Into<ErrorReport>> ErrorReportExt<T> for std::result::Result<T, E> {
   }
 }
 
+/// Error with a JSON body for the client, e.g. to explain why input was
+/// rejected. Other errors only expose their status.
+#[cfg(feature = "backend")]
+#[derive(Debug)]
+pub struct ErrorBody(pub serde_json::Value);
+
+#[cfg(feature = "backend")]
+impl Display for ErrorBody {
+  fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+    Display::fmt(&self.0, f)
+  }
+}
+
+#[cfg(feature = "backend")]
+impl std::error::Error for ErrorBody {}
+
 #[cfg(feature = "backend")]
 impl IntoResponse for ErrorReport {
   fn into_response(self) -> Response {
@@ -304,6 +324,9 @@ impl IntoResponse for ErrorReport {
     } else {
       tracing::debug!("{:?}", self.error);
     }
+    if let Some(ErrorBody(body)) = self.error.downcast_ref() {
+      return (self.status, axum::Json(body)).into_response();
+    }
     self.status.into_response()
   }
 }